@@ -8,6 +8,8 @@ extern crate env_logger;
 extern crate linenoise;
 extern crate llamadb;
 
+use llamadb::DatabaseEngine;
+
 use std::io::Write;
 use std::time::Duration;
 
@@ -17,9 +19,23 @@ use prettyselect::pretty_select;
 fn main() {
     env_logger::init().unwrap();
 
-    let mut lexer = llamadb::sqlsyntax::lexer::Lexer::new();
+    // With a path, the database is stored in (or loaded from) that file.
+    // Without one, the database is in-memory and is lost on exit.
+    match std::env::args().nth(1) {
+        Some(path) => {
+            match llamadb::filedb::FileDb::open(&path) {
+                Ok(mut db) => repl(&mut db),
+                Err(message) => println!("could not open {}: {}", path, message)
+            }
+        },
+        None => repl(&mut llamadb::tempdb::TempDb::new())
+    }
+}
 
-    let mut db = llamadb::tempdb::TempDb::new();
+fn repl<DB: DatabaseEngine>(db: &mut DB) {
+    let mut lexer = llamadb::sqlsyntax::lexer::Lexer::new();
+    // The lines that were fed to the lexer, to show where syntax errors are
+    let mut lines: Vec<String> = Vec::new();
 
     let mut out = std::io::stdout();
 
@@ -38,10 +54,9 @@ fn main() {
                 if input == "testdata" {
                     let mut sink = std::io::sink();
 
-                    match load_testdata(&mut sink, &mut db) {
-                        Ok(()) => println!("Test data loaded."),
-                        Err(message) => println!("{}", message)
-                    };
+                    if load_testdata(&mut sink, db) {
+                        println!("Test data loaded.");
+                    }
                     continue;
                 }
 
@@ -52,66 +67,141 @@ fn main() {
                     linenoise::history_add(&input);
                 }
 
-                while let Some(i) = lexer.tokens.iter().position(|token| token == &llamadb::sqlsyntax::lexer::Token::Semicolon) {
-                    match execute(&mut out, &mut db, &lexer.tokens[0..i+1]) {
+                lines.push(input);
+
+                // The statement with the error is discarded, along with the rest of the input
+                if let Some(e) = lexer.errors.first().cloned() {
+                    print_error(&lines, &llamadb::Error::from(e));
+                    lexer = llamadb::sqlsyntax::lexer::Lexer::new();
+                    lines.clear();
+                    continue;
+                }
+
+                while let Some(i) = lexer.tokens.iter().position(|t| t.token == llamadb::sqlsyntax::lexer::Token::Semicolon) {
+                    match execute(&mut out, db, &lexer.tokens[0..i+1]) {
                         Ok(()) => (),
-                        Err(message) => println!("{}", message)
+                        Err(e) => print_error(&lines, &e)
                     };
 
                     let right = lexer.tokens.split_off(i+1);
                     lexer.tokens = right;
                 }
+
+                // Locations start over from the next line once every statement has been executed
+                if lexer.tokens.is_empty() && lexer.is_no_state() {
+                    lexer = llamadb::sqlsyntax::lexer::Lexer::new();
+                    lines.clear();
+                }
             }
         }
     }
 }
 
-fn execute(out: &mut Write, db: &mut llamadb::tempdb::TempDb, tokens: &[llamadb::sqlsyntax::lexer::Token])
--> Result<(), String>
+/// Prints the error. A syntax error is followed by its line, with a caret under its column.
+fn print_error(lines: &[String], e: &llamadb::Error) {
+    println!("{}", e);
+
+    if let &llamadb::Error::Syntax { location: Some(location), .. } = e {
+        if let Some(line) = lines.get(location.line as usize - 1) {
+            // Tabs are kept so that the caret lines up with the character
+            let indent: String = line.chars().take(location.column as usize - 1).map(|c| {
+                if c == '\t' { '\t' } else { ' ' }
+            }).collect();
+
+            println!("{}", line);
+            println!("{}^", indent);
+        }
+    }
+}
+
+fn execute<DB: DatabaseEngine>(out: &mut Write, db: &mut DB, tokens: &[llamadb::sqlsyntax::lexer::SpannedToken])
+-> Result<(), llamadb::Error>
 {
-    let statement = match llamadb::sqlsyntax::parser::parse_statement(tokens) {
-        Ok(stmt) => stmt,
-        Err(e) => return Err(format!("syntax error: {}", e))
-    };
+    let statement = try!(llamadb::sqlsyntax::parser::parse_statement(tokens));
 
     execute_statement(out, db, statement)
 }
 
-fn execute_statement(out: &mut Write, db: &mut llamadb::tempdb::TempDb, statement: llamadb::sqlsyntax::ast::Statement)
--> Result<(), String>
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs() as f32 + (duration.subsec_nanos() as f32 * 1.0e-9);
+
+    format!("{:.3}s", seconds)
+}
+
+fn execute_statement<DB: DatabaseEngine>(out: &mut Write, db: &mut DB, statement: llamadb::sqlsyntax::ast::Statement)
+-> Result<(), llamadb::Error>
 {
-    use llamadb::tempdb::ExecuteStatementResponse;
+    use llamadb::ExecuteStatementResponse;
 
     let mut execute_result = None;
 
     let duration = Duration::span(|| {
         execute_result = Some(db.execute_statement(statement));
     });
-    
-    let seconds = duration.as_secs() as f32 + (duration.subsec_nanos() as f32 * 1.0e-9);
 
-    let duration_string = format!("{:.3}s", seconds);
+    let duration_string = format_duration(duration);
 
-    let result = match execute_result.unwrap() {
-        Ok(r) => r,
-        Err(e) => return Err(format!("execution error: {}", e))
-    };
+    let result = try!(execute_result.unwrap());
 
     let write_result = match result {
         ExecuteStatementResponse::Created => {
             writeln!(out, "Created ({}).", duration_string)
         },
+        ExecuteStatementResponse::Dropped => {
+            writeln!(out, "Dropped ({}).", duration_string)
+        },
+        ExecuteStatementResponse::Altered => {
+            writeln!(out, "Altered ({}).", duration_string)
+        },
         ExecuteStatementResponse::Inserted(rows) => {
             writeln!(out, "{} rows inserted ({}).", rows, duration_string)
         },
+        ExecuteStatementResponse::Updated(rows) => {
+            writeln!(out, "{} rows updated ({}).", rows, duration_string)
+        },
+        ExecuteStatementResponse::Deleted(rows) => {
+            writeln!(out, "{} rows deleted ({}).", rows, duration_string)
+        },
         ExecuteStatementResponse::Select { column_names, rows } => {
-            pretty_select(out, &column_names, rows, 32).and_then(|row_count| {
-                writeln!(out, "{} rows selected ({}).", row_count, duration_string)
+            // The rows are executed as they're printed, so an error stops the output.
+            // Printing is timed along with the statement, as that's when the rows are read.
+            let mut error = None;
+            let mut write_result = None;
+
+            let print_duration = Duration::span(|| {
+                let rows = rows.scan((), |_, row| {
+                    match row {
+                        Ok(row) => Some(row),
+                        Err(e) => {
+                            error = Some(e);
+                            None
+                        }
+                    }
+                });
+
+                write_result = Some(pretty_select(out, &column_names, rows, 32));
+            });
+
+            if let Some(e) = error {
+                return Err(e);
+            }
+
+            write_result.unwrap().and_then(|row_count| {
+                writeln!(out, "{} rows selected ({}).", row_count, format_duration(duration + print_duration))
             })
         },
         ExecuteStatementResponse::Explain(plan) => {
             writeln!(out, "{}", plan)
         },
+        ExecuteStatementResponse::Began => {
+            writeln!(out, "Transaction started.")
+        },
+        ExecuteStatementResponse::Committed => {
+            writeln!(out, "Committed ({}).", duration_string)
+        },
+        ExecuteStatementResponse::RolledBack => {
+            writeln!(out, "Rolled back.")
+        },
     };
 
     write_result.unwrap();
@@ -119,14 +209,28 @@ fn execute_statement(out: &mut Write, db: &mut llamadb::tempdb::TempDb, statemen
     Ok(())
 }
 
-fn load_testdata(out: &mut Write, db: &mut llamadb::tempdb::TempDb) -> Result<(), String> {
+/// Executes every statement of the test data, and returns whether they all succeeded.
+/// If any of the statements have syntax errors, they're all printed and none are executed.
+fn load_testdata<DB: DatabaseEngine>(out: &mut Write, db: &mut DB) -> bool {
     let test_data = include_str!("testdata.sql");
+    let lines: Vec<String> = test_data.lines().map(|line| line.to_string()).collect();
 
-    let statements = llamadb::sqlsyntax::parse_statements(test_data);
+    let statements = match llamadb::sqlsyntax::parse_statements(test_data) {
+        Ok(statements) => statements,
+        Err(errors) => {
+            for e in &errors {
+                print_error(&lines, e);
+            }
+            return false;
+        }
+    };
 
     for statement in statements {
-        try!(execute_statement(out, db, statement));
+        if let Err(e) = execute_statement(out, db, statement) {
+            println!("{}", e);
+            return false;
+        }
     }
 
-    Ok(())
+    true
 }
@@ -0,0 +1,872 @@
+//! A B+Tree of byte keys, stored in the pages of a pager.
+//!
+//! Keys are compared by their raw bytes, as described in
+//! `docs/Indexing and Sorting.md`. Each key is unique within a tree.
+//!
+//! See `docs/B+Tree.md` for the page layout.
+
+use pager::{Pager, PagerError};
+
+use std::cmp::Ordering;
+use std::fmt;
+
+mod page;
+use self::page::*;
+
+pub enum BTreeError {
+    Pager(PagerError),
+    InvalidCellLength(usize),
+    KeyTooLong(usize),
+    /// The page is not a valid B+Tree page, or the tree is inconsistent.
+    CorruptPage(u64)
+}
+
+impl fmt::Display for BTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        use self::BTreeError::*;
+
+        match self {
+            &Pager(ref e) => write!(f, "{}", e),
+            &InvalidCellLength(length) => write!(f, "invalid B+Tree cell length: {}", length),
+            &KeyTooLong(length) => write!(f, "B+Tree key is too long: {} bytes", length),
+            &CorruptPage(page_id) => write!(f, "corrupt B+Tree page: {}", page_id)
+        }
+    }
+}
+
+impl fmt::Debug for BTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self)
+    }
+}
+
+impl From<PagerError> for BTreeError {
+    fn from(e: PagerError) -> BTreeError {
+        BTreeError::Pager(e)
+    }
+}
+
+pub type BTreeResult<T> = Result<T, BTreeError>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Bound {
+    Included(Vec<u8>),
+    Excluded(Vec<u8>),
+    Unbounded
+}
+
+/// A handle to a B+Tree.
+///
+/// The tree is identified by its root page id, which never changes for the
+/// lifetime of the tree.
+#[derive(Copy, Clone, Debug)]
+pub struct BTree {
+    root_page_id: u64,
+    cell_length: usize
+}
+
+enum InsertResult {
+    AlreadyExists,
+    Inserted,
+    /// The page was split. The right half was moved to a new page.
+    /// The separator cell's left pointer is yet to be set by the parent.
+    Split {
+        separator: Box<[u8]>,
+        right_page_id: u64
+    }
+}
+
+enum RemoveResult {
+    NotFound,
+    Removed { underflow: bool }
+}
+
+impl BTree {
+    /// Creates an empty tree. Its root page is allocated from the pager.
+    pub fn create<P: Pager>(pager: &mut P, cell_length: usize) -> BTreeResult<BTree> {
+        if !is_valid_cell_length(pager.page_size(), cell_length) {
+            return Err(BTreeError::InvalidCellLength(cell_length));
+        }
+
+        let root_page_id = try!(pager.allocate_page());
+        try!(write_node(pager, root_page_id, cell_length, &Node::new_leaf(true)));
+
+        Ok(BTree {
+            root_page_id: root_page_id,
+            cell_length: cell_length
+        })
+    }
+
+    /// Opens an existing tree from its root page.
+    pub fn open<P: Pager>(pager: &P, root_page_id: u64) -> BTreeResult<BTree> {
+        let (cell_length, is_root) = try!(read_cell_length(pager, root_page_id));
+
+        if is_root {
+            Ok(BTree {
+                root_page_id: root_page_id,
+                cell_length: cell_length
+            })
+        } else {
+            Err(BTreeError::CorruptPage(root_page_id))
+        }
+    }
+
+    pub fn root_page_id(&self) -> u64 { self.root_page_id }
+
+    #[cfg(test)]
+    pub fn cell_length(&self) -> usize { self.cell_length }
+
+    fn max_cells<P: Pager>(&self, pager: &P) -> usize {
+        max_cell_count(pager.page_size(), self.cell_length)
+    }
+
+    fn min_cells<P: Pager>(&self, pager: &P) -> usize {
+        self.max_cells(pager) / 2
+    }
+
+    fn read_node<P: Pager>(&self, pager: &P, page_id: u64) -> BTreeResult<Node> {
+        read_node(pager, page_id, self.cell_length)
+    }
+
+    fn write_node<P: Pager>(&self, pager: &mut P, page_id: u64, node: &Node) -> BTreeResult<()> {
+        write_node(pager, page_id, self.cell_length, node)
+    }
+
+    /// Returns the index of the child that may contain the key.
+    fn find_child<P: Pager>(&self, pager: &P, node: &Node, key: &[u8]) -> BTreeResult<usize> {
+        for (i, cell) in node.cells.iter().enumerate() {
+            if try!(compare_cell(pager, cell, key)) == Ordering::Greater {
+                return Ok(i);
+            }
+        }
+
+        Ok(node.cells.len())
+    }
+
+    /// Returns the offset of the first leaf cell that isn't less than the key,
+    /// and whether that cell is equal to the key.
+    fn find_in_leaf<P: Pager>(&self, pager: &P, node: &Node, key: &[u8]) -> BTreeResult<(usize, bool)> {
+        let (mut low, mut high) = (0, node.cells.len());
+
+        while low < high {
+            let mid = (low + high) / 2;
+
+            match try!(compare_cell(pager, &node.cells[mid], key)) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Equal => return Ok((mid, true)),
+                Ordering::Greater => high = mid
+            }
+        }
+
+        Ok((low, false))
+    }
+
+    /// Inserts the key into the tree.
+    /// Returns false if the key was already in the tree.
+    pub fn insert<P: Pager>(&self, pager: &mut P, key: &[u8]) -> BTreeResult<bool> {
+        let root_page_id = self.root_page_id;
+
+        match try!(self.insert_into(pager, root_page_id, key)) {
+            InsertResult::AlreadyExists => Ok(false),
+            InsertResult::Inserted => Ok(true),
+            InsertResult::Split { .. } => unreachable!()
+        }
+    }
+
+    fn insert_into<P: Pager>(&self, pager: &mut P, page_id: u64, key: &[u8]) -> BTreeResult<InsertResult> {
+        let mut node = try!(self.read_node(pager, page_id));
+
+        if node.is_leaf {
+            let (offset, exists) = try!(self.find_in_leaf(pager, &node, key));
+            if exists {
+                return Ok(InsertResult::AlreadyExists);
+            }
+
+            let cell = try!(make_cell(pager, key, 0, self.cell_length));
+            node.cells.insert(offset, cell);
+        } else {
+            let i = try!(self.find_child(pager, &node, key));
+            let child_page_id = node.child(i);
+
+            match try!(self.insert_into(pager, child_page_id, key)) {
+                InsertResult::Split { mut separator, right_page_id } => {
+                    // The child keeps the left half.
+                    set_cell_left_pointer(&mut separator, child_page_id);
+                    node.cells.insert(i, separator);
+                    node.set_child(i + 1, right_page_id);
+                },
+                r => return Ok(r)
+            }
+        }
+
+        if node.cells.len() > self.max_cells(pager) {
+            self.split(pager, page_id, node)
+        } else {
+            try!(self.write_node(pager, page_id, &node));
+            Ok(InsertResult::Inserted)
+        }
+    }
+
+    /// Splits an overfull node in half.
+    ///
+    /// A non-root node keeps the left half in its own page.
+    /// The root page instead moves both halves into new pages, and becomes an
+    /// interior page with a single cell.
+    fn split<P: Pager>(&self, pager: &mut P, page_id: u64, node: Node) -> BTreeResult<InsertResult> {
+        let split_offset = node.cells.len() / 2;
+        let is_leaf = node.is_leaf;
+        let is_root = node.is_root;
+        let old_right_pointer = node.right_pointer;
+
+        let mut left_cells = node.cells;
+        let mut right_cells = left_cells.split_off(split_offset);
+
+        let (separator, left_right_pointer) = if is_leaf {
+            // Leaf keys remain in the leaves; the parent gets a copy of a key.
+            let left_key = try!(cell_key(pager, left_cells.last().unwrap()));
+            let right_key = try!(cell_key(pager, &right_cells[0]));
+            let separator = try!(make_cell(pager, &shortest_separator(&left_key, &right_key), 0, self.cell_length));
+
+            (separator, None)
+        } else {
+            // The middle cell moves up to the parent.
+            // Its left child becomes the right-most child of the left page.
+            let separator = right_cells.remove(0);
+            let left_child = cell_left_pointer(&separator);
+
+            (separator, Some(left_child))
+        };
+
+        let right_page_id = try!(pager.allocate_page());
+        let left_page_id = if is_root { try!(pager.allocate_page()) } else { page_id };
+
+        let right = Node {
+            is_root: false,
+            is_leaf: is_leaf,
+            cells: right_cells,
+            right_pointer: old_right_pointer
+        };
+
+        let left = Node {
+            is_root: false,
+            is_leaf: is_leaf,
+            cells: left_cells,
+            right_pointer: match left_right_pointer {
+                Some(p) => p,
+                // Leaves are linked to the next leaf
+                None => right_page_id
+            }
+        };
+
+        try!(self.write_node(pager, left_page_id, &left));
+        try!(self.write_node(pager, right_page_id, &right));
+
+        if is_root {
+            let mut separator = separator;
+            set_cell_left_pointer(&mut separator, left_page_id);
+
+            let root = Node {
+                is_root: true,
+                is_leaf: false,
+                cells: vec![separator],
+                right_pointer: right_page_id
+            };
+
+            try!(self.write_node(pager, page_id, &root));
+            Ok(InsertResult::Inserted)
+        } else {
+            Ok(InsertResult::Split {
+                separator: separator,
+                right_page_id: right_page_id
+            })
+        }
+    }
+
+    /// Removes the key from the tree.
+    /// Returns false if the key wasn't in the tree.
+    pub fn remove<P: Pager>(&self, pager: &mut P, key: &[u8]) -> BTreeResult<bool> {
+        let root_page_id = self.root_page_id;
+
+        match try!(self.remove_from(pager, root_page_id, key)) {
+            RemoveResult::NotFound => Ok(false),
+            RemoveResult::Removed { .. } => Ok(true)
+        }
+    }
+
+    fn remove_from<P: Pager>(&self, pager: &mut P, page_id: u64, key: &[u8]) -> BTreeResult<RemoveResult> {
+        let mut node = try!(self.read_node(pager, page_id));
+
+        if node.is_leaf {
+            let (offset, exists) = try!(self.find_in_leaf(pager, &node, key));
+            if !exists {
+                return Ok(RemoveResult::NotFound);
+            }
+
+            let cell = node.cells.remove(offset);
+            try!(free_cell(pager, &cell));
+        } else {
+            let i = try!(self.find_child(pager, &node, key));
+            let child_page_id = node.child(i);
+
+            match try!(self.remove_from(pager, child_page_id, key)) {
+                RemoveResult::Removed { underflow: true } => {
+                    try!(self.rebalance(pager, &mut node, i));
+                },
+                r => return Ok(r)
+            }
+
+            if node.is_root && node.cells.is_empty() {
+                // The root has a single child. Pull the child up into the root.
+                let only_child_page_id = node.child(0);
+                let mut child = try!(self.read_node(pager, only_child_page_id));
+                child.is_root = true;
+                try!(pager.free_page(only_child_page_id));
+
+                node = child;
+            }
+        }
+
+        try!(self.write_node(pager, page_id, &node));
+
+        let underflow = !node.is_root && node.cells.len() < self.min_cells(pager);
+        Ok(RemoveResult::Removed { underflow: underflow })
+    }
+
+    /// Fixes the underflowing child `i` of an interior node, by either taking
+    /// a cell from a sibling or merging with a sibling.
+    /// The interior node is modified, but not written.
+    fn rebalance<P: Pager>(&self, pager: &mut P, node: &mut Node, i: usize) -> BTreeResult<()> {
+        let min_cells = self.min_cells(pager);
+
+        if i > 0 {
+            let left = try!(self.read_node(pager, node.child(i - 1)));
+            if left.cells.len() > min_cells {
+                return self.take_from_left(pager, node, i, left);
+            }
+        }
+
+        if i + 1 < node.child_count() {
+            let right = try!(self.read_node(pager, node.child(i + 1)));
+            if right.cells.len() > min_cells {
+                return self.take_from_right(pager, node, i, right);
+            }
+        }
+
+        if i > 0 {
+            self.merge(pager, node, i - 1)
+        } else {
+            self.merge(pager, node, i)
+        }
+    }
+
+    fn take_from_left<P: Pager>(&self, pager: &mut P, node: &mut Node, i: usize, mut left: Node) -> BTreeResult<()> {
+        let (left_page_id, child_page_id) = (node.child(i - 1), node.child(i));
+        let mut child = try!(self.read_node(pager, child_page_id));
+
+        let cell = left.cells.pop().unwrap();
+
+        if child.is_leaf {
+            child.cells.insert(0, cell);
+
+            let left_key = try!(cell_key(pager, left.cells.last().unwrap()));
+            let child_key = try!(cell_key(pager, &child.cells[0]));
+            let separator = try!(make_cell(pager, &shortest_separator(&left_key, &child_key), left_page_id, self.cell_length));
+
+            let old_separator = ::std::mem::replace(&mut node.cells[i - 1], separator);
+            try!(free_cell(pager, &old_separator));
+        } else {
+            // Rotate right: the separator moves down, and the left's last cell moves up.
+            let mut separator = ::std::mem::replace(&mut node.cells[i - 1], cell);
+            set_cell_left_pointer(&mut separator, left.right_pointer);
+            child.cells.insert(0, separator);
+
+            left.right_pointer = cell_left_pointer(&node.cells[i - 1]);
+            set_cell_left_pointer(&mut node.cells[i - 1], left_page_id);
+        }
+
+        try!(self.write_node(pager, left_page_id, &left));
+        self.write_node(pager, child_page_id, &child)
+    }
+
+    fn take_from_right<P: Pager>(&self, pager: &mut P, node: &mut Node, i: usize, mut right: Node) -> BTreeResult<()> {
+        let (child_page_id, right_page_id) = (node.child(i), node.child(i + 1));
+        let mut child = try!(self.read_node(pager, child_page_id));
+
+        let cell = right.cells.remove(0);
+
+        if child.is_leaf {
+            child.cells.push(cell);
+
+            let child_key = try!(cell_key(pager, child.cells.last().unwrap()));
+            let right_key = try!(cell_key(pager, &right.cells[0]));
+            let separator = try!(make_cell(pager, &shortest_separator(&child_key, &right_key), child_page_id, self.cell_length));
+
+            let old_separator = ::std::mem::replace(&mut node.cells[i], separator);
+            try!(free_cell(pager, &old_separator));
+        } else {
+            // Rotate left: the separator moves down, and the right's first cell moves up.
+            let mut separator = ::std::mem::replace(&mut node.cells[i], cell);
+            set_cell_left_pointer(&mut separator, child.right_pointer);
+            child.cells.push(separator);
+
+            child.right_pointer = cell_left_pointer(&node.cells[i]);
+            set_cell_left_pointer(&mut node.cells[i], child_page_id);
+        }
+
+        try!(self.write_node(pager, child_page_id, &child));
+        self.write_node(pager, right_page_id, &right)
+    }
+
+    /// Merges child `i + 1` into child `i`, and frees the page of child `i + 1`.
+    fn merge<P: Pager>(&self, pager: &mut P, node: &mut Node, i: usize) -> BTreeResult<()> {
+        let (left_page_id, right_page_id) = (node.child(i), node.child(i + 1));
+        let mut left = try!(self.read_node(pager, left_page_id));
+        let right = try!(self.read_node(pager, right_page_id));
+
+        let mut separator = node.cells.remove(i);
+        node.set_child(i, left_page_id);
+
+        if left.is_leaf {
+            try!(free_cell(pager, &separator));
+        } else {
+            set_cell_left_pointer(&mut separator, left.right_pointer);
+            left.cells.push(separator);
+        }
+
+        left.cells.extend(right.cells);
+        left.right_pointer = right.right_pointer;
+
+        try!(self.write_node(pager, left_page_id, &left));
+        try!(pager.free_page(right_page_id));
+        Ok(())
+    }
+
+    /// Frees every page of the tree, including the root page.
+    /// The tree must not be used afterwards.
+    pub fn destroy<P: Pager>(self, pager: &mut P) -> BTreeResult<()> {
+        let root_page_id = self.root_page_id;
+        self.destroy_page(pager, root_page_id)
+    }
+
+    fn destroy_page<P: Pager>(&self, pager: &mut P, page_id: u64) -> BTreeResult<()> {
+        let node = try!(self.read_node(pager, page_id));
+
+        if !node.is_leaf {
+            for i in 0..node.child_count() {
+                try!(self.destroy_page(pager, node.child(i)));
+            }
+        }
+
+        for cell in &node.cells {
+            try!(free_cell(pager, cell));
+        }
+
+        try!(pager.free_page(page_id));
+        Ok(())
+    }
+
+    /// Returns true if the key is in the tree.
+    #[cfg(test)]
+    pub fn contains<P: Pager>(&self, pager: &P, key: &[u8]) -> BTreeResult<bool> {
+        let mut page_id = self.root_page_id;
+
+        loop {
+            let node = try!(self.read_node(pager, page_id));
+
+            if node.is_leaf {
+                let (_, exists) = try!(self.find_in_leaf(pager, &node, key));
+                return Ok(exists);
+            } else {
+                let i = try!(self.find_child(pager, &node, key));
+                page_id = node.child(i);
+            }
+        }
+    }
+
+    /// Returns the greatest key in the tree, or None if the tree is empty.
+    #[cfg(test)]
+    pub fn last<P: Pager>(&self, pager: &P) -> BTreeResult<Option<Vec<u8>>> {
+        let mut page_id = self.root_page_id;
+
+        loop {
+            let node = try!(self.read_node(pager, page_id));
+
+            if node.is_leaf {
+                return match node.cells.last() {
+                    Some(cell) => Ok(Some(try!(cell_key(pager, cell)))),
+                    None => Ok(None)
+                };
+            } else {
+                page_id = node.right_pointer;
+            }
+        }
+    }
+
+    /// Iterates every key in the tree, in ascending order.
+    pub fn iter<'a, P: Pager>(&self, pager: &'a P) -> Cursor<'a, P> {
+        self.range(pager, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Iterates the keys between `min` and `max`, in ascending order.
+    pub fn range<'a, P: Pager>(&self, pager: &'a P, min: Bound, max: Bound) -> Cursor<'a, P> {
+        let mut cursor = Cursor {
+            pager: pager,
+            cell_length: self.cell_length,
+            cells: Vec::new(),
+            offset: 0,
+            right_pointer: 0,
+            max: max,
+            seek_error: None,
+            done: false
+        };
+
+        if let Err(e) = cursor.seek(self.root_page_id, &min) {
+            cursor.seek_error = Some(e);
+        }
+
+        cursor
+    }
+}
+
+/// Iterates the keys of a B+Tree by traversing the linked list of leaf pages.
+pub struct Cursor<'a, P: Pager + 'a> {
+    pager: &'a P,
+    cell_length: usize,
+
+    /// The cells of the current leaf page
+    cells: Vec<Box<[u8]>>,
+    offset: usize,
+    /// The next leaf page
+    right_pointer: u64,
+
+    max: Bound,
+    /// Yielded by the first call to `next()`, if the initial search failed.
+    seek_error: Option<BTreeError>,
+    done: bool
+}
+
+impl<'a, P: Pager + 'a> Cursor<'a, P> {
+    /// Descends to the leaf that contains the first key within `min`.
+    fn seek(&mut self, root_page_id: u64, min: &Bound) -> BTreeResult<()> {
+        let tree = BTree { root_page_id: root_page_id, cell_length: self.cell_length };
+        let pager = self.pager;
+
+        let mut page_id = root_page_id;
+
+        loop {
+            let node = try!(tree.read_node(pager, page_id));
+
+            if node.is_leaf {
+                self.offset = match min {
+                    &Bound::Unbounded => 0,
+                    &Bound::Included(ref key) => try!(tree.find_in_leaf(pager, &node, key)).0,
+                    &Bound::Excluded(ref key) => {
+                        match try!(tree.find_in_leaf(pager, &node, key)) {
+                            (offset, true) => offset + 1,
+                            (offset, false) => offset
+                        }
+                    }
+                };
+
+                self.cells = node.cells;
+                self.right_pointer = node.right_pointer;
+                return Ok(());
+            } else {
+                let i = match min {
+                    &Bound::Unbounded => 0,
+                    &Bound::Included(ref key) | &Bound::Excluded(ref key) => try!(tree.find_child(pager, &node, key))
+                };
+
+                page_id = node.child(i);
+            }
+        }
+    }
+
+    fn next_key(&mut self) -> BTreeResult<Option<Vec<u8>>> {
+        loop {
+            if self.offset < self.cells.len() {
+                let cell = &self.cells[self.offset];
+                self.offset += 1;
+
+                let past_max = match self.max {
+                    Bound::Unbounded => false,
+                    Bound::Included(ref key) => try!(compare_cell(self.pager, cell, key)) == Ordering::Greater,
+                    Bound::Excluded(ref key) => try!(compare_cell(self.pager, cell, key)) != Ordering::Less
+                };
+
+                if past_max {
+                    return Ok(None);
+                }
+
+                return Ok(Some(try!(cell_key(self.pager, cell))));
+            } else if self.right_pointer != 0 {
+                // End of leaf page. Keep traversing right.
+                let node = try!(read_node(self.pager, self.right_pointer, self.cell_length));
+                self.cells = node.cells;
+                self.offset = 0;
+                self.right_pointer = node.right_pointer;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+impl<'a, P: Pager + 'a> Iterator for Cursor<'a, P> {
+    type Item = BTreeResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<BTreeResult<Vec<u8>>> {
+        if self.done {
+            return None;
+        }
+
+        if let Some(e) = self.seek_error.take() {
+            self.done = true;
+            return Some(Err(e));
+        }
+
+        match self.next_key() {
+            Ok(Some(key)) => Some(Ok(key)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BTree, BTreeError, Bound};
+    use super::page::{read_node, Node};
+    use pager::{Pager, MemoryPager};
+    use std::collections::BTreeSet;
+
+    /// A deterministic sequence of pseudo-random numbers.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> u64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            self.0 >> 33
+        }
+    }
+
+    fn key(n: u64, length: usize) -> Vec<u8> {
+        let mut key = format!("{:08}", n).into_bytes();
+        while key.len() < length {
+            let v = key[key.len() - 8];
+            key.push(v);
+        }
+        key
+    }
+
+    fn keys(tree: &BTree, pager: &MemoryPager) -> Vec<Vec<u8>> {
+        tree.iter(pager).map(|k| k.unwrap()).collect()
+    }
+
+    /// Checks that every page is sorted, within the bounds given by its
+    /// parent, and that every leaf is at the same depth.
+    /// Returns the depth of the tree.
+    fn check_invariants(tree: &BTree, pager: &MemoryPager) -> usize {
+        fn check(tree: &BTree, pager: &MemoryPager, page_id: u64, min: Option<&[u8]>, max: Option<&[u8]>) -> usize {
+            let node = read_node(pager, page_id, tree.cell_length).unwrap();
+
+            if !node.is_root {
+                assert!(node.cells.len() >= tree.min_cells(pager), "page {} underflows", page_id);
+            }
+
+            let cell_keys: Vec<Vec<u8>> = node.cells.iter().map(|c| super::page::cell_key(pager, c).unwrap()).collect();
+
+            for w in cell_keys.windows(2) {
+                assert!(w[0] < w[1]);
+            }
+            for k in &cell_keys {
+                if let Some(min) = min { assert!(&k[..] >= min); }
+                if let Some(max) = max { assert!(&k[..] < max); }
+            }
+
+            if node.is_leaf {
+                1
+            } else {
+                let depths: Vec<usize> = (0..node.child_count()).map(|i| {
+                    let child_min = if i == 0 { min } else { Some(&cell_keys[i - 1][..]) };
+                    let child_max = if i == node.cells.len() { max } else { Some(&cell_keys[i][..]) };
+                    check(tree, pager, node.child(i), child_min, child_max)
+                }).collect();
+
+                assert!(depths.iter().all(|&d| d == depths[0]));
+                depths[0] + 1
+            }
+        }
+
+        let root: Node = read_node(pager, tree.root_page_id(), tree.cell_length).unwrap();
+        assert!(root.is_root);
+
+        check(tree, pager, tree.root_page_id(), None, None)
+    }
+
+    fn test_against_set(page_size: usize, cell_length: usize, key_length: usize, count: u64) {
+        let mut pager = MemoryPager::new(page_size).unwrap();
+        let tree = BTree::create(&mut pager, cell_length).unwrap();
+        let mut set = BTreeSet::new();
+        let mut rng = Lcg(key_length as u64);
+
+        for _ in 0..count {
+            let k = key(rng.next() % (count * 2), key_length);
+            assert_eq!(tree.insert(&mut pager, &k).unwrap(), set.insert(k.clone()));
+            assert!(tree.contains(&pager, &k).unwrap());
+        }
+
+        assert!(check_invariants(&tree, &pager) > 1);
+        assert_eq!(keys(&tree, &pager), set.iter().cloned().collect::<Vec<_>>());
+        assert_eq!(tree.last(&pager).unwrap(), set.iter().next_back().cloned());
+
+        for _ in 0..count * 2 {
+            let k = key(rng.next() % (count * 2), key_length);
+            assert_eq!(tree.remove(&mut pager, &k).unwrap(), set.remove(&k));
+            assert!(!tree.contains(&pager, &k).unwrap());
+        }
+
+        check_invariants(&tree, &pager);
+        assert_eq!(keys(&tree, &pager), set.iter().cloned().collect::<Vec<_>>());
+        assert_eq!(tree.last(&pager).unwrap(), set.iter().next_back().cloned());
+
+        let remaining: Vec<Vec<u8>> = set.iter().cloned().collect();
+        for k in remaining {
+            assert!(tree.remove(&mut pager, &k).unwrap());
+        }
+
+        assert_eq!(check_invariants(&tree, &pager), 1);
+        assert!(keys(&tree, &pager).is_empty());
+        assert_eq!(tree.last(&pager).unwrap(), None);
+    }
+
+    #[test]
+    fn test_splits_and_merges() {
+        // 2 cells per page: every other insertion splits a page
+        test_against_set(64, 24, 8, 300);
+        test_against_set(512, 24, 8, 2000);
+        test_against_set(512, 60, 40, 500);
+    }
+
+    #[test]
+    fn test_overflow() {
+        // Every key is spread over several overflow pages
+        test_against_set(128, 32, 500, 200);
+    }
+
+    #[test]
+    fn test_free_pages_are_reused() {
+        let mut pager = MemoryPager::new(128).unwrap();
+        let tree = BTree::create(&mut pager, 32).unwrap();
+
+        for round in 0..3 {
+            for n in 0..100 {
+                tree.insert(&mut pager, &key(n, 300)).unwrap();
+            }
+
+            let page_count = pager.page_count();
+
+            for n in 0..100 {
+                assert!(tree.remove(&mut pager, &key(n, 300)).unwrap());
+            }
+
+            if round > 0 {
+                // Every page besides the root was freed, and later reused
+                assert_eq!(pager.page_count(), page_count);
+            }
+        }
+    }
+
+    #[test]
+    fn test_destroy() {
+        let mut pager = MemoryPager::new(128).unwrap();
+
+        let mut page_count = None;
+
+        for _ in 0..3 {
+            let tree = BTree::create(&mut pager, 32).unwrap();
+            for n in 0..100 {
+                tree.insert(&mut pager, &key(n, 300)).unwrap();
+            }
+
+            // Every page of the destroyed tree is reused by the next tree
+            if let Some(page_count) = page_count {
+                assert_eq!(pager.page_count(), page_count);
+            }
+            page_count = Some(pager.page_count());
+
+            tree.destroy(&mut pager).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_range() {
+        let mut pager = MemoryPager::new(64).unwrap();
+        let tree = BTree::create(&mut pager, 24).unwrap();
+
+        for n in (0..50).map(|n| n * 2) {
+            assert!(tree.insert(&mut pager, &key(n, 8)).unwrap());
+        }
+
+        // Duplicates aren't inserted
+        assert!(!tree.insert(&mut pager, &key(10, 8)).unwrap());
+
+        let range = |min: Bound, max: Bound| -> Vec<Vec<u8>> {
+            tree.range(&pager, min, max).map(|k| k.unwrap()).collect()
+        };
+
+        let expected = |ns: &[u64]| -> Vec<Vec<u8>> {
+            ns.iter().map(|&n| key(n, 8)).collect()
+        };
+
+        assert_eq!(range(Bound::Included(key(10, 8)), Bound::Included(key(16, 8))), expected(&[10, 12, 14, 16]));
+        assert_eq!(range(Bound::Excluded(key(10, 8)), Bound::Excluded(key(16, 8))), expected(&[12, 14]));
+        assert_eq!(range(Bound::Included(key(11, 8)), Bound::Excluded(key(17, 8))), expected(&[12, 14, 16]));
+        assert_eq!(range(Bound::Unbounded, Bound::Excluded(key(6, 8))), expected(&[0, 2, 4]));
+        assert_eq!(range(Bound::Excluded(key(94, 8)), Bound::Unbounded), expected(&[96, 98]));
+        assert_eq!(range(Bound::Included(key(99, 8)), Bound::Unbounded), expected(&[]));
+        assert_eq!(range(Bound::Included(key(20, 8)), Bound::Excluded(key(20, 8))), expected(&[]));
+        assert_eq!(range(Bound::Unbounded, Bound::Unbounded).len(), 50);
+    }
+
+    #[test]
+    fn test_reopen() {
+        let mut pager = MemoryPager::new(64).unwrap();
+        let root_page_id = {
+            let tree = BTree::create(&mut pager, 24).unwrap();
+            for n in 0..20 {
+                tree.insert(&mut pager, &key(n, 8)).unwrap();
+            }
+            tree.root_page_id()
+        };
+
+        let tree = BTree::open(&pager, root_page_id).unwrap();
+        assert_eq!(tree.cell_length(), 24);
+        assert_eq!(keys(&tree, &pager).len(), 20);
+
+        // A non-root page can't be opened as a tree
+        let child = read_node(&pager, root_page_id, 24).unwrap().child(0);
+        assert!(BTree::open(&pager, child).is_err());
+    }
+
+    #[test]
+    fn test_invalid_cell_length() {
+        let mut pager = MemoryPager::new(64).unwrap();
+
+        match BTree::create(&mut pager, 23) {
+            Err(BTreeError::InvalidCellLength(23)) => (),
+            _ => panic!("cell length must be at least 24")
+        }
+
+        match BTree::create(&mut pager, 25) {
+            Err(BTreeError::InvalidCellLength(25)) => (),
+            _ => panic!("a page must fit at least 2 cells")
+        }
+    }
+}
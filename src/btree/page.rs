@@ -0,0 +1,287 @@
+//! Reading and writing of B+Tree pages and cells.
+//! See `docs/B+Tree.md` for the layouts.
+
+use byteutils;
+use pager::Pager;
+use super::{BTreeError, BTreeResult};
+
+use std::cmp::{self, Ordering};
+
+pub const PAGE_HEADER_LENGTH: usize = 16;
+pub const CELL_HEADER_LENGTH: usize = 12;
+pub const OVERFLOW_POINTER_LENGTH: usize = 8;
+pub const MIN_CELL_LENGTH: usize = 24;
+
+const FLAG_LEAF: u8 = 0x01;
+const FLAG_ROOT: u8 = 0x02;
+
+/// The maximum amount of cells that fit in a page.
+pub fn max_cell_count(page_size: usize, cell_length: usize) -> usize {
+    (page_size - PAGE_HEADER_LENGTH) / cell_length
+}
+
+pub fn is_valid_cell_length(page_size: usize, cell_length: usize) -> bool {
+    cell_length >= MIN_CELL_LENGTH && page_size >= PAGE_HEADER_LENGTH + cell_length * 2
+}
+
+/// A decoded B+Tree page.
+///
+/// Cells are kept in their raw, fixed-length form so they can be moved
+/// between pages byte-for-byte.
+///
+/// For interior pages, child `i` is the left page pointer of cell `i`,
+/// and the last child is the right page pointer.
+pub struct Node {
+    pub is_root: bool,
+    pub is_leaf: bool,
+    pub cells: Vec<Box<[u8]>>,
+    pub right_pointer: u64
+}
+
+impl Node {
+    pub fn new_leaf(is_root: bool) -> Node {
+        Node {
+            is_root: is_root,
+            is_leaf: true,
+            cells: Vec::new(),
+            right_pointer: 0
+        }
+    }
+
+    pub fn child_count(&self) -> usize {
+        self.cells.len() + 1
+    }
+
+    pub fn child(&self, i: usize) -> u64 {
+        if i < self.cells.len() {
+            cell_left_pointer(&self.cells[i])
+        } else {
+            self.right_pointer
+        }
+    }
+
+    pub fn set_child(&mut self, i: usize, page_id: u64) {
+        if i < self.cells.len() {
+            set_cell_left_pointer(&mut self.cells[i], page_id);
+        } else {
+            self.right_pointer = page_id;
+        }
+    }
+}
+
+pub fn read_cell_length<P: Pager>(pager: &P, page_id: u64) -> BTreeResult<(usize, bool)> {
+    let mut buf = vec![0; pager.page_size()];
+    try!(pager.read_page(page_id, &mut buf));
+
+    let cell_length = byteutils::read_u16_le(&buf[6..8]) as usize;
+    let is_root = buf[0] & FLAG_ROOT != 0;
+
+    if is_valid_cell_length(pager.page_size(), cell_length) {
+        Ok((cell_length, is_root))
+    } else {
+        Err(BTreeError::CorruptPage(page_id))
+    }
+}
+
+pub fn read_node<P: Pager>(pager: &P, page_id: u64, cell_length: usize) -> BTreeResult<Node> {
+    let mut buf = vec![0; pager.page_size()];
+    try!(pager.read_page(page_id, &mut buf));
+
+    let flags = buf[0];
+    let cell_count = byteutils::read_u16_le(&buf[4..6]) as usize;
+    let page_cell_length = byteutils::read_u16_le(&buf[6..8]) as usize;
+    let right_pointer = byteutils::read_u64_le(&buf[8..16]);
+
+    if page_cell_length != cell_length || cell_count > max_cell_count(buf.len(), cell_length) {
+        return Err(BTreeError::CorruptPage(page_id));
+    }
+
+    let cells = (0..cell_count).map(|i| {
+        let offset = PAGE_HEADER_LENGTH + i * cell_length;
+        buf[offset..offset + cell_length].to_vec().into_boxed_slice()
+    }).collect();
+
+    Ok(Node {
+        is_root: flags & FLAG_ROOT != 0,
+        is_leaf: flags & FLAG_LEAF != 0,
+        cells: cells,
+        right_pointer: right_pointer
+    })
+}
+
+pub fn write_node<P: Pager>(pager: &mut P, page_id: u64, cell_length: usize, node: &Node) -> BTreeResult<()> {
+    assert!(node.cells.len() <= max_cell_count(pager.page_size(), cell_length));
+
+    let mut buf = vec![0; pager.page_size()];
+
+    buf[0] = (if node.is_root { FLAG_ROOT } else { 0 }) | (if node.is_leaf { FLAG_LEAF } else { 0 });
+    byteutils::write_u16_le(node.cells.len() as u16, &mut buf[4..6]);
+    byteutils::write_u16_le(cell_length as u16, &mut buf[6..8]);
+    byteutils::write_u64_le(node.right_pointer, &mut buf[8..16]);
+
+    for (i, cell) in node.cells.iter().enumerate() {
+        let offset = PAGE_HEADER_LENGTH + i * cell_length;
+        buf[offset..offset + cell_length].copy_from_slice(cell);
+    }
+
+    try!(pager.write_page(page_id, &buf));
+    Ok(())
+}
+
+pub fn cell_left_pointer(cell: &[u8]) -> u64 {
+    byteutils::read_u64_le(&cell[0..8])
+}
+
+pub fn set_cell_left_pointer(cell: &mut [u8], page_id: u64) {
+    byteutils::write_u64_le(page_id, &mut cell[0..8]);
+}
+
+fn cell_payload_length(cell: &[u8]) -> usize {
+    byteutils::read_u32_le(&cell[8..12]) as usize
+}
+
+fn has_overflow(cell: &[u8]) -> bool {
+    cell_payload_length(cell) > cell.len() - CELL_HEADER_LENGTH
+}
+
+fn cell_overflow_page(cell: &[u8]) -> u64 {
+    let o = CELL_HEADER_LENGTH;
+    byteutils::read_u64_le(&cell[o..o + OVERFLOW_POINTER_LENGTH])
+}
+
+fn cell_in_page_payload(cell: &[u8]) -> &[u8] {
+    if has_overflow(cell) {
+        &cell[CELL_HEADER_LENGTH + OVERFLOW_POINTER_LENGTH..]
+    } else {
+        &cell[CELL_HEADER_LENGTH..CELL_HEADER_LENGTH + cell_payload_length(cell)]
+    }
+}
+
+/// Creates a cell for the key. If the key doesn't fit in the cell,
+/// the remainder is written to newly allocated overflow pages.
+///
+/// Overflow pages start with an 8-byte pointer to the next overflow page
+/// (0 if there is none), followed by payload data.
+pub fn make_cell<P: Pager>(pager: &mut P, key: &[u8], left_pointer: u64, cell_length: usize) -> BTreeResult<Box<[u8]>> {
+    use std::u32;
+
+    if key.len() > u32::MAX as usize {
+        return Err(BTreeError::KeyTooLong(key.len()));
+    }
+
+    let mut cell = vec![0; cell_length];
+    set_cell_left_pointer(&mut cell, left_pointer);
+    byteutils::write_u32_le(key.len() as u32, &mut cell[8..12]);
+
+    if key.len() <= cell_length - CELL_HEADER_LENGTH {
+        cell[CELL_HEADER_LENGTH..CELL_HEADER_LENGTH + key.len()].copy_from_slice(key);
+    } else {
+        let in_page_length = cell_length - CELL_HEADER_LENGTH - OVERFLOW_POINTER_LENGTH;
+        cell[CELL_HEADER_LENGTH + OVERFLOW_POINTER_LENGTH..].copy_from_slice(&key[0..in_page_length]);
+
+        let overflow_page = try!(write_overflow_pages(pager, &key[in_page_length..]));
+        byteutils::write_u64_le(overflow_page, &mut cell[CELL_HEADER_LENGTH..CELL_HEADER_LENGTH + OVERFLOW_POINTER_LENGTH]);
+    }
+
+    Ok(cell.into_boxed_slice())
+}
+
+fn write_overflow_pages<P: Pager>(pager: &mut P, data: &[u8]) -> BTreeResult<u64> {
+    let chunk_length = pager.page_size() - OVERFLOW_POINTER_LENGTH;
+
+    let page_ids: Vec<u64> = try!(data.chunks(chunk_length).map(|_| pager.allocate_page()).collect());
+
+    let mut buf = vec![0; pager.page_size()];
+
+    for (i, chunk) in data.chunks(chunk_length).enumerate() {
+        let next = if i + 1 < page_ids.len() { page_ids[i + 1] } else { 0 };
+
+        for v in buf.iter_mut() { *v = 0; }
+        byteutils::write_u64_le(next, &mut buf[0..OVERFLOW_POINTER_LENGTH]);
+        buf[OVERFLOW_POINTER_LENGTH..OVERFLOW_POINTER_LENGTH + chunk.len()].copy_from_slice(chunk);
+
+        try!(pager.write_page(page_ids[i], &buf));
+    }
+
+    Ok(page_ids[0])
+}
+
+/// Frees the overflow pages of a cell, if it has any.
+pub fn free_cell<P: Pager>(pager: &mut P, cell: &[u8]) -> BTreeResult<()> {
+    if has_overflow(cell) {
+        let mut buf = vec![0; pager.page_size()];
+        let mut page_id = cell_overflow_page(cell);
+
+        while page_id != 0 {
+            try!(pager.read_page(page_id, &mut buf));
+            try!(pager.free_page(page_id));
+            page_id = byteutils::read_u64_le(&buf[0..OVERFLOW_POINTER_LENGTH]);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the entire key of a cell, including any overflow data.
+pub fn cell_key<P: Pager>(pager: &P, cell: &[u8]) -> BTreeResult<Vec<u8>> {
+    let payload_length = cell_payload_length(cell);
+
+    let mut key = Vec::with_capacity(payload_length);
+    key.extend_from_slice(cell_in_page_payload(cell));
+
+    if has_overflow(cell) {
+        let mut buf = vec![0; pager.page_size()];
+        let mut page_id = cell_overflow_page(cell);
+
+        while key.len() < payload_length {
+            if page_id == 0 {
+                return Err(BTreeError::CorruptPage(page_id));
+            }
+
+            try!(pager.read_page(page_id, &mut buf));
+
+            let remaining = payload_length - key.len();
+            let data = &buf[OVERFLOW_POINTER_LENGTH..];
+            key.extend_from_slice(&data[0..cmp::min(remaining, data.len())]);
+
+            page_id = byteutils::read_u64_le(&buf[0..OVERFLOW_POINTER_LENGTH]);
+        }
+    }
+
+    Ok(key)
+}
+
+/// Compares the key of a cell with `key`.
+///
+/// Overflow pages are only read if the in-page payload compares equal to the
+/// beginning of `key`.
+pub fn compare_cell<P: Pager>(pager: &P, cell: &[u8], key: &[u8]) -> BTreeResult<Ordering> {
+    let payload_length = cell_payload_length(cell);
+    let in_page = cell_in_page_payload(cell);
+
+    let n = cmp::min(in_page.len(), key.len());
+    match in_page[0..n].cmp(&key[0..n]) {
+        Ordering::Equal => (),
+        o => return Ok(o)
+    }
+
+    if payload_length <= key.len() && !has_overflow(cell) {
+        return Ok(payload_length.cmp(&key.len()));
+    }
+
+    if key.len() <= in_page.len() {
+        // The key is a prefix of the cell's payload.
+        return Ok(payload_length.cmp(&key.len()));
+    }
+
+    // Sad path: we need to read overflow pages.
+    let full_key = try!(cell_key(pager, cell));
+    Ok((&full_key as &[u8]).cmp(key))
+}
+
+/// Returns the shortest key that sorts after `left` and no later than `right`.
+/// `left` must sort before `right`.
+pub fn shortest_separator(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let common = left.iter().zip(right.iter()).take_while(|&(l, r)| l == r).count();
+    right[0..common + 1].to_vec()
+}
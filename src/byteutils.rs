@@ -136,12 +136,16 @@ pub fn write_sdbinteger(value: i64, buf: &mut [u8]) {
 pub fn write_dbfloat(value: f64, buf: &mut [u8]) {
     use std::mem;
 
+    // -0 is equal to 0, so both are written as 0. Values that are equal then
+    // always have the same bytes, in rows as well as in keys.
+    let value = if value == 0.0 { 0.0 } else { value };
+
     let raw: u64 = unsafe {
         // assumes that the platform uses IEEE 754 encoding for floats
         mem::transmute(value)
     };
 
-    if value < 0.0 {
+    if raw >> 63 == 1 {
         write_udbinteger(raw ^ (!0), buf)
     } else {
         write_udbinteger(raw ^ (1 << 63), buf)
@@ -178,9 +182,9 @@ pub fn write_uvar(value: u64, buf: &mut [u8]) -> Option<usize> {
 #[cfg(test)]
 mod test {
     use super::{read_u16_le, read_u32_le, read_u64_le, read_uvar};
-    use super::{read_udbinteger, read_sdbinteger};
+    use super::{read_udbinteger, read_sdbinteger, read_dbfloat};
     use super::{write_u16_le, write_u32_le, write_u64_le, write_uvar};
-    use super::{write_udbinteger, write_sdbinteger};
+    use super::{write_udbinteger, write_sdbinteger, write_dbfloat};
     use std;
 
     static TEST_U16: [(u16, &'static [u8]); 3] = [
@@ -226,6 +230,14 @@ mod test {
         (std::i64::MAX, &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
     ];
 
+    static TEST_DBFLOAT: [(f64, &'static [u8]); 5] = [
+        (std::f64::NEG_INFINITY, &[0x00, 0x0F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+        (-1.0, &[0x40, 0x0F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+        (0.0, &[0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+        (1.0, &[0xBF, 0xF0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+        (std::f64::INFINITY, &[0xFF, 0xF0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ];
+
     #[test]
     fn test_read_u16_le() {
         for &(v, buf) in TEST_U16.iter() {
@@ -329,4 +341,22 @@ mod test {
             assert_eq!(buf, b);
         }
     }
+
+    #[test]
+    fn test_dbfloat() {
+        let mut write_buf = [0; 8];
+
+        for &(v, buf) in TEST_DBFLOAT.iter() {
+            write_dbfloat(v, &mut write_buf);
+            assert_eq!(buf, write_buf);
+
+            let r = read_dbfloat(buf);
+            assert_eq!(v, r);
+            assert_eq!(v.is_sign_negative(), r.is_sign_negative());
+        }
+
+        // -0 is written as 0
+        write_dbfloat(-0.0, &mut write_buf);
+        assert_eq!(&[0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], &write_buf);
+    }
 }
@@ -0,0 +1,333 @@
+//! The API for embedding a database in an application.
+//!
+//! A `Connection` owns a database, in memory or in a file, and executes SQL
+//! text against it. The values of a statement's placeholders are passed
+//! alongside it, so that they never need to be quoted into the SQL.
+
+use std::path::Path;
+
+use databaseengine::{DatabaseEngine, ExecuteStatementResponse};
+use error::Error;
+use filedb::FileDb;
+use preparedstatement::{self, PreparedStatement};
+use tempdb::TempDb;
+use types::Variant;
+
+pub struct Connection {
+    db: Box<DatabaseEngine>
+}
+
+impl Connection {
+    /// Opens a new database that lives in memory, and is lost when the connection is dropped.
+    pub fn open_in_memory() -> Connection {
+        Connection {
+            db: Box::new(TempDb::new())
+        }
+    }
+
+    /// Opens the database file at the path, and creates it if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Connection, Error> {
+        let db = try!(FileDb::open(path));
+
+        Ok(Connection {
+            db: Box::new(db)
+        })
+    }
+
+    /// Executes a statement that doesn't yield rows.
+    /// Returns the number of rows that were inserted, updated or deleted.
+    pub fn execute(&mut self, sql: &str, params: &[Variant]) -> Result<u64, Error> {
+        let mut prepared = try!(preparedstatement::prepare(sql));
+        changed_rows(try!(prepared.execute_with(&mut *self.db, params)))
+    }
+
+    /// Executes a SELECT or EXPLAIN statement, and returns its rows.
+    pub fn query(&mut self, sql: &str, params: &[Variant]) -> Result<Rows, Error> {
+        let mut prepared = try!(preparedstatement::prepare(sql));
+        rows(try!(prepared.execute_with(&mut *self.db, params)))
+    }
+
+    /// Parses and compiles a statement that can be executed many times with
+    /// different parameters.
+    pub fn prepare(&mut self, sql: &str) -> Result<Statement, Error> {
+        let mut prepared = try!(preparedstatement::prepare(sql));
+        try!(prepared.compile(&*self.db));
+
+        Ok(Statement {
+            conn: self,
+            prepared: prepared
+        })
+    }
+}
+
+/// A statement that is parsed and compiled once, and executed against its connection.
+pub struct Statement<'a> {
+    conn: &'a mut Connection,
+    prepared: PreparedStatement
+}
+
+impl<'a> Statement<'a> {
+    /// The number of values that the statement is executed with
+    pub fn parameter_count(&self) -> u32 {
+        self.prepared.parameter_count()
+    }
+
+    /// Returns the number of the parameter with the name, without its `:`.
+    pub fn parameter_number(&self, name: &str) -> Option<u32> {
+        self.prepared.parameter_number(name)
+    }
+
+    /// See `Connection::execute`.
+    pub fn execute(&mut self, params: &[Variant]) -> Result<u64, Error> {
+        changed_rows(try!(self.prepared.execute_with(&mut *self.conn.db, params)))
+    }
+
+    /// See `Connection::query`.
+    pub fn query(&mut self, params: &[Variant]) -> Result<Rows, Error> {
+        rows(try!(self.prepared.execute_with(&mut *self.conn.db, params)))
+    }
+}
+
+fn changed_rows(response: ExecuteStatementResponse) -> Result<u64, Error> {
+    match response {
+        ExecuteStatementResponse::Inserted(count) |
+        ExecuteStatementResponse::Updated(count) |
+        ExecuteStatementResponse::Deleted(count) => Ok(count),
+        ExecuteStatementResponse::Select { .. } |
+        ExecuteStatementResponse::Explain(..) => Err(Error::Invalid(format!("the statement yields rows; use query instead of execute"))),
+        _ => Ok(0)
+    }
+}
+
+fn rows<'a>(response: ExecuteStatementResponse<'a>) -> Result<Rows<'a>, Error> {
+    match response {
+        ExecuteStatementResponse::Select { column_names, rows } => {
+            Ok(Rows {
+                column_names: column_names,
+                rows: rows
+            })
+        },
+        // The plan is a single row with a single column
+        ExecuteStatementResponse::Explain(plan) => {
+            let row: Box<[Variant]> = vec![Variant::StringLiteral(plan)].into_boxed_slice();
+
+            Ok(Rows {
+                column_names: vec!["plan".to_string()].into_boxed_slice(),
+                rows: Box::new(Some(Ok(row)).into_iter())
+            })
+        },
+        _ => Err(Error::Invalid(format!("the statement doesn't yield rows; use execute instead of query")))
+    }
+}
+
+/// The rows of a query. They're read from the database as they're iterated,
+/// and an error stops the iteration.
+pub struct Rows<'a> {
+    column_names: Box<[String]>,
+    rows: Box<Iterator<Item=Result<Box<[Variant]>, Error>> + 'a>
+}
+
+impl<'a> Rows<'a> {
+    pub fn column_names(&self) -> &[String] {
+        &self.column_names
+    }
+}
+
+impl<'a> Iterator for Rows<'a> {
+    type Item = Result<Row, Error>;
+
+    fn next(&mut self) -> Option<Result<Row, Error>> {
+        self.rows.next().map(|row| row.map(|values| Row { values: values }))
+    }
+}
+
+pub struct Row {
+    values: Box<[Variant]>
+}
+
+impl Row {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn values(&self) -> &[Variant] {
+        &self.values
+    }
+
+    /// Converts the value of the column at the offset to a Rust type.
+    /// Fails if the value can't be represented by the type: NULL is only
+    /// converted to `Option`, and integers must be in the type's range.
+    pub fn get<T: FromVariant>(&self, offset: usize) -> Result<T, Error> {
+        match self.values.get(offset) {
+            Some(value) => T::from_variant(value),
+            None => Err(Error::Invalid(format!("column offset {} is out of range: the row has {} columns", offset, self.values.len())))
+        }
+    }
+}
+
+/// Rust types that a row's values can be converted to.
+pub trait FromVariant: Sized {
+    fn from_variant(value: &Variant) -> Result<Self, Error>;
+}
+
+fn cannot_convert<T>(value: &Variant, type_name: &str) -> Result<T, Error> {
+    Err(Error::TypeMismatch(format!("cannot convert {} to {}", value, type_name)))
+}
+
+impl FromVariant for Variant {
+    fn from_variant(value: &Variant) -> Result<Variant, Error> {
+        Ok(value.clone())
+    }
+}
+
+impl<T: FromVariant> FromVariant for Option<T> {
+    fn from_variant(value: &Variant) -> Result<Option<T>, Error> {
+        match value {
+            &Variant::Null => Ok(None),
+            value => T::from_variant(value).map(Some)
+        }
+    }
+}
+
+impl FromVariant for i64 {
+    fn from_variant(value: &Variant) -> Result<i64, Error> {
+        match value {
+            &Variant::SignedInteger(n) => Ok(n),
+            &Variant::UnsignedInteger(n) if n <= i64::max_value() as u64 => Ok(n as i64),
+            value => cannot_convert(value, "i64")
+        }
+    }
+}
+
+impl FromVariant for u64 {
+    fn from_variant(value: &Variant) -> Result<u64, Error> {
+        match value {
+            &Variant::UnsignedInteger(n) => Ok(n),
+            &Variant::SignedInteger(n) if n >= 0 => Ok(n as u64),
+            value => cannot_convert(value, "u64")
+        }
+    }
+}
+
+impl FromVariant for i32 {
+    fn from_variant(value: &Variant) -> Result<i32, Error> {
+        match i64::from_variant(value) {
+            Ok(n) if n >= i32::min_value() as i64 && n <= i32::max_value() as i64 => Ok(n as i32),
+            _ => cannot_convert(value, "i32")
+        }
+    }
+}
+
+impl FromVariant for u32 {
+    fn from_variant(value: &Variant) -> Result<u32, Error> {
+        match u64::from_variant(value) {
+            Ok(n) if n <= u32::max_value() as u64 => Ok(n as u32),
+            _ => cannot_convert(value, "u32")
+        }
+    }
+}
+
+impl FromVariant for f64 {
+    fn from_variant(value: &Variant) -> Result<f64, Error> {
+        match value {
+            &Variant::Float(n) => Ok(*n),
+            &Variant::SignedInteger(n) => Ok(n as f64),
+            &Variant::UnsignedInteger(n) => Ok(n as f64),
+            value => cannot_convert(value, "f64")
+        }
+    }
+}
+
+impl FromVariant for String {
+    fn from_variant(value: &Variant) -> Result<String, Error> {
+        match value {
+            &Variant::StringLiteral(ref s) => Ok(s.clone()),
+            value => cannot_convert(value, "String")
+        }
+    }
+}
+
+impl FromVariant for Vec<u8> {
+    fn from_variant(value: &Variant) -> Result<Vec<u8>, Error> {
+        match value {
+            &Variant::Bytes(ref bytes) => Ok(bytes.clone()),
+            value => cannot_convert(value, "Vec<u8>")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use error::Error;
+    use types::Variant;
+    use super::Connection;
+
+    #[test]
+    fn test_connection() {
+        let mut conn = Connection::open_in_memory();
+
+        assert_eq!(conn.execute("CREATE TABLE t (a I64, b STRING NULL, c F64 NULL);", &[]).unwrap(), 0);
+        assert_eq!(conn.execute("INSERT INTO t VALUES (-1, 'x', 0.5), (2, NULL, 1.5);", &[]).unwrap(), 2);
+
+        {
+            let mut insert = conn.prepare("INSERT INTO t VALUES (?, :b, ?);").unwrap();
+            assert_eq!(insert.parameter_count(), 3);
+            assert_eq!(insert.parameter_number("b"), Some(2));
+
+            for i in 3..6 {
+                let params = [Variant::SignedInteger(i), Variant::StringLiteral(format!("y{}", i)), Variant::Null];
+                assert_eq!(insert.execute(&params).unwrap(), 1);
+            }
+
+            assert!(insert.execute(&[Variant::Null]).is_err());
+        }
+
+        assert_eq!(conn.execute("UPDATE t SET c = a * 2 WHERE a > 2;", &[]).unwrap(), 3);
+
+        let mut rows = conn.query("SELECT a, b, c FROM t WHERE a <= ? ORDER BY a;", &[Variant::SignedInteger(3)]).unwrap();
+        assert_eq!(rows.column_names(), &["a".to_string(), "b".to_string(), "c".to_string()]);
+
+        let row = rows.next().unwrap().unwrap();
+        assert_eq!(row.len(), 3);
+        assert_eq!(row.get::<i64>(0).unwrap(), -1);
+        assert_eq!(row.get::<String>(1).unwrap(), "x");
+        assert_eq!(row.get::<f64>(2).unwrap(), 0.5);
+        assert!(row.get::<u64>(0).is_err());
+        assert!(row.get::<i64>(3).is_err());
+
+        let row = rows.next().unwrap().unwrap();
+        assert_eq!(row.get::<u32>(0).unwrap(), 2);
+        assert_eq!(row.get::<Option<String>>(1).unwrap(), None);
+        assert!(row.get::<String>(1).is_err());
+
+        let row = rows.next().unwrap().unwrap();
+        assert_eq!(row.get::<Option<String>>(1).unwrap(), Some("y3".to_string()));
+        assert_eq!(row.get::<f64>(2).unwrap(), 6.0);
+
+        assert!(rows.next().is_none());
+    }
+
+    #[test]
+    fn test_connection_errors() {
+        let mut conn = Connection::open_in_memory();
+        conn.execute("CREATE TABLE t (a U32);", &[]).unwrap();
+
+        assert!(conn.execute("SELECT a FROM t;", &[]).is_err());
+        assert!(conn.query("INSERT INTO t VALUES (1);", &[]).is_err());
+        assert!(conn.execute("INSERT INTO t VALUES (?);", &[]).is_err());
+        assert_eq!(conn.execute("INSERT INTO u VALUES (1);", &[]), Err(Error::UnknownTable("u".to_string())));
+        assert_eq!(conn.execute("SELECT b FROM t;", &[]).err(), Some(Error::UnknownColumn("b".to_string())));
+
+        match conn.execute("INSERT INTO t VALUES (1)) ;", &[]) {
+            Err(Error::Syntax { location: Some(location), .. }) => assert_eq!((location.line, location.column), (1, 25)),
+            e => panic!("expected a syntax error, got {:?}", e)
+        }
+        match conn.execute("INSERT INTO t VALUES ('a' # 1);", &[]) {
+            Err(Error::Syntax { location: Some(location), .. }) => assert_eq!((location.line, location.column), (1, 27)),
+            e => panic!("expected a syntax error, got {:?}", e)
+        }
+
+        let rows: Vec<String> = conn.query("EXPLAIN SELECT a FROM t;", &[]).unwrap().map(|row| row.unwrap().get(0).unwrap()).collect();
+        assert_eq!(rows.len(), 1);
+    }
+}
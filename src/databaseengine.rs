@@ -0,0 +1,2173 @@
+//! Statement execution that is shared by every storage engine.
+
+use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
+use databaseinfo::{DatabaseInfo, TableInfo, ColumnInfo, IndexInfo, ForeignKey, ForeignKeyAction};
+use databasestorage::DatabaseStorage;
+use error::Error;
+use identifier::Identifier;
+use types::{DbType, Variant};
+use sqlsyntax::ast;
+use queryplan::{self, ExecuteQueryPlan, QueryPlan, QueryPlanRows, SExpression};
+
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub enum ExecuteStatementResponse<'a> {
+    Created,
+    Dropped,
+    Altered,
+    Inserted(u64),
+    Updated(u64),
+    Deleted(u64),
+    /// The rows are read from the database as they're iterated.
+    /// An error stops the iteration.
+    Select {
+        column_names: Box<[String]>,
+        rows: Box<Iterator<Item=Result<Box<[Variant]>, Error>> + 'a>
+    },
+    Explain(String),
+    Began,
+    Committed,
+    RolledBack
+}
+
+pub type ExecuteStatementResult<'a> = Result<ExecuteStatementResponse<'a>, Error>;
+
+/// A database that can execute SQL statements.
+pub trait DatabaseEngine {
+    /// Executes a statement whose placeholders have the values of `params`.
+    /// The value of the placeholder numbered N is `params[N - 1]`.
+    fn execute_statement_with_params(&mut self, stmt: &ast::Statement, params: &[Variant]) -> ExecuteStatementResult;
+
+    fn execute_statement(&mut self, stmt: ast::Statement) -> ExecuteStatementResult {
+        self.execute_statement_with_params(&stmt, &[])
+    }
+
+    /// Compiles a statement whose placeholders are numbered from `?1` to
+    /// `?parameter_count`, so that it can be executed any number of times
+    /// with `execute_compiled`.
+    fn compile_statement(&self, stmt: &ast::Statement, parameter_count: u32) -> Result<CompiledStatement, Error>;
+
+    /// Returns false if the statement was compiled against another database,
+    /// or the schema has changed since it was compiled.
+    fn is_up_to_date(&self, stmt: &CompiledStatement) -> bool;
+
+    /// Executes a compiled statement with the values of its placeholders.
+    /// Fails if the statement isn't up to date.
+    fn execute_compiled(&mut self, stmt: &CompiledStatement, params: &[Variant]) -> ExecuteStatementResult;
+}
+
+/// A statement that's compiled against the schema of a database.
+///
+/// The query plans of SELECT, INSERT, UPDATE and DELETE statements are kept,
+/// along with the tables and columns that they refer to, until the schema
+/// changes. Other statements are kept as they are.
+pub struct CompiledStatement {
+    schema_version: u64,
+    /// A `Compiled` of the engine that compiled the statement
+    statement: Box<Any>
+}
+
+/// Returns a schema version that no database has had before.
+///
+/// Every database has a schema version, which changes whenever a table or index
+/// is created, dropped or altered, or a transaction is rolled back. A statement
+/// that's compiled against one version can't be executed with any other.
+pub fn new_schema_version() -> u64 {
+    static NEXT_SCHEMA_VERSION: AtomicUsize = AtomicUsize::new(0);
+
+    NEXT_SCHEMA_VERSION.fetch_add(1, Ordering::SeqCst) as u64
+}
+
+pub struct ColumnDefinition {
+    pub name: Identifier,
+    pub dbtype: DbType,
+    pub nullable: bool,
+    pub primary_key: bool,
+    pub unique: bool,
+    pub foreign_key: Option<ForeignKey>
+}
+
+pub struct IndexDefinition {
+    pub name: Identifier,
+    /// The offsets of the indexed columns
+    pub columns: Vec<u32>,
+    pub unique: bool
+}
+
+/// The mutations that a storage engine must provide to execute statements.
+pub trait DatabaseStorageMut: DatabaseInfo<ColumnValue=Variant> + DatabaseStorage<Info=Self> + Sized {
+    fn create_table(&mut self, name: Identifier, columns: Vec<ColumnDefinition>) -> Result<(), Error>;
+
+    /// Creates an index on the table, and adds every row of the table to it.
+    /// Fails if the index is unique and two rows have the same values.
+    fn create_index(&mut self, table_name: &Identifier, index: IndexDefinition) -> Result<(), Error>;
+
+    /// Removes the table, its indexes, and every row in it
+    fn drop_table(&mut self, name: &Identifier) -> Result<(), Error>;
+
+    /// Renames the table, along with the foreign keys that reference it
+    fn rename_table(&mut self, name: &Identifier, new_name: Identifier) -> Result<(), Error>;
+
+    /// Replaces the columns of the table, and rewrites every row with the new column data.
+    /// `rows` has the column data of every row in the table, along with its rowid.
+    /// Indexes follow their columns by name; an index on a removed column is dropped.
+    fn replace_columns(&mut self, table_name: &Identifier, columns: Vec<ColumnDefinition>, rows: Vec<(u64, Vec<(Box<[u8]>, Option<bool>)>)>) -> Result<(), Error>;
+
+    /// rowid is automatically added, and is not included as a specified column.
+    /// Fails if the row violates a unique index.
+    fn insert_row(&mut self, table_name: &Identifier, column_data: Vec<(Box<[u8]>, Option<bool>)>) -> Result<(), Error>;
+
+    /// Every row of the table, along with its rowid
+    fn rows_with_rowids(&self, table_name: &Identifier) -> Result<Vec<(u64, Vec<Variant>)>, Error>;
+
+    /// Replaces every column of the row with the rowid.
+    /// Fails if the row violates a unique index.
+    fn update_row(&mut self, table_name: &Identifier, rowid: u64, column_data: Vec<(Box<[u8]>, Option<bool>)>) -> Result<(), Error>;
+
+    fn delete_row(&mut self, table_name: &Identifier, rowid: u64) -> Result<(), Error>;
+
+    fn begin_transaction(&mut self) -> Result<(), Error>;
+    fn commit_transaction(&mut self) -> Result<(), Error>;
+
+    /// Discards every change since the transaction began.
+    fn rollback_transaction(&mut self) -> Result<(), Error>;
+
+    fn in_transaction(&self) -> bool;
+
+    /// Called before every statement that may modify the database.
+    fn begin_statement(&mut self) -> Result<(), Error>;
+
+    /// Called after every statement that may have modified the database.
+    /// If the statement failed, its changes are discarded.
+    /// Outside of a transaction, the statement's changes are committed.
+    fn end_statement(&mut self, success: bool) -> Result<(), Error>;
+
+    /// See `new_schema_version`.
+    fn schema_version(&self) -> u64;
+    fn set_schema_version(&mut self, version: u64);
+}
+
+pub fn execute_statement<'a, DB: DatabaseStorageMut>(db: &'a mut DB, stmt: &ast::Statement, params: &[Variant]) -> ExecuteStatementResult<'a> {
+    match stmt {
+        &ast::Statement::Create(ref create_stmt) => {
+            execute_schema_change(db, |db| {
+                match create_stmt {
+                    &ast::CreateStatement::Table(ref s) => create_table(db, s),
+                    &ast::CreateStatement::Index(ref s) => create_index(db, s)
+                }
+            })
+        },
+        &ast::Statement::Drop(ref drop_stmt) => {
+            execute_schema_change(db, |db| {
+                match drop_stmt {
+                    &ast::DropStatement::Table(ref s) => drop_table(db, s)
+                }
+            })
+        },
+        &ast::Statement::Alter(ref alter_stmt) => {
+            execute_schema_change(db, |db| {
+                match alter_stmt {
+                    &ast::AlterStatement::Table(ref s) => alter_table(db, s)
+                }
+            })
+        },
+        &ast::Statement::Transaction(ref transaction_stmt) => transaction(db, transaction_stmt),
+        &ast::Statement::Insert(..) |
+        &ast::Statement::Update(..) |
+        &ast::Statement::Delete(..) |
+        &ast::Statement::Select(..) |
+        &ast::Statement::Explain(..) => {
+            let compiled = try!(compile(db, stmt, params.len() as u32));
+            execute(db, &compiled, params)
+        }
+    }
+}
+
+pub fn compile_statement<DB: DatabaseStorageMut + 'static>(db: &DB, stmt: &ast::Statement, parameter_count: u32) -> Result<CompiledStatement, Error> {
+    let compiled = try!(compile(db, stmt, parameter_count));
+
+    Ok(CompiledStatement {
+        schema_version: db.schema_version(),
+        statement: Box::new(compiled)
+    })
+}
+
+pub fn is_up_to_date<DB: DatabaseStorageMut>(db: &DB, stmt: &CompiledStatement) -> bool {
+    // No two databases have the same schema version
+    stmt.schema_version == db.schema_version()
+}
+
+pub fn execute_compiled<'a, DB: DatabaseStorageMut + 'static>(db: &'a mut DB, stmt: &CompiledStatement, params: &[Variant]) -> ExecuteStatementResult<'a> {
+    if !is_up_to_date(db, stmt) {
+        return Err(Error::Invalid(format!("the statement must be compiled again, as the schema has changed")));
+    }
+
+    match stmt.statement.downcast_ref::<Compiled<DB>>() {
+        Some(compiled) => execute(db, compiled, params),
+        None => unreachable!()
+    }
+}
+
+/// A statement that's compiled against the schema of a database;
+/// see `CompiledStatement`.
+enum Compiled<DB: DatabaseInfo> {
+    Insert(CompiledInsert<DB>),
+    Update(CompiledUpdate<DB>),
+    Delete(CompiledDelete<DB>),
+    Select {
+        column_names: Box<[String]>,
+        /// Shared with the rows of every execution
+        expr: Rc<SExpression<DB>>
+    },
+    /// The plan doesn't depend on the values of the placeholders
+    Explain(String),
+    /// Statements that change the schema or the transaction, which are
+    /// executed as they are
+    Other(ast::Statement)
+}
+
+struct CompiledInsert<DB: DatabaseInfo> {
+    table_name: Identifier,
+    column_types: Vec<(DbType, bool)>,
+    ast_index_to_column_index: Vec<u32>,
+    source: CompiledInsertSource<DB>
+}
+
+enum CompiledInsertSource<DB: DatabaseInfo> {
+    Values(Vec<Vec<SExpression<DB>>>),
+    Select(SExpression<DB>)
+}
+
+struct CompiledUpdate<DB: DatabaseInfo> {
+    table_name: Identifier,
+    column_types: Vec<(DbType, bool)>,
+    /// The offsets of the assigned columns, and their values
+    assignments: Vec<(u32, SExpression<DB>)>,
+    /// Finds the rows to update; see `find_rows`
+    search: SExpression<DB>
+}
+
+struct CompiledDelete<DB: DatabaseInfo> {
+    table_name: Identifier,
+    /// Finds the rows to delete; see `find_rows`
+    search: SExpression<DB>
+}
+
+fn compile<DB: DatabaseStorageMut>(db: &DB, stmt: &ast::Statement, parameter_count: u32) -> Result<Compiled<DB>, Error> {
+    match stmt {
+        &ast::Statement::Insert(ref insert_stmt) => Ok(Compiled::Insert(try!(compile_insert(db, insert_stmt, parameter_count)))),
+        &ast::Statement::Update(ref update_stmt) => Ok(Compiled::Update(try!(compile_update(db, update_stmt, parameter_count)))),
+        &ast::Statement::Delete(ref delete_stmt) => Ok(Compiled::Delete(try!(compile_delete(db, delete_stmt, parameter_count)))),
+        &ast::Statement::Select(ref select_stmt) => {
+            let plan = try!(QueryPlan::compile_select(db, select_stmt, parameter_count));
+            debug!("{}", plan);
+
+            let column_names: Vec<String> = plan.out_column_names.iter().map(|ident| ident.to_string()).collect();
+
+            Ok(Compiled::Select {
+                column_names: column_names.into_boxed_slice(),
+                expr: Rc::new(plan.expr)
+            })
+        },
+        &ast::Statement::Explain(ast::ExplainStatement::Select(ref select_stmt)) => {
+            let plan = try!(QueryPlan::compile_select(db, select_stmt, parameter_count));
+            Ok(Compiled::Explain(plan.to_string()))
+        },
+        stmt => Ok(Compiled::Other(stmt.clone()))
+    }
+}
+
+fn execute<'a, DB: DatabaseStorageMut>(db: &'a mut DB, compiled: &Compiled<DB>, params: &[Variant]) -> ExecuteStatementResult<'a> {
+    match compiled {
+        &Compiled::Insert(ref insert) => execute_modification(db, |db| insert_into(db, insert, params)),
+        &Compiled::Update(ref update_) => execute_modification(db, |db| update(db, update_, params)),
+        &Compiled::Delete(ref delete_) => execute_modification(db, |db| delete(db, delete_, params)),
+        &Compiled::Select { ref column_names, ref expr } => select(db, column_names, expr, params),
+        &Compiled::Explain(ref plan) => Ok(ExecuteStatementResponse::Explain(plan.clone())),
+        &Compiled::Other(ref stmt) => execute_statement(db, stmt, params)
+    }
+}
+
+/// A statement that changes the schema gives the database a new schema version,
+/// even if it fails.
+fn execute_schema_change<DB, F>(db: &mut DB, f: F) -> ExecuteStatementResult<'static>
+where DB: DatabaseStorageMut, F: FnOnce(&mut DB) -> ExecuteStatementResult<'static>
+{
+    let result = execute_modification(db, f);
+    db.set_schema_version(new_schema_version());
+
+    result
+}
+
+/// Statements are atomic: if any part of a statement fails, none of it is applied.
+fn execute_modification<DB, F>(db: &mut DB, f: F) -> ExecuteStatementResult<'static>
+where DB: DatabaseStorageMut, F: FnOnce(&mut DB) -> ExecuteStatementResult<'static>
+{
+    try!(db.begin_statement());
+
+    let result = f(db);
+    try!(db.end_statement(result.is_ok()));
+
+    result
+}
+
+fn transaction<DB: DatabaseStorageMut>(db: &mut DB, stmt: &ast::TransactionStatement) -> ExecuteStatementResult<'static> {
+    match stmt {
+        &ast::TransactionStatement::Begin => {
+            if db.in_transaction() {
+                return Err(Error::Invalid(format!("cannot BEGIN: a transaction is already active")));
+            }
+
+            try!(db.begin_transaction());
+            Ok(ExecuteStatementResponse::Began)
+        },
+        &ast::TransactionStatement::Commit => {
+            if !db.in_transaction() {
+                return Err(Error::Invalid(format!("cannot COMMIT: no transaction is active")));
+            }
+
+            try!(db.commit_transaction());
+            Ok(ExecuteStatementResponse::Committed)
+        },
+        &ast::TransactionStatement::Rollback => {
+            if !db.in_transaction() {
+                return Err(Error::Invalid(format!("cannot ROLLBACK: no transaction is active")));
+            }
+
+            // The transaction may have changed the schema
+            let result = db.rollback_transaction();
+            db.set_schema_version(new_schema_version());
+
+            try!(result);
+            Ok(ExecuteStatementResponse::RolledBack)
+        }
+    }
+}
+
+fn create_table<DB: DatabaseStorageMut>(db: &mut DB, stmt: &ast::CreateTableStatement) -> ExecuteStatementResult<'static> {
+    let table_name = try!(table_identifier(&stmt.table));
+
+    let columns_result: Result<Vec<_>, Error>;
+    columns_result = stmt.columns.iter().map(column_definition).collect();
+
+    let (mut columns, references): (Vec<_>, Vec<_>) = try!(columns_result).into_iter().unzip();
+
+    if columns.iter().filter(|c| c.primary_key).count() > 1 {
+        return Err(Error::Invalid(format!("table {} has more than one PRIMARY KEY", table_name)));
+    }
+
+    let foreign_keys_result: Result<Vec<_>, Error> = references.into_iter().zip(columns.iter()).map(|(reference, column)| {
+        match reference {
+            Some(reference) => foreign_key(db, &table_name, &columns, column, reference).map(Some),
+            None => Ok(None)
+        }
+    }).collect();
+
+    for (column, foreign_key) in columns.iter_mut().zip(try!(foreign_keys_result)) {
+        column.foreign_key = foreign_key;
+    }
+
+    let indexes: Vec<IndexDefinition> = columns.iter().enumerate().filter_map(|(offset, column)| {
+        constraint_index(&table_name, column, offset as u32)
+    }).collect();
+
+    try!(db.create_table(table_name.clone(), columns));
+
+    for index in indexes {
+        try!(db.create_index(&table_name, index));
+    }
+
+    Ok(ExecuteStatementResponse::Created)
+}
+
+fn create_index<DB: DatabaseStorageMut>(db: &mut DB, stmt: &ast::CreateIndexStatement) -> ExecuteStatementResult<'static> {
+    let table_name = try!(table_identifier(&stmt.table));
+    let name = try!(Identifier::new(&stmt.name).ok_or(Error::Invalid(format!("Bad index name: {}", stmt.name))));
+
+    let mut columns = Vec::new();
+
+    {
+        let table = try!(db.find_table_by_name(&table_name).ok_or(Error::UnknownTable(table_name.to_string())));
+
+        for column_name in &stmt.columns {
+            let ident = try!(Identifier::new(&column_name).ok_or(Error::Invalid(format!("Bad column name: {}", column_name))));
+            let offset = match table.find_column_by_name(&ident) {
+                Some(column) => column.get_offset(),
+                None => return Err(Error::UnknownColumn(ident.to_string()))
+            };
+
+            if columns.contains(&offset) {
+                return Err(Error::Invalid(format!("column {} is indexed more than once", ident)));
+            }
+
+            columns.push(offset);
+        }
+    }
+
+    try!(db.create_index(&table_name, IndexDefinition {
+        name: name,
+        columns: columns,
+        unique: stmt.unique
+    }));
+
+    Ok(ExecuteStatementResponse::Created)
+}
+
+/// The unique index that enforces a column's PRIMARY KEY or UNIQUE constraint
+fn constraint_index(table_name: &Identifier, column: &ColumnDefinition, offset: u32) -> Option<IndexDefinition> {
+    let name = if column.primary_key {
+        format!("{}_pkey", table_name)
+    } else if column.unique {
+        format!("{}_{}_key", table_name, column.name)
+    } else {
+        return None;
+    };
+
+    Some(IndexDefinition {
+        name: Identifier::new(&name).unwrap(),
+        columns: vec![offset],
+        unique: true
+    })
+}
+
+/// The error for a row that has the same values as another row in a unique index
+pub fn unique_violation<T: TableInfo>(table: &T, column_offsets: &[u32]) -> Error {
+    let columns: Vec<&T::Column> = column_offsets.iter().map(|&offset| table.find_column_by_offset(offset).unwrap()).collect();
+    let column_names: Vec<String> = columns.iter().map(|c| c.get_name().to_string()).collect();
+
+    let constraint = if columns.len() == 1 && columns[0].is_primary_key() {
+        "PRIMARY KEY"
+    } else {
+        "UNIQUE"
+    };
+
+    Error::Constraint(format!("{} constraint violated: duplicate value in {}({})", constraint, table.get_name(), column_names.join(", ")))
+}
+
+/// A REFERENCES constraint, before the referenced table and column are looked up
+struct ColumnReference {
+    table: Identifier,
+    /// If None, the PRIMARY KEY of the table is referenced
+    column: Option<Identifier>,
+    on_delete: ForeignKeyAction
+}
+
+fn column_definition(column: &ast::CreateTableColumn) -> Result<(ColumnDefinition, Option<ColumnReference>), Error> {
+    let name = try!(Identifier::new(&column.column_name).ok_or(Error::Invalid(format!("Bad column name: {}", column.column_name))));
+    let type_name = try!(Identifier::new(&column.type_name).ok_or(Error::Invalid(format!("{} is not a valid column type", column.type_name))));
+
+    let has_constraint = |constraint: ast::CreateTableColumnConstraintType| {
+        column.constraints.iter().any(|c| c.constraint == constraint)
+    };
+
+    let nullable = has_constraint(ast::CreateTableColumnConstraintType::Nullable);
+    let primary_key = has_constraint(ast::CreateTableColumnConstraintType::PrimaryKey);
+    let unique = has_constraint(ast::CreateTableColumnConstraintType::Unique);
+
+    let reference = match column.constraints.iter().filter_map(|c| {
+        match c.constraint {
+            ast::CreateTableColumnConstraintType::ForeignKey { ref table, ref columns, ref on_delete } => Some((table, columns, on_delete)),
+            _ => None
+        }
+    }).next() {
+        Some((table, columns, on_delete)) => {
+            let table_name = try!(table_identifier(table));
+
+            let column_name = match *columns {
+                Some(ref columns) if columns.len() == 1 => {
+                    Some(try!(Identifier::new(&columns[0]).ok_or(Error::Invalid(format!("Bad column name: {}", columns[0])))))
+                },
+                Some(_) => return Err(Error::Invalid(format!("column {} must reference exactly one column", name))),
+                None => None
+            };
+
+            let on_delete = match *on_delete {
+                Some(ast::ForeignKeyAction::Cascade) => ForeignKeyAction::Cascade,
+                Some(ast::ForeignKeyAction::SetNull) => ForeignKeyAction::SetNull,
+                Some(ast::ForeignKeyAction::Restrict) | None => ForeignKeyAction::Restrict
+            };
+
+            if on_delete == ForeignKeyAction::SetNull && !nullable {
+                return Err(Error::Invalid(format!("column {} is ON DELETE SET NULL, but can't be NULL", name)));
+            }
+
+            Some(ColumnReference {
+                table: table_name,
+                column: column_name,
+                on_delete: on_delete
+            })
+        },
+        None => None
+    };
+
+    let type_array_size = match column.type_array_size {
+        Some(Some(ref s)) => {
+            let v = try!(parse_number_as_u64(s));
+            Some(Some(v))
+        },
+        Some(None) => Some(None),
+        None => None
+    };
+
+    let dbtype = try!(DbType::from_identifier(&type_name, type_array_size).ok_or(Error::Invalid(format!("{} is not a valid column type", type_name))));
+
+    if primary_key && nullable {
+        return Err(Error::Invalid(format!("PRIMARY KEY column {} can't be NULL", name)));
+    }
+
+    Ok((ColumnDefinition {
+        name: name,
+        dbtype: dbtype,
+        nullable: nullable,
+        primary_key: primary_key,
+        unique: unique,
+        foreign_key: None
+    }, reference))
+}
+
+/// Looks up the column that a REFERENCES constraint refers to, which must be a
+/// PRIMARY KEY or UNIQUE column of the same type.
+/// `columns` are the columns of `table_name`, which may reference itself.
+fn foreign_key<DB>(db: &DB, table_name: &Identifier, columns: &[ColumnDefinition], column: &ColumnDefinition, reference: ColumnReference) -> Result<ForeignKey, Error>
+where DB: DatabaseStorageMut
+{
+    // The name, type, and whether the referenced column is a PRIMARY KEY or UNIQUE
+    let referenced: Option<(Identifier, DbType, bool)> = if &reference.table == table_name {
+        columns.iter().find(|c| {
+            match reference.column {
+                Some(ref name) => &c.name == name,
+                None => c.primary_key
+            }
+        }).map(|c| (c.name.clone(), c.dbtype, c.primary_key || c.unique))
+    } else {
+        let table = try!(db.find_table_by_name(&reference.table).ok_or(Error::UnknownTable(reference.table.to_string())));
+
+        let found = match reference.column {
+            Some(ref name) => table.find_column_by_name(name),
+            None => (0..table.get_column_count()).map(|i| table.find_column_by_offset(i).unwrap()).find(|c| c.is_primary_key())
+        };
+
+        found.map(|c| (c.get_name().clone(), *c.get_dbtype(), c.is_primary_key() || c.is_unique()))
+    };
+
+    let (referenced_name, referenced_dbtype, is_key) = try!(referenced.ok_or_else(|| {
+        match reference.column {
+            Some(ref name) => Error::UnknownColumn(format!("{}.{}", reference.table, name)),
+            None => Error::Invalid(format!("table {} has no PRIMARY KEY to reference", reference.table))
+        }
+    }));
+
+    if !is_key {
+        return Err(Error::Invalid(format!("{}({}) can't be referenced, as it isn't a PRIMARY KEY or UNIQUE column", reference.table, referenced_name)));
+    }
+
+    if referenced_dbtype != column.dbtype {
+        return Err(Error::TypeMismatch(format!("column {} has type {:?}, but references {}({}) of type {:?}",
+            column.name, column.dbtype, reference.table, referenced_name, referenced_dbtype)));
+    }
+
+    Ok(ForeignKey {
+        table: reference.table,
+        column: referenced_name,
+        on_delete: reference.on_delete
+    })
+}
+
+fn drop_table<DB: DatabaseStorageMut>(db: &mut DB, stmt: &ast::DropTableStatement) -> ExecuteStatementResult<'static> {
+    let table_name = try!(table_identifier(&stmt.table));
+
+    if db.find_table_by_name(&table_name).is_none() {
+        if stmt.if_exists {
+            return Ok(ExecuteStatementResponse::Dropped);
+        } else {
+            return Err(Error::UnknownTable(table_name.to_string()));
+        }
+    }
+
+    if let Some(reference) = references_to(db, &table_name).into_iter().find(|r| r.table != table_name) {
+        return Err(Error::Constraint(format!("cannot drop {}, as {}({}) references it", table_name, reference.table, reference.column)));
+    }
+
+    try!(db.drop_table(&table_name));
+
+    Ok(ExecuteStatementResponse::Dropped)
+}
+
+fn alter_table<DB: DatabaseStorageMut>(db: &mut DB, stmt: &ast::AlterTableStatement) -> ExecuteStatementResult<'static> {
+    let table_name = try!(table_identifier(&stmt.table));
+
+    match &stmt.action {
+        &ast::AlterTableAction::RenameTo(ref new_name) => {
+            let new_name = try!(Identifier::new(&new_name).ok_or(Error::Invalid(format!("Bad table name: {}", new_name))));
+            try!(db.rename_table(&table_name, new_name));
+        },
+        &ast::AlterTableAction::AddColumn(ref column) => {
+            let (mut column, reference) = try!(column_definition(column));
+            let mut index = None;
+
+            if let Some(reference) = reference {
+                let columns = {
+                    let table = try!(db.find_table_by_name(&table_name).ok_or(Error::UnknownTable(table_name.to_string())));
+                    column_definitions(table)
+                };
+
+                column.foreign_key = Some(try!(foreign_key(db, &table_name, &columns, &column, reference)));
+            }
+
+            let has_foreign_key = column.foreign_key.is_some();
+
+            try!(alter_columns(db, &table_name, |columns, rows| {
+                if columns.iter().any(|c| c.name == column.name) {
+                    return Err(Error::Invalid(format!("column {} already exists in table", column.name)));
+                }
+
+                if column.primary_key && columns.iter().any(|c| c.primary_key) {
+                    return Err(Error::Invalid(format!("table {} already has a PRIMARY KEY", table_name)));
+                }
+
+                index = constraint_index(&table_name, &column, columns.len() as u32);
+
+                // Existing rows get the column's default value
+                let default_value = if column.nullable {
+                    Variant::Null
+                } else {
+                    Variant::from_bytes(column.dbtype, column.dbtype.get_default()).unwrap()
+                };
+
+                for row in rows {
+                    row.push(default_value.clone());
+                }
+
+                columns.push(column);
+                Ok(())
+            }));
+
+            // Fails if the table has more than one row, as every row has the same value
+            if let Some(index) = index {
+                try!(db.create_index(&table_name, index));
+            }
+
+            // The existing rows must reference the default value
+            if has_foreign_key {
+                for (_, row) in try!(db.rows_with_rowids(&table_name)) {
+                    try!(check_references(db, &table_name, &row));
+                }
+            }
+        },
+        &ast::AlterTableAction::DropColumn(ref column_name) => {
+            let ident = try!(Identifier::new(&column_name).ok_or(Error::Invalid(format!("Bad column name: {}", column_name))));
+
+            let referenced_by = references_to(db, &table_name).into_iter().find(|r| r.referenced_column == ident);
+            if let Some(reference) = referenced_by {
+                return Err(Error::Constraint(format!("cannot drop {}, as {}({}) references it", ident, reference.table, reference.column)));
+            }
+
+            try!(alter_columns(db, &table_name, |columns, rows| {
+                let offset = try!(columns.iter().position(|c| c.name == ident).ok_or(Error::UnknownColumn(ident.to_string())));
+
+                if columns.len() == 1 {
+                    return Err(Error::Invalid(format!("cannot drop {}, the only column of the table", ident)));
+                }
+
+                for row in rows {
+                    row.remove(offset);
+                }
+
+                columns.remove(offset);
+                Ok(())
+            }));
+        }
+    }
+
+    Ok(ExecuteStatementResponse::Altered)
+}
+
+/// Changes the columns of a table, and rewrites every row to match.
+/// `f` is given the table's columns and the values of every row.
+fn alter_columns<DB, F>(db: &mut DB, table_name: &Identifier, f: F) -> Result<(), Error>
+where DB: DatabaseStorageMut, F: FnOnce(&mut Vec<ColumnDefinition>, Vec<&mut Vec<Variant>>) -> Result<(), Error>
+{
+    let mut columns: Vec<ColumnDefinition> = {
+        let table = try!(db.find_table_by_name(table_name).ok_or(Error::UnknownTable(table_name.to_string())));
+        column_definitions(table)
+    };
+
+    let mut rows = try!(db.rows_with_rowids(table_name));
+
+    try!(f(&mut columns, rows.iter_mut().map(|&mut (_, ref mut row)| row).collect()));
+
+    let rows_result: Result<Vec<_>, Error> = rows.into_iter().map(|(rowid, row)| {
+        let column_data: Result<Vec<_>, Error> = row.into_iter().zip(columns.iter()).map(|(value, column)| {
+            let mut buf = Vec::new();
+            let is_null = try!(variant_to_data(value, column.dbtype, column.nullable, &mut buf));
+            Ok((buf.into_boxed_slice(), is_null))
+        }).collect();
+
+        Ok((rowid, try!(column_data)))
+    }).collect();
+
+    db.replace_columns(table_name, columns, try!(rows_result))
+}
+
+fn column_definitions<T: TableInfo>(table: &T) -> Vec<ColumnDefinition> {
+    (0..table.get_column_count()).map(|i| {
+        let c = table.find_column_by_offset(i).unwrap();
+        ColumnDefinition {
+            name: c.get_name().clone(),
+            dbtype: *c.get_dbtype(),
+            nullable: c.is_nullable(),
+            primary_key: c.is_primary_key(),
+            unique: c.is_unique(),
+            foreign_key: c.get_foreign_key().cloned()
+        }
+    }).collect()
+}
+
+fn compile_insert<DB: DatabaseStorageMut>(db: &DB, stmt: &ast::InsertStatement, parameter_count: u32) -> Result<CompiledInsert<DB>, Error> {
+    let table_name = try!(table_identifier(&stmt.table));
+    let table = try!(db.find_table_by_name(&table_name).ok_or(Error::UnknownTable(table_name.to_string())));
+
+    let column_types = (0..table.get_column_count()).map(|i| {
+        let c = table.find_column_by_offset(i).unwrap();
+        (*c.get_dbtype(), c.is_nullable())
+    }).collect();
+
+    let ast_index_to_column_index: Vec<u32> = match stmt.into_columns {
+        // Column names listed; map specified columns
+        Some(ref v) => try!(v.iter().map(|column_name| {
+            let ident = try!(Identifier::new(&column_name).ok_or(Error::Invalid(format!("Bad column name: {}", column_name))));
+            match table.find_column_by_name(&ident) {
+                Some(column) => Ok(column.get_offset()),
+                None => Err(Error::UnknownColumn(column_name.to_string()))
+            }
+        }).collect()),
+        // No column names are listed; map all columns
+        None => (0..table.get_column_count()).collect()
+    };
+
+    trace!("ast_index_to_column_index: {:?}", ast_index_to_column_index);
+
+    let source = match stmt.source {
+        ast::InsertSource::Values(ref rows) => {
+            CompiledInsertSource::Values(try!(rows.iter().map(|row| {
+                if ast_index_to_column_index.len() != row.len() {
+                    return Err(Error::Invalid(format!("INSERT value contains wrong amount of columns")));
+                }
+
+                row.iter().map(|expr| {
+                    queryplan::compile_ast_expression(db, None, expr, parameter_count).map_err(Error::from)
+                }).collect()
+            }).collect()))
+        },
+        ast::InsertSource::Select(ref select) => {
+            let plan = try!(QueryPlan::compile_select(db, select, parameter_count));
+
+            if ast_index_to_column_index.len() != plan.out_column_names.len() {
+                return Err(Error::Invalid(format!("INSERT query yields {} columns, but {} columns are inserted",
+                    plan.out_column_names.len(), ast_index_to_column_index.len())));
+            }
+
+            CompiledInsertSource::Select(plan.expr)
+        }
+    };
+
+    Ok(CompiledInsert {
+        table_name: table_name,
+        column_types: column_types,
+        ast_index_to_column_index: ast_index_to_column_index,
+        source: source
+    })
+}
+
+fn insert_into<DB: DatabaseStorageMut>(db: &mut DB, stmt: &CompiledInsert<DB>, params: &[Variant]) -> ExecuteStatementResult<'static> {
+    trace!("inserting rows into {}", stmt.table_name);
+
+    let table_name = &stmt.table_name;
+    let column_types = &stmt.column_types;
+
+    // Every row is evaluated before any are inserted, so that the source may
+    // read from the table being inserted into without seeing its own rows.
+    let source_rows: Vec<Vec<Variant>>;
+
+    {
+        let db: &DB = db;
+        let execute = ExecuteQueryPlan::new(db, params);
+
+        source_rows = match stmt.source {
+            CompiledInsertSource::Values(ref rows) => {
+                try!(rows.iter().map(|row| {
+                    row.iter().map(|sexpr| execute.execute_expression(sexpr)).collect()
+                }).collect())
+            },
+            CompiledInsertSource::Select(ref expr) => {
+                let mut rows = Vec::new();
+                try!(execute.execute_query_plan(expr, &mut |r| {
+                    rows.push(r.to_vec());
+                    Ok(())
+                }));
+                rows
+            }
+        };
+    }
+
+    let count = source_rows.len() as u64;
+    let mut inserted_rows = Vec::new();
+
+    for row in source_rows {
+        let mut values: Vec<Option<Variant>>;
+        values = (0..column_types.len()).map(|_| None).collect();
+
+        for (i, value) in row.into_iter().enumerate() {
+            values[stmt.ast_index_to_column_index[i] as usize] = Some(value);
+        }
+
+        let v: Vec<_> = try!(column_types.iter().zip(values.into_iter()).map(|(&(dbtype, nullable), value)| {
+            match value {
+                Some(value) => {
+                    let mut buf = Vec::new();
+                    let is_null = try!(variant_to_data(value, dbtype, nullable, &mut buf));
+                    Ok((buf.into_boxed_slice(), is_null))
+                },
+                None => {
+                    // use default value for column type
+                    let is_null = if nullable { Some(true) } else { None };
+                    let data = if nullable { Vec::new() } else { dbtype.get_default().into_owned() };
+                    Ok((data.into_boxed_slice(), is_null))
+                }
+            }
+        }).collect::<Result<_, Error>>());
+
+        inserted_rows.push(data_row(column_types, &v));
+        try!(db.insert_row(table_name, v));
+    }
+
+    // References are checked once every row is inserted, so that the rows may reference each other
+    for row in inserted_rows {
+        try!(check_references(db, table_name, &row));
+    }
+
+    Ok(ExecuteStatementResponse::Inserted(count))
+}
+
+fn compile_update<DB: DatabaseStorageMut>(db: &DB, stmt: &ast::UpdateStatement, parameter_count: u32) -> Result<CompiledUpdate<DB>, Error> {
+    let table_name = try!(table_identifier(&stmt.table));
+    let table = try!(db.find_table_by_name(&table_name).ok_or(Error::UnknownTable(table_name.to_string())));
+
+    let column_types = (0..table.get_column_count()).map(|i| {
+        let c = table.find_column_by_offset(i).unwrap();
+        (*c.get_dbtype(), c.is_nullable())
+    }).collect();
+
+    let mut assignments = Vec::new();
+
+    for assignment in &stmt.assignments {
+        let ident = try!(Identifier::new(&assignment.column_name).ok_or(Error::Invalid(format!("Bad column name: {}", assignment.column_name))));
+        let column_offset = match table.find_column_by_name(&ident) {
+            Some(column) => column.get_offset(),
+            None => return Err(Error::UnknownColumn(ident.to_string()))
+        };
+
+        if assignments.iter().any(|&(offset, _)| offset == column_offset) {
+            return Err(Error::Invalid(format!("column {} is assigned more than once", ident)));
+        }
+
+        let expr = try!(queryplan::compile_ast_expression(db, Some(table), &assignment.expr, parameter_count));
+        assignments.push((column_offset, expr));
+    }
+
+    let search = try!(queryplan::compile_table_search(db, table, stmt.where_expr.as_ref(), parameter_count));
+
+    Ok(CompiledUpdate {
+        table_name: table_name,
+        column_types: column_types,
+        assignments: assignments,
+        search: search
+    })
+}
+
+fn update<DB: DatabaseStorageMut>(db: &mut DB, stmt: &CompiledUpdate<DB>, params: &[Variant]) -> ExecuteStatementResult<'static> {
+    trace!("updating rows of {}", stmt.table_name);
+
+    let table_name = &stmt.table_name;
+    let column_types = &stmt.column_types;
+
+    // The rows are read before any are updated, so that every row is only updated once.
+    let updated_rows: Vec<(u64, Vec<Variant>, Vec<(Box<[u8]>, Option<bool>)>)>;
+
+    {
+        let db: &DB = db;
+        let execute = ExecuteQueryPlan::new(db, params);
+        let mut rows = Vec::new();
+
+        for (rowid, row) in try!(find_rows(&execute, &stmt.search)) {
+            // Every expression sees the row as it was before the update
+            let mut new_row = row.clone();
+            for &(column_offset, ref expr) in &stmt.assignments {
+                new_row[column_offset as usize] = try!(execute.execute_expression_on_row(expr, &row));
+            }
+
+            let column_data: Result<Vec<_>, Error> = new_row.into_iter().zip(column_types.iter()).map(|(value, &(dbtype, nullable))| {
+                let mut buf = Vec::new();
+                let is_null = try!(variant_to_data(value, dbtype, nullable, &mut buf));
+                Ok((buf.into_boxed_slice(), is_null))
+            }).collect();
+
+            rows.push((rowid, row, try!(column_data)));
+        }
+
+        updated_rows = rows;
+    }
+
+    let count = updated_rows.len() as u64;
+    let mut changed_rows = Vec::new();
+
+    for (rowid, old_row, column_data) in updated_rows {
+        changed_rows.push((old_row, data_row(column_types, &column_data)));
+        try!(db.update_row(table_name, rowid, column_data));
+    }
+
+    let references = references_to(db, table_name);
+
+    for &(_, ref new_row) in &changed_rows {
+        try!(check_references(db, table_name, new_row));
+    }
+
+    try!(check_not_referenced(db, table_name, &references, &changed_rows));
+
+    Ok(ExecuteStatementResponse::Updated(count))
+}
+
+fn compile_delete<DB: DatabaseStorageMut>(db: &DB, stmt: &ast::DeleteStatement, parameter_count: u32) -> Result<CompiledDelete<DB>, Error> {
+    let table_name = try!(table_identifier(&stmt.table));
+    let search = {
+        let table = try!(db.find_table_by_name(&table_name).ok_or(Error::UnknownTable(table_name.to_string())));
+        try!(queryplan::compile_table_search(db, table, stmt.where_expr.as_ref(), parameter_count))
+    };
+
+    Ok(CompiledDelete {
+        table_name: table_name,
+        search: search
+    })
+}
+
+fn delete<DB: DatabaseStorageMut>(db: &mut DB, stmt: &CompiledDelete<DB>, params: &[Variant]) -> ExecuteStatementResult<'static> {
+    trace!("deleting rows of {}", stmt.table_name);
+
+    // The WHERE clause may select from the same table, so every row is tested before any are deleted.
+    let deleted_rows: Vec<(u64, Vec<Variant>)>;
+
+    {
+        let db: &DB = db;
+        deleted_rows = try!(find_rows(&ExecuteQueryPlan::new(db, params), &stmt.search));
+    }
+
+    // Rows deleted by ON DELETE CASCADE aren't counted
+    let count = deleted_rows.len() as u64;
+
+    try!(delete_rows(db, &stmt.table_name, deleted_rows));
+
+    Ok(ExecuteStatementResponse::Deleted(count))
+}
+
+/// The rows of a table that pass the WHERE clause, along with their rowids.
+/// `search` is a plan from `queryplan::compile_table_search`, which searches
+/// an index if one finds the rows.
+fn find_rows<DB>(execute: &ExecuteQueryPlan<DB>, search: &SExpression<DB>) -> Result<Vec<(u64, Vec<Variant>)>, Error>
+where DB: DatabaseStorageMut
+{
+    let mut rows = Vec::new();
+
+    try!(execute.execute_query_plan(search, &mut |row| {
+        rows.push(split_rowid(row));
+        Ok(())
+    }));
+
+    Ok(rows)
+}
+
+/// Splits a row that's yielded by `DatabaseStorage` into its rowid and its column values.
+fn split_rowid(row: &[Variant]) -> (u64, Vec<Variant>) {
+    match row.split_last() {
+        Some((&Variant::UnsignedInteger(rowid), values)) => (rowid, values.to_vec()),
+        _ => unreachable!()
+    }
+}
+
+/// A column that references a column of another table, or of its own table
+struct Reference {
+    table: Identifier,
+    column: Identifier,
+    column_offset: u32,
+    referenced_column: Identifier,
+    referenced_offset: u32,
+    on_delete: ForeignKeyAction
+}
+
+/// Every column that references a column of the table
+fn references_to<DB: DatabaseStorageMut>(db: &DB, table_name: &Identifier) -> Vec<Reference> {
+    let referenced_table = match db.find_table_by_name(table_name) {
+        Some(table) => table,
+        None => return Vec::new()
+    };
+
+    let mut references = Vec::new();
+
+    for name in db.get_table_names() {
+        let table = db.find_table_by_name(&name).unwrap();
+
+        for offset in 0..table.get_column_count() {
+            let column = table.find_column_by_offset(offset).unwrap();
+
+            if let Some(foreign_key) = column.get_foreign_key() {
+                if &foreign_key.table == table_name {
+                    let referenced_column = referenced_table.find_column_by_name(&foreign_key.column).unwrap();
+
+                    references.push(Reference {
+                        table: name.clone(),
+                        column: column.get_name().clone(),
+                        column_offset: offset,
+                        referenced_column: referenced_column.get_name().clone(),
+                        referenced_offset: referenced_column.get_offset(),
+                        on_delete: foreign_key.on_delete
+                    });
+                }
+            }
+        }
+    }
+
+    references
+}
+
+/// The index whose first column is the column, if there is one
+fn column_index<T: TableInfo>(table: &T, column_offset: u32) -> Option<&T::Index> {
+    (0..table.get_index_count()).map(|i| table.find_index_by_offset(i).unwrap()).find(|index| {
+        index.get_column_offsets().first() == Some(&column_offset)
+    })
+}
+
+/// The rows of the table that have one of the values in the column.
+/// Each value is searched for in an index of the column if there is one;
+/// otherwise the table is scanned once for all of the values.
+fn rows_with_values<DB>(db: &DB, table_name: &Identifier, column_offset: u32, values: &HashSet<Variant>) -> Result<Vec<(u64, Vec<Variant>)>, Error>
+where DB: DatabaseStorageMut
+{
+    let table = try!(db.find_table_by_name(table_name).ok_or(Error::UnknownTable(table_name.to_string())));
+    let mut rows = Vec::new();
+
+    match column_index(table, column_offset) {
+        Some(index) => {
+            for value in values {
+                let bound = [value.clone()];
+
+                // The search may yield rows with other values
+                for row in db.search_index(table, index, Some(&bound), Some(&bound)) {
+                    let row = try!(row);
+                    if &row[column_offset as usize] == value {
+                        rows.push(split_rowid(&row));
+                    }
+                }
+            }
+        },
+        None => {
+            for row in db.scan_rows(table) {
+                let row = try!(row);
+                if values.contains(&row[column_offset as usize]) {
+                    rows.push(split_rowid(&row));
+                }
+            }
+        }
+    }
+
+    Ok(rows)
+}
+
+/// Fails if a REFERENCES column of the row has a value that isn't in the referenced column
+fn check_references<DB: DatabaseStorageMut>(db: &DB, table_name: &Identifier, row: &[Variant]) -> Result<(), Error> {
+    let table = try!(db.find_table_by_name(table_name).ok_or(Error::UnknownTable(table_name.to_string())));
+
+    for offset in 0..table.get_column_count() {
+        let column = table.find_column_by_offset(offset).unwrap();
+        let value = &row[offset as usize];
+
+        if let Some(foreign_key) = column.get_foreign_key() {
+            if value.is_null() {
+                continue;
+            }
+
+            let referenced_offset = {
+                let referenced_table = try!(db.find_table_by_name(&foreign_key.table).ok_or(Error::UnknownTable(foreign_key.table.to_string())));
+                referenced_table.find_column_by_name(&foreign_key.column).unwrap().get_offset()
+            };
+
+            // The referenced column is PRIMARY KEY or UNIQUE, so its index is searched
+            let values = Some(value.clone()).into_iter().collect();
+
+            if try!(rows_with_values(db, &foreign_key.table, referenced_offset, &values)).is_empty() {
+                return Err(Error::Constraint(format!("FOREIGN KEY constraint violated: {}({}) = {} is not in {}({})",
+                    table_name, column.get_name(), value, foreign_key.table, foreign_key.column)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails if a referenced value that was changed by an update is still referenced.
+/// `changed_rows` has the old and new values of every updated row.
+fn check_not_referenced<DB>(db: &DB, table_name: &Identifier, references: &[Reference], changed_rows: &[(Vec<Variant>, Vec<Variant>)]) -> Result<(), Error>
+where DB: DatabaseStorageMut
+{
+    for reference in references {
+        let offset = reference.referenced_offset as usize;
+
+        let changed_values: HashSet<Variant> = changed_rows.iter().filter(|&&(ref old_row, ref new_row)| {
+            !old_row[offset].is_null() && old_row[offset] != new_row[offset]
+        }).map(|&(ref old_row, _)| old_row[offset].clone()).collect();
+
+        if changed_values.is_empty() {
+            continue;
+        }
+
+        // Another row may have been given the value by the same statement
+        let kept_values: HashSet<Variant> = try!(rows_with_values(db, table_name, reference.referenced_offset, &changed_values))
+            .into_iter().map(|(_, row)| row[offset].clone()).collect();
+        let removed_values: HashSet<Variant> = changed_values.difference(&kept_values).cloned().collect();
+
+        if removed_values.is_empty() {
+            continue;
+        }
+
+        if let Some((_, row)) = try!(rows_with_values(db, &reference.table, reference.column_offset, &removed_values)).into_iter().next() {
+            return Err(referenced_violation(table_name, reference, &row[reference.column_offset as usize]));
+        }
+    }
+
+    Ok(())
+}
+
+fn referenced_violation(table_name: &Identifier, reference: &Reference, value: &Variant) -> Error {
+    Error::Constraint(format!("FOREIGN KEY constraint violated: {}({}) = {} is referenced by {}({})",
+        table_name, reference.referenced_column, value, reference.table, reference.column))
+}
+
+/// Deletes the rows of the table, and applies the ON DELETE action of every column that references them.
+///
+/// Every row that the statement deletes, including by cascades, is found
+/// before any are deleted. A row with a RESTRICT reference to a deleted row
+/// only fails the statement if it isn't deleted too.
+fn delete_rows<DB>(db: &mut DB, table_name: &Identifier, rows: Vec<(u64, Vec<Variant>)>) -> Result<(), Error>
+where DB: DatabaseStorageMut
+{
+    let mut deleted = HashSet::new();
+    let mut deleted_rows = Vec::new();
+    // The rows that fail the statement unless they're deleted, and the error if they aren't
+    let mut restricted_rows = Vec::new();
+    // The rows whose referencing columns are set to NULL, unless they're deleted
+    let mut set_null_rows: HashMap<(Identifier, u64), Vec<Variant>> = HashMap::new();
+
+    let mut pending = vec![(table_name.clone(), rows)];
+
+    while let Some((table_name, rows)) = pending.pop() {
+        // A row may be deleted by more than one cascade
+        let rows: Vec<_> = rows.into_iter().filter(|&(rowid, _)| deleted.insert((table_name.clone(), rowid))).collect();
+
+        for reference in references_to(db, &table_name) {
+            let offset = reference.referenced_offset as usize;
+            let values: HashSet<Variant> = rows.iter().map(|&(_, ref row)| row[offset].clone()).filter(|value| !value.is_null()).collect();
+
+            if values.is_empty() {
+                continue;
+            }
+
+            let referencing_rows = try!(rows_with_values(db, &reference.table, reference.column_offset, &values));
+
+            match reference.on_delete {
+                ForeignKeyAction::Restrict => {
+                    for (rowid, row) in referencing_rows {
+                        let error = referenced_violation(&table_name, &reference, &row[reference.column_offset as usize]);
+                        restricted_rows.push(((reference.table.clone(), rowid), error));
+                    }
+                },
+                ForeignKeyAction::Cascade => {
+                    pending.push((reference.table.clone(), referencing_rows));
+                },
+                ForeignKeyAction::SetNull => {
+                    for (rowid, row) in referencing_rows {
+                        let row = set_null_rows.entry((reference.table.clone(), rowid)).or_insert(row);
+                        row[reference.column_offset as usize] = Variant::Null;
+                    }
+                }
+            }
+        }
+
+        deleted_rows.extend(rows.into_iter().map(|(rowid, _)| (table_name.clone(), rowid)));
+    }
+
+    for (row, error) in restricted_rows {
+        if !deleted.contains(&row) {
+            return Err(error);
+        }
+    }
+
+    for ((table_name, rowid), row) in set_null_rows {
+        if deleted.contains(&(table_name.clone(), rowid)) {
+            continue;
+        }
+
+        let column_data = {
+            let table = db.find_table_by_name(&table_name).unwrap();
+            try!(row_data(table, row))
+        };
+
+        try!(db.update_row(&table_name, rowid, column_data));
+    }
+
+    for (table_name, rowid) in deleted_rows {
+        try!(db.delete_row(&table_name, rowid));
+    }
+
+    Ok(())
+}
+
+fn select<'a, DB: DatabaseStorageMut>(db: &'a DB, column_names: &[String], expr: &Rc<SExpression<DB>>, params: &[Variant]) -> ExecuteStatementResult<'a> {
+    let rows = try!(QueryPlanRows::new(db, expr.clone(), params));
+
+    Ok(ExecuteStatementResponse::Select {
+        column_names: column_names.to_vec().into_boxed_slice(),
+        rows: Box::new(rows.map(|row| row.map(|row| row.into_boxed_slice())))
+    })
+}
+
+/// The name of a table. Tables can't be qualified with the name of a database.
+fn table_identifier(table: &ast::Table) -> Result<Identifier, Error> {
+    if let Some(ref database_name) = table.database_name {
+        return Err(Error::Invalid(format!("{}.{}: tables can't be qualified with a database name", database_name, table.table_name)));
+    }
+
+    Identifier::new(&table.table_name).ok_or(Error::Invalid(format!("Bad table name: {}", table.table_name)))
+}
+
+fn parse_number_as_u64(number: &str) -> Result<u64, Error> {
+    number.parse().map_err(|_| Error::Invalid(format!("{} is not a valid number", number)))
+}
+
+/// Converts the values of a row to the column data of the table
+fn row_data<T: TableInfo>(table: &T, row: Vec<Variant>) -> Result<Vec<(Box<[u8]>, Option<bool>)>, Error> {
+    row.into_iter().enumerate().map(|(i, value)| {
+        let column = table.find_column_by_offset(i as u32).unwrap();
+        let mut buf = Vec::new();
+        let is_null = try!(variant_to_data(value, *column.get_dbtype(), column.is_nullable(), &mut buf));
+        Ok((buf.into_boxed_slice(), is_null))
+    }).collect()
+}
+
+/// The values of a row's column data, as they are read back from the table
+fn data_row(column_types: &[(DbType, bool)], column_data: &[(Box<[u8]>, Option<bool>)]) -> Vec<Variant> {
+    column_types.iter().zip(column_data.iter()).map(|(&(dbtype, _), &(ref data, is_null))| {
+        if is_null == Some(true) {
+            Variant::Null
+        } else {
+            Variant::from_bytes(dbtype, Cow::Borrowed(data)).unwrap()
+        }
+    }).collect()
+}
+
+fn variant_to_data(value: Variant, column_type: DbType, nullable: bool, buf: &mut Vec<u8>)
+-> Result<Option<bool>, Error> {
+    match (value.is_null(), nullable) {
+        (true, true) => Ok(Some(true)),
+        (true, false) => {
+            Err(Error::Constraint(format!("cannot insert NULL into column that doesn't allow NULL")))
+        },
+        (false, nullable) => {
+            let bytes = match value.clone().to_bytes(column_type) {
+                Ok(bytes) => bytes,
+                Err(()) => return Err(Error::TypeMismatch(format!("cannot convert {} to column type {:?}", value, column_type)))
+            };
+            buf.extend_from_slice(&bytes);
+
+            Ok(if nullable { Some(false) } else { None })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DatabaseEngine, ExecuteStatementResponse};
+    use databaseinfo::DatabaseInfo;
+    use error::Error;
+    use filedb::FileDb;
+    use identifier::Identifier;
+    use queryplan;
+    use sqlsyntax::{ast, parse_statement};
+    use tempdb::TempDb;
+    use types::Variant;
+    use pager::{temp_path, remove_database_files};
+
+    use std::path::Path;
+
+    /// Defines a module of tests that run the generic test function with each
+    /// engine: `tempdb` with a TempDb, and `filedb` with a FileDb in a new file.
+    /// The block, if any, runs after the FileDb is closed, with the file's path.
+    macro_rules! engine_tests {
+        ($test:ident) => (engine_tests!($test, |_path| {}););
+        ($test:ident, |$path:ident| $after_close:block) => (
+            mod $test {
+                use super::*;
+
+                #[test]
+                fn tempdb() {
+                    $test(&mut TempDb::new());
+                }
+
+                #[test]
+                fn filedb() {
+                    with_temp_path(stringify!($test), |$path| {
+                        $test(&mut FileDb::open($path).unwrap());
+                        $after_close
+                    });
+                }
+            }
+        )
+    }
+
+    /// Runs the function with the path of a new database file, and removes
+    /// the file afterwards.
+    fn with_temp_path<F: FnOnce(&Path)>(name: &str, f: F) {
+        let path = temp_path(&format!("engine-{}", name));
+        f(&path);
+        remove_database_files(&path);
+    }
+
+    /// Executes the statement, reading every row if it's a SELECT.
+    fn execute<DB: DatabaseEngine>(db: &mut DB, sql: &str) -> Result<(), Error> {
+        match try!(db.execute_statement(parse_statement(sql).unwrap())) {
+            ExecuteStatementResponse::Select { rows, .. } => {
+                for row in rows {
+                    try!(row);
+                }
+                Ok(())
+            },
+            _ => Ok(())
+        }
+    }
+
+    fn select<DB: DatabaseEngine>(db: &mut DB, sql: &str) -> Vec<String> {
+        match db.execute_statement(parse_statement(sql).unwrap()) {
+            Ok(ExecuteStatementResponse::Select { rows, .. }) => {
+                rows.map(|row| {
+                    let row = row.unwrap_or_else(|e| panic!("{}: {}", sql, e));
+                    let values: Vec<String> = row.iter().map(|v| v.to_string()).collect();
+                    values.join(", ")
+                }).collect()
+            },
+            Ok(_) => panic!("{}: not a SELECT", sql),
+            Err(e) => panic!("{}: {}", sql, e)
+        }
+    }
+
+    fn test_transactions<DB: DatabaseEngine>(db: &mut DB) {
+        execute(db, "CREATE TABLE t (a U32, b STRING);").unwrap();
+        execute(db, "INSERT INTO t VALUES (1, 'a');").unwrap();
+
+        // The second row fails, so the first row isn't inserted either
+        assert!(execute(db, "INSERT INTO t VALUES (2, 'b'), (3, NULL);").is_err());
+        assert_eq!(select(db, "SELECT * FROM t;"), vec!["1, a"]);
+
+        execute(db, "BEGIN;").unwrap();
+        execute(db, "INSERT INTO t VALUES (2, 'b');").unwrap();
+        assert_eq!(select(db, "SELECT count(*) FROM t;"), vec!["2"]);
+        execute(db, "ROLLBACK;").unwrap();
+        assert_eq!(select(db, "SELECT * FROM t;"), vec!["1, a"]);
+
+        // A failed statement doesn't end the transaction
+        execute(db, "BEGIN TRANSACTION;").unwrap();
+        execute(db, "CREATE TABLE u (x U32);").unwrap();
+        execute(db, "INSERT INTO u VALUES (1), (2);").unwrap();
+        execute(db, "INSERT INTO t VALUES (3, 'c');").unwrap();
+        assert!(execute(db, "INSERT INTO t VALUES (4, 'd'), (5, NULL);").is_err());
+        execute(db, "COMMIT;").unwrap();
+        assert_eq!(select(db, "SELECT * FROM t;"), vec!["1, a", "3, c"]);
+        assert_eq!(select(db, "SELECT sum(x) FROM u;"), vec!["3"]);
+
+        execute(db, "BEGIN;").unwrap();
+        execute(db, "CREATE TABLE v (x U32);").unwrap();
+        execute(db, "INSERT INTO u VALUES (3);").unwrap();
+        execute(db, "ROLLBACK;").unwrap();
+        assert!(execute(db, "SELECT * FROM v;").is_err());
+        assert_eq!(select(db, "SELECT sum(x) FROM u;"), vec!["3"]);
+
+        execute(db, "BEGIN;").unwrap();
+        assert!(execute(db, "BEGIN;").is_err());
+        execute(db, "ROLLBACK;").unwrap();
+        assert!(execute(db, "COMMIT;").is_err());
+        assert!(execute(db, "ROLLBACK;").is_err());
+    }
+
+    #[test]
+    fn test_order_by() {
+        let mut db = TempDb::new();
+        execute(&mut db, "CREATE TABLE t (a U32, b STRING, c I32 NULL);").unwrap();
+        execute(&mut db, "INSERT INTO t VALUES (1, 'x', 5), (2, 'y', NULL), (3, 'x', -2), (4, 'z', 5);").unwrap();
+
+        assert_eq!(select(&mut db, "SELECT a FROM t ORDER BY c;"), vec!["2", "3", "1", "4"]);
+        assert_eq!(select(&mut db, "SELECT a FROM t ORDER BY c DESC;"), vec!["1", "4", "3", "2"]);
+        assert_eq!(select(&mut db, "SELECT a FROM t ORDER BY b DESC, a DESC;"), vec!["4", "2", "3", "1"]);
+
+        // By alias, by position, and by an expression that isn't a result column
+        assert_eq!(select(&mut db, "SELECT a, a * 2 AS d FROM t ORDER BY d DESC;"), vec!["4, 8", "3, 6", "2, 4", "1, 2"]);
+        assert_eq!(select(&mut db, "SELECT b, a FROM t ORDER BY 1, 2 DESC;"), vec!["x, 3", "x, 1", "y, 2", "z, 4"]);
+        assert_eq!(select(&mut db, "SELECT b FROM t ORDER BY 0 - a;"), vec!["z", "x", "y", "x"]);
+
+        assert_eq!(select(&mut db, "SELECT b, count(*) n FROM t GROUP BY b ORDER BY n DESC, b;"), vec!["x, 2", "y, 1", "z, 1"]);
+        assert_eq!(select(&mut db, "SELECT b FROM t GROUP BY b ORDER BY max(a);"), vec!["y", "x", "z"]);
+
+        assert!(execute(&mut db, "SELECT a FROM t ORDER BY 2;").is_err());
+
+        // Integers that overflow become floats, which sort by value among the integers
+        execute(&mut db, "CREATE TABLE n (a I64);").unwrap();
+        execute(&mut db, "INSERT INTO n VALUES (5), (0 - 9223372036854775807 - 1), (0 - 7), (9223372036854775807);").unwrap();
+        assert_eq!(select(&mut db, "SELECT a - 1 FROM n ORDER BY a - 1;"),
+            vec!["-9223372036854776000", "-8", "4", "9223372036854775806"]);
+        assert_eq!(select(&mut db, "SELECT a + 1 FROM n ORDER BY a + 1 DESC;"),
+            vec!["9223372036854776000", "6", "-6", "-9223372036854775807"]);
+        assert_eq!(select(&mut db, "SELECT a * 2 FROM n ORDER BY a * 2;"),
+            vec!["-18446744073709552000", "-14", "10", "18446744073709552000"]);
+
+        let plan = match db.execute_statement(parse_statement("EXPLAIN SELECT a FROM t ORDER BY b;").unwrap()) {
+            Ok(ExecuteStatementResponse::Explain(plan)) => plan,
+            _ => panic!("expected a query plan")
+        };
+        assert!(plan.contains("(sort :source-id"));
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut db = TempDb::new();
+        execute(&mut db, "CREATE TABLE t (a U32);").unwrap();
+        execute(&mut db, "INSERT INTO t VALUES (1), (2), (3), (4), (5);").unwrap();
+
+        assert_eq!(select(&mut db, "SELECT a FROM t LIMIT 2;"), vec!["1", "2"]);
+        assert_eq!(select(&mut db, "SELECT a FROM t LIMIT 2 OFFSET 2;"), vec!["3", "4"]);
+        assert_eq!(select(&mut db, "SELECT a FROM t LIMIT 10 OFFSET 4;"), vec!["5"]);
+        assert_eq!(select(&mut db, "SELECT a FROM t LIMIT 0;"), Vec::<String>::new());
+        assert_eq!(select(&mut db, "SELECT a FROM t ORDER BY a DESC LIMIT 1 + 1;"), vec!["5", "4"]);
+        assert_eq!(select(&mut db, "SELECT a FROM (SELECT a FROM t LIMIT 3) s, (SELECT a b FROM t LIMIT 2) u;").len(), 6);
+
+        assert!(execute(&mut db, "SELECT a FROM t LIMIT 0 - 1;").is_err());
+        assert!(execute(&mut db, "SELECT a FROM t LIMIT 'a';").is_err());
+
+        // The scan stops early: the second row's subquery would yield two rows
+        execute(&mut db, "CREATE TABLE u (b U32);").unwrap();
+        execute(&mut db, "INSERT INTO u VALUES (1), (2), (2);").unwrap();
+        assert!(execute(&mut db, "SELECT (SELECT b FROM u WHERE b = a) FROM t;").is_err());
+        assert_eq!(select(&mut db, "SELECT (SELECT b FROM u WHERE b = a) FROM t LIMIT 1;"), vec!["1"]);
+        assert_eq!(select(&mut db, "SELECT (SELECT b FROM u WHERE b = 2 LIMIT 1) FROM t LIMIT 1;"), vec!["2"]);
+    }
+
+    #[test]
+    fn test_streamed_select() {
+        let mut db = TempDb::new();
+        execute(&mut db, "CREATE TABLE t (a U32);").unwrap();
+        execute(&mut db, "INSERT INTO t VALUES (1), (2), (3);").unwrap();
+        execute(&mut db, "CREATE TABLE u (b U32);").unwrap();
+        execute(&mut db, "INSERT INTO u VALUES (1), (2), (2);").unwrap();
+
+        // The rows before the one whose subquery fails are yielded, and nothing after it
+        let rows: Vec<Result<String, Error>> = match db.execute_statement(parse_statement("SELECT (SELECT b FROM u WHERE b = a) FROM t;").unwrap()) {
+            Ok(ExecuteStatementResponse::Select { rows, .. }) => {
+                rows.map(|row| row.map(|row| row[0].to_string())).collect()
+            },
+            _ => panic!("expected rows")
+        };
+        assert_eq!(rows, vec![Ok("1".to_string()), Err(Error::Invalid("subquery must yield exactly one row".to_string()))]);
+
+        // Only the rows that are read are executed
+        match db.execute_statement(parse_statement("SELECT a, (SELECT b FROM u WHERE b = a) FROM t;").unwrap()) {
+            Ok(ExecuteStatementResponse::Select { mut rows, .. }) => {
+                let row = rows.next().unwrap().unwrap();
+                assert_eq!(row[0].to_string(), "1");
+            },
+            _ => panic!("expected rows")
+        }
+
+        // Errors that are found before any row is read fail the statement
+        assert!(db.execute_statement(parse_statement("SELECT a FROM t LIMIT 0 - 1;").unwrap()).is_err());
+        assert!(db.execute_statement(parse_statement("SELECT a FROM t ORDER BY (SELECT b FROM u);").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_distinct() {
+        let mut db = TempDb::new();
+        execute(&mut db, "CREATE TABLE t (a U32, b STRING NULL);").unwrap();
+        execute(&mut db, "INSERT INTO t VALUES (1, 'x'), (2, 'y'), (1, 'x'), (3, NULL), (2, 'z'), (3, NULL);").unwrap();
+
+        assert_eq!(select(&mut db, "SELECT DISTINCT a FROM t ORDER BY a;"), vec!["1", "2", "3"]);
+        assert_eq!(select(&mut db, "SELECT DISTINCT a, b FROM t ORDER BY a DESC, b;"), vec!["3, NULL", "2, y", "2, z", "1, x"]);
+        assert_eq!(select(&mut db, "SELECT DISTINCT a FROM t ORDER BY a DESC LIMIT 2;"), vec!["3", "2"]);
+
+        // NULLs aren't counted
+        assert_eq!(select(&mut db, "SELECT count(DISTINCT a), count(DISTINCT b), sum(DISTINCT a), count(b) FROM t;"), vec!["3, 3, 6, 4"]);
+        assert_eq!(select(&mut db, "SELECT a, count(DISTINCT b) FROM t GROUP BY a ORDER BY a;"), vec!["1, 1", "2, 2", "3, 0"]);
+    }
+
+    engine_tests!(test_transactions);
+
+    fn test_update<DB: DatabaseEngine>(db: &mut DB) {
+        execute(db, "CREATE TABLE t (a U32, b STRING, c I32 NULL);").unwrap();
+        execute(db, "INSERT INTO t VALUES (1, 'x', 5), (2, 'y', NULL), (3, 'z', -2);").unwrap();
+
+        match db.execute_statement(parse_statement("UPDATE t SET c = a * 10, b = b || '!' WHERE a >= 2;").unwrap()) {
+            Ok(ExecuteStatementResponse::Updated(2)) => (),
+            _ => panic!("expected 2 rows to be updated")
+        }
+        assert_eq!(select(db, "SELECT * FROM t;"), vec!["1, x, 5", "2, y!, 20", "3, z!, 30"]);
+
+        // Every assignment sees the old row
+        execute(db, "UPDATE t SET a = c, c = a WHERE b = 'x';").unwrap();
+        assert_eq!(select(db, "SELECT * FROM t WHERE b = 'x';"), vec!["5, x, 1"]);
+
+        execute(db, "UPDATE t SET c = NULL;").unwrap();
+        assert_eq!(select(db, "SELECT count(c) FROM t;"), vec!["0"]);
+
+        // The last row fails, so no rows are updated
+        assert!(execute(db, "UPDATE t SET b = NULL WHERE a > 2;").is_err());
+        assert!(execute(db, "UPDATE t SET a = 'abc';").is_err());
+        assert!(execute(db, "UPDATE t SET d = 1;").is_err());
+        assert!(execute(db, "UPDATE t SET a = 1, a = 2;").is_err());
+        assert_eq!(select(db, "SELECT a, b FROM t;"), vec!["5, x", "2, y!", "3, z!"]);
+
+        execute(db, "BEGIN;").unwrap();
+        execute(db, "UPDATE t SET b = 'rolled back';").unwrap();
+        execute(db, "ROLLBACK;").unwrap();
+        assert_eq!(select(db, "SELECT b FROM t;"), vec!["x", "y!", "z!"]);
+    }
+
+    engine_tests!(test_update);
+
+    fn test_delete<DB: DatabaseEngine>(db: &mut DB) {
+        execute(db, "CREATE TABLE t (a U32, b STRING NULL);").unwrap();
+        execute(db, "INSERT INTO t VALUES (1, 'x'), (2, NULL), (3, 'z'), (4, 'x');").unwrap();
+
+        match db.execute_statement(parse_statement("DELETE FROM t WHERE b = 'x';").unwrap()) {
+            Ok(ExecuteStatementResponse::Deleted(2)) => (),
+            _ => panic!("expected 2 rows to be deleted")
+        }
+        assert_eq!(select(db, "SELECT a FROM t;"), vec!["2", "3"]);
+
+        // Every row is tested before any are deleted
+        execute(db, "INSERT INTO t VALUES (5, 'y');").unwrap();
+        execute(db, "DELETE FROM t WHERE a < (SELECT max(s.a) FROM t s);").unwrap();
+        assert_eq!(select(db, "SELECT * FROM t;"), vec!["5, y"]);
+
+        assert!(execute(db, "DELETE FROM t WHERE c = 1;").is_err());
+        assert!(execute(db, "DELETE FROM u;").is_err());
+
+        execute(db, "BEGIN;").unwrap();
+        execute(db, "DELETE FROM t;").unwrap();
+        assert_eq!(select(db, "SELECT * FROM t;"), Vec::<String>::new());
+        execute(db, "ROLLBACK;").unwrap();
+        assert_eq!(select(db, "SELECT * FROM t;"), vec!["5, y"]);
+
+        // Rowids aren't reused while the database is open
+        execute(db, "DELETE FROM t;").unwrap();
+        execute(db, "INSERT INTO t VALUES (6, 'w');").unwrap();
+        assert_eq!(select(db, "SELECT * FROM t;"), vec!["6, w"]);
+    }
+
+    engine_tests!(test_delete);
+
+    fn test_insert_select<DB: DatabaseEngine>(db: &mut DB) {
+        execute(db, "CREATE TABLE t (a U32, b STRING NULL);").unwrap();
+        execute(db, "CREATE TABLE u (x U32, y STRING NULL, z F64);").unwrap();
+        execute(db, "INSERT INTO t VALUES (1, 'x'), (2, NULL), (3, 'z');").unwrap();
+
+        match db.execute_statement(parse_statement("INSERT INTO u (y, x) SELECT b, a * 10 FROM t WHERE a > 1;").unwrap()) {
+            Ok(ExecuteStatementResponse::Inserted(2)) => (),
+            _ => panic!("expected 2 rows to be inserted")
+        }
+        assert_eq!(select(db, "SELECT * FROM u;"), vec!["20, NULL, 0", "30, z, 0"]);
+
+        // The query doesn't see the rows that are being inserted
+        execute(db, "INSERT INTO t SELECT a + 3, b FROM t;").unwrap();
+        assert_eq!(select(db, "SELECT a FROM t;"), vec!["1", "2", "3", "4", "5", "6"]);
+        execute(db, "INSERT INTO t VALUES ((SELECT count(*) FROM t), 'a'), ((SELECT count(*) FROM t), 'b');").unwrap();
+        assert_eq!(select(db, "SELECT a FROM t WHERE a = 6;"), vec!["6", "6", "6"]);
+
+        assert!(execute(db, "INSERT INTO u SELECT a, b FROM t;").is_err());
+        assert!(execute(db, "INSERT INTO u (x) SELECT b FROM t;").is_err());
+        assert!(execute(db, "INSERT INTO u (x, y) SELECT b, a FROM t WHERE b = 'x';").is_err());
+        assert_eq!(select(db, "SELECT count(*) FROM u;"), vec!["2"]);
+    }
+
+    engine_tests!(test_insert_select);
+
+    fn test_drop_and_alter_table<DB: DatabaseEngine>(db: &mut DB) {
+        execute(db, "CREATE TABLE t (a U32, b STRING);").unwrap();
+        execute(db, "INSERT INTO t VALUES (1, 'x'), (2, 'y');").unwrap();
+
+        execute(db, "ALTER TABLE t ADD COLUMN c I32 NULL;").unwrap();
+        execute(db, "ALTER TABLE t ADD d U8;").unwrap();
+        assert_eq!(select(db, "SELECT * FROM t;"), vec!["1, x, NULL, 0", "2, y, NULL, 0"]);
+        execute(db, "INSERT INTO t VALUES (3, 'z', -1, 7);").unwrap();
+
+        execute(db, "ALTER TABLE t DROP COLUMN b;").unwrap();
+        assert_eq!(select(db, "SELECT * FROM t;"), vec!["1, NULL, 0", "2, NULL, 0", "3, -1, 7"]);
+        assert!(execute(db, "SELECT b FROM t;").is_err());
+
+        assert!(execute(db, "ALTER TABLE t ADD a U32;").is_err());
+        assert!(execute(db, "ALTER TABLE t DROP COLUMN b;").is_err());
+        assert!(execute(db, "ALTER TABLE t ADD e FOO;").is_err());
+
+        execute(db, "ALTER TABLE t RENAME TO u;").unwrap();
+        assert!(execute(db, "SELECT * FROM t;").is_err());
+        assert_eq!(select(db, "SELECT a FROM u;"), vec!["1", "2", "3"]);
+
+        execute(db, "CREATE TABLE v (x U32);").unwrap();
+        assert!(execute(db, "ALTER TABLE v RENAME TO u;").is_err());
+        assert!(execute(db, "ALTER TABLE v DROP x;").is_err());
+
+        execute(db, "DROP TABLE v;").unwrap();
+        assert!(execute(db, "SELECT * FROM v;").is_err());
+        assert!(execute(db, "DROP TABLE v;").is_err());
+        execute(db, "DROP TABLE IF EXISTS v;").unwrap();
+
+        // Schema changes are rolled back like any other change
+        execute(db, "BEGIN;").unwrap();
+        execute(db, "ALTER TABLE u DROP COLUMN c;").unwrap();
+        execute(db, "ALTER TABLE u RENAME TO w;").unwrap();
+        execute(db, "INSERT INTO w VALUES (4, 8);").unwrap();
+        execute(db, "DROP TABLE w;").unwrap();
+        execute(db, "ROLLBACK;").unwrap();
+        assert_eq!(select(db, "SELECT * FROM u;"), vec!["1, NULL, 0", "2, NULL, 0", "3, -1, 7"]);
+
+        // A dropped table's name can be reused
+        execute(db, "DROP TABLE u;").unwrap();
+        execute(db, "CREATE TABLE u (b STRING);").unwrap();
+        assert_eq!(select(db, "SELECT * FROM u;"), Vec::<String>::new());
+    }
+
+    engine_tests!(test_drop_and_alter_table);
+
+    fn test_unique_constraints<DB: DatabaseEngine>(db: &mut DB) {
+        execute(db, "CREATE TABLE t (id U32 PRIMARY KEY, email STRING UNIQUE, nick STRING NULL UNIQUE);").unwrap();
+        execute(db, "INSERT INTO t VALUES (1, 'a@x', 'a'), (2, 'b@x', NULL), (3, 'c@x', NULL);").unwrap();
+
+        match execute(db, "INSERT INTO t VALUES (1, 'd@x', NULL);") {
+            Err(e) => assert_eq!(e, Error::Constraint("PRIMARY KEY constraint violated: duplicate value in t(id)".to_string())),
+            Ok(()) => panic!("expected a PRIMARY KEY violation")
+        }
+        match execute(db, "INSERT INTO t VALUES (4, 'a@x', NULL);") {
+            Err(e) => assert_eq!(e, Error::Constraint("UNIQUE constraint violated: duplicate value in t(email)".to_string())),
+            Ok(()) => panic!("expected a UNIQUE violation")
+        }
+
+        // Rows that collide within the same statement
+        assert!(execute(db, "INSERT INTO t VALUES (4, 'd@x', 'd'), (5, 'e@x', 'd');").is_err());
+        assert!(execute(db, "INSERT INTO t SELECT id + 10, 'same', NULL FROM t;").is_err());
+        assert_eq!(select(db, "SELECT count(*) FROM t;"), vec!["3"]);
+
+        // A row can keep its own value, but can't take another row's
+        execute(db, "UPDATE t SET email = 'a@x', nick = 'aa' WHERE id = 1;").unwrap();
+        assert!(execute(db, "UPDATE t SET id = 1 WHERE id = 2;").is_err());
+        assert!(execute(db, "UPDATE t SET nick = 'aa' WHERE id = 3;").is_err());
+        execute(db, "UPDATE t SET id = id + 10;").unwrap();
+        assert_eq!(select(db, "SELECT id FROM t;"), vec!["11", "12", "13"]);
+
+        // A deleted row's value can be reused
+        execute(db, "DELETE FROM t WHERE id = 11;").unwrap();
+        execute(db, "INSERT INTO t VALUES (11, 'a@x', 'aa');").unwrap();
+
+        // The indexes are rolled back along with the rows
+        execute(db, "BEGIN;").unwrap();
+        execute(db, "DELETE FROM t WHERE id = 12;").unwrap();
+        execute(db, "INSERT INTO t VALUES (20, 'b@x', NULL);").unwrap();
+        execute(db, "ROLLBACK;").unwrap();
+        assert!(execute(db, "INSERT INTO t VALUES (12, 'z@x', NULL);").is_err());
+        execute(db, "INSERT INTO t VALUES (20, 'z@x', NULL);").unwrap();
+
+        assert!(execute(db, "CREATE TABLE u (a U32 PRIMARY KEY, b U32 PRIMARY KEY);").is_err());
+        assert!(execute(db, "CREATE TABLE u (a U32 NULL PRIMARY KEY);").is_err());
+
+        // Indexes survive ALTER TABLE
+        execute(db, "ALTER TABLE t DROP COLUMN nick;").unwrap();
+        execute(db, "ALTER TABLE t ADD COLUMN n U32 NULL UNIQUE;").unwrap();
+        assert!(execute(db, "INSERT INTO t VALUES (20, 'y@x', NULL);").is_err());
+        execute(db, "INSERT INTO t VALUES (21, 'y@x', 1);").unwrap();
+        assert!(execute(db, "INSERT INTO t VALUES (22, 'x@x', 1);").is_err());
+
+        // Every existing row would get the same value
+        assert!(execute(db, "ALTER TABLE t ADD COLUMN m U32 UNIQUE;").is_err());
+        assert!(execute(db, "ALTER TABLE t ADD COLUMN m U32 PRIMARY KEY;").is_err());
+        assert_eq!(select(db, "SELECT * FROM t WHERE id = 21;"), vec!["21, y@x, 1"]);
+    }
+
+    engine_tests!(test_unique_constraints);
+
+    fn test_foreign_keys<DB: DatabaseEngine>(db: &mut DB) {
+        execute(db, "CREATE TABLE parent (id U32 PRIMARY KEY, code STRING UNIQUE, note STRING);").unwrap();
+        execute(db, "CREATE TABLE child (id U32 PRIMARY KEY, parent_id U32 REFERENCES parent ON DELETE CASCADE);").unwrap();
+        execute(db, "CREATE TABLE pet (name STRING, parent_code STRING NULL REFERENCES parent(code) ON DELETE SET NULL);").unwrap();
+        execute(db, "CREATE TABLE toy (name STRING, child_id U32 REFERENCES child(id));").unwrap();
+
+        // The referenced table and column are checked
+        assert!(execute(db, "CREATE TABLE u (a U32 REFERENCES nothing);").is_err());
+        assert!(execute(db, "CREATE TABLE u (a U32 REFERENCES parent(nothing));").is_err());
+        assert!(execute(db, "CREATE TABLE u (a STRING REFERENCES parent(note));").is_err());
+        assert!(execute(db, "CREATE TABLE u (a STRING REFERENCES parent(id));").is_err());
+        assert!(execute(db, "CREATE TABLE u (a U32 REFERENCES toy);").is_err());
+        assert!(execute(db, "CREATE TABLE u (a STRING REFERENCES parent(code) ON DELETE SET NULL);").is_err());
+
+        execute(db, "INSERT INTO parent VALUES (1, 'a', ''), (2, 'b', ''), (3, 'c', '');").unwrap();
+        execute(db, "INSERT INTO child VALUES (10, 1), (11, 1), (20, 2);").unwrap();
+        execute(db, "INSERT INTO pet VALUES ('rex', 'a'), ('tom', 'b'), ('stray', NULL);").unwrap();
+        execute(db, "INSERT INTO toy VALUES ('ball', 20);").unwrap();
+
+        match execute(db, "INSERT INTO child VALUES (30, 4);") {
+            Err(e) => assert_eq!(e, Error::Constraint("FOREIGN KEY constraint violated: child(parent_id) = 4 is not in parent(id)".to_string())),
+            Ok(()) => panic!("expected a FOREIGN KEY violation")
+        }
+        assert!(execute(db, "INSERT INTO child SELECT id + 30, id + 1 FROM parent;").is_err());
+        assert!(execute(db, "UPDATE child SET parent_id = 4 WHERE id = 10;").is_err());
+        assert_eq!(select(db, "SELECT * FROM child;"), vec!["10, 1", "11, 1", "20, 2"]);
+
+        // A referenced value can't be changed while it's referenced
+        assert!(execute(db, "UPDATE parent SET code = 'z' WHERE id = 1;").is_err());
+        execute(db, "UPDATE parent SET code = 'z' WHERE id = 3;").unwrap();
+
+        // RESTRICT: the ball references child 20
+        match execute(db, "DELETE FROM parent WHERE id = 2;") {
+            Err(e) => assert_eq!(e, Error::Constraint("FOREIGN KEY constraint violated: child(id) = 20 is referenced by toy(child_id)".to_string())),
+            Ok(()) => panic!("expected a FOREIGN KEY violation")
+        }
+        assert_eq!(select(db, "SELECT name, parent_code FROM pet WHERE name = 'tom';"), vec!["tom, b"]);
+
+        // CASCADE and SET NULL
+        execute(db, "DELETE FROM parent WHERE id = 1;").unwrap();
+        assert_eq!(select(db, "SELECT * FROM child;"), vec!["20, 2"]);
+        assert_eq!(select(db, "SELECT * FROM pet WHERE name = 'rex';"), vec!["rex, NULL"]);
+
+        execute(db, "DELETE FROM toy;").unwrap();
+        execute(db, "DELETE FROM parent WHERE id = 2;").unwrap();
+        assert_eq!(select(db, "SELECT * FROM child;"), Vec::<String>::new());
+
+        // A table can reference itself, and rows can reference rows inserted by the same statement
+        execute(db, "CREATE TABLE node (id U32 PRIMARY KEY, up U32 NULL REFERENCES node ON DELETE CASCADE);").unwrap();
+        execute(db, "INSERT INTO node VALUES (2, 1), (1, NULL), (3, 2), (4, NULL);").unwrap();
+        execute(db, "DELETE FROM node WHERE id <= 2;").unwrap();
+        assert_eq!(select(db, "SELECT * FROM node;"), vec!["4, NULL"]);
+
+        // A RESTRICT reference only fails if the referencing row isn't deleted too,
+        // whichever row the statement finds first
+        execute(db, "CREATE TABLE tree (id U32 PRIMARY KEY, up U32 NULL REFERENCES tree);").unwrap();
+        execute(db, "INSERT INTO tree VALUES (1, NULL), (2, 1), (3, 2), (4, NULL);").unwrap();
+        assert!(execute(db, "DELETE FROM tree WHERE id = 2;").is_err());
+        execute(db, "DELETE FROM tree WHERE id <= 3;").unwrap();
+        assert_eq!(select(db, "SELECT * FROM tree;"), vec!["4, NULL"]);
+        execute(db, "DROP TABLE tree;").unwrap();
+
+        // Referenced tables and columns can't be dropped
+        assert!(execute(db, "DROP TABLE parent;").is_err());
+        assert!(execute(db, "ALTER TABLE parent DROP COLUMN code;").is_err());
+        execute(db, "ALTER TABLE parent DROP COLUMN note;").unwrap();
+
+        // Renaming a referenced table renames the references too, including a table's own
+        execute(db, "ALTER TABLE parent RENAME TO mother;").unwrap();
+        execute(db, "ALTER TABLE mother RENAME TO parent;").unwrap();
+        assert!(execute(db, "INSERT INTO child VALUES (30, 4);").is_err());
+        execute(db, "INSERT INTO parent VALUES (4, 'd');").unwrap();
+        execute(db, "INSERT INTO child VALUES (30, 4);").unwrap();
+        execute(db, "DELETE FROM parent WHERE id = 4;").unwrap();
+        assert_eq!(select(db, "SELECT * FROM child;"), Vec::<String>::new());
+
+        execute(db, "ALTER TABLE node RENAME TO vertex;").unwrap();
+        assert!(execute(db, "INSERT INTO vertex VALUES (5, 6);").is_err());
+
+        // The existing rows get the default value, which must be referenced
+        assert!(execute(db, "ALTER TABLE pet ADD COLUMN other U32 REFERENCES parent;").is_err());
+        execute(db, "ALTER TABLE pet ADD COLUMN other U32 NULL REFERENCES parent;").unwrap();
+        assert!(execute(db, "INSERT INTO pet VALUES ('fido', NULL, 9);").is_err());
+        execute(db, "INSERT INTO pet VALUES ('fido', NULL, 3);").unwrap();
+
+        execute(db, "DROP TABLE pet;").unwrap();
+        execute(db, "DROP TABLE toy;").unwrap();
+        execute(db, "DROP TABLE child;").unwrap();
+        execute(db, "DROP TABLE parent;").unwrap();
+    }
+
+    engine_tests!(test_foreign_keys, |path| {
+        // The constraint is kept in the catalog, renamed with its table
+        let mut db = FileDb::open(path).unwrap();
+        assert!(execute(&mut db, "INSERT INTO vertex VALUES (5, 6);").is_err());
+        execute(&mut db, "INSERT INTO vertex VALUES (5, 4), (6, 5);").unwrap();
+        execute(&mut db, "DELETE FROM vertex WHERE id = 4;").unwrap();
+        assert_eq!(select(&mut db, "SELECT * FROM vertex;"), Vec::<String>::new());
+    });
+
+    fn explain<DB: DatabaseEngine>(db: &mut DB, sql: &str) -> String {
+        match db.execute_statement(parse_statement(sql).unwrap()) {
+            Ok(ExecuteStatementResponse::Explain(plan)) => plan,
+            Ok(_) => panic!("{}: not an EXPLAIN", sql),
+            Err(e) => panic!("{}: {}", sql, e)
+        }
+    }
+
+    fn test_indexes<DB: DatabaseEngine>(db: &mut DB) {
+        execute(db, "CREATE TABLE t (id U32 PRIMARY KEY, name STRING, n U32 NULL);").unwrap();
+        execute(db, "INSERT INTO t VALUES (1, 'a', 1), (2, 'b', NULL), (3, 'c', 1), (4, 'b', 2), (5, 'd', NULL);").unwrap();
+
+        assert!(execute(db, "CREATE INDEX t_x ON t (x);").is_err());
+        assert!(execute(db, "CREATE INDEX t_x ON t (name, name);").is_err());
+        assert!(execute(db, "CREATE INDEX t_x ON nothing (name);").is_err());
+        assert!(execute(db, "CREATE UNIQUE INDEX t_name ON t (name);").is_err());
+        execute(db, "CREATE INDEX t_name ON t (name);").unwrap();
+        assert!(execute(db, "CREATE INDEX t_name ON t (n);").is_err());
+        execute(db, "CREATE UNIQUE INDEX t_name_n ON t (name, n);").unwrap();
+
+        // A unique index made with CREATE INDEX is enforced like a UNIQUE column
+        assert!(execute(db, "INSERT INTO t VALUES (6, 'b', 2);").is_err());
+        execute(db, "INSERT INTO t VALUES (6, 'b', NULL);").unwrap();
+
+        assert!(explain(db, "EXPLAIN SELECT * FROM t WHERE id = 3;").contains("(search `t` :source-id 0 :index `t_pkey`"));
+        assert!(explain(db, "EXPLAIN SELECT * FROM t WHERE name = 'b' AND n = 2;").contains(":index `t_name_n`"));
+        assert!(explain(db, "EXPLAIN SELECT * FROM t WHERE n = 2;").contains("(scan `t`"));
+
+        assert_eq!(select(db, "SELECT * FROM t WHERE id = 3;"), vec!["3, c, 1"]);
+        assert_eq!(select(db, "SELECT id FROM t WHERE 3 < id;"), vec!["4", "5", "6"]);
+        assert_eq!(select(db, "SELECT id FROM t WHERE id >= 2 AND id < 4;"), vec!["2", "3"]);
+        assert_eq!(select(db, "SELECT id FROM t WHERE id > 2 AND id <= 2;"), Vec::<String>::new());
+        assert_eq!(select(db, "SELECT id FROM t WHERE name = 'b';"), vec!["2", "4", "6"]);
+        assert_eq!(select(db, "SELECT id FROM t WHERE name >= 'b' AND name < 'd';"), vec!["2", "4", "6", "3"]);
+        assert_eq!(select(db, "SELECT id FROM t WHERE name = 'b' AND n = 2;"), vec!["4"]);
+        assert_eq!(select(db, "SELECT id FROM t WHERE name = 'b' AND n > 0;"), vec!["4"]);
+        assert_eq!(select(db, "SELECT id FROM t WHERE name = 'b' AND n < 3;"), vec!["4"]);
+        assert_eq!(select(db, "SELECT id FROM t WHERE name = 'b' AND n = NULL;"), Vec::<String>::new());
+
+        // Values that can't be stored in the column
+        assert_eq!(select(db, "SELECT id FROM t WHERE id = 4294967297;"), Vec::<String>::new());
+        assert_eq!(select(db, "SELECT id FROM t WHERE id < 4294967297;"), vec!["1", "2", "3", "4", "5", "6"]);
+        assert_eq!(select(db, "SELECT id FROM t WHERE id > 4294967297;"), Vec::<String>::new());
+        assert_eq!(select(db, "SELECT id FROM t WHERE id >= 1.5 AND id < 3;"), select(db, "SELECT id FROM t WHERE id + 0 >= 1.5 AND id + 0 < 3;"));
+        assert_eq!(select(db, "SELECT id FROM t WHERE name > 1 ORDER BY id;"), select(db, "SELECT id FROM t WHERE name || '' > 1 ORDER BY id;"));
+
+        // Signed integers and floats sort in order too
+        execute(db, "CREATE TABLE m (a I32, b F64 NULL);").unwrap();
+        execute(db, "INSERT INTO m VALUES (-3, 1.5), (2, 0 - 2.5), (0 - 1, NULL), (7, 0.0), (0, 0 - 0.0);").unwrap();
+        execute(db, "CREATE INDEX m_a ON m (a);").unwrap();
+        execute(db, "CREATE INDEX m_b ON m (b);").unwrap();
+        assert!(explain(db, "EXPLAIN SELECT a FROM m WHERE a > 0 - 2;").contains(":index `m_a`"));
+        assert_eq!(select(db, "SELECT a FROM m WHERE a > 0 - 2;"), vec!["-1", "0", "2", "7"]);
+        assert_eq!(select(db, "SELECT a FROM m WHERE a >= 0 - 3 AND a < 1;"), vec!["-3", "-1", "0"]);
+        assert_eq!(select(db, "SELECT a FROM m WHERE b < 1.0;"), vec!["2", "7", "0"]);
+        assert_eq!(select(db, "SELECT a FROM m WHERE b = 0.0;"), vec!["7", "0"]);
+        assert_eq!(select(db, "SELECT a FROM m WHERE b = 0 - 0.0;"), vec!["7", "0"]);
+
+        // The index follows changes to the rows
+        execute(db, "UPDATE t SET name = 'e' WHERE id = 4;").unwrap();
+        execute(db, "DELETE FROM t WHERE id = 2;").unwrap();
+        assert_eq!(select(db, "SELECT id FROM t WHERE name = 'b';"), vec!["6"]);
+        assert_eq!(select(db, "SELECT id FROM t WHERE name = 'e';"), vec!["4"]);
+
+        // Joins search the inner table with values from the outer table
+        execute(db, "CREATE TABLE u (t_id U32, x STRING);").unwrap();
+        execute(db, "INSERT INTO u VALUES (3, 'p'), (9, 'q'), (1, 'r');").unwrap();
+
+        let sql = "SELECT u.x, t.name FROM u, t WHERE t.id = u.t_id;";
+        assert!(explain(db, &format!("EXPLAIN {}", sql)).contains("(search `t` :source-id 1 :index `t_pkey`\n    (min\n      (column-field :source-id 0"));
+        assert_eq!(select(db, sql), vec!["p, c", "r, a"]);
+
+        assert_eq!(select(db, "SELECT u.x, t.name FROM u INNER JOIN t ON t.id = u.t_id;"), vec!["p, c", "r, a"]);
+        assert_eq!(select(db, "SELECT u.x, t.name FROM u LEFT JOIN t ON t.id = u.t_id;"), vec!["p, c", "q, NULL", "r, a"]);
+        assert_eq!(select(db, "SELECT u.x, t.name FROM u LEFT JOIN t ON t.id = u.t_id AND t.name = 'a';"), vec!["p, NULL", "q, NULL", "r, a"]);
+    }
+
+    engine_tests!(test_indexes, |path| {
+        // The index is kept in the catalog
+        let mut db = FileDb::open(path).unwrap();
+        assert!(explain(&mut db, "EXPLAIN SELECT * FROM t WHERE name = 'e';").contains(":index `t_name`"));
+        assert_eq!(select(&mut db, "SELECT id FROM t WHERE name = 'e';"), vec!["4"]);
+        assert!(execute(&mut db, "INSERT INTO t VALUES (7, 'e', 2);").is_err());
+    });
+
+    #[test]
+    fn test_join_order() {
+        let mut db = TempDb::new();
+        execute(&mut db, "CREATE TABLE big (id U32 PRIMARY KEY, x U32);").unwrap();
+        execute(&mut db, "CREATE TABLE small (name STRING, big_id U32);").unwrap();
+
+        let values: Vec<String> = (0..100).map(|i| format!("({}, {})", i, i % 7)).collect();
+        execute(&mut db, &format!("INSERT INTO big VALUES {};", values.join(", "))).unwrap();
+        execute(&mut db, "INSERT INTO small VALUES ('a', 5), ('b', 50), ('c', 500);").unwrap();
+
+        // The smaller table is scanned, and the larger one is searched for each of its rows
+        let sql = "SELECT small.name, big.x FROM big, small WHERE big.id = small.big_id;";
+        assert!(explain(&mut db, &format!("EXPLAIN {}", sql)).contains("\n(scan `small` :source-id 1\n  (search `big` :source-id 0"));
+        assert_eq!(select(&mut db, sql), vec!["a, 5", "b, 1"]);
+
+        // Predicates are checked as soon as the rows they refer to are known
+        let sql = "SELECT big.id FROM big, small WHERE big.x = 3 AND small.name = 'b' AND big.id > small.big_id;";
+        assert!(explain(&mut db, &format!("EXPLAIN {}", sql)).contains(
+            "\n(scan `small` :source-id 1\n  (if \n    (= \n      (column-field :source-id 1 :column-offset 0)\n      b)\n    (search `big`"));
+        assert_eq!(select(&mut db, sql), vec!["52", "59", "66", "73", "80", "87", "94"]);
+
+        let sql = "SELECT small.name, big.x FROM small LEFT JOIN big ON big.id = small.big_id WHERE small.name <> 'a';";
+        assert!(explain(&mut db, &format!("EXPLAIN {}", sql)).contains("\n(scan `small` :source-id 0\n  (if \n    (<> "));
+        assert_eq!(select(&mut db, sql), vec!["b, 1", "c, NULL"]);
+        assert_eq!(select(&mut db, "SELECT small.name FROM small LEFT JOIN big ON big.id = small.big_id WHERE big.x = 1;"), vec!["b"]);
+
+        // A subquery is checked once the rows it refers to are known
+        let sql = "SELECT small.name FROM small, big WHERE big.id = small.big_id AND (SELECT count(*) FROM small s WHERE s.big_id = big.id) = 1;";
+        assert_eq!(select(&mut db, sql), vec!["a", "b"]);
+
+        // A predicate that refers to no table is checked first
+        assert!(explain(&mut db, "EXPLAIN SELECT * FROM small, big WHERE 1 = 0;").contains("\n(if"));
+        assert_eq!(select(&mut db, "SELECT * FROM small, big WHERE 1 = 0;"), Vec::<String>::new());
+
+        // Tables that are equally cheap in any order keep their written order
+        execute(&mut db, "CREATE TABLE other (name STRING, big_id U32);").unwrap();
+        execute(&mut db, "INSERT INTO other VALUES ('x', 1), ('y', 2), ('z', 3);").unwrap();
+        assert!(explain(&mut db, "EXPLAIN SELECT * FROM other, small;").contains("\n(scan `other`"));
+        assert!(explain(&mut db, "EXPLAIN SELECT * FROM small, other;").contains("\n(scan `small`"));
+    }
+
+    #[test]
+    fn test_outer_joins() {
+        let mut db = TempDb::new();
+        execute(&mut db, "CREATE TABLE a (id U32, x STRING);").unwrap();
+        execute(&mut db, "CREATE TABLE b (id U32, y STRING);").unwrap();
+        execute(&mut db, "CREATE TABLE c (id U32, z STRING);").unwrap();
+        execute(&mut db, "INSERT INTO a VALUES (1, 'a1'), (2, 'a2'), (3, 'a3');").unwrap();
+        execute(&mut db, "INSERT INTO b VALUES (2, 'b2'), (3, 'b3'), (4, 'b4');").unwrap();
+        execute(&mut db, "INSERT INTO c VALUES (3, 'c3'), (4, 'c4'), (5, 'c5');").unwrap();
+
+        let sql = "SELECT a.x, b.y FROM a RIGHT JOIN b ON a.id = b.id ORDER BY b.y;";
+        assert!(explain(&mut db, &format!("EXPLAIN {}", sql)).contains("(outer-join :source-id "));
+        assert_eq!(select(&mut db, sql), vec!["a2, b2", "a3, b3", "NULL, b4"]);
+        assert_eq!(select(&mut db, "SELECT * FROM a RIGHT OUTER JOIN b ON a.id = b.id WHERE b.id = 4;"), vec!["NULL, NULL, 4, b4"]);
+
+        let sql = "SELECT a.x, b.y FROM a FULL OUTER JOIN b ON a.id = b.id ORDER BY a.x, b.y;";
+        assert_eq!(select(&mut db, sql), vec!["NULL, b4", "a1, NULL", "a2, b2", "a3, b3"]);
+
+        // Rows that fail the ON clause are padded on both sides
+        let sql = "SELECT a.x, b.y FROM a FULL JOIN b ON a.id = b.id AND a.x <> 'a2' ORDER BY a.x, b.y;";
+        assert_eq!(select(&mut db, sql), vec!["NULL, b2", "NULL, b4", "a1, NULL", "a2, NULL", "a3, b3"]);
+
+        // Every table before a RIGHT JOIN is on its left side
+        let sql = "SELECT a.x, b.y, c.z FROM a JOIN b ON a.id = b.id RIGHT JOIN c ON c.id = b.id ORDER BY c.z;";
+        assert_eq!(select(&mut db, sql), vec!["a3, b3, c3", "NULL, NULL, c4", "NULL, NULL, c5"]);
+
+        let sql = "SELECT a.id, b.id, c.z FROM a FULL JOIN b ON a.id = b.id LEFT JOIN c ON c.id = b.id WHERE c.z <> 'c3';";
+        assert_eq!(select(&mut db, sql), vec!["NULL, 4, c4"]);
+
+        assert_eq!(select(&mut db, "SELECT count(*) FROM a CROSS JOIN b;"), vec!["9"]);
+
+        // USING and NATURAL join on the columns with the same names
+        assert_eq!(select(&mut db, "SELECT a.x, b.y FROM a JOIN b USING (id) ORDER BY a.x;"), vec!["a2, b2", "a3, b3"]);
+        assert_eq!(select(&mut db, "SELECT a.x, b.y FROM a NATURAL JOIN b ORDER BY a.x;"), vec!["a2, b2", "a3, b3"]);
+        assert_eq!(select(&mut db, "SELECT b.y, c.z FROM b NATURAL RIGHT JOIN c ORDER BY c.z;"), vec!["b3, c3", "b4, c4", "NULL, c5"]);
+        assert_eq!(select(&mut db, "SELECT count(*) FROM a NATURAL JOIN (SELECT x AS w FROM a) AS s;"), vec!["9"]);
+        assert!(execute(&mut db, "SELECT * FROM a JOIN b USING (y);").is_err());
+        assert!(execute(&mut db, "SELECT * FROM a JOIN b ON a.id = b.id JOIN c USING (id);").is_err());
+
+        // The joined columns are merged into one, which comes first in *
+        assert_eq!(select(&mut db, "SELECT id FROM a JOIN b USING (id) ORDER BY id;"), vec!["2", "3"]);
+        assert_eq!(select(&mut db, "SELECT * FROM a JOIN b USING (id) ORDER BY id;"), vec!["2, a2, b2", "3, a3, b3"]);
+        assert_eq!(select(&mut db, "SELECT * FROM a NATURAL LEFT JOIN b ORDER BY id;"), vec!["1, a1, NULL", "2, a2, b2", "3, a3, b3"]);
+        assert_eq!(select(&mut db, "SELECT id, a.id, b.id FROM a NATURAL RIGHT JOIN b ORDER BY id;"), vec!["2, 2, 2", "3, 3, 3", "4, NULL, 4"]);
+        assert_eq!(select(&mut db, "SELECT * FROM a FULL JOIN b USING (id) ORDER BY id;"),
+            vec!["1, a1, NULL", "2, a2, b2", "3, a3, b3", "4, NULL, b4"]);
+        assert_eq!(select(&mut db, "SELECT id FROM a FULL JOIN b USING (id) WHERE id > 2 ORDER BY id;"), vec!["3", "4"]);
+        assert_eq!(select(&mut db, "SELECT id, count(*) FROM a FULL JOIN b USING (id) GROUP BY id ORDER BY id DESC LIMIT 2;"), vec!["4, 1", "3, 1"]);
+
+        // A merged column is merged again by the next join
+        assert_eq!(select(&mut db, "SELECT * FROM a FULL JOIN b USING (id) FULL JOIN c USING (id) ORDER BY id;"),
+            vec!["1, a1, NULL, NULL", "2, a2, b2, NULL", "3, a3, b3, c3", "4, NULL, b4, c4", "5, NULL, NULL, c5"]);
+        assert_eq!(select(&mut db, "SELECT id, z FROM a NATURAL JOIN b NATURAL JOIN c;"), vec!["3, c3"]);
+    }
+
+    fn test_hash_join<DB: DatabaseEngine>(db: &mut DB) {
+        execute(db, "CREATE TABLE artist (id U32, name STRING);").unwrap();
+        execute(db, "CREATE TABLE album (title STRING, artist_id I32 NULL);").unwrap();
+        execute(db, "INSERT INTO artist VALUES (1, 'x'), (2, 'y'), (3, 'z');").unwrap();
+        execute(db, "INSERT INTO album VALUES ('a', 1), ('b', 1), ('c', 2), ('d', NULL), ('e', -1);").unwrap();
+
+        // Without an index on either column, the inner table is hash joined
+        let sql = "SELECT album.title, artist.name FROM album, artist WHERE album.artist_id = artist.id ORDER BY album.title;";
+        assert!(explain(db, &format!("EXPLAIN {}", sql)).contains("\n    (hash-join :source-id "));
+        assert_eq!(select(db, sql), vec!["a, x", "b, x", "c, y"]);
+
+        let sql = "SELECT album.title, artist.name FROM album JOIN artist ON artist.id = album.artist_id WHERE artist.name <> 'y' ORDER BY album.title;";
+        assert!(explain(db, &format!("EXPLAIN {}", sql)).contains("(hash-join"));
+        assert_eq!(select(db, sql), vec!["a, x", "b, x"]);
+
+        // Keys on more than one column
+        let sql = "SELECT a.title, b.title FROM album a, album b WHERE a.artist_id = b.artist_id AND a.title = b.title ORDER BY a.title;";
+        assert!(explain(db, &format!("EXPLAIN {}", sql)).contains("(build-keys\n"));
+        assert_eq!(select(db, sql), vec!["a, a", "b, b", "c, c", "e, e"]);
+
+        // -0 has the same key as 0
+        execute(db, "CREATE TABLE x (n F64);").unwrap();
+        execute(db, "CREATE TABLE y (n F64);").unwrap();
+        execute(db, "INSERT INTO x VALUES (0.0), (1.5);").unwrap();
+        execute(db, "INSERT INTO y VALUES (0 - 0.0), (1.5);").unwrap();
+        let sql = "SELECT x.n, y.n FROM x, y WHERE x.n = y.n ORDER BY x.n;";
+        assert!(explain(db, &format!("EXPLAIN {}", sql)).contains("(hash-join"));
+        assert_eq!(select(db, sql), vec!["0, 0", "1.5, 1.5"]);
+
+        // Left joins check the rest of the ON clause, and pad unmatched rows with NULLs
+        let sql = "SELECT artist.name, album.title FROM artist LEFT JOIN album ON album.artist_id = artist.id AND album.title <> 'b' ORDER BY artist.name;";
+        assert!(explain(db, &format!("EXPLAIN {}", sql)).contains("(right-rows-if-none "));
+        assert_eq!(select(db, sql), vec!["x, a", "y, c", "z, NULL"]);
+
+        // NULL is never equal to another value
+        let sql = "SELECT album.title, artist.name FROM album LEFT JOIN artist ON artist.id = album.artist_id ORDER BY album.title;";
+        assert_eq!(select(db, sql), vec!["a, x", "b, x", "c, y", "d, NULL", "e, NULL"]);
+
+        // Columns with different kinds of types are compared with casts, so they aren't hash joined
+        let sql = "SELECT album.title FROM album, artist WHERE album.title = artist.id;";
+        assert!(!explain(db, &format!("EXPLAIN {}", sql)).contains("(hash-join"));
+        assert_eq!(select(db, sql), Vec::<String>::new());
+
+        // A table with an index on the key is searched instead
+        execute(db, "CREATE INDEX artist_id ON artist (id);").unwrap();
+        let sql = "SELECT album.title, artist.name FROM album, artist WHERE album.artist_id = artist.id ORDER BY album.title;";
+        assert!(!explain(db, &format!("EXPLAIN {}", sql)).contains("(hash-join"));
+        assert_eq!(select(db, sql), vec!["a, x", "b, x", "c, y"]);
+    }
+
+    engine_tests!(test_hash_join);
+
+    #[test]
+    fn test_update_and_delete_search_indexes() {
+        let mut db = TempDb::new();
+        execute(&mut db, "CREATE TABLE t (id U32 PRIMARY KEY, n U32);").unwrap();
+        execute(&mut db, "INSERT INTO t VALUES (1, 10), (2, 20), (3, 30);").unwrap();
+
+        // The rows of an UPDATE or DELETE are found like those of a SELECT
+        let where_expr = match parse_statement("DELETE FROM t WHERE id = 2;").unwrap() {
+            ast::Statement::Delete(stmt) => stmt.where_expr,
+            _ => unreachable!()
+        };
+
+        {
+            let table = db.find_table_by_name(&Identifier::new("t").unwrap()).unwrap();
+            let plan = queryplan::compile_table_search(&db, table, where_expr.as_ref(), 0).ok().unwrap();
+            assert!(plan.to_string().contains("(search `t` :source-id 0 :index `t_pkey`"));
+        }
+
+        execute(&mut db, "UPDATE t SET n = n + 1 WHERE id = 2;").unwrap();
+        execute(&mut db, "DELETE FROM t WHERE id >= 3;").unwrap();
+        assert_eq!(select(&mut db, "SELECT * FROM t;"), vec!["1, 10", "2, 21"]);
+    }
+
+    fn test_errors<DB: DatabaseEngine>(db: &mut DB) {
+        let unknown_table = |name: &str| Err(Error::UnknownTable(name.to_string()));
+        let unknown_column = |name: &str| Err(Error::UnknownColumn(name.to_string()));
+
+        execute(db, "CREATE TABLE t (id U32 PRIMARY KEY, name STRING, n U32 NULL);").unwrap();
+        execute(db, "INSERT INTO t VALUES (1, 'a', 10), (2, 'b', NULL);").unwrap();
+
+        assert_eq!(execute(db, "SELECT * FROM u;"), unknown_table("u"));
+        assert_eq!(execute(db, "INSERT INTO u VALUES (1);"), unknown_table("u"));
+        assert_eq!(execute(db, "DROP TABLE u;"), unknown_table("u"));
+        assert_eq!(execute(db, "SELECT x FROM t;"), unknown_column("x"));
+        assert_eq!(execute(db, "UPDATE t SET x = 1;"), unknown_column("x"));
+
+        match execute(db, "INSERT INTO t VALUES (1, 'c', NULL);") {
+            Err(Error::Constraint(..)) => (),
+            e => panic!("expected a constraint error, got {:?}", e)
+        }
+        match execute(db, "INSERT INTO t VALUES (3, NULL, NULL);") {
+            Err(Error::Constraint(..)) => (),
+            e => panic!("expected a constraint error, got {:?}", e)
+        }
+        match execute(db, "INSERT INTO t VALUES ('c', 'c', NULL);") {
+            Err(Error::TypeMismatch(..)) => (),
+            e => panic!("expected a type mismatch, got {:?}", e)
+        }
+        match execute(db, "SELECT sum(name) FROM t;") {
+            Err(Error::TypeMismatch(..)) => (),
+            e => panic!("expected a type mismatch, got {:?}", e)
+        }
+        match execute(db, "SELECT * FROM main.t;") {
+            Err(Error::Invalid(..)) => (),
+            e => panic!("expected an invalid statement, got {:?}", e)
+        }
+        match execute(db, "INSERT INTO main.t VALUES (3, 'c', NULL);") {
+            Err(Error::Invalid(..)) => (),
+            e => panic!("expected an invalid statement, got {:?}", e)
+        }
+        match execute(db, "SELECT id & 1 FROM t;") {
+            Err(Error::Invalid(..)) => (),
+            e => panic!("expected an invalid statement, got {:?}", e)
+        }
+
+        // Integer overflow is done with floats instead of failing
+        assert_eq!(select(db, "SELECT 9223372036854775807 + 1, id - 3, 0 - n FROM t WHERE id = 1;"), vec!["9223372036854776000, -2, -10"]);
+    }
+
+    engine_tests!(test_errors);
+
+    fn test_compiled_statements<DB: DatabaseEngine>(db: &mut DB) {
+        execute(db, "CREATE TABLE t (id U32 PRIMARY KEY, name STRING);").unwrap();
+
+        // A compiled statement is executed with new values for its placeholders each time
+        let insert = db.compile_statement(&parse_statement("INSERT INTO t VALUES (?1, ?2 || '!');").unwrap(), 2).unwrap();
+        for &(id, name) in &[(1, "a"), (2, "b"), (3, "c")] {
+            db.execute_compiled(&insert, &[Variant::UnsignedInteger(id), Variant::StringLiteral(name.to_string())]).unwrap();
+        }
+
+        let select = db.compile_statement(&parse_statement("SELECT name FROM t WHERE id >= ?;").unwrap(), 1).unwrap();
+        let names = |db: &mut DB, select, id| -> Vec<String> {
+            match db.execute_compiled(select, &[Variant::UnsignedInteger(id)]) {
+                Ok(ExecuteStatementResponse::Select { rows, .. }) => rows.map(|row| row.unwrap()[0].to_string()).collect(),
+                Ok(_) => panic!("expected SELECT response"),
+                Err(e) => panic!("{}", e)
+            }
+        };
+        assert_eq!(names(db, &select, 2), vec!["b!", "c!"]);
+        assert_eq!(names(db, &select, 3), vec!["c!"]);
+
+        // Changing the rows doesn't change the schema
+        execute(db, "UPDATE t SET name = 'x' WHERE id = 3;").unwrap();
+        execute(db, "DELETE FROM t WHERE id = 1;").unwrap();
+        assert!(db.is_up_to_date(&insert));
+        assert_eq!(names(db, &select, 3), vec!["x"]);
+
+        // A statement must be compiled again once the schema changes,
+        // even if the change fails or is rolled back
+        execute(db, "BEGIN;").unwrap();
+        execute(db, "CREATE INDEX t_name ON t (name);").unwrap();
+        assert!(!db.is_up_to_date(&select));
+        match db.execute_compiled(&select, &[Variant::UnsignedInteger(1)]) {
+            Err(Error::Invalid(..)) => (),
+            Err(e) => panic!("expected an invalid statement, got {:?}", e),
+            Ok(_) => panic!("expected an invalid statement")
+        }
+
+        let select = db.compile_statement(&parse_statement("SELECT name FROM t WHERE id >= ?;").unwrap(), 1).unwrap();
+        assert_eq!(names(db, &select, 0), vec!["b!", "x"]);
+        execute(db, "ROLLBACK;").unwrap();
+        assert!(!db.is_up_to_date(&select));
+
+        let select = db.compile_statement(&parse_statement("SELECT name FROM t WHERE id >= ?;").unwrap(), 1).unwrap();
+        assert!(execute(db, "CREATE TABLE t (id U32);").is_err());
+        assert!(!db.is_up_to_date(&select));
+
+        // A statement can't be executed against another database
+        let select = db.compile_statement(&parse_statement("SELECT name FROM t WHERE id >= ?;").unwrap(), 1).unwrap();
+        let mut other = TempDb::new();
+        execute(&mut other, "CREATE TABLE t (id U32 PRIMARY KEY, name STRING);").unwrap();
+        assert!(!other.is_up_to_date(&select));
+        assert!(other.execute_compiled(&select, &[Variant::UnsignedInteger(1)]).is_err());
+
+        // Placeholders are numbered up to the parameter count
+        assert!(db.compile_statement(&parse_statement("SELECT name FROM t WHERE id = ?2;").unwrap(), 1).is_err());
+    }
+
+    engine_tests!(test_compiled_statements);
+}
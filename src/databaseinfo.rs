@@ -11,15 +11,23 @@ pub trait DatabaseInfo {
     type ColumnValue: ColumnValueOps + fmt::Display + Clone + Eq + Hash + 'static;
 
     fn find_table_by_name(&self, name: &Identifier) -> Option<&Self::Table>;
+    fn get_table_names(&self) -> Vec<Identifier>;
 }
 
 pub trait TableInfo {
     type Column: ColumnInfo;
+    type Index: IndexInfo;
 
     fn get_name(&self) -> &Identifier;
     fn get_column_count(&self) -> u32;
     fn find_column_by_offset(&self, offset: u32) -> Option<&Self::Column>;
     fn find_column_by_name(&self, name: &Identifier) -> Option<&Self::Column>;
+    fn get_index_count(&self) -> u32;
+    fn find_index_by_offset(&self, offset: u32) -> Option<&Self::Index>;
+
+    fn find_index_by_name(&self, name: &Identifier) -> Option<&Self::Index> {
+        (0..self.get_index_count()).map(|i| self.find_index_by_offset(i).unwrap()).find(|index| index.get_name() == name)
+    }
 
     fn get_column_names(&self) -> Vec<Identifier> {
         (0..self.get_column_count()).map(|i| {
@@ -33,4 +41,36 @@ pub trait ColumnInfo {
     fn get_offset(&self) -> u32;
     fn get_name(&self) -> &Identifier;
     fn get_dbtype(&self) -> &DbType;
+    fn is_nullable(&self) -> bool;
+    fn is_primary_key(&self) -> bool;
+    fn is_unique(&self) -> bool;
+    fn get_foreign_key(&self) -> Option<&ForeignKey>;
+}
+
+pub trait IndexInfo {
+    fn get_name(&self) -> &Identifier;
+    /// The offsets of the indexed columns, in the order they're indexed
+    fn get_column_offsets(&self) -> &[u32];
+    fn is_unique(&self) -> bool;
+}
+
+/// A column's REFERENCES constraint.
+/// Every non-null value of the column must be in the referenced column.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForeignKey {
+    pub table: Identifier,
+    /// A PRIMARY KEY or UNIQUE column of the referenced table
+    pub column: Identifier,
+    pub on_delete: ForeignKeyAction
+}
+
+/// What happens to the referencing rows when a referenced row is deleted
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ForeignKeyAction {
+    /// The referenced row can't be deleted
+    Restrict,
+    /// The referencing rows are deleted too
+    Cascade,
+    /// The referencing columns are set to NULL
+    SetNull
 }
@@ -1,4 +1,5 @@
-use databaseinfo::DatabaseInfo;
+use databaseinfo::{DatabaseInfo, TableInfo};
+use error::Error;
 use std::borrow::Cow;
 use std::cmp::Eq;
 use std::hash::Hash;
@@ -8,6 +9,26 @@ pub trait DatabaseStorage {
 
     fn scan_table<'a>(&'a self, table: &'a <Self::Info as DatabaseInfo>::Table)
     -> Box<Group<ColumnValue=<Self::Info as DatabaseInfo>::ColumnValue> + 'a>;
+
+    /// Iterates through every row of the table.
+    /// A row that can't be read is an error, and ends the iteration.
+    ///
+    /// Each row has a value for every column of the table, followed by the
+    /// row's rowid as an unsigned integer.
+    fn scan_rows<'a>(&'a self, table: &'a <Self::Info as DatabaseInfo>::Table)
+    -> Box<Iterator<Item=Result<Cow<'a, [<Self::Info as DatabaseInfo>::ColumnValue]>, Error>> + 'a>;
+
+    /// Iterates through the rows of the table that are in a range of the index.
+    ///
+    /// `min` and `max` are values for the first columns of the index, and are inclusive.
+    /// A bound that can't be found in the index is ignored, so rows outside of
+    /// the range may also be yielded.
+    /// The rows are followed by their rowids, as with `scan_rows`.
+    fn search_index<'a>(&'a self, table: &'a <Self::Info as DatabaseInfo>::Table,
+        index: &'a <<Self::Info as DatabaseInfo>::Table as TableInfo>::Index,
+        min: Option<&[<Self::Info as DatabaseInfo>::ColumnValue]>,
+        max: Option<&[<Self::Info as DatabaseInfo>::ColumnValue]>)
+    -> Box<Iterator<Item=Result<Cow<'a, [<Self::Info as DatabaseInfo>::ColumnValue]>, Error>> + 'a>;
 }
 
 pub trait Group {
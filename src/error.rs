@@ -0,0 +1,106 @@
+use btree::BTreeError;
+use pager::PagerError;
+use queryplan::QueryPlanCompileError;
+use rowkey::UpdateError;
+use sqlsyntax::lexer::{LexerError, Location};
+use sqlsyntax::parser::RuleError;
+
+use std::error;
+use std::fmt;
+
+/// Everything that can go wrong when executing SQL, by its cause.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The SQL text can't be lexed or parsed.
+    Syntax {
+        message: String,
+        location: Option<Location>
+    },
+    /// There's no table with the name.
+    UnknownTable(String),
+    /// There's no column with the name, in the table or in the scope of the query.
+    UnknownColumn(String),
+    /// The statement would violate a PRIMARY KEY, UNIQUE, FOREIGN KEY or NULL constraint.
+    Constraint(String),
+    /// A value doesn't have the type that's needed, and can't be converted to it.
+    TypeMismatch(String),
+    /// The database file can't be read or written, or its contents are corrupt.
+    Io(String),
+    /// The statement can't be executed for any other reason.
+    Invalid(String)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &Error::Syntax { ref message, location: Some(location) } => write!(f, "syntax error at {}: {}", location, message),
+            &Error::Syntax { ref message, location: None } => write!(f, "syntax error: {}", message),
+            &Error::UnknownTable(ref name) => write!(f, "Could not find table named {}", name),
+            &Error::UnknownColumn(ref name) => write!(f, "Could not find column named {}", name),
+            &Error::Io(ref message) => write!(f, "I/O error: {}", message),
+            &Error::Constraint(ref message) |
+            &Error::TypeMismatch(ref message) |
+            &Error::Invalid(ref message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            &Error::Syntax { .. } => "syntax error",
+            &Error::UnknownTable(..) => "unknown table",
+            &Error::UnknownColumn(..) => "unknown column",
+            &Error::Constraint(..) => "constraint violated",
+            &Error::TypeMismatch(..) => "type mismatch",
+            &Error::Io(..) => "I/O error",
+            &Error::Invalid(..) => "invalid statement"
+        }
+    }
+}
+
+impl From<LexerError> for Error {
+    fn from(e: LexerError) -> Error {
+        Error::Syntax {
+            message: e.to_string(),
+            location: Some(e.location())
+        }
+    }
+}
+
+impl From<RuleError> for Error {
+    fn from(e: RuleError) -> Error {
+        Error::Syntax {
+            message: e.to_string(),
+            location: Some(e.location())
+        }
+    }
+}
+
+impl From<QueryPlanCompileError> for Error {
+    fn from(e: QueryPlanCompileError) -> Error {
+        match e {
+            QueryPlanCompileError::TableDoesNotExist(name) => Error::UnknownTable(name.to_string()),
+            QueryPlanCompileError::ColumnDoesNotExist(name) => Error::UnknownColumn(name.to_string()),
+            e => Error::Invalid(e.to_string())
+        }
+    }
+}
+
+impl From<UpdateError> for Error {
+    fn from(e: UpdateError) -> Error {
+        Error::TypeMismatch(e.to_string())
+    }
+}
+
+impl From<PagerError> for Error {
+    fn from(e: PagerError) -> Error {
+        Error::Io(e.to_string())
+    }
+}
+
+impl From<BTreeError> for Error {
+    fn from(e: BTreeError) -> Error {
+        Error::Io(e.to_string())
+    }
+}
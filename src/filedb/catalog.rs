@@ -0,0 +1,292 @@
+//! The catalog is a B+Tree that describes every table and index in the
+//! database file. Each key of the catalog describes one table:
+//!
+//! | Size and type | Name                                          |
+//! |---------------|-----------------------------------------------|
+//! | 1, u8         | Kind: 1 for tables                            |
+//! | Variable      | Table name, null-terminated                   |
+//! | 8, u64-be     | Root page id of the table's B+Tree            |
+//! | 8, u64-be     | Next rowid                                    |
+//! | 8, u64-be     | Row count                                     |
+//! | For each column:                                              |
+//! | Variable      | Column name, null-terminated                  |
+//! | 1, u8         | Type tag                                      |
+//! | 8, u64-be     | Type parameter; the length of fixed types     |
+//! | 1, u8         | Flags: 0000FUPN. F: foreign key, U: unique, P: primary key, N: nullable |
+//! | If F is set:                                                  |
+//! | Variable      | Referenced table name, null-terminated        |
+//! | Variable      | Referenced column name, null-terminated       |
+//! | 1, u8         | ON DELETE action: 0 RESTRICT, 1 CASCADE, 2 SET NULL |
+//!
+//! Or one index:
+//!
+//! | Size and type | Name                                          |
+//! |---------------|-----------------------------------------------|
+//! | 1, u8         | Kind: 2 for indexes                           |
+//! | Variable      | Table name, null-terminated                   |
+//! | Variable      | Index name, null-terminated                   |
+//! | 8, u64-be     | Root page id of the index's B+Tree            |
+//! | 1, u8         | Flags: 0000000U. U: unique                    |
+//! | For each indexed column:                                      |
+//! | 8, u64-be     | Column offset                                 |
+//!
+//! After the kind, keys begin with the table name, so the catalog is sorted
+//! by table name. Every table comes before every index.
+//!
+//! The next rowid is stored rather than found from the table's last row, so
+//! that the rowids of deleted rows are never handed out again. The row count
+//! is stored so that planning a query doesn't need to read every row. A
+//! table's entry is replaced whenever either of them changes.
+
+use btree::BTree;
+use byteutils;
+use databaseinfo::{ForeignKey, ForeignKeyAction};
+use error::Error;
+use identifier::Identifier;
+use pager::Pager;
+use types::DbType;
+
+use super::table::{Column, Index, Table};
+
+const KIND_TABLE: u8 = 1;
+const KIND_INDEX: u8 = 2;
+
+const TYPE_NULL: u8 = 0;
+const TYPE_BYTE_DYNAMIC: u8 = 1;
+const TYPE_BYTE_FIXED: u8 = 2;
+const TYPE_UNSIGNED_INTEGER: u8 = 3;
+const TYPE_SIGNED_INTEGER: u8 = 4;
+const TYPE_F64: u8 = 5;
+const TYPE_STRING: u8 = 6;
+
+const FLAG_NULLABLE: u8 = 0x01;
+const FLAG_PRIMARY_KEY: u8 = 0x02;
+const FLAG_UNIQUE: u8 = 0x04;
+const FLAG_FOREIGN_KEY: u8 = 0x08;
+
+const ACTION_RESTRICT: u8 = 0;
+const ACTION_CASCADE: u8 = 1;
+const ACTION_SET_NULL: u8 = 2;
+
+pub enum Entry {
+    Table(Table),
+    Index { table_name: Identifier, index: Index }
+}
+
+pub fn encode_table(table: &Table) -> Vec<u8> {
+    let mut key = vec![KIND_TABLE];
+    push_name(&mut key, &table.name);
+    push_u64(&mut key, table.btree.root_page_id());
+    push_u64(&mut key, table.next_rowid);
+    push_u64(&mut key, table.row_count);
+
+    for column in &table.columns {
+        let (tag, parameter) = match column.dbtype {
+            DbType::Null => (TYPE_NULL, 0),
+            DbType::ByteDynamic => (TYPE_BYTE_DYNAMIC, 0),
+            DbType::ByteFixed(n) => (TYPE_BYTE_FIXED, n),
+            DbType::Integer { signed: false, bytes } => (TYPE_UNSIGNED_INTEGER, bytes as u64),
+            DbType::Integer { signed: true, bytes } => (TYPE_SIGNED_INTEGER, bytes as u64),
+            DbType::F64 => (TYPE_F64, 0),
+            DbType::String => (TYPE_STRING, 0)
+        };
+
+        push_name(&mut key, &column.name);
+        key.push(tag);
+        push_u64(&mut key, parameter);
+        let mut flags = 0;
+        if column.nullable { flags |= FLAG_NULLABLE; }
+        if column.primary_key { flags |= FLAG_PRIMARY_KEY; }
+        if column.unique { flags |= FLAG_UNIQUE; }
+        if column.foreign_key.is_some() { flags |= FLAG_FOREIGN_KEY; }
+        key.push(flags);
+
+        if let Some(ref foreign_key) = column.foreign_key {
+            push_name(&mut key, &foreign_key.table);
+            push_name(&mut key, &foreign_key.column);
+            key.push(match foreign_key.on_delete {
+                ForeignKeyAction::Restrict => ACTION_RESTRICT,
+                ForeignKeyAction::Cascade => ACTION_CASCADE,
+                ForeignKeyAction::SetNull => ACTION_SET_NULL
+            });
+        }
+    }
+
+    key
+}
+
+pub fn encode_index(table: &Table, index: &Index) -> Vec<u8> {
+    let mut key = vec![KIND_INDEX];
+    push_name(&mut key, &table.name);
+    push_name(&mut key, &index.name);
+    push_u64(&mut key, index.btree.root_page_id());
+    key.push(if index.unique { FLAG_UNIQUE } else { 0 });
+
+    for &offset in &index.columns {
+        push_u64(&mut key, offset as u64);
+    }
+
+    key
+}
+
+/// Decodes a catalog key, and opens the B+Tree of the table or index.
+pub fn decode<P: Pager>(pager: &P, key: &[u8]) -> Result<Entry, Error> {
+    let corrupt = || Error::Io(format!("corrupt catalog entry: {:?}", key));
+
+    let mut reader = Reader { buf: key, offset: 0 };
+
+    match try!(reader.byte().ok_or_else(&corrupt)) {
+        KIND_TABLE => Ok(Entry::Table(try!(decode_table(pager, reader, &corrupt)))),
+        KIND_INDEX => {
+            let table_name = try!(reader.name().ok_or_else(&corrupt));
+            let name = try!(reader.name().ok_or_else(&corrupt));
+            let root_page_id = try!(reader.u64().ok_or_else(&corrupt));
+            let flags = try!(reader.byte().ok_or_else(&corrupt));
+
+            let mut columns = Vec::new();
+
+            while !reader.is_empty() {
+                columns.push(try!(reader.u64().ok_or_else(&corrupt)) as u32);
+            }
+
+            let btree = try!(BTree::open(pager, root_page_id));
+
+            Ok(Entry::Index {
+                table_name: table_name,
+                index: Index {
+                    name: name,
+                    columns: columns,
+                    unique: flags & FLAG_UNIQUE != 0,
+                    btree: btree
+                }
+            })
+        },
+        _ => Err(corrupt())
+    }
+}
+
+/// Decodes the rest of a table's key, and opens the table's B+Tree.
+fn decode_table<P, F>(pager: &P, mut reader: Reader, corrupt: &F) -> Result<Table, Error>
+where P: Pager, F: Fn() -> Error
+{
+    let name = try!(reader.name().ok_or_else(corrupt));
+    let root_page_id = try!(reader.u64().ok_or_else(corrupt));
+    let next_rowid = try!(reader.u64().ok_or_else(corrupt));
+    let row_count = try!(reader.u64().ok_or_else(corrupt));
+
+    let mut columns = Vec::new();
+
+    while !reader.is_empty() {
+        let column_name = try!(reader.name().ok_or_else(corrupt));
+        let tag = try!(reader.byte().ok_or_else(corrupt));
+        let parameter = try!(reader.u64().ok_or_else(corrupt));
+        let flags = try!(reader.byte().ok_or_else(corrupt));
+
+        let dbtype = match tag {
+            TYPE_NULL => DbType::Null,
+            TYPE_BYTE_DYNAMIC => DbType::ByteDynamic,
+            TYPE_BYTE_FIXED => DbType::ByteFixed(parameter),
+            TYPE_UNSIGNED_INTEGER => DbType::Integer { signed: false, bytes: parameter as u8 },
+            TYPE_SIGNED_INTEGER => DbType::Integer { signed: true, bytes: parameter as u8 },
+            TYPE_F64 => DbType::F64,
+            TYPE_STRING => DbType::String,
+            _ => return Err(corrupt())
+        };
+
+        let foreign_key = if flags & FLAG_FOREIGN_KEY != 0 {
+            let table = try!(reader.name().ok_or_else(corrupt));
+            let column = try!(reader.name().ok_or_else(corrupt));
+            let on_delete = match try!(reader.byte().ok_or_else(corrupt)) {
+                ACTION_RESTRICT => ForeignKeyAction::Restrict,
+                ACTION_CASCADE => ForeignKeyAction::Cascade,
+                ACTION_SET_NULL => ForeignKeyAction::SetNull,
+                _ => return Err(corrupt())
+            };
+
+            Some(ForeignKey {
+                table: table,
+                column: column,
+                on_delete: on_delete
+            })
+        } else {
+            None
+        };
+
+        columns.push(Column {
+            offset: columns.len() as u32,
+            name: column_name,
+            dbtype: dbtype,
+            nullable: flags & FLAG_NULLABLE != 0,
+            primary_key: flags & FLAG_PRIMARY_KEY != 0,
+            unique: flags & FLAG_UNIQUE != 0,
+            foreign_key: foreign_key
+        });
+    }
+
+    let btree = try!(BTree::open(pager, root_page_id));
+
+    Ok(Table {
+        name: name,
+        columns: columns,
+        btree: btree,
+        next_rowid: next_rowid,
+        row_count: row_count,
+        // Filled in as the catalog's index entries are read
+        indexes: Vec::new()
+    })
+}
+
+fn push_name(key: &mut Vec<u8>, name: &Identifier) {
+    key.extend_from_slice(name.as_bytes());
+    key.push(0);
+}
+
+fn push_u64(key: &mut Vec<u8>, value: u64) {
+    let mut buf = [0; 8];
+    byteutils::write_udbinteger(value, &mut buf);
+    key.extend_from_slice(&buf);
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    offset: usize
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.offset >= self.buf.len()
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        if self.offset < self.buf.len() {
+            self.offset += 1;
+            Some(self.buf[self.offset - 1])
+        } else {
+            None
+        }
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        if self.offset + 8 <= self.buf.len() {
+            self.offset += 8;
+            Some(byteutils::read_udbinteger(&self.buf[self.offset - 8..self.offset]))
+        } else {
+            None
+        }
+    }
+
+    fn name(&mut self) -> Option<Identifier> {
+        let rest = &self.buf[self.offset..];
+        let length = match rest.iter().position(|&b| b == 0) {
+            Some(length) => length,
+            None => return None
+        };
+
+        self.offset += length + 1;
+
+        match ::std::str::from_utf8(&rest[0..length]) {
+            Ok(s) => Identifier::new(s),
+            Err(_) => None
+        }
+    }
+}
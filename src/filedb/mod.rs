@@ -0,0 +1,878 @@
+//! A database that is stored in a single file.
+//!
+//! Every table is a B+Tree of rows, keyed by rowid, and every index is a
+//! B+Tree of index keys.
+//! The tables and indexes are described by the catalog, which is a B+Tree that
+//! is always rooted at page 1.
+//!
+//! Outside of a transaction, changes are flushed to the file after every
+//! statement. Within a transaction, changes are held in the pager's cache
+//! until COMMIT.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use btree::{BTree, Bound};
+use databaseengine::{self, ColumnDefinition, CompiledStatement, DatabaseEngine, DatabaseStorageMut, ExecuteStatementResult, IndexDefinition};
+use databaseinfo::DatabaseInfo;
+use databasestorage::{Group, DatabaseStorage};
+use error::Error;
+use identifier::Identifier;
+use indexkey;
+use pager::{DiskPager, Pager};
+use rowkey;
+use types::Variant;
+use sqlsyntax::ast;
+
+mod catalog;
+mod table;
+use self::table::{Index, Table};
+
+const PAGE_SIZE: usize = 4096;
+const CELL_LENGTH: usize = 256;
+
+const CATALOG_ROOT_PAGE_ID: u64 = 1;
+
+pub struct FileDb {
+    pager: DiskPager,
+    catalog: BTree,
+    tables: Vec<Table>,
+
+    in_transaction: bool,
+    /// The tables as of the start of the current statement
+    savepoint_tables: Option<Vec<Table>>,
+    schema_version: u64
+}
+
+impl DatabaseInfo for FileDb {
+    type Table = Table;
+    type ColumnValue = Variant;
+
+    fn find_table_by_name(&self, name: &Identifier) -> Option<&Table> {
+        self.tables.iter().find(|t| &t.name == name)
+    }
+
+    fn get_table_names(&self) -> Vec<Identifier> {
+        self.tables.iter().map(|t| t.name.clone()).collect()
+    }
+}
+
+struct ScanGroup<'a> {
+    pager: &'a DiskPager,
+    table: &'a Table
+}
+
+impl<'a> Group for ScanGroup<'a> {
+    type ColumnValue = Variant;
+
+    fn get_any_row<'b>(&'b self) -> Option<Cow<'b, [Variant]>> {
+        self.iter().nth(0)
+    }
+
+    // Scan groups are only used to count the rows when planning queries.
+    // The count is kept in the catalog, so no rows are read.
+
+    fn count(&self) -> u64 {
+        self.table.row_count
+    }
+
+    // Queries read their rows with `scan_rows`, which reports read errors,
+    // so this stops at the first one.
+    fn iter<'b>(&'b self) -> Box<Iterator<Item=Cow<'b, [Variant]>> + 'b> {
+        Box::new(table_rows(self.pager, self.table).take_while(|row| row.is_ok()).filter_map(|row| row.ok()))
+    }
+}
+
+fn table_rows<'a>(pager: &'a DiskPager, table: &'a Table) -> Box<Iterator<Item=Result<Cow<'a, [Variant]>, Error>> + 'a> {
+    let columns: &'a [self::table::Column] = &table.columns;
+
+    Box::new(table.btree.iter(pager).map(move |key| {
+        use std::borrow::IntoCow;
+
+        let raw_key = try!(key.map_err(|e| Error::Io(format!("could not read table {}: {}", table.name, e))));
+
+        trace!("KEY: {:?}", raw_key);
+
+        Ok(try!(rowkey::decode_row_with_rowid(columns, &raw_key)).into_cow())
+    }))
+}
+
+impl DatabaseStorage for FileDb {
+    type Info = FileDb;
+
+    fn scan_table<'a>(&'a self, table: &'a Table)
+    -> Box<Group<ColumnValue=Variant> + 'a>
+    {
+        Box::new(ScanGroup {
+            pager: &self.pager,
+            table: table
+        })
+    }
+
+    fn scan_rows<'a>(&'a self, table: &'a Table)
+    -> Box<Iterator<Item=Result<Cow<'a, [Variant]>, Error>> + 'a>
+    {
+        table_rows(&self.pager, table)
+    }
+
+    fn search_index<'a>(&'a self, table: &'a Table, index: &'a Index, min: Option<&[Variant]>, max: Option<&[Variant]>)
+    -> Box<Iterator<Item=Result<Cow<'a, [Variant]>, Error>> + 'a>
+    {
+        let pager = &self.pager;
+
+        let min = min.and_then(|values| indexkey::encode_values(&table.columns, &index.columns, values));
+        let max = max.and_then(|values| indexkey::encode_values(&table.columns, &index.columns, values));
+
+        let min = match min {
+            Some(min) => Bound::Included(min),
+            None => Bound::Unbounded
+        };
+
+        let keys = index.btree.range(pager, min, Bound::Unbounded).map(move |key| {
+            key.map_err(|e| Error::Io(format!("could not read index {}: {}", index.name, e)))
+        });
+
+        // A read error isn't past the max, so it's yielded
+        Box::new(keys.take_while(move |key| {
+            match (key, &max) {
+                (&Ok(ref key), &Some(ref max)) => indexkey::is_at_or_before(key, max),
+                _ => true
+            }
+        }).map(move |key| {
+            use std::borrow::IntoCow;
+
+            let row_key = try!(find_row_key(pager, table, indexkey::decode_rowid(&try!(key))));
+            Ok(try!(rowkey::decode_row_with_rowid(&table.columns, &row_key)).into_cow())
+        }))
+    }
+}
+
+impl FileDb {
+    /// Opens the database file at `path`.
+    /// If the file doesn't exist, a new database file is created.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileDb, Error> {
+        let path = path.as_ref();
+
+        if path.exists() {
+            FileDb::open_existing(path)
+        } else {
+            FileDb::create(path)
+        }
+    }
+
+    fn create(path: &Path) -> Result<FileDb, Error> {
+        let mut pager = try!(DiskPager::create(path, PAGE_SIZE));
+
+        let catalog = try!(BTree::create(&mut pager, CELL_LENGTH));
+        assert_eq!(catalog.root_page_id(), CATALOG_ROOT_PAGE_ID);
+
+        try!(pager.flush());
+
+        Ok(FileDb {
+            pager: pager,
+            catalog: catalog,
+            tables: Vec::new(),
+            in_transaction: false,
+            savepoint_tables: None,
+            schema_version: databaseengine::new_schema_version()
+        })
+    }
+
+    fn open_existing(path: &Path) -> Result<FileDb, Error> {
+        let pager = try!(DiskPager::open(path));
+
+        let catalog = try!(BTree::open(&pager, CATALOG_ROOT_PAGE_ID));
+
+        let tables = try!(FileDb::load_tables(&pager, catalog));
+
+        Ok(FileDb {
+            pager: pager,
+            catalog: catalog,
+            tables: tables,
+            in_transaction: false,
+            savepoint_tables: None,
+            schema_version: databaseengine::new_schema_version()
+        })
+    }
+
+    fn load_tables(pager: &DiskPager, catalog: BTree) -> Result<Vec<Table>, Error> {
+        let mut tables = Vec::new();
+
+        for key in catalog.iter(pager) {
+            let key = try!(key);
+
+            match try!(catalog::decode(pager, &key)) {
+                catalog::Entry::Table(table) => {
+                    debug!("loaded table: {:?}", table);
+                    tables.push(table);
+                },
+                catalog::Entry::Index { table_name, index } => {
+                    // Tables come before indexes in the catalog
+                    let table = try!(tables.iter_mut().find(|t| t.name == table_name).ok_or(Error::Io(format!("index {} has no table", index.name))));
+
+                    debug!("loaded index: {:?}", index);
+                    table.indexes.push(index);
+                }
+            }
+        }
+
+        Ok(tables)
+    }
+}
+
+impl DatabaseStorageMut for FileDb {
+    fn create_table(&mut self, name: Identifier, columns: Vec<ColumnDefinition>) -> Result<(), Error> {
+        if self.tables.iter().any(|t| t.name == name) {
+            return Err(Error::Invalid(format!("Table {} already exists", name)));
+        }
+
+        let columns = columns.into_iter().enumerate().map(|(i, column)| {
+            table::Column {
+                offset: i as u32,
+                name: column.name,
+                dbtype: column.dbtype,
+                nullable: column.nullable,
+                primary_key: column.primary_key,
+                unique: column.unique,
+                foreign_key: column.foreign_key
+            }
+        }).collect();
+
+        let btree = try!(BTree::create(&mut self.pager, CELL_LENGTH));
+
+        let table = Table {
+            name: name,
+            columns: columns,
+            btree: btree,
+            next_rowid: 1,
+            row_count: 0,
+            indexes: Vec::new()
+        };
+
+        try!(self.catalog.insert(&mut self.pager, &catalog::encode_table(&table)));
+
+        debug!("adding table: {:?}", table);
+        self.tables.push(table);
+
+        Ok(())
+    }
+
+    fn create_index(&mut self, table_name: &Identifier, index: IndexDefinition) -> Result<(), Error> {
+        if self.tables.iter().any(|t| t.indexes.iter().any(|i| i.name == index.name)) {
+            return Err(Error::Invalid(format!("Index {} already exists", index.name)));
+        }
+
+        let table = match self.tables.iter_mut().find(|t| &t.name == table_name) {
+            Some(s) => s,
+            None => return Err(Error::UnknownTable(table_name.to_string()))
+        };
+
+        let btree = try!(BTree::create(&mut self.pager, CELL_LENGTH));
+
+        let index = Index {
+            name: index.name,
+            columns: index.columns,
+            unique: index.unique,
+            btree: btree
+        };
+
+        try!(fill_index(&mut self.pager, table, &index));
+        try!(self.catalog.insert(&mut self.pager, &catalog::encode_index(table, &index)));
+
+        debug!("adding index: {:?}", index);
+        table.indexes.push(index);
+
+        Ok(())
+    }
+
+    fn drop_table(&mut self, name: &Identifier) -> Result<(), Error> {
+        let index = try!(self.tables.iter().position(|t| &t.name == name).ok_or(Error::UnknownTable(name.to_string())));
+        let table = self.tables.remove(index);
+
+        debug!("dropping table: {:?}", table);
+
+        try!(remove_from_catalog(&mut self.pager, &self.catalog, &table));
+
+        for index in &table.indexes {
+            try!(index.btree.destroy(&mut self.pager));
+        }
+
+        table.btree.destroy(&mut self.pager).map_err(Error::from)
+    }
+
+    fn rename_table(&mut self, name: &Identifier, new_name: Identifier) -> Result<(), Error> {
+        if self.tables.iter().any(|t| t.name == new_name) {
+            return Err(Error::Invalid(format!("Table {} already exists", new_name)));
+        }
+
+        if !self.tables.iter().any(|t| &t.name == name) {
+            return Err(Error::UnknownTable(name.to_string()));
+        }
+
+        for table in self.tables.iter_mut() {
+            let references = table.columns.iter().any(|c| {
+                c.foreign_key.as_ref().map_or(false, |foreign_key| &foreign_key.table == name)
+            });
+
+            if &table.name == name {
+                // The catalog is keyed by table name, so the table's entries are replaced
+                try!(remove_from_catalog(&mut self.pager, &self.catalog, table));
+                table.name = new_name.clone();
+                rename_foreign_keys(table, name, &new_name);
+                try!(add_to_catalog(&mut self.pager, &self.catalog, table));
+            } else if references {
+                let old_entry = catalog::encode_table(table);
+                rename_foreign_keys(table, name, &new_name);
+                try!(replace_in_catalog(&mut self.pager, &self.catalog, &old_entry, table));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn replace_columns(&mut self, table_name: &Identifier, columns: Vec<ColumnDefinition>, rows: Vec<(u64, Vec<(Box<[u8]>, Option<bool>)>)>) -> Result<(), Error> {
+        let table = match self.tables.iter_mut().find(|t| &t.name == table_name) {
+            Some(s) => s,
+            None => return Err(Error::UnknownTable(table_name.to_string()))
+        };
+
+        let columns: Vec<table::Column> = columns.into_iter().enumerate().map(|(i, column)| {
+            table::Column {
+                offset: i as u32,
+                name: column.name,
+                dbtype: column.dbtype,
+                nullable: column.nullable,
+                primary_key: column.primary_key,
+                unique: column.unique,
+                foreign_key: column.foreign_key
+            }
+        }).collect();
+
+        // The rows are written to a new B+Tree, which replaces the old one.
+        let btree = try!(BTree::create(&mut self.pager, CELL_LENGTH));
+
+        for (rowid, column_data) in rows {
+            let key = try!(rowkey::encode_row(rowid, &columns, column_data.into_iter()));
+            try!(btree.insert(&mut self.pager, &key));
+        }
+
+        try!(remove_from_catalog(&mut self.pager, &self.catalog, table));
+
+        let old_btree = table.btree;
+        let old_columns = ::std::mem::replace(&mut table.columns, columns);
+        let old_indexes = ::std::mem::replace(&mut table.indexes, Vec::new());
+        table.btree = btree;
+
+        try!(old_btree.destroy(&mut self.pager));
+
+        // Indexes follow their columns by name, and are rebuilt from the new rows
+        for old_index in old_indexes {
+            try!(old_index.btree.destroy(&mut self.pager));
+
+            let columns: Option<Vec<u32>> = old_index.columns.iter().map(|&offset| {
+                let name = &old_columns[offset as usize].name;
+                table.columns.iter().position(|c| &c.name == name).map(|offset| offset as u32)
+            }).collect();
+
+            if let Some(columns) = columns {
+                let index = Index {
+                    name: old_index.name,
+                    columns: columns,
+                    unique: old_index.unique,
+                    btree: try!(BTree::create(&mut self.pager, CELL_LENGTH))
+                };
+
+                try!(fill_index(&mut self.pager, table, &index));
+                table.indexes.push(index);
+            } else {
+                debug!("dropping index: {:?}", old_index.name);
+            }
+        }
+
+        try!(add_to_catalog(&mut self.pager, &self.catalog, table));
+
+        debug!("altered table: {:?}", table);
+        Ok(())
+    }
+
+    fn insert_row(&mut self, table_name: &Identifier, column_data: Vec<(Box<[u8]>, Option<bool>)>) -> Result<(), Error> {
+        let table = match self.tables.iter_mut().find(|t| &t.name == table_name) {
+            Some(s) => s,
+            None => return Err(Error::UnknownTable(table_name.to_string()))
+        };
+
+        let key = try!(rowkey::encode_row(table.next_rowid, &table.columns, column_data.into_iter()));
+        try!(check_unique(&self.pager, table, &key));
+
+        trace!("inserting row {} into {}", table.next_rowid, table.name);
+
+        let old_entry = catalog::encode_table(table);
+
+        try!(insert_key(&mut self.pager, table, &key));
+        table.next_rowid += 1;
+        table.row_count += 1;
+
+        replace_in_catalog(&mut self.pager, &self.catalog, &old_entry, table)
+    }
+
+    fn rows_with_rowids(&self, table_name: &Identifier) -> Result<Vec<(u64, Vec<Variant>)>, Error> {
+        let table = try!(self.find_table_by_name(table_name).ok_or(Error::UnknownTable(table_name.to_string())));
+
+        let mut rows = Vec::new();
+
+        for key in table.btree.iter(&self.pager) {
+            let key = try!(key);
+            rows.push((rowkey::decode_rowid(&key), try!(rowkey::decode_row(&table.columns, &key))));
+        }
+
+        Ok(rows)
+    }
+
+    fn update_row(&mut self, table_name: &Identifier, rowid: u64, column_data: Vec<(Box<[u8]>, Option<bool>)>) -> Result<(), Error> {
+        let table = match self.tables.iter().find(|t| &t.name == table_name) {
+            Some(s) => s,
+            None => return Err(Error::UnknownTable(table_name.to_string()))
+        };
+
+        let old_key = try!(find_row_key(&self.pager, table, rowid));
+        let new_key = try!(rowkey::encode_row(rowid, &table.columns, column_data.into_iter()));
+        try!(check_unique(&self.pager, table, &new_key));
+
+        trace!("replacing row {} in {}", rowid, table.name);
+
+        try!(remove_key(&mut self.pager, table, &old_key));
+        insert_key(&mut self.pager, table, &new_key)
+    }
+
+    fn delete_row(&mut self, table_name: &Identifier, rowid: u64) -> Result<(), Error> {
+        let table = match self.tables.iter_mut().find(|t| &t.name == table_name) {
+            Some(s) => s,
+            None => return Err(Error::UnknownTable(table_name.to_string()))
+        };
+
+        let key = try!(find_row_key(&self.pager, table, rowid));
+
+        trace!("deleting row {} from {}", rowid, table.name);
+
+        let old_entry = catalog::encode_table(table);
+
+        try!(remove_key(&mut self.pager, table, &key));
+        table.row_count -= 1;
+
+        replace_in_catalog(&mut self.pager, &self.catalog, &old_entry, table)
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), Error> {
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Error> {
+        self.in_transaction = false;
+        self.pager.flush().map_err(Error::from)
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Error> {
+        self.in_transaction = false;
+        self.pager.discard();
+
+        // CREATE TABLE may have been rolled back, and rowids may have been used.
+        self.tables = try!(FileDb::load_tables(&self.pager, self.catalog));
+        Ok(())
+    }
+
+    fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    fn begin_statement(&mut self) -> Result<(), Error> {
+        self.pager.savepoint();
+        self.savepoint_tables = Some(self.tables.clone());
+        Ok(())
+    }
+
+    fn end_statement(&mut self, success: bool) -> Result<(), Error> {
+        let savepoint_tables = self.savepoint_tables.take();
+
+        if success {
+            self.pager.release_savepoint();
+        } else {
+            self.pager.rollback_to_savepoint();
+
+            if let Some(tables) = savepoint_tables {
+                self.tables = tables;
+            }
+        }
+
+        if self.in_transaction {
+            Ok(())
+        } else {
+            self.pager.flush().map_err(Error::from)
+        }
+    }
+
+    fn schema_version(&self) -> u64 {
+        self.schema_version
+    }
+
+    fn set_schema_version(&mut self, version: u64) {
+        self.schema_version = version;
+    }
+}
+
+/// Finds the key of the row with the rowid.
+fn find_row_key(pager: &DiskPager, table: &Table, rowid: u64) -> Result<Vec<u8>, Error> {
+    let min = Bound::Included(rowkey::rowid_prefix(rowid));
+    let max = Bound::Excluded(rowkey::rowid_prefix(rowid + 1));
+
+    match table.btree.range(pager, min, max).next() {
+        Some(key) => key.map_err(Error::from),
+        None => Err(Error::Invalid(format!("Could not find row {} in {}", rowid, table.name)))
+    }
+}
+
+/// Fails if the row has the same values as another row in a unique index.
+fn check_unique(pager: &DiskPager, table: &Table, key: &[u8]) -> Result<(), Error> {
+    let rowid = rowkey::decode_rowid(key);
+    let row = try!(rowkey::decode_row(&table.columns, key));
+
+    for index in &table.indexes {
+        try!(check_unique_in_index(pager, table, index, rowid, &row));
+    }
+
+    Ok(())
+}
+
+fn check_unique_in_index(pager: &DiskPager, table: &Table, index: &Index, rowid: u64, row: &[Variant]) -> Result<(), Error> {
+    if !index.unique || indexkey::has_null(&index.columns, row) {
+        return Ok(());
+    }
+
+    let prefix = indexkey::encode_prefix(&table.columns, &index.columns, row);
+
+    for key in index.btree.range(pager, Bound::Included(prefix.clone()), Bound::Unbounded) {
+        let key = try!(key);
+
+        if !key.starts_with(&prefix) {
+            break;
+        }
+
+        if indexkey::decode_rowid(&key) != rowid {
+            return Err(databaseengine::unique_violation(table, &index.columns));
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds the row's key to the table and to every index.
+fn insert_key(pager: &mut DiskPager, table: &Table, key: &[u8]) -> Result<(), Error> {
+    let rowid = rowkey::decode_rowid(key);
+    let row = try!(rowkey::decode_row(&table.columns, key));
+
+    for index in &table.indexes {
+        let index_key = indexkey::encode_key(&table.columns, &index.columns, &row, rowid);
+        try!(index.btree.insert(pager, &index_key));
+    }
+
+    try!(table.btree.insert(pager, key));
+    Ok(())
+}
+
+/// Removes the row's key from the table and from every index.
+fn remove_key(pager: &mut DiskPager, table: &Table, key: &[u8]) -> Result<(), Error> {
+    let rowid = rowkey::decode_rowid(key);
+    let row = try!(rowkey::decode_row(&table.columns, key));
+
+    for index in &table.indexes {
+        let index_key = indexkey::encode_key(&table.columns, &index.columns, &row, rowid);
+        try!(index.btree.remove(pager, &index_key));
+    }
+
+    try!(table.btree.remove(pager, key));
+    Ok(())
+}
+
+/// Adds every row of the table to an empty index.
+/// Fails if the index is unique and two rows have the same values.
+fn fill_index(pager: &mut DiskPager, table: &Table, index: &Index) -> Result<(), Error> {
+    let mut keys = Vec::new();
+
+    for key in table.btree.iter(&*pager) {
+        keys.push(try!(key));
+    }
+
+    for key in keys {
+        let rowid = rowkey::decode_rowid(&key);
+        let row = try!(rowkey::decode_row(&table.columns, &key));
+
+        try!(check_unique_in_index(pager, table, index, rowid, &row));
+
+        let index_key = indexkey::encode_key(&table.columns, &index.columns, &row, rowid);
+        try!(index.btree.insert(pager, &index_key));
+    }
+
+    Ok(())
+}
+
+/// Removes the catalog entries of the table and its indexes.
+fn remove_from_catalog(pager: &mut DiskPager, catalog: &BTree, table: &Table) -> Result<(), Error> {
+    try!(catalog.remove(pager, &catalog::encode_table(table)));
+
+    for index in &table.indexes {
+        try!(catalog.remove(pager, &catalog::encode_index(table, index)));
+    }
+
+    Ok(())
+}
+
+/// Replaces the table's catalog entry, `old_entry`, after a value that's
+/// stored in the entry has changed.
+fn replace_in_catalog(pager: &mut DiskPager, catalog: &BTree, old_entry: &[u8], table: &Table) -> Result<(), Error> {
+    try!(catalog.remove(pager, old_entry));
+    try!(catalog.insert(pager, &catalog::encode_table(table)));
+    Ok(())
+}
+
+/// Points the table's foreign keys that reference `name` at `new_name`
+fn rename_foreign_keys(table: &mut Table, name: &Identifier, new_name: &Identifier) {
+    for column in table.columns.iter_mut() {
+        if let Some(ref mut foreign_key) = column.foreign_key {
+            if &foreign_key.table == name {
+                foreign_key.table = new_name.clone();
+            }
+        }
+    }
+}
+
+/// Adds the catalog entries of the table and its indexes.
+fn add_to_catalog(pager: &mut DiskPager, catalog: &BTree, table: &Table) -> Result<(), Error> {
+    try!(catalog.insert(pager, &catalog::encode_table(table)));
+
+    for index in &table.indexes {
+        try!(catalog.insert(pager, &catalog::encode_index(table, index)));
+    }
+
+    Ok(())
+}
+
+impl DatabaseEngine for FileDb {
+    fn execute_statement_with_params(&mut self, stmt: &ast::Statement, params: &[Variant]) -> ExecuteStatementResult {
+        databaseengine::execute_statement(self, stmt, params)
+    }
+
+    fn compile_statement(&self, stmt: &ast::Statement, parameter_count: u32) -> Result<CompiledStatement, Error> {
+        databaseengine::compile_statement(self, stmt, parameter_count)
+    }
+
+    fn is_up_to_date(&self, stmt: &CompiledStatement) -> bool {
+        databaseengine::is_up_to_date(self, stmt)
+    }
+
+    fn execute_compiled(&mut self, stmt: &CompiledStatement, params: &[Variant]) -> ExecuteStatementResult {
+        databaseengine::execute_compiled(self, stmt, params)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FileDb;
+    use databaseengine::{DatabaseEngine, DatabaseStorageMut, ExecuteStatementResponse};
+    use databaseinfo::DatabaseInfo;
+    use databasestorage::DatabaseStorage;
+    use identifier::Identifier;
+    use sqlsyntax::parse_statement;
+    use pager::{temp_path, remove_database_files};
+    use std::iter;
+
+    fn execute(db: &mut FileDb, sql: &str) {
+        if let Err(e) = db.execute_statement(parse_statement(sql).unwrap()) {
+            panic!("{}: {}", sql, e);
+        }
+    }
+
+    fn select(db: &mut FileDb, sql: &str) -> Vec<String> {
+        match db.execute_statement(parse_statement(sql).unwrap()) {
+            Ok(ExecuteStatementResponse::Select { rows, .. }) => {
+                rows.map(|row| {
+                    let row = row.unwrap_or_else(|e| panic!("{}: {}", sql, e));
+                    let values: Vec<String> = row.iter().map(|v| v.to_string()).collect();
+                    values.join(", ")
+                }).collect()
+            },
+            Ok(_) => panic!("{}: not a SELECT", sql),
+            Err(e) => panic!("{}: {}", sql, e)
+        }
+    }
+
+    #[test]
+    fn test_reopen() {
+        let path = temp_path("filedb-reopen");
+
+        {
+            let mut db = FileDb::open(&path).unwrap();
+            execute(&mut db, "CREATE TABLE person (id U32, name STRING, salary U64 NULL);");
+            execute(&mut db, "CREATE TABLE country (id U32, name VARCHAR);");
+            execute(&mut db, "INSERT INTO person VALUES (1, 'Joe', NULL), (2, 'Quentin', 44232);");
+            execute(&mut db, "INSERT INTO country (id, name) VALUES (1, 'Canada');");
+        }
+
+        {
+            let mut db = FileDb::open(&path).unwrap();
+            assert_eq!(select(&mut db, "SELECT * FROM person;"), vec!["1, Joe, NULL", "2, Quentin, 44232"]);
+            assert_eq!(select(&mut db, "SELECT name FROM country;"), vec!["Canada"]);
+
+            // Row ids continue from where they left off
+            execute(&mut db, "INSERT INTO person (id, name) VALUES (3, 'Barbara');");
+
+            assert!(db.execute_statement(parse_statement("CREATE TABLE country (id U32);").unwrap()).is_err());
+        }
+
+        {
+            let mut db = FileDb::open(&path).unwrap();
+            assert_eq!(select(&mut db, "SELECT id, salary FROM person;"), vec!["1, NULL", "2, 44232", "3, NULL"]);
+        }
+
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_rowids_of_deleted_rows_are_not_reused() {
+        let path = temp_path("filedb-rowids");
+        let t = Identifier::new("t").unwrap();
+
+        {
+            let mut db = FileDb::open(&path).unwrap();
+            execute(&mut db, "CREATE TABLE t (n U32);");
+            execute(&mut db, "INSERT INTO t VALUES (1), (2), (3);");
+            execute(&mut db, "DELETE FROM t WHERE n >= 2;");
+        }
+
+        {
+            let mut db = FileDb::open(&path).unwrap();
+            execute(&mut db, "INSERT INTO t VALUES (4);");
+
+            let rowids: Vec<u64> = db.rows_with_rowids(&t).unwrap().into_iter().map(|(rowid, _)| rowid).collect();
+            assert_eq!(rowids, vec![1, 4]);
+        }
+
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_row_counts_are_kept() {
+        let path = temp_path("filedb-row-counts");
+        let t = Identifier::new("t").unwrap();
+        let row_count = |db: &FileDb| db.scan_table(db.find_table_by_name(&t).unwrap()).count();
+
+        {
+            let mut db = FileDb::open(&path).unwrap();
+            execute(&mut db, "CREATE TABLE t (n U32 UNIQUE);");
+            assert_eq!(row_count(&db), 0);
+
+            execute(&mut db, "INSERT INTO t VALUES (1), (2), (3);");
+            execute(&mut db, "DELETE FROM t WHERE n = 2;");
+            assert_eq!(row_count(&db), 2);
+
+            // A failed statement and a rolled back transaction don't change the count
+            assert!(db.execute_statement(parse_statement("INSERT INTO t VALUES (4), (1);").unwrap()).is_err());
+            execute(&mut db, "BEGIN;");
+            execute(&mut db, "INSERT INTO t VALUES (5);");
+            execute(&mut db, "ROLLBACK;");
+            assert_eq!(row_count(&db), 2);
+        }
+
+        let db = FileDb::open(&path).unwrap();
+        assert_eq!(row_count(&db), 2);
+
+        drop(db);
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_many_rows() {
+        let path = temp_path("filedb-many-rows");
+
+        {
+            let mut db = FileDb::open(&path).unwrap();
+            execute(&mut db, "CREATE TABLE t (n U32, padding STRING);");
+
+            for i in 0..50 {
+                let values: Vec<String> = (0..20).map(|j| {
+                    let padding: String = iter::repeat('x').take(j * 40).collect();
+                    format!("({}, '{}')", i * 20 + j, padding)
+                }).collect();
+                execute(&mut db, &format!("INSERT INTO t VALUES {};", values.join(", ")));
+            }
+        }
+
+        let mut db = FileDb::open(&path).unwrap();
+        assert_eq!(select(&mut db, "SELECT count(*), sum(n) FROM t;"), vec!["1000, 499500"]);
+
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_drop_and_alter_table() {
+        let path = temp_path("filedb-alter");
+
+        {
+            let mut db = FileDb::open(&path).unwrap();
+            execute(&mut db, "CREATE TABLE t (a U32, b STRING);");
+            execute(&mut db, "CREATE TABLE u (x U32);");
+            execute(&mut db, "INSERT INTO t VALUES (1, 'x'), (2, 'y');");
+            execute(&mut db, "ALTER TABLE t ADD COLUMN c I32 NULL;");
+            execute(&mut db, "ALTER TABLE t DROP COLUMN a;");
+            execute(&mut db, "ALTER TABLE t RENAME TO v;");
+            execute(&mut db, "DROP TABLE u;");
+
+            execute(&mut db, "CREATE TABLE w (a U32 PRIMARY KEY, b STRING UNIQUE);");
+            execute(&mut db, "INSERT INTO w VALUES (1, 'x');");
+            execute(&mut db, "ALTER TABLE w RENAME TO x;");
+        }
+
+        {
+            let mut db = FileDb::open(&path).unwrap();
+            assert_eq!(select(&mut db, "SELECT * FROM v;"), vec!["x, NULL", "y, NULL"]);
+            assert!(db.execute_statement(parse_statement("SELECT * FROM t;").unwrap()).is_err());
+            assert!(db.execute_statement(parse_statement("SELECT * FROM u;").unwrap()).is_err());
+
+            // Row ids continue from where they left off
+            execute(&mut db, "INSERT INTO v VALUES ('z', 3);");
+            assert_eq!(select(&mut db, "SELECT * FROM v;"), vec!["x, NULL", "y, NULL", "z, 3"]);
+
+            // The renamed table's constraints are still enforced
+            assert!(db.execute_statement(parse_statement("INSERT INTO x VALUES (1, 'y');").unwrap()).is_err());
+            assert!(db.execute_statement(parse_statement("INSERT INTO x VALUES (2, 'x');").unwrap()).is_err());
+            execute(&mut db, "INSERT INTO x VALUES (2, 'y');");
+        }
+
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_uncommitted_transaction_is_lost() {
+        let path = temp_path("filedb-uncommitted");
+
+        {
+            let mut db = FileDb::open(&path).unwrap();
+            execute(&mut db, "CREATE TABLE t (n U32);");
+
+            execute(&mut db, "BEGIN;");
+            execute(&mut db, "INSERT INTO t VALUES (1), (2);");
+            execute(&mut db, "COMMIT;");
+
+            execute(&mut db, "BEGIN;");
+            execute(&mut db, "CREATE TABLE u (n U32);");
+            execute(&mut db, "INSERT INTO t VALUES (3);");
+        }
+
+        {
+            let mut db = FileDb::open(&path).unwrap();
+            assert_eq!(select(&mut db, "SELECT n FROM t;"), vec!["1", "2"]);
+            assert!(db.execute_statement(parse_statement("SELECT * FROM u;").unwrap()).is_err());
+        }
+
+        remove_database_files(&path);
+    }
+}
@@ -0,0 +1,87 @@
+use btree::BTree;
+use types::DbType;
+use databaseinfo::{ColumnInfo, ForeignKey, IndexInfo, TableInfo};
+use identifier::Identifier;
+
+#[derive(Clone, Debug)]
+pub struct Table {
+    pub name: Identifier,
+    pub columns: Vec<Column>,
+
+    /// The rows of the table, keyed by rowid.
+    /// See the `rowkey` module for the key layout.
+    pub btree: BTree,
+    pub next_rowid: u64,
+    /// Kept up to date as rows are inserted and deleted, so that the rows
+    /// don't need to be counted to plan a query.
+    pub row_count: u64,
+
+    pub indexes: Vec<Index>
+}
+
+#[derive(Clone, Debug)]
+pub struct Index {
+    pub name: Identifier,
+    /// The offsets of the indexed columns
+    pub columns: Vec<u32>,
+    pub unique: bool,
+
+    /// See the `indexkey` module for the key layout.
+    pub btree: BTree
+}
+
+#[derive(Clone, Debug)]
+pub struct Column {
+    pub offset: u32,
+    pub name: Identifier,
+    pub dbtype: DbType,
+    pub nullable: bool,
+    pub primary_key: bool,
+    pub unique: bool,
+    pub foreign_key: Option<ForeignKey>
+}
+
+impl TableInfo for Table {
+    type Column = Column;
+    type Index = Index;
+
+    fn get_name(&self) -> &Identifier { &self.name }
+
+    fn get_column_count(&self) -> u32 { self.columns.len() as u32 }
+
+    fn find_column_by_offset(&self, offset: u32) -> Option<&Column> {
+        let i = offset as usize;
+
+        if i < self.columns.len() {
+            Some(&self.columns[i])
+        } else {
+            None
+        }
+    }
+
+    fn find_column_by_name(&self, name: &Identifier) -> Option<&Column> {
+        self.columns.iter().find(|c| &c.name == name)
+    }
+
+    fn get_index_count(&self) -> u32 { self.indexes.len() as u32 }
+
+    fn find_index_by_offset(&self, offset: u32) -> Option<&Index> {
+        self.indexes.get(offset as usize)
+    }
+}
+
+impl IndexInfo for Index {
+    fn get_name(&self) -> &Identifier { &self.name }
+    fn get_column_offsets(&self) -> &[u32] { &self.columns }
+    fn is_unique(&self) -> bool { self.unique }
+}
+
+impl ColumnInfo for Column {
+    fn get_offset(&self) -> u32 { self.offset }
+    fn get_name(&self) -> &Identifier { &self.name }
+    fn get_dbtype(&self) -> &DbType { &self.dbtype }
+    fn is_nullable(&self) -> bool { self.nullable }
+    fn is_primary_key(&self) -> bool { self.primary_key }
+    fn is_unique(&self) -> bool { self.unique }
+    fn get_foreign_key(&self) -> Option<&ForeignKey> { self.foreign_key.as_ref() }
+}
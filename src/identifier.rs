@@ -20,7 +20,7 @@ use std::ops::Deref;
 ///
 /// When stored and compared, identifiers must be folded into a canonical,
 /// lower-case representation. This process is known as normalization.
-#[derive(PartialEq, Eq, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone)]
 pub struct Identifier {
     value: String
 }
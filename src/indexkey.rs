@@ -0,0 +1,169 @@
+//! Indexes are stored as sets of keys. Each key holds the values of the
+//! indexed columns, followed by the row id of the row they come from:
+//!
+//! | Size and type | Name                                                       |
+//! |---------------|------------------------------------------------------------|
+//! | Variable      | The indexed columns' values, as an ascending `sortkey`     |
+//! | 8, u64-be     | Row id                                                     |
+//!
+//! Keys sort in the order of their values, and then by row id.
+//! The encoded values are prefix-free: the encoding of a set of values never
+//! begins with the encoding of a different set of values.
+//! Every key that has the same values therefore begins with the same prefix,
+//! and the keys with the prefix can be found with a range scan.
+
+use byteutils;
+use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
+use databaseinfo::ColumnInfo;
+use sortkey::{self, KeyColumn, Order};
+use types::{DbType, Variant};
+
+use std::borrow::Cow;
+
+fn key_column<C: ColumnInfo>(column: &C) -> KeyColumn {
+    KeyColumn {
+        dbtype: *column.get_dbtype(),
+        nullable: column.is_nullable(),
+        order: Order::Ascending
+    }
+}
+
+/// Encodes the values of the indexed columns of a row.
+///
+/// `column_offsets` are the offsets of the indexed columns in `columns` and `row`.
+pub fn encode_prefix<C: ColumnInfo>(columns: &[C], column_offsets: &[u32], row: &[Variant]) -> Vec<u8> {
+    let key_columns: Vec<KeyColumn> = column_offsets.iter().map(|&offset| key_column(&columns[offset as usize])).collect();
+    let values = column_offsets.iter().map(|&offset| &row[offset as usize]);
+
+    sortkey::encode_key(&key_columns, values).unwrap()
+}
+
+/// Encodes values for the first indexed columns, as the prefix of every key
+/// that begins with the values.
+///
+/// Returns None if a value can't be stored in its column without being changed,
+/// as the value would be found in the wrong place.
+pub fn encode_values<C: ColumnInfo>(columns: &[C], column_offsets: &[u32], values: &[Variant]) -> Option<Vec<u8>> {
+    let mut key = Vec::new();
+
+    for (&offset, value) in column_offsets.iter().zip(values.iter()) {
+        let column = key_column(&columns[offset as usize]);
+
+        if !value.is_null() && !is_exact(value, column.dbtype) {
+            return None;
+        }
+
+        if sortkey::push_value(&mut key, &column, value).is_err() {
+            return None;
+        }
+    }
+
+    Some(key)
+}
+
+/// Returns true if the key is at or before the maximum of a range.
+/// Keys that begin with the maximum's values are in the range.
+pub fn is_at_or_before(key: &[u8], max: &[u8]) -> bool {
+    key.starts_with(max) || key < max
+}
+
+/// Returns true if storing the value in a column of the type doesn't change it
+fn is_exact(value: &Variant, dbtype: DbType) -> bool {
+    let data = match value.clone().to_bytes(dbtype) {
+        Ok(data) => data,
+        Err(()) => return false
+    };
+
+    let stored = match Variant::from_bytes(dbtype, Cow::Borrowed(&data)) {
+        Ok(stored) => stored,
+        Err(()) => return false
+    };
+
+    match (value, &stored) {
+        (&Variant::SignedInteger(a), &Variant::UnsignedInteger(b)) => a >= 0 && a as u64 == b,
+        (&Variant::UnsignedInteger(a), &Variant::SignedInteger(b)) => b >= 0 && b as u64 == a,
+        (a, b) => a == b
+    }
+}
+
+/// Encodes the index key of a row.
+pub fn encode_key<C: ColumnInfo>(columns: &[C], column_offsets: &[u32], row: &[Variant], rowid: u64) -> Vec<u8> {
+    let mut key = encode_prefix(columns, column_offsets, row);
+
+    let mut buf = [0; 8];
+    byteutils::write_udbinteger(rowid, &mut buf);
+    key.extend_from_slice(&buf);
+
+    key
+}
+
+pub fn decode_rowid(key: &[u8]) -> u64 {
+    byteutils::read_udbinteger(&key[key.len() - 8..])
+}
+
+/// Returns true if any of the indexed columns of the row are NULL.
+/// NULL is never equal to another value, so such rows never violate a unique index.
+pub fn has_null(column_offsets: &[u32], row: &[Variant]) -> bool {
+    column_offsets.iter().any(|&offset| row[offset as usize].is_null())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_rowid, encode_key, encode_prefix};
+    use databaseinfo::{ColumnInfo, ForeignKey};
+    use identifier::Identifier;
+    use types::{DbType, Variant};
+
+    struct Column {
+        name: Identifier,
+        dbtype: DbType,
+        nullable: bool
+    }
+
+    impl ColumnInfo for Column {
+        fn get_offset(&self) -> u32 { 0 }
+        fn get_name(&self) -> &Identifier { &self.name }
+        fn get_dbtype(&self) -> &DbType { &self.dbtype }
+        fn is_nullable(&self) -> bool { self.nullable }
+        fn is_primary_key(&self) -> bool { false }
+        fn is_unique(&self) -> bool { false }
+        fn get_foreign_key(&self) -> Option<&ForeignKey> { None }
+    }
+
+    fn column(dbtype: DbType, nullable: bool) -> Column {
+        Column {
+            name: Identifier::new("c").unwrap(),
+            dbtype: dbtype,
+            nullable: nullable
+        }
+    }
+
+    #[test]
+    fn test_prefix_free() {
+        let columns = vec![column(DbType::ByteDynamic, true), column(DbType::ByteDynamic, false)];
+        let offsets = [0, 1];
+
+        let rows = vec![
+            vec![Variant::Null, Variant::Bytes(vec![])],
+            vec![Variant::Bytes(vec![]), Variant::Bytes(vec![0])],
+            vec![Variant::Bytes(vec![0]), Variant::Bytes(vec![])],
+            vec![Variant::Bytes(vec![0, 0]), Variant::Bytes(vec![])],
+            vec![Variant::Bytes(vec![0, 0xFF]), Variant::Bytes(vec![])],
+            vec![Variant::Bytes(vec![1]), Variant::Bytes(vec![0, 0])]
+        ];
+
+        let prefixes: Vec<Vec<u8>> = rows.iter().map(|row| encode_prefix(&columns, &offsets, row)).collect();
+
+        for (i, a) in prefixes.iter().enumerate() {
+            for (j, b) in prefixes.iter().enumerate() {
+                if i != j {
+                    assert!(!b.starts_with(a), "{:?} is a prefix of {:?}", a, b);
+                }
+            }
+        }
+
+        let key = encode_key(&columns, &offsets, &rows[3], 1234);
+        assert!(key.starts_with(&prefixes[3]));
+        assert_eq!(decode_rowid(&key), 1234);
+    }
+}
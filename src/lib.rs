@@ -3,13 +3,29 @@
 #[macro_use]
 extern crate log;
 
+pub mod filedb;
 pub mod sqlsyntax;
 pub mod tempdb;
 
+pub use connection::{Connection, FromVariant, Row, Rows, Statement};
+pub use databaseengine::{DatabaseEngine, ExecuteStatementResponse, ExecuteStatementResult};
+pub use error::Error;
+pub use preparedstatement::{prepare, PreparedStatement};
+pub use types::{DbType, F64NoNaN, Variant};
+
+mod btree;
 mod byteutils;
 mod columnvalueops;
+mod connection;
+mod databaseengine;
 mod databaseinfo;
 mod databasestorage;
+mod error;
 mod identifier;
+mod indexkey;
+mod pager;
+mod preparedstatement;
 mod queryplan;
+mod rowkey;
+mod sortkey;
 mod types;
@@ -0,0 +1,730 @@
+use byteutils;
+use super::{Pager, PagerError, PagerResult, is_valid_page_size};
+use super::wal::{self, CommitState};
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+static MAGIC: &'static [u8; 8] = b"LlamaDB\0";
+
+/// The header occupies the start of the file, where page 0 would otherwise be.
+///
+/// | Size and type | Name                 |
+/// |---------------|----------------------|
+/// | 8             | Magic: "LlamaDB\0"   |
+/// | 4, u32-le     | Page size            |
+/// | 8, u64-le     | Page count           |
+/// | 8, u64-le     | First free page      |
+/// | 8, u64-le     | Free page count      |
+const HEADER_LENGTH: usize = 36;
+
+const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+struct CachedPage {
+    data: Box<[u8]>,
+    dirty: bool,
+    last_used: u64
+}
+
+/// The cached pages, along with the clean ones in the order they were used,
+/// so that the least recently used clean page is found without a scan.
+struct PageCache {
+    pages: HashMap<u64, CachedPage>,
+    /// `(last_used, page_id)` of every clean page
+    clean_pages: BTreeSet<(u64, u64)>
+}
+
+impl PageCache {
+    fn new() -> PageCache {
+        PageCache {
+            pages: HashMap::new(),
+            clean_pages: BTreeSet::new()
+        }
+    }
+
+    /// Returns the page, and marks it as used at `t`.
+    fn get(&mut self, page_id: u64, t: u64) -> Option<&CachedPage> {
+        match self.pages.get_mut(&page_id) {
+            Some(page) => {
+                if !page.dirty {
+                    self.clean_pages.remove(&(page.last_used, page_id));
+                    self.clean_pages.insert((t, page_id));
+                }
+
+                page.last_used = t;
+                Some(page)
+            },
+            None => None
+        }
+    }
+
+    fn insert(&mut self, page_id: u64, page: CachedPage) {
+        if !page.dirty {
+            self.clean_pages.insert((page.last_used, page_id));
+        }
+
+        if let Some(old_page) = self.pages.insert(page_id, page) {
+            if !old_page.dirty {
+                self.clean_pages.remove(&(old_page.last_used, page_id));
+            }
+        }
+    }
+
+    fn remove(&mut self, page_id: u64) {
+        if let Some(page) = self.pages.remove(&page_id) {
+            if !page.dirty {
+                self.clean_pages.remove(&(page.last_used, page_id));
+            }
+        }
+    }
+
+    fn has_dirty_pages(&self) -> bool {
+        self.pages.len() > self.clean_pages.len()
+    }
+
+    /// The ids of the dirty pages, in ascending order
+    fn dirty_page_ids(&self) -> Vec<u64> {
+        let mut page_ids: Vec<u64> = self.pages.iter().filter(|&(_, page)| page.dirty).map(|(&page_id, _)| page_id).collect();
+        page_ids.sort();
+        page_ids
+    }
+
+    fn mark_clean(&mut self, page_id: u64) {
+        let page = self.pages.get_mut(&page_id).unwrap();
+
+        if page.dirty {
+            page.dirty = false;
+            self.clean_pages.insert((page.last_used, page_id));
+        }
+    }
+
+    /// Evicts the least recently used clean pages until at most `capacity` are left.
+    fn evict(&mut self, capacity: usize) {
+        while self.clean_pages.len() > capacity {
+            let lru = *self.clean_pages.iter().next().unwrap();
+            self.clean_pages.remove(&lru);
+            self.pages.remove(&lru.1);
+        }
+    }
+}
+
+struct Savepoint {
+    state: CommitState,
+    /// The contents of every page that was written since the savepoint.
+    /// None if the page wasn't dirty, and can be read back from the file.
+    pages: HashMap<u64, Option<Box<[u8]>>>
+}
+
+/// A pager backed by a file.
+///
+/// Recently used pages are kept in a cache. Writes are held in the cache
+/// until `flush()` is called; dirty pages are never evicted.
+///
+/// Flushes go through a write-ahead log, so that a crash during a flush
+/// leaves the file either before or after the flush. See the `wal` module.
+pub struct DiskPager {
+    file: File,
+    wal: File,
+    page_size: usize,
+    page_count: u64,
+    /// The head of a linked list of freed pages.
+    /// The first 8 bytes of a freed page point to the next freed page.
+    free_list_head: u64,
+    free_page_count: u64,
+
+    /// The state as of the last flush
+    flushed_state: CommitState,
+    savepoint: Option<Savepoint>,
+
+    cache: RefCell<PageCache>,
+    cache_capacity: usize,
+    clock: Cell<u64>
+}
+
+impl DiskPager {
+    /// Creates a new, empty file. Any existing file at `path` is truncated.
+    pub fn create<P: AsRef<Path>>(path: P, page_size: usize) -> PagerResult<DiskPager> {
+        if !is_valid_page_size(page_size) {
+            return Err(PagerError::InvalidPageSize(page_size));
+        }
+
+        let path = path.as_ref();
+        let file = try!(OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path));
+        let wal = try!(OpenOptions::new().read(true).write(true).create(true).truncate(true).open(wal::wal_path(path)));
+
+        let mut pager = DiskPager::new(file, wal, page_size, CommitState {
+            page_count: 0,
+            free_list_head: 0,
+            free_page_count: 0
+        });
+        try!(pager.write_header());
+        try!(pager.file.sync_all());
+        try!(wal::clear(&mut pager.wal));
+        Ok(pager)
+    }
+
+    /// Opens a file previously created with `DiskPager::create`.
+    ///
+    /// If the last flush was interrupted, it's completed from the write-ahead log.
+    pub fn open<P: AsRef<Path>>(path: P) -> PagerResult<DiskPager> {
+        let path = path.as_ref();
+        let mut file = try!(OpenOptions::new().read(true).write(true).open(path));
+        let wal = try!(OpenOptions::new().read(true).write(true).create(true).open(wal::wal_path(path)));
+
+        let mut header = [0; HEADER_LENGTH];
+        if try!(read_fully(&mut file, &mut header)) != HEADER_LENGTH {
+            return Err(PagerError::BadHeader);
+        }
+
+        if &header[0..8] != MAGIC {
+            return Err(PagerError::BadHeader);
+        }
+
+        let page_size = byteutils::read_u32_le(&header[8..12]) as usize;
+        if !is_valid_page_size(page_size) {
+            return Err(PagerError::BadHeader);
+        }
+
+        let state = CommitState {
+            page_count: byteutils::read_u64_le(&header[12..20]),
+            free_list_head: byteutils::read_u64_le(&header[20..28]),
+            free_page_count: byteutils::read_u64_le(&header[28..36])
+        };
+
+        let mut pager = DiskPager::new(file, wal, page_size, state);
+        try!(pager.recover());
+        Ok(pager)
+    }
+
+    fn new(file: File, wal: File, page_size: usize, state: CommitState) -> DiskPager {
+        DiskPager {
+            file: file,
+            wal: wal,
+            page_size: page_size,
+            page_count: state.page_count,
+            free_list_head: state.free_list_head,
+            free_page_count: state.free_page_count,
+            flushed_state: state,
+            savepoint: None,
+            cache: RefCell::new(PageCache::new()),
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            clock: Cell::new(0)
+        }
+    }
+
+    /// Sets the maximum amount of clean pages to keep in the cache.
+    #[cfg(test)]
+    pub fn set_cache_capacity(&mut self, pages: usize) {
+        self.cache_capacity = pages;
+        self.evict();
+    }
+
+    #[cfg(test)]
+    pub fn cached_page_count(&self) -> usize {
+        self.cache.borrow().pages.len()
+    }
+
+    /// Marks a point that the pager can be rolled back to with
+    /// `rollback_to_savepoint`. Replaces the existing savepoint, if any.
+    ///
+    /// Flushing releases the savepoint.
+    pub fn savepoint(&mut self) {
+        self.savepoint = Some(Savepoint {
+            state: self.commit_state(),
+            pages: HashMap::new()
+        });
+    }
+
+    pub fn release_savepoint(&mut self) {
+        self.savepoint = None;
+    }
+
+    /// Undoes every write, allocation and free since the savepoint,
+    /// and releases the savepoint.
+    pub fn rollback_to_savepoint(&mut self) {
+        let savepoint = match self.savepoint.take() {
+            Some(savepoint) => savepoint,
+            None => return
+        };
+
+        let t = self.tick();
+
+        {
+            let mut cache = self.cache.borrow_mut();
+
+            for (page_id, data) in savepoint.pages {
+                match data {
+                    Some(data) => {
+                        cache.insert(page_id, CachedPage {
+                            data: data,
+                            dirty: true,
+                            last_used: t
+                        });
+                    },
+                    None => cache.remove(page_id)
+                }
+            }
+        }
+
+        self.set_state(savepoint.state);
+    }
+
+    /// Undoes every change since the last flush.
+    pub fn discard(&mut self) {
+        self.savepoint = None;
+
+        {
+            let mut cache = self.cache.borrow_mut();
+
+            for page_id in cache.dirty_page_ids() {
+                cache.remove(page_id);
+            }
+        }
+
+        let state = self.flushed_state;
+        self.set_state(state);
+    }
+
+    fn check_page_id(&self, page_id: u64, buf_len: usize) -> PagerResult<()> {
+        if page_id == 0 || page_id > self.page_count {
+            Err(PagerError::InvalidPageId(page_id))
+        } else if buf_len != self.page_size {
+            Err(PagerError::BadBufferLength(buf_len))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        let t = self.clock.get() + 1;
+        self.clock.set(t);
+        t
+    }
+
+    /// Evicts the least recently used clean pages until the cache fits its capacity.
+    fn evict(&self) {
+        self.cache.borrow_mut().evict(self.cache_capacity);
+    }
+
+    fn commit_state(&self) -> CommitState {
+        CommitState {
+            page_count: self.page_count,
+            free_list_head: self.free_list_head,
+            free_page_count: self.free_page_count
+        }
+    }
+
+    fn set_state(&mut self, state: CommitState) {
+        self.page_count = state.page_count;
+        self.free_list_head = state.free_list_head;
+        self.free_page_count = state.free_page_count;
+    }
+
+    /// Replays the write-ahead log, if it holds a complete flush.
+    fn recover(&mut self) -> PagerResult<()> {
+        let commit = match try!(wal::read(&mut self.wal, self.page_size)) {
+            Some(commit) => commit,
+            None => {
+                // Nothing was committed to the log, so the file was never touched.
+                return wal::clear(&mut self.wal);
+            }
+        };
+
+        debug!("recovering {} pages from the write-ahead log", commit.pages.len());
+
+        self.set_state(commit.state);
+
+        for (page_id, data) in commit.pages {
+            self.write_to_cache(page_id, data);
+        }
+
+        self.checkpoint()
+    }
+
+    /// Writes the dirty pages and the pager state to the write-ahead log.
+    /// Once the log is written, the flush survives a crash.
+    fn write_wal(&mut self) -> PagerResult<()> {
+        let state = self.commit_state();
+        let cache = self.cache.borrow();
+
+        let dirty_pages: Vec<(u64, &[u8])> = cache.dirty_page_ids().into_iter().map(|page_id| {
+            (page_id, &cache.pages[&page_id].data as &[u8])
+        }).collect();
+
+        wal::write(&mut self.wal, self.page_size, &dirty_pages, state)
+    }
+
+    /// Writes the dirty pages to the file, and empties the write-ahead log.
+    fn checkpoint(&mut self) -> PagerResult<()> {
+        {
+            let mut cache = self.cache.borrow_mut();
+
+            for page_id in cache.dirty_page_ids() {
+                try!(self.file.seek(SeekFrom::Start(page_id * self.page_size as u64)));
+                try!(self.file.write_all(&cache.pages[&page_id].data));
+                cache.mark_clean(page_id);
+            }
+        }
+
+        try!(self.write_header());
+        try!(self.file.sync_all());
+        try!(wal::clear(&mut self.wal));
+
+        self.flushed_state = self.commit_state();
+        self.savepoint = None;
+
+        self.evict();
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> PagerResult<()> {
+        let mut header = [0; HEADER_LENGTH];
+        header[0..8].copy_from_slice(MAGIC);
+        byteutils::write_u32_le(self.page_size as u32, &mut header[8..12]);
+        byteutils::write_u64_le(self.page_count, &mut header[12..20]);
+        byteutils::write_u64_le(self.free_list_head, &mut header[20..28]);
+        byteutils::write_u64_le(self.free_page_count, &mut header[28..36]);
+
+        try!(self.file.seek(SeekFrom::Start(0)));
+        try!(self.file.write_all(&header));
+        Ok(())
+    }
+
+    fn write_to_cache(&mut self, page_id: u64, data: Box<[u8]>) {
+        let t = self.tick();
+        let mut cache = self.cache.borrow_mut();
+
+        if let Some(ref mut savepoint) = self.savepoint {
+            if !savepoint.pages.contains_key(&page_id) {
+                let old_data = match cache.pages.get(&page_id) {
+                    Some(page) if page.dirty => Some(page.data.clone()),
+                    _ => None
+                };
+                savepoint.pages.insert(page_id, old_data);
+            }
+        }
+
+        cache.insert(page_id, CachedPage {
+            data: data,
+            dirty: true,
+            last_used: t
+        });
+    }
+}
+
+/// Reads until `buf` is full or the end of the file is reached.
+/// Returns the amount of bytes read.
+fn read_fully(file: &mut Read, buf: &mut [u8]) -> PagerResult<usize> {
+    let mut offset = 0;
+
+    while offset < buf.len() {
+        match try!(file.read(&mut buf[offset..])) {
+            0 => break,
+            n => offset += n
+        }
+    }
+
+    Ok(offset)
+}
+
+impl Pager for DiskPager {
+    fn page_size(&self) -> usize { self.page_size }
+
+    fn page_count(&self) -> u64 { self.page_count }
+
+    fn read_page(&self, page_id: u64, buf: &mut [u8]) -> PagerResult<()> {
+        try!(self.check_page_id(page_id, buf.len()));
+
+        let t = self.tick();
+
+        if let Some(page) = self.cache.borrow_mut().get(page_id, t) {
+            buf.copy_from_slice(&page.data);
+            return Ok(());
+        }
+
+        let mut file = &self.file;
+        try!(file.seek(SeekFrom::Start(page_id * self.page_size as u64)));
+
+        // Pages that were allocated but never flushed past the end of the file are zero-filled.
+        let read = try!(read_fully(&mut file, buf));
+        for v in buf[read..].iter_mut() {
+            *v = 0;
+        }
+
+        self.cache.borrow_mut().insert(page_id, CachedPage {
+            data: buf.to_vec().into_boxed_slice(),
+            dirty: false,
+            last_used: t
+        });
+        self.evict();
+
+        Ok(())
+    }
+
+    fn write_page(&mut self, page_id: u64, data: &[u8]) -> PagerResult<()> {
+        try!(self.check_page_id(page_id, data.len()));
+        self.write_to_cache(page_id, data.to_vec().into_boxed_slice());
+        Ok(())
+    }
+
+    fn allocate_page(&mut self) -> PagerResult<u64> {
+        let page_id = if self.free_list_head != 0 {
+            let page_id = self.free_list_head;
+
+            let mut buf = vec![0; self.page_size];
+            try!(self.read_page(page_id, &mut buf));
+
+            self.free_list_head = byteutils::read_u64_le(&buf[0..8]);
+            self.free_page_count -= 1;
+            page_id
+        } else {
+            self.page_count += 1;
+            self.page_count
+        };
+
+        let zeroes = vec![0; self.page_size].into_boxed_slice();
+        self.write_to_cache(page_id, zeroes);
+
+        Ok(page_id)
+    }
+
+    fn free_page(&mut self, page_id: u64) -> PagerResult<()> {
+        let page_size = self.page_size;
+        try!(self.check_page_id(page_id, page_size));
+
+        let mut buf = vec![0; page_size];
+        byteutils::write_u64_le(self.free_list_head, &mut buf[0..8]);
+        self.write_to_cache(page_id, buf.into_boxed_slice());
+
+        self.free_list_head = page_id;
+        self.free_page_count += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> PagerResult<()> {
+        let has_dirty_pages = self.cache.borrow().has_dirty_pages();
+
+        if has_dirty_pages {
+            try!(self.write_wal());
+            try!(self.checkpoint());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DiskPager;
+    use super::super::Pager;
+    use super::super::{temp_path, remove_database_files};
+    use super::super::wal::wal_path;
+    use std::fs::{self, File};
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::path::Path;
+
+    fn read_file(path: &Path) -> Vec<u8> {
+        let mut buf = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    fn write_file(path: &Path, data: &[u8]) {
+        File::create(path).unwrap().write_all(data).unwrap();
+    }
+
+    fn page_is(pager: &DiskPager, page_id: u64, value: u8) -> bool {
+        let mut buf = vec![0; pager.page_size()];
+        pager.read_page(page_id, &mut buf).unwrap();
+        buf.iter().all(|&v| v == value)
+    }
+
+    /// Flushes three pages of 1s, then crashes during a second flush right
+    /// after the log was written.
+    /// Returns the contents of the database file and the log.
+    fn crash_after_writing_log(path: &Path) -> (Vec<u8>, Vec<u8>) {
+        let mut pager = DiskPager::create(path, 64).unwrap();
+
+        for _ in 0..3 {
+            let page_id = pager.allocate_page().unwrap();
+            pager.write_page(page_id, &[1; 64]).unwrap();
+        }
+        pager.flush().unwrap();
+
+        pager.write_page(2, &[2; 64]).unwrap();
+        let page_id = pager.allocate_page().unwrap();
+        pager.write_page(page_id, &[2; 64]).unwrap();
+        pager.free_page(1).unwrap();
+
+        pager.write_wal().unwrap();
+        drop(pager);
+
+        (read_file(path), read_file(&wal_path(path)))
+    }
+
+    fn assert_before_crash(pager: &DiskPager) {
+        assert_eq!(pager.page_count(), 3);
+        assert!(page_is(pager, 1, 1));
+        assert!(page_is(pager, 2, 1));
+        assert!(page_is(pager, 3, 1));
+    }
+
+    fn assert_after_crash(pager: &mut DiskPager) {
+        assert_eq!(pager.page_count(), 4);
+        assert!(page_is(pager, 2, 2));
+        assert!(page_is(pager, 3, 1));
+        assert!(page_is(pager, 4, 2));
+        assert_eq!(pager.allocate_page().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_recovery_from_torn_log() {
+        let path = temp_path("wal-torn-log");
+        let (db, log) = crash_after_writing_log(&path);
+
+        for length in 0..log.len() + 1 {
+            write_file(&path, &db);
+            write_file(&wal_path(&path), &log[0..length]);
+
+            let mut pager = DiskPager::open(&path).unwrap();
+
+            if length == log.len() {
+                assert_after_crash(&mut pager);
+            } else {
+                // The commit record is incomplete, so the log is discarded
+                assert_before_crash(&pager);
+            }
+
+            // Recovery always leaves an empty log behind
+            assert_eq!(read_file(&wal_path(&path)).len(), 0);
+        }
+
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_recovery_from_torn_database_write() {
+        let path = temp_path("wal-torn-database");
+        let (mut db, log) = crash_after_writing_log(&path);
+
+        // Page 2 was half-written when the crash happened
+        for v in db[2 * 64..2 * 64 + 32].iter_mut() {
+            *v = 2;
+        }
+
+        write_file(&path, &db);
+        write_file(&wal_path(&path), &log);
+
+        assert_after_crash(&mut DiskPager::open(&path).unwrap());
+
+        // Once recovered, the file opens the same without the log
+        fs::remove_file(&wal_path(&path)).unwrap();
+        assert_after_crash(&mut DiskPager::open(&path).unwrap());
+
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_corrupt_log_is_discarded() {
+        let path = temp_path("wal-corrupt");
+        let (db, log) = crash_after_writing_log(&path);
+
+        // Flip a byte in turn of: the header, a page record, and the commit record
+        for &offset in &[0, 12, 12 + 9 + 20, log.len() - 20, log.len() - 1] {
+            let mut corrupt_log = log.clone();
+            corrupt_log[offset] ^= 0xFF;
+
+            write_file(&path, &db);
+            write_file(&wal_path(&path), &corrupt_log);
+
+            assert_before_crash(&DiskPager::open(&path).unwrap());
+        }
+
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_flush_empties_log() {
+        let path = temp_path("wal-flush");
+
+        let mut pager = DiskPager::create(&path, 64).unwrap();
+        let page_id = pager.allocate_page().unwrap();
+        pager.write_page(page_id, &[5; 64]).unwrap();
+        pager.flush().unwrap();
+
+        let mut wal = File::open(&wal_path(&path)).unwrap();
+        assert_eq!(wal.seek(SeekFrom::End(0)).unwrap(), 0);
+
+        drop(pager);
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let path = temp_path("disk-pager-lru");
+
+        let mut pager = DiskPager::create(&path, 64).unwrap();
+        pager.set_cache_capacity(2);
+
+        for _ in 0..3 {
+            let page_id = pager.allocate_page().unwrap();
+            pager.write_page(page_id, &[page_id as u8; 64]).unwrap();
+        }
+        pager.flush().unwrap();
+
+        let is_cached = |pager: &DiskPager, page_id: u64| pager.cache.borrow().pages.contains_key(&page_id);
+        assert!(!is_cached(&pager, 1) && is_cached(&pager, 2) && is_cached(&pager, 3));
+
+        // Page 2 is used more recently than page 3, so page 3 makes room for page 1
+        assert!(page_is(&pager, 2, 2));
+        assert!(page_is(&pager, 1, 1));
+        assert!(is_cached(&pager, 1) && is_cached(&pager, 2) && !is_cached(&pager, 3));
+
+        // Dirty pages don't count against the capacity
+        pager.write_page(3, &[4; 64]).unwrap();
+        assert!(page_is(&pager, 1, 1));
+        assert_eq!(pager.cached_page_count(), 3);
+
+        drop(pager);
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_savepoint_and_discard() {
+        let path = temp_path("disk-pager-savepoint");
+
+        let mut pager = DiskPager::create(&path, 64).unwrap();
+        for _ in 0..3 {
+            let page_id = pager.allocate_page().unwrap();
+            pager.write_page(page_id, &[1; 64]).unwrap();
+        }
+        pager.flush().unwrap();
+
+        // Page 1 is dirty before the savepoint
+        pager.write_page(1, &[2; 64]).unwrap();
+
+        pager.savepoint();
+        pager.write_page(1, &[3; 64]).unwrap();
+        pager.write_page(2, &[3; 64]).unwrap();
+        let page_id = pager.allocate_page().unwrap();
+        pager.write_page(page_id, &[3; 64]).unwrap();
+        pager.free_page(3).unwrap();
+        pager.rollback_to_savepoint();
+
+        assert_eq!(pager.page_count(), 3);
+        assert!(page_is(&pager, 1, 2));
+        assert!(page_is(&pager, 2, 1));
+        assert!(page_is(&pager, 3, 1));
+
+        // Discarding undoes the write from before the savepoint too
+        pager.discard();
+        assert_before_crash(&pager);
+        assert_eq!(pager.allocate_page().unwrap(), 4);
+
+        drop(pager);
+        remove_database_files(&path);
+    }
+}
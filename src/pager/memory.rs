@@ -0,0 +1,76 @@
+use super::{Pager, PagerError, PagerResult, is_valid_page_size};
+
+/// A pager whose pages live in process memory.
+/// Everything is lost once the pager is dropped.
+pub struct MemoryPager {
+    page_size: usize,
+    pages: Vec<Box<[u8]>>,
+    free_pages: Vec<u64>
+}
+
+impl MemoryPager {
+    pub fn new(page_size: usize) -> PagerResult<MemoryPager> {
+        if !is_valid_page_size(page_size) {
+            return Err(PagerError::InvalidPageSize(page_size));
+        }
+
+        Ok(MemoryPager {
+            page_size: page_size,
+            pages: Vec::new(),
+            free_pages: Vec::new()
+        })
+    }
+
+    fn page_index(&self, page_id: u64, buf_len: usize) -> PagerResult<usize> {
+        if page_id == 0 || page_id > self.pages.len() as u64 {
+            Err(PagerError::InvalidPageId(page_id))
+        } else if buf_len != self.page_size {
+            Err(PagerError::BadBufferLength(buf_len))
+        } else {
+            Ok((page_id - 1) as usize)
+        }
+    }
+}
+
+impl Pager for MemoryPager {
+    fn page_size(&self) -> usize { self.page_size }
+
+    fn page_count(&self) -> u64 { self.pages.len() as u64 }
+
+    fn read_page(&self, page_id: u64, buf: &mut [u8]) -> PagerResult<()> {
+        let i = try!(self.page_index(page_id, buf.len()));
+        buf.copy_from_slice(&self.pages[i]);
+        Ok(())
+    }
+
+    fn write_page(&mut self, page_id: u64, data: &[u8]) -> PagerResult<()> {
+        let i = try!(self.page_index(page_id, data.len()));
+        self.pages[i].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn allocate_page(&mut self) -> PagerResult<u64> {
+        if let Some(page_id) = self.free_pages.pop() {
+            let i = (page_id - 1) as usize;
+            for v in self.pages[i].iter_mut() {
+                *v = 0;
+            }
+            Ok(page_id)
+        } else {
+            self.pages.push(vec![0; self.page_size].into_boxed_slice());
+            Ok(self.pages.len() as u64)
+        }
+    }
+
+    fn free_page(&mut self, page_id: u64) -> PagerResult<()> {
+        let page_size = self.page_size;
+        try!(self.page_index(page_id, page_size));
+        self.free_pages.push(page_id);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> PagerResult<()> {
+        // There's nothing to make durable.
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+//! The pager partitions a backing store into a cache-friendly, addressable,
+//! and fixed-sized collection of pages.
+//!
+//! See `docs/Pager.md` for the invariants that every pager upholds.
+
+use std::fmt;
+use std::io;
+#[cfg(test)]
+use std::path::{Path, PathBuf};
+
+mod disk;
+// Nothing stores a B+Tree in memory yet, as TempDb keeps its rows in
+// `BTreeSet`s, so only the tests use the memory pager so far.
+#[allow(dead_code)]
+mod memory;
+mod wal;
+pub use self::disk::DiskPager;
+#[allow(unused_imports)]
+pub use self::memory::MemoryPager;
+
+pub const MIN_PAGE_SIZE: usize = 64;
+pub const MAX_PAGE_SIZE: usize = 65536;
+
+pub enum PagerError {
+    Io(io::Error),
+    InvalidPageSize(usize),
+    /// The page id is 0, or the page was never allocated.
+    InvalidPageId(u64),
+    /// A buffer passed to the pager doesn't match the page size.
+    BadBufferLength(usize),
+    /// The backing store doesn't contain a valid pager header.
+    BadHeader
+}
+
+impl fmt::Display for PagerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        use self::PagerError::*;
+
+        match self {
+            &Io(ref e) => write!(f, "pager I/O error: {}", e),
+            &InvalidPageSize(size) => {
+                write!(f, "invalid page size: {} (must be a power of 2 from {} to {})", size, MIN_PAGE_SIZE, MAX_PAGE_SIZE)
+            },
+            &InvalidPageId(page_id) => write!(f, "invalid page id: {}", page_id),
+            &BadBufferLength(len) => write!(f, "buffer length {} does not match the page size", len),
+            &BadHeader => write!(f, "not a LlamaDB file, or the file header is corrupt")
+        }
+    }
+}
+
+impl fmt::Debug for PagerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self)
+    }
+}
+
+impl From<io::Error> for PagerError {
+    fn from(e: io::Error) -> PagerError {
+        PagerError::Io(e)
+    }
+}
+
+pub type PagerResult<T> = Result<T, PagerError>;
+
+pub fn is_valid_page_size(page_size: usize) -> bool {
+    page_size >= MIN_PAGE_SIZE && page_size <= MAX_PAGE_SIZE && page_size.is_power_of_two()
+}
+
+/// Returns the path of a database file for a test, named after the test and
+/// the process so that concurrent test runs don't share files.
+/// Whatever an earlier run left at the path is removed.
+#[cfg(test)]
+pub fn temp_path(name: &str) -> PathBuf {
+    use std::{env, process};
+
+    let mut path = env::temp_dir();
+    path.push(format!("llamadb-test-{}-{}", process::id(), name));
+    remove_database_files(&path);
+    path
+}
+
+/// Removes a database file and its write-ahead log, if they exist.
+#[cfg(test)]
+pub fn remove_database_files(path: &Path) {
+    use std::fs;
+
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(wal::wal_path(path));
+}
+
+/// A collection of fixed-sized pages.
+///
+/// Valid page ids range from 1 to `page_count()`, inclusive.
+/// Page 0 is never a valid page id.
+///
+/// Freed pages are reused by later allocations. Reading a page that has been
+/// freed yields unspecified contents.
+pub trait Pager {
+    fn page_size(&self) -> usize;
+
+    /// The highest page id that was ever allocated.
+    // Only the tests ask for the page count so far.
+    #[allow(dead_code)]
+    fn page_count(&self) -> u64;
+
+    /// Copies the contents of the page into `buf`.
+    /// `buf` must be exactly one page long.
+    fn read_page(&self, page_id: u64, buf: &mut [u8]) -> PagerResult<()>;
+
+    /// Replaces the contents of the page with `data`.
+    /// `data` must be exactly one page long.
+    fn write_page(&mut self, page_id: u64, data: &[u8]) -> PagerResult<()>;
+
+    /// Allocates a zero-filled page, reusing a freed page if there is one.
+    fn allocate_page(&mut self) -> PagerResult<u64>;
+
+    /// Returns the page to the pager so that it can be reused.
+    fn free_page(&mut self, page_id: u64) -> PagerResult<()>;
+
+    /// Makes every write since the last flush durable.
+    fn flush(&mut self) -> PagerResult<()>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Pager, PagerError, DiskPager, MemoryPager, is_valid_page_size};
+    use super::{temp_path, remove_database_files};
+    use std::fs;
+
+    fn page_filled_with(pager: &Pager, value: u8) -> Vec<u8> {
+        vec![value; pager.page_size()]
+    }
+
+    fn test_read_write(pager: &mut Pager) {
+        let a = pager.allocate_page().unwrap();
+        let b = pager.allocate_page().unwrap();
+        assert!(a != 0 && b != 0 && a != b);
+
+        let mut buf = page_filled_with(pager, 0xFF);
+
+        // Newly allocated pages are zero-filled
+        pager.read_page(a, &mut buf).unwrap();
+        assert!(buf.iter().all(|&v| v == 0));
+
+        let data_a = page_filled_with(pager, 0xAA);
+        let data_b = page_filled_with(pager, 0xBB);
+        pager.write_page(a, &data_a).unwrap();
+        pager.write_page(b, &data_b).unwrap();
+
+        pager.read_page(a, &mut buf).unwrap();
+        assert_eq!(buf, data_a);
+        pager.read_page(b, &mut buf).unwrap();
+        assert_eq!(buf, data_b);
+    }
+
+    fn test_free_page_reuse(pager: &mut Pager) {
+        let a = pager.allocate_page().unwrap();
+        let b = pager.allocate_page().unwrap();
+        let count = pager.page_count();
+
+        let data = page_filled_with(pager, 0x42);
+        pager.write_page(a, &data).unwrap();
+
+        pager.free_page(a).unwrap();
+        pager.free_page(b).unwrap();
+
+        let c = pager.allocate_page().unwrap();
+        let d = pager.allocate_page().unwrap();
+        assert!((c == a && d == b) || (c == b && d == a));
+        assert_eq!(pager.page_count(), count);
+
+        // Reused pages are zero-filled
+        let mut buf = page_filled_with(pager, 0xFF);
+        pager.read_page(a, &mut buf).unwrap();
+        assert!(buf.iter().all(|&v| v == 0));
+
+        pager.allocate_page().unwrap();
+        assert_eq!(pager.page_count(), count + 1);
+    }
+
+    fn test_invalid_pages(pager: &mut Pager) {
+        let mut buf = page_filled_with(pager, 0);
+        let beyond = pager.page_count() + 1;
+
+        match pager.read_page(0, &mut buf) {
+            Err(PagerError::InvalidPageId(0)) => (),
+            _ => panic!("page 0 must be invalid")
+        }
+
+        match pager.read_page(beyond, &mut buf) {
+            Err(PagerError::InvalidPageId(_)) => (),
+            _ => panic!("unallocated page must be invalid")
+        }
+
+        let page_id = pager.allocate_page().unwrap();
+        let mut short_buf = vec![0; pager.page_size() - 1];
+        match pager.read_page(page_id, &mut short_buf) {
+            Err(PagerError::BadBufferLength(_)) => (),
+            _ => panic!("buffer must match the page size")
+        }
+    }
+
+    #[test]
+    fn test_page_sizes() {
+        assert!(is_valid_page_size(64));
+        assert!(is_valid_page_size(4096));
+        assert!(is_valid_page_size(65536));
+        assert!(!is_valid_page_size(32));
+        assert!(!is_valid_page_size(100));
+        assert!(!is_valid_page_size(131072));
+
+        assert!(MemoryPager::new(100).is_err());
+        assert!(DiskPager::create(&temp_path("bad-page-size"), 32).is_err());
+    }
+
+    #[test]
+    fn test_memory_pager() {
+        test_read_write(&mut MemoryPager::new(64).unwrap());
+        test_free_page_reuse(&mut MemoryPager::new(64).unwrap());
+        test_invalid_pages(&mut MemoryPager::new(64).unwrap());
+    }
+
+    #[test]
+    fn test_disk_pager() {
+        let path = temp_path("disk-pager");
+
+        test_read_write(&mut DiskPager::create(&path, 512).unwrap());
+        test_free_page_reuse(&mut DiskPager::create(&path, 512).unwrap());
+        test_invalid_pages(&mut DiskPager::create(&path, 512).unwrap());
+
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_disk_pager_reopen() {
+        let path = temp_path("disk-pager-reopen");
+
+        let (a, b, c) = {
+            let mut pager = DiskPager::create(&path, 128).unwrap();
+            let a = pager.allocate_page().unwrap();
+            let b = pager.allocate_page().unwrap();
+            let c = pager.allocate_page().unwrap();
+            pager.write_page(a, &[1; 128]).unwrap();
+            pager.write_page(c, &[3; 128]).unwrap();
+            pager.free_page(b).unwrap();
+            pager.flush().unwrap();
+            (a, b, c)
+        };
+
+        let mut pager = DiskPager::open(&path).unwrap();
+        assert_eq!(pager.page_size(), 128);
+        assert_eq!(pager.page_count(), 3);
+
+        let mut buf = [0; 128];
+        pager.read_page(a, &mut buf).unwrap();
+        assert!(buf.iter().all(|&v| v == 1));
+        pager.read_page(c, &mut buf).unwrap();
+        assert!(buf.iter().all(|&v| v == 3));
+
+        // The free list survives being reopened
+        assert_eq!(pager.allocate_page().unwrap(), b);
+
+        drop(pager);
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_disk_pager_unflushed_writes_are_lost() {
+        let path = temp_path("disk-pager-unflushed");
+
+        {
+            let mut pager = DiskPager::create(&path, 64).unwrap();
+            let page_id = pager.allocate_page().unwrap();
+            pager.write_page(page_id, &[7; 64]).unwrap();
+            pager.flush().unwrap();
+
+            pager.write_page(page_id, &[8; 64]).unwrap();
+            pager.allocate_page().unwrap();
+        }
+
+        let pager = DiskPager::open(&path).unwrap();
+        assert_eq!(pager.page_count(), 1);
+
+        let mut buf = [0; 64];
+        pager.read_page(1, &mut buf).unwrap();
+        assert!(buf.iter().all(|&v| v == 7));
+
+        drop(pager);
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_disk_pager_cache_eviction() {
+        let path = temp_path("disk-pager-cache");
+
+        {
+            let mut pager = DiskPager::create(&path, 64).unwrap();
+            pager.set_cache_capacity(4);
+
+            let page_ids: Vec<u64> = (0..32).map(|_| pager.allocate_page().unwrap()).collect();
+
+            for &page_id in &page_ids {
+                pager.write_page(page_id, &[page_id as u8; 64]).unwrap();
+            }
+
+            // Dirty pages can't be evicted until they're flushed
+            assert!(pager.cached_page_count() >= 32);
+            pager.flush().unwrap();
+            assert!(pager.cached_page_count() <= 4);
+
+            let mut buf = [0; 64];
+            for &page_id in &page_ids {
+                pager.read_page(page_id, &mut buf).unwrap();
+                assert!(buf.iter().all(|&v| v == page_id as u8));
+                assert!(pager.cached_page_count() <= 4);
+            }
+        }
+
+        remove_database_files(&path);
+    }
+
+    #[test]
+    fn test_disk_pager_bad_header() {
+        let path = temp_path("disk-pager-bad-header");
+        fs::File::create(&path).unwrap();
+
+        match DiskPager::open(&path) {
+            Err(PagerError::BadHeader) => (),
+            _ => panic!("empty file must have a bad header")
+        }
+
+        remove_database_files(&path);
+    }
+}
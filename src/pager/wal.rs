@@ -0,0 +1,207 @@
+//! The write-ahead log of a disk pager.
+//!
+//! On flush, every dirty page is first written to the log, followed by a
+//! commit record. Only once the log is synced are the pages written to the
+//! database file. The log is emptied once the database file is synced.
+//!
+//! If the process dies while the database file is being written, the log is
+//! replayed the next time the file is opened. A log without a valid commit
+//! record is discarded, leaving the database file as it was at the previous
+//! flush.
+//!
+//! The log starts with a header:
+//!
+//! | Size and type | Name                   |
+//! |---------------|------------------------|
+//! | 8             | Magic: "LlamaWAL"      |
+//! | 4, u32-le     | Page size              |
+//!
+//! Followed by any amount of page records:
+//!
+//! | Size and type | Name                   |
+//! |---------------|------------------------|
+//! | 1, u8         | Kind: 1                |
+//! | 8, u64-le     | Page id                |
+//! | Page size     | Page data              |
+//! | 8, u64-le     | Checksum               |
+//!
+//! Followed by a commit record:
+//!
+//! | Size and type | Name                   |
+//! |---------------|------------------------|
+//! | 1, u8         | Kind: 2                |
+//! | 8, u64-le     | Page count             |
+//! | 8, u64-le     | First free page        |
+//! | 8, u64-le     | Free page count        |
+//! | 8, u64-le     | Checksum               |
+//!
+//! Each checksum covers its own record, and is seeded with the previous
+//! record's checksum. A record with a bad checksum ends the log.
+
+use byteutils;
+use super::PagerResult;
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+static MAGIC: &'static [u8; 8] = b"LlamaWAL";
+
+const HEADER_LENGTH: usize = 12;
+
+const KIND_PAGE: u8 = 1;
+const KIND_COMMIT: u8 = 2;
+
+const CHECKSUM_LENGTH: usize = 8;
+const COMMIT_LENGTH: usize = 1 + 8 * 3 + CHECKSUM_LENGTH;
+
+/// The pager state that is committed along with the pages.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CommitState {
+    pub page_count: u64,
+    pub free_list_head: u64,
+    pub free_page_count: u64
+}
+
+/// A committed set of pages, read back from the log.
+pub struct Commit {
+    pub pages: Vec<(u64, Box<[u8]>)>,
+    pub state: CommitState
+}
+
+/// The log of `database.db` is `database.db-wal`.
+pub fn wal_path(path: &Path) -> PathBuf {
+    let mut s: OsString = path.as_os_str().to_owned();
+    s.push("-wal");
+    PathBuf::from(s)
+}
+
+/// FNV-1a, seeded with the previous checksum.
+fn checksum(seed: u64, data: &[u8]) -> u64 {
+    data.iter().fold(seed, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn initial_checksum(page_size: usize) -> u64 {
+    let mut buf = [0; 4];
+    byteutils::write_u32_le(page_size as u32, &mut buf);
+    checksum(0xcbf29ce484222325, &buf)
+}
+
+/// Replaces the contents of the log with the pages and a commit record,
+/// and syncs the log.
+pub fn write(file: &mut File, page_size: usize, pages: &[(u64, &[u8])], state: CommitState) -> PagerResult<()> {
+    let mut buf = Vec::with_capacity(HEADER_LENGTH + pages.len() * (1 + 8 + page_size + CHECKSUM_LENGTH) + COMMIT_LENGTH);
+
+    buf.extend_from_slice(MAGIC);
+    push_u32(&mut buf, page_size as u32);
+
+    let mut sum = initial_checksum(page_size);
+
+    for &(page_id, data) in pages {
+        assert_eq!(data.len(), page_size);
+
+        let start = buf.len();
+        buf.push(KIND_PAGE);
+        push_u64(&mut buf, page_id);
+        buf.extend_from_slice(data);
+
+        sum = checksum(sum, &buf[start..]);
+        push_u64(&mut buf, sum);
+    }
+
+    let start = buf.len();
+    buf.push(KIND_COMMIT);
+    push_u64(&mut buf, state.page_count);
+    push_u64(&mut buf, state.free_list_head);
+    push_u64(&mut buf, state.free_page_count);
+
+    sum = checksum(sum, &buf[start..]);
+    push_u64(&mut buf, sum);
+
+    try!(file.set_len(0));
+    try!(file.seek(SeekFrom::Start(0)));
+    try!(file.write_all(&buf));
+    try!(file.sync_all());
+    Ok(())
+}
+
+/// Empties the log. Called once the log's pages are durable in the database file.
+pub fn clear(file: &mut File) -> PagerResult<()> {
+    try!(file.set_len(0));
+    try!(file.sync_all());
+    Ok(())
+}
+
+/// Reads the committed pages from the log.
+/// Returns None if the log is empty, or if the header or any record is missing or damaged.
+pub fn read(file: &mut File, page_size: usize) -> PagerResult<Option<Commit>> {
+    let mut buf = Vec::new();
+    try!(file.seek(SeekFrom::Start(0)));
+    try!(file.read_to_end(&mut buf));
+
+    if buf.len() < HEADER_LENGTH {
+        // The log is empty, or the header itself was torn
+        return Ok(None);
+    }
+
+    if &buf[0..8] != MAGIC || byteutils::read_u32_le(&buf[8..12]) as usize != page_size {
+        return Ok(None);
+    }
+
+    let mut sum = initial_checksum(page_size);
+    let mut offset = HEADER_LENGTH;
+    let mut pages = Vec::new();
+
+    loop {
+        let record_length = match buf.get(offset) {
+            Some(&KIND_PAGE) => 1 + 8 + page_size + CHECKSUM_LENGTH,
+            Some(&KIND_COMMIT) => COMMIT_LENGTH,
+            // Garbage, or the end of the log without a commit record
+            _ => return Ok(None)
+        };
+
+        if offset + record_length > buf.len() {
+            // Torn record
+            return Ok(None);
+        }
+
+        let record = &buf[offset..offset + record_length];
+        let body = &record[0..record_length - CHECKSUM_LENGTH];
+
+        sum = checksum(sum, body);
+        if byteutils::read_u64_le(&record[record_length - CHECKSUM_LENGTH..]) != sum {
+            return Ok(None);
+        }
+
+        if record[0] == KIND_COMMIT {
+            return Ok(Some(Commit {
+                pages: pages,
+                state: CommitState {
+                    page_count: byteutils::read_u64_le(&body[1..9]),
+                    free_list_head: byteutils::read_u64_le(&body[9..17]),
+                    free_page_count: byteutils::read_u64_le(&body[17..25])
+                }
+            }));
+        }
+
+        let page_id = byteutils::read_u64_le(&body[1..9]);
+        pages.push((page_id, body[9..].to_vec().into_boxed_slice()));
+
+        offset += record_length;
+    }
+}
+
+fn push_u32(buf: &mut Vec<u8>, value: u32) {
+    let mut b = [0; 4];
+    byteutils::write_u32_le(value, &mut b);
+    buf.extend_from_slice(&b);
+}
+
+fn push_u64(buf: &mut Vec<u8>, value: u64) {
+    let mut b = [0; 8];
+    byteutils::write_u64_le(value, &mut b);
+    buf.extend_from_slice(&b);
+}
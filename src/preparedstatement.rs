@@ -0,0 +1,232 @@
+//! Statements that are parsed once and executed any number of times, with
+//! different values for their placeholders.
+//!
+//! A statement is compiled the first time it's executed, and its query plan
+//! is kept for the executions that follow. It's only compiled again if it's
+//! executed against another database, or the schema of the database changes.
+
+use databaseengine::{CompiledStatement, DatabaseEngine, ExecuteStatementResult};
+use error::Error;
+use sqlsyntax::{ast, lexer, parser};
+use types::Variant;
+
+pub struct PreparedStatement {
+    statement: ast::Statement,
+    /// The names of the parameters, by their number minus one
+    parameter_names: Vec<Option<String>>,
+    /// The bound values of the parameters, by their number minus one
+    params: Vec<Variant>,
+    /// The statement as it was compiled against the database it was last executed against
+    compiled: Option<CompiledStatement>
+}
+
+/// Parses a single statement, whose placeholders are `?`, `?NNN` or `:name`.
+/// Every parameter is NULL until it's bound.
+pub fn prepare(sql: &str) -> Result<PreparedStatement, Error> {
+    let mut lexer = lexer::Lexer::new();
+
+    lexer.feed_characters(sql.chars());
+    lexer.feed_character(None);
+
+    if let Some(e) = lexer.errors.into_iter().next() {
+        return Err(Error::from(e));
+    }
+
+    let statement = try!(parser::parse_statement(&lexer.tokens));
+    let params = vec![Variant::Null; lexer.placeholder_names.len()];
+
+    Ok(PreparedStatement {
+        statement: statement,
+        parameter_names: lexer.placeholder_names,
+        params: params,
+        compiled: None
+    })
+}
+
+impl PreparedStatement {
+    /// The largest parameter number in the statement
+    pub fn parameter_count(&self) -> u32 {
+        self.parameter_names.len() as u32
+    }
+
+    /// Returns the number of the parameter with the name, without its `:`.
+    pub fn parameter_number(&self, name: &str) -> Option<u32> {
+        self.parameter_names.iter().position(|n| n.as_ref().map(|n| &n[..]) == Some(name)).map(|i| i as u32 + 1)
+    }
+
+    /// Sets the value of the parameter with the number, starting from 1.
+    pub fn bind(&mut self, number: u32, value: Variant) -> Result<(), Error> {
+        if number == 0 || number > self.parameter_count() {
+            return Err(Error::Invalid(format!("parameter number {} is out of range: the statement has {} parameters", number, self.parameter_count())));
+        }
+
+        self.params[number as usize - 1] = value;
+        Ok(())
+    }
+
+    /// Sets the value of the parameter with the name, without its `:`.
+    pub fn bind_name(&mut self, name: &str, value: Variant) -> Result<(), Error> {
+        match self.parameter_number(name) {
+            Some(number) => self.bind(number, value),
+            None => Err(Error::Invalid(format!("the statement has no parameter named :{}", name)))
+        }
+    }
+
+    /// Sets every parameter back to NULL.
+    pub fn clear_bindings(&mut self) {
+        for value in self.params.iter_mut() {
+            *value = Variant::Null;
+        }
+    }
+
+    pub fn execute<'a, DB: DatabaseEngine + ?Sized>(&mut self, db: &'a mut DB) -> ExecuteStatementResult<'a> {
+        try!(self.compile(db));
+        db.execute_compiled(self.compiled.as_ref().unwrap(), &self.params)
+    }
+
+    /// Executes the statement with `params` instead of the bound values.
+    /// There must be a value for every parameter.
+    pub fn execute_with<'a, DB: DatabaseEngine + ?Sized>(&mut self, db: &'a mut DB, params: &[Variant]) -> ExecuteStatementResult<'a> {
+        if params.len() != self.params.len() {
+            return Err(Error::Invalid(format!("the statement has {} parameters, but {} values were given", self.params.len(), params.len())));
+        }
+
+        try!(self.compile(db));
+        db.execute_compiled(self.compiled.as_ref().unwrap(), params)
+    }
+
+    /// Compiles the statement against the database, unless it's already
+    /// compiled against its current schema. The statement is also compiled
+    /// when it's executed, if it needs to be.
+    pub fn compile<DB: DatabaseEngine + ?Sized>(&mut self, db: &DB) -> Result<(), Error> {
+        let is_up_to_date = match self.compiled {
+            Some(ref compiled) => db.is_up_to_date(compiled),
+            None => false
+        };
+
+        if !is_up_to_date {
+            let parameter_count = self.parameter_count();
+            self.compiled = Some(try!(db.compile_statement(&self.statement, parameter_count)));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use databaseengine::{DatabaseEngine, ExecuteStatementResponse};
+    use sqlsyntax::parse_statement;
+    use tempdb::TempDb;
+    use types::Variant;
+    use super::{prepare, PreparedStatement};
+
+    fn select(db: &mut TempDb, sql: &str, params: &[Variant]) -> Vec<String> {
+        match db.execute_statement_with_params(&parse_statement(sql).unwrap(), params) {
+            Ok(ExecuteStatementResponse::Select { rows, .. }) => {
+                rows.map(|row| {
+                    let row = row.unwrap();
+                    let v: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+                    v.join(", ")
+                }).collect()
+            },
+            Ok(_) => panic!("expected SELECT response"),
+            Err(e) => panic!("{}", e)
+        }
+    }
+
+    #[test]
+    fn test_prepared_insert() {
+        let mut db = TempDb::new();
+        db.execute_statement(parse_statement("CREATE TABLE t (a U32, b STRING NULL);").unwrap()).unwrap();
+
+        let mut insert = prepare("INSERT INTO t VALUES (?, ? || '!');").unwrap();
+        assert_eq!(insert.parameter_count(), 2);
+
+        for i in 1..4 {
+            insert.bind(1, Variant::UnsignedInteger(i)).unwrap();
+            insert.bind(2, Variant::StringLiteral(format!("x{}", i))).unwrap();
+            insert.execute(&mut db).unwrap();
+        }
+
+        // Unbound parameters are NULL
+        insert.clear_bindings();
+        insert.bind(1, Variant::UnsignedInteger(4)).unwrap();
+        insert.execute(&mut db).unwrap();
+
+        assert!(insert.bind(0, Variant::Null).is_err());
+        assert!(insert.bind(3, Variant::Null).is_err());
+
+        assert_eq!(select(&mut db, "SELECT a, b FROM t;", &[]), vec!["1, x1!", "2, x2!", "3, x3!", "4, NULL"]);
+    }
+
+    #[test]
+    fn test_prepared_parameters() {
+        let mut db = TempDb::new();
+        db.execute_statement(parse_statement("CREATE TABLE t (a U32, b STRING);").unwrap()).unwrap();
+        db.execute_statement(parse_statement("INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c');").unwrap()).unwrap();
+
+        let mut stmt = prepare("SELECT b FROM t WHERE a >= :min AND a <= ?3 AND b <> :skip OR a = :min * 10;").unwrap();
+        assert_eq!(stmt.parameter_count(), 4);
+        assert_eq!(stmt.parameter_number("min"), Some(1));
+        assert_eq!(stmt.parameter_number("skip"), Some(4));
+        assert_eq!(stmt.parameter_number("none"), None);
+
+        stmt.bind_name("min", Variant::UnsignedInteger(2)).unwrap();
+        stmt.bind(3, Variant::UnsignedInteger(3)).unwrap();
+        stmt.bind_name("skip", Variant::StringLiteral("c".to_string())).unwrap();
+        assert!(stmt.bind_name("none", Variant::Null).is_err());
+
+        let rows: Vec<String> = match stmt.execute(&mut db).unwrap() {
+            ExecuteStatementResponse::Select { rows, .. } => rows.map(|row| row.unwrap()[0].to_string()).collect(),
+            _ => panic!("expected SELECT response")
+        };
+        assert_eq!(rows, vec!["b"]);
+
+        // A statement that isn't prepared has no parameters
+        assert!(db.execute_statement(parse_statement("SELECT a FROM t WHERE a = ?;").unwrap()).is_err());
+        assert_eq!(select(&mut db, "SELECT a FROM t WHERE a = ?2;", &[Variant::Null, Variant::UnsignedInteger(3)]), vec!["3"]);
+    }
+
+    #[test]
+    fn test_prepared_compiled_once() {
+        let rows = |stmt: &mut PreparedStatement, db: &mut TempDb| -> Vec<String> {
+            match stmt.execute(db).unwrap() {
+                ExecuteStatementResponse::Select { rows, .. } => {
+                    rows.map(|row| {
+                        let v: Vec<String> = row.unwrap().iter().map(|value| value.to_string()).collect();
+                        v.join(", ")
+                    }).collect()
+                },
+                _ => panic!("expected SELECT response")
+            }
+        };
+
+        let mut db = TempDb::new();
+        db.execute_statement(parse_statement("CREATE TABLE t (a U32, b STRING);").unwrap()).unwrap();
+        db.execute_statement(parse_statement("INSERT INTO t VALUES (1, 'x'), (2, 'y');").unwrap()).unwrap();
+
+        let mut stmt = prepare("SELECT * FROM t WHERE a = ?;").unwrap();
+        stmt.bind(1, Variant::UnsignedInteger(1)).unwrap();
+        assert_eq!(rows(&mut stmt, &mut db), vec!["1, x"]);
+
+        // The compiled statement is kept while the schema stays the same
+        db.execute_statement(parse_statement("INSERT INTO t VALUES (1, 'z');").unwrap()).unwrap();
+        assert!(db.is_up_to_date(stmt.compiled.as_ref().unwrap()));
+        assert_eq!(rows(&mut stmt, &mut db), vec!["1, x", "1, z"]);
+
+        // It's compiled again once the table is replaced, whose columns are now in another order
+        db.execute_statement(parse_statement("DROP TABLE t;").unwrap()).unwrap();
+        db.execute_statement(parse_statement("CREATE TABLE t (b STRING, a U32);").unwrap()).unwrap();
+        db.execute_statement(parse_statement("INSERT INTO t VALUES ('w', 1);").unwrap()).unwrap();
+        assert!(!db.is_up_to_date(stmt.compiled.as_ref().unwrap()));
+        assert_eq!(rows(&mut stmt, &mut db), vec!["w, 1"]);
+        assert!(db.is_up_to_date(stmt.compiled.as_ref().unwrap()));
+
+        // ... and when it's executed against another database
+        let mut other = TempDb::new();
+        other.execute_statement(parse_statement("CREATE TABLE t (a U32);").unwrap()).unwrap();
+        other.execute_statement(parse_statement("INSERT INTO t VALUES (1);").unwrap()).unwrap();
+        assert_eq!(rows(&mut stmt, &mut other), vec!["1"]);
+    }
+}
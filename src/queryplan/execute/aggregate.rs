@@ -1,8 +1,9 @@
 use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
+use error::Error;
 use super::super::sexpression::AggregateOp;
 
 pub trait AggregateFunction<ColumnValue> {
-    fn feed(&mut self, value: ColumnValue);
+    fn feed(&mut self, value: ColumnValue) -> Result<(), Error>;
     fn finish(self: Box<Self>) -> ColumnValue;
 }
 
@@ -12,10 +13,11 @@ struct Count {
 
 impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Count
 {
-    fn feed(&mut self, value: ColumnValue) {
+    fn feed(&mut self, value: ColumnValue) -> Result<(), Error> {
         if !value.is_null() {
             self.count += 1;
         }
+        Ok(())
     }
 
     fn finish(self: Box<Self>) -> ColumnValue {
@@ -23,17 +25,23 @@ impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Count
     }
 }
 
+fn to_number<ColumnValue: ColumnValueOps>(value: ColumnValue) -> Result<f64, Error> {
+    let dbtype = value.get_dbtype();
+    value.to_f64().map_err(|()| Error::TypeMismatch(format!("can't add up values of type {:?}: they aren't numbers", dbtype)))
+}
+
 struct Avg {
     sum: f64,
     count: u64
 }
 
 impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Avg {
-    fn feed(&mut self, value: ColumnValue) {
+    fn feed(&mut self, value: ColumnValue) -> Result<(), Error> {
         if !value.is_null() {
-            self.sum += value.to_f64().unwrap();
+            self.sum += try!(to_number(value));
             self.count += 1;
         }
+        Ok(())
     }
 
     fn finish(self: Box<Self>) -> ColumnValue {
@@ -51,11 +59,12 @@ struct Sum {
 }
 
 impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Sum {
-    fn feed(&mut self, value: ColumnValue) {
+    fn feed(&mut self, value: ColumnValue) -> Result<(), Error> {
         if !value.is_null() {
-            self.sum += value.to_f64().unwrap();
+            self.sum += try!(to_number(value));
             self.count += 1;
         }
+        Ok(())
     }
 
     fn finish(self: Box<Self>) -> ColumnValue {
@@ -72,7 +81,7 @@ struct Min<ColumnValue> {
 }
 
 impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Min<ColumnValue> {
-    fn feed(&mut self, value: ColumnValue) {
+    fn feed(&mut self, value: ColumnValue) -> Result<(), Error> {
         let set = !value.is_null() && if let Some(r) = self.value.as_ref() {
             match value.compare(r) {
                 Some(-1) => true,
@@ -83,6 +92,7 @@ impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Min<ColumnV
         };
 
         if set { self.value = Some(value); }
+        Ok(())
     }
 
     fn finish(self: Box<Self>) -> ColumnValue {
@@ -95,7 +105,7 @@ struct Max<ColumnValue> {
 }
 
 impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Max<ColumnValue> {
-    fn feed(&mut self, value: ColumnValue) {
+    fn feed(&mut self, value: ColumnValue) -> Result<(), Error> {
         let set = !value.is_null() && if let Some(r) = self.value.as_ref() {
             match value.compare(r) {
                 Some(1) => true,
@@ -106,6 +116,7 @@ impl<ColumnValue: ColumnValueOps> AggregateFunction<ColumnValue> for Max<ColumnV
         };
 
         if set { self.value = Some(value); }
+        Ok(())
     }
 
     fn finish(self: Box<Self>) -> ColumnValue {
@@ -1,7 +1,17 @@
 use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
-use databaseinfo::DatabaseInfo;
+use databaseinfo::{DatabaseInfo, TableInfo};
 use databasestorage::{DatabaseStorage, Group};
-use super::sexpression::{BinaryOp, UnaryOp, SExpression};
+use error::Error;
+use identifier::Identifier;
+use sortkey::{self, Order};
+use super::sexpression::{BinaryOp, UnaryOp, SExpression, SortOrder};
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::rc::Rc;
+use std::vec;
 
 mod aggregate;
 use self::aggregate::*;
@@ -9,25 +19,27 @@ use self::aggregate::*;
 mod groupbuckets;
 use self::groupbuckets::GroupBuckets;
 
-enum SourceType<'a, ColumnValue: Sized + 'static> {
-    Row(&'a [ColumnValue]),
-    Group(&'a Group<ColumnValue=ColumnValue>)
+enum SourceType<'a, ColumnValue: Clone + Eq + Hash + 'static> {
+    Row(Cow<'a, [ColumnValue]>),
+    Group(Box<Group<ColumnValue=ColumnValue> + 'a>)
 }
 
-struct Source<'a, ColumnValue: Sized + 'static> {
-    parent: Option<&'a Source<'a, ColumnValue>>,
+/// A row or group that expressions refer to by its source id.
+/// It's shared by the cursors of the nodes that are nested in the one that yielded it.
+struct Source<'a, ColumnValue: Clone + Eq + Hash + 'static> {
+    parent: Option<Rc<Source<'a, ColumnValue>>>,
     source_id: u32,
     source_type: SourceType<'a, ColumnValue>
 }
 
-impl<'a, ColumnValue: Sized> Source<'a, ColumnValue> {
+impl<'a, ColumnValue: Clone + Eq + Hash + 'static> Source<'a, ColumnValue> {
     fn find_row_from_source_id(&self, source_id: u32) -> Option<&[ColumnValue]> {
         if self.source_id == source_id {
             match &self.source_type {
-                &SourceType::Row(row) => Some(row),
+                &SourceType::Row(ref row) => Some(row),
                 _ => None
             }
-        } else if let Some(parent) = self.parent {
+        } else if let Some(ref parent) = self.parent {
             parent.find_row_from_source_id(source_id)
         } else {
             None
@@ -37,10 +49,10 @@ impl<'a, ColumnValue: Sized> Source<'a, ColumnValue> {
     fn find_group_from_source_id(&self, source_id: u32) -> Option<&Group<ColumnValue=ColumnValue>> {
         if self.source_id == source_id {
             match &self.source_type {
-                &SourceType::Group(group) => Some(group),
+                &SourceType::Group(ref group) => Some(&**group),
                 _ => None
             }
-        } else if let Some(parent) = self.parent {
+        } else if let Some(ref parent) = self.parent {
             parent.find_group_from_source_id(source_id)
         } else {
             None
@@ -48,164 +60,454 @@ impl<'a, ColumnValue: Sized> Source<'a, ColumnValue> {
     }
 }
 
-/// The query plan is currently defined as a recursive language.
-/// Because of this, it would take some work (and foresight) to make query plan
-/// execution co-operate with the concept of iterators.
-/// For now, callbacks are used to yield rows.
+/// The state of a node of a query plan whose rows are being read.
 ///
-/// TODO: translate query plan into procedural language
-/// (such as VM instructions, like those found in SQLite's VBDE).
+/// A cursor doesn't refer to its node; the node is passed along with it
+/// whenever a row is read. This way, the rows of a query plan can be read
+/// by whoever owns the query plan, long after it's been borrowed.
+enum Cursor<'a, ColumnValue: Clone + Eq + Hash + 'static> {
+    /// No more rows are yielded.
+    Empty,
+    /// Rows that are already known, such as those of a hash table.
+    /// The node isn't executed.
+    Values(vec::IntoIter<Vec<ColumnValue>>),
+    /// Scan, Search and Sort
+    Rows(RowsCursor<'a, ColumnValue>),
+    /// Map, LeftJoin and HashJoin
+    Join(JoinCursor<'a, ColumnValue>),
+    OuterJoin(OuterJoinCursor<'a, ColumnValue>),
+    TempGroupBy(GroupsCursor<'a, ColumnValue>),
+    Distinct {
+        in_cursor: Box<Cursor<'a, ColumnValue>>,
+        yielded_rows: HashSet<Box<[ColumnValue]>>
+    },
+    Limit {
+        in_cursor: Box<Cursor<'a, ColumnValue>>,
+        skip: u64,
+        remaining: u64
+    },
+    Yield(Option<Vec<ColumnValue>>),
+    If {
+        /// The offset of the chain whose yield_fn is executed, or the number
+        /// of chains if it's the else
+        branch: usize,
+        cursor: Box<Cursor<'a, ColumnValue>>
+    }
+}
+
+/// Executes the node's yield_fn on each of the rows.
+struct RowsCursor<'a, ColumnValue: Clone + Eq + Hash + 'static> {
+    source: Option<Rc<Source<'a, ColumnValue>>>,
+    rows: Box<Iterator<Item=Result<Cow<'a, [ColumnValue]>, Error>> + 'a>,
+    yield_cursor: Option<Box<Cursor<'a, ColumnValue>>>
+}
+
+/// Executes the node's yield_out_fn on each of the rows of its yield_in_fn
+/// that pass the predicate.
+struct JoinCursor<'a, ColumnValue: Clone + Eq + Hash + 'static> {
+    source: Option<Rc<Source<'a, ColumnValue>>>,
+    in_cursor: Box<Cursor<'a, ColumnValue>>,
+    one_or_more_rows: bool,
+    yield_cursor: Option<Box<Cursor<'a, ColumnValue>>>
+}
+
+struct OuterJoinCursor<'a, ColumnValue: Clone + Eq + Hash + 'static> {
+    source: Option<Rc<Source<'a, ColumnValue>>>,
+    left_cursor: Box<Cursor<'a, ColumnValue>>,
+    /// The left row that's being joined to the right rows
+    left_row: Option<Vec<ColumnValue>>,
+    right_rows: Vec<Vec<ColumnValue>>,
+    right_rows_joined: Vec<bool>,
+    /// The next right row to join to the left row
+    right_offset: usize,
+    /// The next right row to check once the left rows have run out
+    unmatched_offset: usize,
+    one_or_more_rows: bool
+}
+
+/// Executes the node's yield_out_fn on each of the groups.
+struct GroupsCursor<'a, ColumnValue: Clone + Eq + Hash + 'static> {
+    source: Option<Rc<Source<'a, ColumnValue>>>,
+    groups: groupbuckets::IntoIter<ColumnValue>,
+    yield_cursor: Option<Box<Cursor<'a, ColumnValue>>>
+}
+
+/// The rows of a hash join's yield_in_fn, by their hash keys
+type HashTable<ColumnValue> = HashMap<Vec<u8>, Vec<Vec<ColumnValue>>>;
+
+/// Executes query plans.
+///
+/// The rows of a node are read one at a time from its cursor, which reads
+/// rows from the cursors of the nodes it executes as it needs them.
+/// Only the nodes that need all of their input before yielding a row
+/// (such as sorts and groups) keep rows in memory.
 pub struct ExecuteQueryPlan<'s, Storage: DatabaseStorage + 's> {
-    storage: &'s Storage
+    storage: &'s Storage,
+    /// The values of the plan's placeholders, starting from `?1`
+    params: Vec<<Storage::Info as DatabaseInfo>::ColumnValue>,
+    /// The hash tables of the hash joins that have been executed, by the
+    /// address of their node. Cleared whenever a plan is executed.
+    hash_tables: RefCell<HashMap<usize, Rc<HashTable<<Storage::Info as DatabaseInfo>::ColumnValue>>>>
 }
 
-impl<'a, 's, Storage: DatabaseStorage> ExecuteQueryPlan<'s, Storage>
+/// The rows of a query plan, which are read from the database as they're iterated.
+/// Iteration stops after the first error.
+pub struct QueryPlanRows<'a, Storage: DatabaseStorage + 'a>
 where <Storage::Info as DatabaseInfo>::Table: 'a
 {
-    pub fn new(storage: &'s Storage) -> ExecuteQueryPlan<'s, Storage> {
+    execute: ExecuteQueryPlan<'a, Storage>,
+    expr: Rc<SExpression<Storage::Info>>,
+    cursor: Cursor<'a, <Storage::Info as DatabaseInfo>::ColumnValue>
+}
+
+impl<'a, Storage> QueryPlanRows<'a, Storage>
+where Storage: DatabaseStorage<Info=Storage> + DatabaseInfo
+{
+    /// Starts executing the query plan, with the values of its placeholders.
+    /// The nodes that read all of their input up front, such as a top-level
+    /// sort, are executed before this returns.
+    ///
+    /// The plan is shared, so that a compiled plan can be executed again
+    /// while the rows of an earlier execution are being read.
+    pub fn new(storage: &'a Storage, expr: Rc<SExpression<Storage::Info>>, params: &[<Storage::Info as DatabaseInfo>::ColumnValue])
+    -> Result<QueryPlanRows<'a, Storage>, Error>
+    {
+        let execute = ExecuteQueryPlan::new(storage, params);
+        let cursor = try!(execute.open(&expr, None));
+
+        Ok(QueryPlanRows {
+            execute: execute,
+            expr: expr,
+            cursor: cursor
+        })
+    }
+}
+
+impl<'a, Storage> Iterator for QueryPlanRows<'a, Storage>
+where Storage: DatabaseStorage<Info=Storage> + DatabaseInfo
+{
+    type Item = Result<Vec<<Storage::Info as DatabaseInfo>::ColumnValue>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.execute.next(&self.expr, &mut self.cursor) {
+            Ok(row) => row.map(Ok),
+            Err(e) => {
+                self.cursor = Cursor::Empty;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<'s, Storage> ExecuteQueryPlan<'s, Storage>
+where Storage: DatabaseStorage<Info=Storage> + DatabaseInfo
+{
+    /// `params` are the values of the placeholders of the plans that are executed.
+    pub fn new(storage: &'s Storage, params: &[<Storage::Info as DatabaseInfo>::ColumnValue]) -> ExecuteQueryPlan<'s, Storage> {
         ExecuteQueryPlan {
-            storage: storage
+            storage: storage,
+            params: params.to_vec(),
+            hash_tables: RefCell::new(HashMap::new())
         }
     }
 
-    // TODO: result_cb should yield a boxed array instead of a reference
-    pub fn execute_query_plan<'b, 'c>(&self, expr: &SExpression<'a, Storage::Info>,
-    result_cb: &'c mut FnMut(&[<Storage::Info as DatabaseInfo>::ColumnValue]) -> Result<(), String>)
-    -> Result<(), String>
+    pub fn execute_query_plan<'c>(&self, expr: &SExpression<Storage::Info>,
+    result_cb: &'c mut FnMut(&[<Storage::Info as DatabaseInfo>::ColumnValue]) -> Result<(), Error>)
+    -> Result<(), Error>
     {
-        self.execute(expr, result_cb, None)
+        self.hash_tables.borrow_mut().clear();
+
+        let mut cursor = try!(self.open(expr, None));
+
+        while let Some(row) = try!(self.next(expr, &mut cursor)) {
+            try!(result_cb(&row));
+        }
+
+        Ok(())
     }
 
-    pub fn execute_expression(&self, expr: &SExpression<'a, Storage::Info>)
-    -> Result<<Storage::Info as DatabaseInfo>::ColumnValue, String>
+    pub fn execute_expression(&self, expr: &SExpression<Storage::Info>)
+    -> Result<<Storage::Info as DatabaseInfo>::ColumnValue, Error>
     {
+        self.hash_tables.borrow_mut().clear();
         self.resolve_value(expr, None)
     }
 
-    fn execute<'b, 'c>(&self, expr: &SExpression<'a, Storage::Info>,
-        result_cb: &'c mut FnMut(&[<Storage::Info as DatabaseInfo>::ColumnValue]) -> Result<(), String>,
-        source: Option<&Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>)
-    -> Result<(), String>
+    /// Executes an expression from `compile_ast_expression` on a row of its table.
+    pub fn execute_expression_on_row(&self, expr: &SExpression<Storage::Info>,
+        row: &[<Storage::Info as DatabaseInfo>::ColumnValue])
+    -> Result<<Storage::Info as DatabaseInfo>::ColumnValue, Error>
     {
-        match expr {
-            &SExpression::Scan { table, source_id, ref yield_fn } => {
-                let group = self.storage.scan_table(table);
-                for row in group.iter() {
-                    let new_source = Source {
-                        parent: source,
-                        source_id: source_id,
-                        source_type: SourceType::Row(&row)
-                    };
+        let source = Rc::new(Source {
+            parent: None,
+            source_id: super::ROW_SOURCE_ID,
+            source_type: SourceType::Row(Cow::Borrowed(row))
+        });
+
+        self.hash_tables.borrow_mut().clear();
+        self.resolve_value(expr, Some(&source))
+    }
 
-                    try!(self.execute(yield_fn, result_cb, Some(&new_source)));
-                }
+    /// Returns the hash key of the values, or None if any of them are NULL.
+    fn hash_key<'b>(&self, values: &[SExpression<Storage::Info>],
+        source: Option<&Rc<Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>>)
+    -> Result<Option<Vec<u8>>, Error>
+    where 's: 'b
+    {
+        let mut key = Vec::new();
 
-                Ok(())
-            },
-            &SExpression::LeftJoin { source_id, ref yield_in_fn, ref predicate, ref yield_out_fn, ref right_rows_if_none } => {
-                let mut one_or_more_rows = false;
+        for value in values {
+            let value = try!(self.resolve_value(value, source));
 
-                try!(self.execute(yield_in_fn, &mut |row| {
-                    let new_source = Source {
-                        parent: source,
-                        source_id: source_id,
-                        source_type: SourceType::Row(row)
-                    };
+            if value.is_null() {
+                return Ok(None);
+            }
 
-                    let pred_result = try!(self.resolve_value(predicate, Some(&new_source)));
+            try!(sortkey::push_any_value(&mut key, Order::Ascending, &value).map_err(|()| {
+                Error::TypeMismatch(format!("join value can't be hashed"))
+            }));
+        }
 
-                    if pred_result.tests_true() {
-                        one_or_more_rows = true;
-                        self.execute(yield_out_fn, result_cb, Some(&new_source))
-                    } else {
-                        Ok(())
-                    }
-                }, source));
+        Ok(Some(key))
+    }
 
-                if !one_or_more_rows {
-                    // no rows were matched
-                    let new_source = Source {
-                        parent: source,
-                        source_id: source_id,
-                        source_type: SourceType::Row(right_rows_if_none)
-                    };
+    /// Returns the hash table of a hash join, executing its yield_in_fn the
+    /// first time the node is executed.
+    fn hash_table<'b>(&self, expr: &SExpression<Storage::Info>, source_id: u32,
+        yield_in_fn: &SExpression<Storage::Info>, build_keys: &[SExpression<Storage::Info>],
+        source: Option<&Rc<Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>>)
+    -> Result<Rc<HashTable<<Storage::Info as DatabaseInfo>::ColumnValue>>, Error>
+    where 's: 'b
+    {
+        let address = expr as *const _ as usize;
 
-                    self.execute(yield_out_fn, result_cb, Some(&new_source))
-                } else {
-                    Ok(())
-                }
+        if let Some(table) = self.hash_tables.borrow().get(&address) {
+            return Ok(table.clone());
+        }
+
+        let mut table = HashMap::new();
+        let mut cursor = try!(self.open(yield_in_fn, source));
+
+        while let Some(row) = try!(self.next(yield_in_fn, &mut cursor)) {
+            let key = {
+                let new_source = Rc::new(Source {
+                    parent: source.cloned(),
+                    source_id: source_id,
+                    source_type: SourceType::Row(Cow::Borrowed(&row[..]))
+                });
+
+                try!(self.hash_key(build_keys, Some(&new_source)))
+            };
+
+            if let Some(key) = key {
+                table.entry(key).or_insert_with(Vec::new).push(row);
+            }
+        }
+
+        let table = Rc::new(table);
+        self.hash_tables.borrow_mut().insert(address, table.clone());
+        Ok(table)
+    }
+
+    /// Finds a table that a plan reads from. A compiled plan names its tables,
+    /// so the table may no longer exist if the plan is kept.
+    fn find_table(&self, name: &Identifier) -> Result<&'s <Storage as DatabaseInfo>::Table, Error> {
+        self.storage.find_table_by_name(name).ok_or(Error::UnknownTable(name.to_string()))
+    }
+
+    /// Starts executing a node that yields rows.
+    fn open<'b>(&self, expr: &SExpression<Storage::Info>,
+        source: Option<&Rc<Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>>)
+    -> Result<Cursor<'b, <Storage::Info as DatabaseInfo>::ColumnValue>, Error>
+    where 's: 'b
+    {
+        match expr {
+            &SExpression::Scan { ref table, .. } => {
+                let table = try!(self.find_table(table));
+
+                Ok(Cursor::Rows(RowsCursor {
+                    source: source.cloned(),
+                    rows: self.storage.scan_rows(table),
+                    yield_cursor: None
+                }))
             },
-            &SExpression::Map { source_id, ref yield_in_fn, ref yield_out_fn } => {
-                self.execute(yield_in_fn, &mut |row| {
-                    let new_source = Source {
-                        parent: source,
-                        source_id: source_id,
-                        source_type: SourceType::Row(row)
-                    };
+            &SExpression::Search { ref table, ref index, ref min, ref max, .. } => {
+                let table = try!(self.find_table(table));
+                let index = try!(table.find_index_by_name(index).ok_or(Error::Invalid(format!("Could not find index named {}", index))));
+
+                let min = match min.as_ref() {
+                    Some(values) => Some(try!(self.resolve_values(values, source))),
+                    None => None
+                };
+                let max = match max.as_ref() {
+                    Some(values) => Some(try!(self.resolve_values(values, source))),
+                    None => None
+                };
+
+                let rows = self.storage.search_index(table, index, min.as_ref().map(|v| &v[..]), max.as_ref().map(|v| &v[..]));
+
+                Ok(Cursor::Rows(RowsCursor {
+                    source: source.cloned(),
+                    rows: rows,
+                    yield_cursor: None
+                }))
+            },
+            &SExpression::LeftJoin { ref yield_in_fn, .. } |
+            &SExpression::Map { ref yield_in_fn, .. } => {
+                Ok(Cursor::Join(JoinCursor {
+                    source: source.cloned(),
+                    in_cursor: Box::new(try!(self.open(yield_in_fn, source))),
+                    one_or_more_rows: false,
+                    yield_cursor: None
+                }))
+            },
+            &SExpression::HashJoin { source_id, ref yield_in_fn, ref build_keys, ref probe_keys, .. } => {
+                let table = try!(self.hash_table(expr, source_id, yield_in_fn, build_keys, source));
+
+                let rows = match try!(self.hash_key(probe_keys, source)) {
+                    Some(key) => table.get(&key).cloned().unwrap_or_else(Vec::new),
+                    None => Vec::new()
+                };
+
+                Ok(Cursor::Join(JoinCursor {
+                    source: source.cloned(),
+                    in_cursor: Box::new(Cursor::Values(rows.into_iter())),
+                    one_or_more_rows: false,
+                    yield_cursor: None
+                }))
+            },
+            &SExpression::OuterJoin { ref left_fn, ref right_fn, .. } => {
+                let mut right_rows = Vec::new();
+                let mut right_cursor = try!(self.open(right_fn, source));
 
-                    self.execute(yield_out_fn, result_cb, Some(&new_source))
-                }, source)
+                while let Some(row) = try!(self.next(right_fn, &mut right_cursor)) {
+                    right_rows.push(row);
+                }
+
+                Ok(Cursor::OuterJoin(OuterJoinCursor {
+                    source: source.cloned(),
+                    left_cursor: Box::new(try!(self.open(left_fn, source))),
+                    left_row: None,
+                    right_rows_joined: vec![false; right_rows.len()],
+                    right_rows: right_rows,
+                    right_offset: 0,
+                    unmatched_offset: 0,
+                    one_or_more_rows: false
+                }))
             },
-            &SExpression::TempGroupBy { source_id, ref yield_in_fn, ref group_by_values, ref yield_out_fn } => {
+            &SExpression::TempGroupBy { source_id, ref yield_in_fn, ref group_by_values, .. } => {
                 let mut group_buckets = GroupBuckets::new();
+                let mut in_cursor = try!(self.open(yield_in_fn, source));
 
-                try!(self.execute(yield_in_fn, &mut |row| {
-                    let new_source = Source {
-                        parent: source,
-                        source_id: source_id,
-                        source_type: SourceType::Row(row)
-                    };
+                while let Some(row) = try!(self.next(yield_in_fn, &mut in_cursor)) {
+                    let key = {
+                        let new_source = Rc::new(Source {
+                            parent: source.cloned(),
+                            source_id: source_id,
+                            source_type: SourceType::Row(Cow::Borrowed(&row[..]))
+                        });
 
-                    let result: Result<Vec<_>, _> = group_by_values.iter().map(|value| {
-                        self.resolve_value(value, Some(&new_source))
-                    }).collect();
+                        try!(self.resolve_values(group_by_values, Some(&new_source)))
+                    };
 
-                    let key = try!(result);
+                    group_buckets.insert(key.into_boxed_slice(), row.into_boxed_slice());
+                }
 
-                    // TODO: don't box up row
-                    let row_boxed = row.to_vec().into_boxed_slice();
+                // the group buckets have been filled.
+                // now to yield for each group...
 
-                    group_buckets.insert(key.into_boxed_slice(), row_boxed);
+                Ok(Cursor::TempGroupBy(GroupsCursor {
+                    source: source.cloned(),
+                    groups: group_buckets.into_iter(),
+                    yield_cursor: None
+                }))
+            },
+            &SExpression::Sort { source_id, ref yield_in_fn, ref sort_by, .. } => {
+                let mut rows = Vec::new();
+                let mut in_cursor = try!(self.open(yield_in_fn, source));
+
+                while let Some(row) = try!(self.next(yield_in_fn, &mut in_cursor)) {
+                    // Rows are sorted by the bytes of a key made from their sort-by values
+                    let mut key = Vec::new();
+
+                    {
+                        let new_source = Rc::new(Source {
+                            parent: source.cloned(),
+                            source_id: source_id,
+                            source_type: SourceType::Row(Cow::Borrowed(&row[..]))
+                        });
+
+                        for sort_key in sort_by {
+                            let value = try!(self.resolve_value(&sort_key.expr, Some(&new_source)));
+
+                            let order = match sort_key.order {
+                                SortOrder::Ascending => Order::Ascending,
+                                SortOrder::Descending => Order::Descending
+                            };
 
-                    Ok(())
-                }, source));
+                            try!(sortkey::push_any_value(&mut key, order, &value).map_err(|()| {
+                                Error::TypeMismatch(format!("ORDER BY value can't be sorted"))
+                            }));
+                        }
+                    }
 
-                // the group buckets have been filled.
-                // now to yield for each group...
+                    rows.push((key, row));
+                }
 
-                for group in group_buckets {
-                    let new_source = Source {
-                        parent: source,
-                        source_id: source_id,
-                        source_type: SourceType::Group(&group)
-                    };
+                // The sort is stable; rows with equal keys keep their order.
+                rows.sort_by(|a, b| a.0.cmp(&b.0));
 
-                    try!(self.execute(yield_out_fn, result_cb, Some(&new_source)));
+                Ok(Cursor::Rows(RowsCursor {
+                    source: source.cloned(),
+                    rows: Box::new(rows.into_iter().map(|(_, row)| Ok(Cow::Owned(row)))),
+                    yield_cursor: None
+                }))
+            },
+            &SExpression::Distinct { ref yield_in_fn } => {
+                Ok(Cursor::Distinct {
+                    in_cursor: Box::new(try!(self.open(yield_in_fn, source))),
+                    yielded_rows: HashSet::new()
+                })
+            },
+            &SExpression::Limit { ref yield_in_fn, ref count, ref offset } => {
+                let count = try!(self.resolve_row_count(count, source, "LIMIT"));
+                let offset = match offset.as_ref() {
+                    Some(offset) => try!(self.resolve_row_count(offset, source, "OFFSET")),
+                    None => 0
+                };
+
+                if count == 0 {
+                    return Ok(Cursor::Empty);
                 }
 
-                Ok(())
+                Ok(Cursor::Limit {
+                    in_cursor: Box::new(try!(self.open(yield_in_fn, source))),
+                    skip: offset,
+                    remaining: count
+                })
             },
             &SExpression::Yield { ref fields } => {
-                let columns: Result<Vec<_>, _>;
-                columns = fields.iter().map(|e| self.resolve_value(e, source)).collect();
-                match columns {
-                    Ok(columns) => result_cb(&columns),
-                    Err(e) => Err(e)
-                }
+                Ok(Cursor::Yield(Some(try!(self.resolve_values(fields, source)))))
             },
             &SExpression::If { ref chains, ref else_ } => {
-                for chain in chains {
+                for (i, chain) in chains.iter().enumerate() {
                     let pred_result = try!(self.resolve_value(&chain.predicate, source));
 
                     if pred_result.tests_true() {
-                        return self.execute(&chain.yield_fn, result_cb, source);
+                        return Ok(Cursor::If {
+                            branch: i,
+                            cursor: Box::new(try!(self.open(&chain.yield_fn, source)))
+                        });
                     }
                 }
 
                 if let Some(e) = else_.as_ref() {
-                    self.execute(e, result_cb, source)
+                    Ok(Cursor::If {
+                        branch: chains.len(),
+                        cursor: Box::new(try!(self.open(e, source)))
+                    })
                 } else {
-                    Ok(())
+                    Ok(Cursor::Empty)
                 }
             },
             &SExpression::ColumnField { .. } |
@@ -213,18 +515,265 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
             &SExpression::UnaryOp { .. } |
             &SExpression::AggregateOp { .. } |
             &SExpression::CountAll { .. } |
-            &SExpression::Value(..) => {
-                Err(format!("encountered expression that cannot yield rows"))
+            &SExpression::Value(..) |
+            &SExpression::Parameter(..) => {
+                Err(Error::Invalid(format!("encountered expression that cannot yield rows")))
             }
         }
     }
 
-    fn resolve_value<'b>(&self, expr: &SExpression<'a, Storage::Info>,
-        source: Option<&Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>)
-    -> Result<<Storage::Info as DatabaseInfo>::ColumnValue, String>
+    /// Reads the next row of a node from its cursor.
+    /// Returns None once the node has yielded all of its rows.
+    fn next<'b>(&self, expr: &SExpression<Storage::Info>,
+        cursor: &mut Cursor<'b, <Storage::Info as DatabaseInfo>::ColumnValue>)
+    -> Result<Option<Vec<<Storage::Info as DatabaseInfo>::ColumnValue>>, Error>
+    where 's: 'b
+    {
+        let row = try!(self.next_row(expr, cursor));
+
+        if row.is_none() {
+            // The cursor's rows and nested cursors aren't needed anymore
+            *cursor = Cursor::Empty;
+        }
+
+        Ok(row)
+    }
+
+    fn next_row<'b>(&self, expr: &SExpression<Storage::Info>,
+        cursor: &mut Cursor<'b, <Storage::Info as DatabaseInfo>::ColumnValue>)
+    -> Result<Option<Vec<<Storage::Info as DatabaseInfo>::ColumnValue>>, Error>
+    where 's: 'b
+    {
+        match (expr, cursor) {
+            (_, &mut Cursor::Empty) => Ok(None),
+            (_, &mut Cursor::Values(ref mut rows)) => Ok(rows.next()),
+            (&SExpression::Scan { source_id, ref yield_fn, .. }, &mut Cursor::Rows(ref mut c)) |
+            (&SExpression::Search { source_id, ref yield_fn, .. }, &mut Cursor::Rows(ref mut c)) |
+            (&SExpression::Sort { source_id, yield_out_fn: ref yield_fn, .. }, &mut Cursor::Rows(ref mut c)) => {
+                let RowsCursor { ref source, ref mut rows, ref mut yield_cursor } = *c;
+
+                self.next_nested(yield_fn, yield_cursor, &mut || {
+                    match rows.next() {
+                        Some(row) => Ok(Some(Rc::new(Source {
+                            parent: source.clone(),
+                            source_id: source_id,
+                            source_type: SourceType::Row(try!(row))
+                        }))),
+                        None => Ok(None)
+                    }
+                })
+            },
+            (&SExpression::LeftJoin { source_id, ref yield_in_fn, ref predicate, ref yield_out_fn, ref right_rows_if_none }, &mut Cursor::Join(ref mut c)) => {
+                self.next_join(source_id, yield_in_fn, Some(predicate), yield_out_fn, Some(right_rows_if_none), c)
+            },
+            (&SExpression::HashJoin { source_id, ref yield_in_fn, ref predicate, ref yield_out_fn, ref right_rows_if_none, .. }, &mut Cursor::Join(ref mut c)) => {
+                let predicate = predicate.as_ref().map(|e| &**e);
+                let right_rows_if_none = right_rows_if_none.as_ref().map(|v| &v[..]);
+
+                self.next_join(source_id, yield_in_fn, predicate, yield_out_fn, right_rows_if_none, c)
+            },
+            (&SExpression::Map { source_id, ref yield_in_fn, ref yield_out_fn }, &mut Cursor::Join(ref mut c)) => {
+                self.next_join(source_id, yield_in_fn, None, yield_out_fn, None, c)
+            },
+            (&SExpression::OuterJoin { source_id, ref left_fn, ref predicate, ref left_rows_if_none, ref right_rows_if_none, .. }, &mut Cursor::OuterJoin(ref mut c)) => {
+                loop {
+                    if let Some(ref left_row) = c.left_row {
+                        while c.right_offset < c.right_rows.len() {
+                            let i = c.right_offset;
+                            c.right_offset += 1;
+
+                            let row: Vec<_> = left_row.iter().chain(c.right_rows[i].iter()).cloned().collect();
+
+                            let joined = {
+                                let new_source = Rc::new(Source {
+                                    parent: c.source.clone(),
+                                    source_id: source_id,
+                                    source_type: SourceType::Row(Cow::Borrowed(&row[..]))
+                                });
+
+                                try!(self.resolve_value(predicate, Some(&new_source))).tests_true()
+                            };
+
+                            if joined {
+                                c.right_rows_joined[i] = true;
+                                c.one_or_more_rows = true;
+                                return Ok(Some(row));
+                            }
+                        }
+                    }
+
+                    if let Some(left_row) = c.left_row.take() {
+                        match right_rows_if_none.as_ref() {
+                            Some(right_row) if !c.one_or_more_rows => {
+                                return Ok(Some(left_row.iter().chain(right_row.iter()).cloned().collect()));
+                            },
+                            _ => ()
+                        }
+                    }
+
+                    match try!(self.next(left_fn, &mut c.left_cursor)) {
+                        Some(left_row) => {
+                            c.left_row = Some(left_row);
+                            c.right_offset = 0;
+                            c.one_or_more_rows = false;
+                        },
+                        None => break
+                    }
+                }
+
+                // Once the left rows have run out, the right rows that weren't joined are yielded
+                if let Some(left_row) = left_rows_if_none.as_ref() {
+                    while c.unmatched_offset < c.right_rows.len() {
+                        let i = c.unmatched_offset;
+                        c.unmatched_offset += 1;
+
+                        if !c.right_rows_joined[i] {
+                            return Ok(Some(left_row.iter().chain(c.right_rows[i].iter()).cloned().collect()));
+                        }
+                    }
+                }
+
+                Ok(None)
+            },
+            (&SExpression::TempGroupBy { source_id, ref yield_out_fn, .. }, &mut Cursor::TempGroupBy(ref mut c)) => {
+                let GroupsCursor { ref source, ref mut groups, ref mut yield_cursor } = *c;
+
+                self.next_nested(yield_out_fn, yield_cursor, &mut || {
+                    Ok(groups.next().map(|group| {
+                        Rc::new(Source {
+                            parent: source.clone(),
+                            source_id: source_id,
+                            source_type: SourceType::Group(Box::new(group))
+                        })
+                    }))
+                })
+            },
+            (&SExpression::Distinct { ref yield_in_fn }, &mut Cursor::Distinct { ref mut in_cursor, ref mut yielded_rows }) => {
+                while let Some(row) = try!(self.next(yield_in_fn, in_cursor)) {
+                    // TODO: don't box up row
+                    if yielded_rows.insert(row.clone().into_boxed_slice()) {
+                        return Ok(Some(row));
+                    }
+                }
+
+                Ok(None)
+            },
+            (&SExpression::Limit { ref yield_in_fn, .. }, &mut Cursor::Limit { ref mut in_cursor, ref mut skip, ref mut remaining }) => {
+                while *skip > 0 {
+                    if try!(self.next(yield_in_fn, in_cursor)).is_none() {
+                        return Ok(None);
+                    }
+                    *skip -= 1;
+                }
+
+                // No more rows are read once enough have been yielded
+                if *remaining == 0 {
+                    return Ok(None);
+                }
+
+                let row = try!(self.next(yield_in_fn, in_cursor));
+                if row.is_some() {
+                    *remaining -= 1;
+                }
+
+                Ok(row)
+            },
+            (&SExpression::Yield { .. }, &mut Cursor::Yield(ref mut row)) => {
+                Ok(row.take())
+            },
+            (&SExpression::If { ref chains, ref else_ }, &mut Cursor::If { branch, ref mut cursor }) => {
+                let yield_fn = match chains.get(branch) {
+                    Some(chain) => &chain.yield_fn,
+                    None => else_.as_ref().unwrap()
+                };
+
+                self.next(yield_fn, cursor)
+            },
+            _ => unreachable!()
+        }
+    }
+
+    /// Reads the next row of a node that executes yield_fn on each of the
+    /// sources from `next_source`.
+    fn next_nested<'b>(&self, yield_fn: &SExpression<Storage::Info>,
+        yield_cursor: &mut Option<Box<Cursor<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>>,
+        next_source: &mut FnMut() -> Result<Option<Rc<Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>>, Error>)
+    -> Result<Option<Vec<<Storage::Info as DatabaseInfo>::ColumnValue>>, Error>
+    where 's: 'b
+    {
+        loop {
+            if let Some(ref mut cursor) = *yield_cursor {
+                if let Some(row) = try!(self.next(yield_fn, cursor)) {
+                    return Ok(Some(row));
+                }
+            }
+
+            *yield_cursor = match try!(next_source()) {
+                Some(source) => Some(Box::new(try!(self.open(yield_fn, Some(&source))))),
+                None => return Ok(None)
+            };
+        }
+    }
+
+    /// Reads the next row of a join: yield_out_fn is executed on each row of
+    /// yield_in_fn that passes the predicate, or on `rows_if_none` if none do.
+    fn next_join<'b>(&self, source_id: u32, yield_in_fn: &SExpression<Storage::Info>,
+        predicate: Option<&SExpression<Storage::Info>>, yield_out_fn: &SExpression<Storage::Info>,
+        rows_if_none: Option<&[<Storage::Info as DatabaseInfo>::ColumnValue]>,
+        cursor: &mut JoinCursor<'b, <Storage::Info as DatabaseInfo>::ColumnValue>)
+    -> Result<Option<Vec<<Storage::Info as DatabaseInfo>::ColumnValue>>, Error>
+    where 's: 'b
+    {
+        let JoinCursor { ref source, ref mut in_cursor, ref mut one_or_more_rows, ref mut yield_cursor } = *cursor;
+
+        self.next_nested(yield_out_fn, yield_cursor, &mut || {
+            while let Some(row) = try!(self.next(yield_in_fn, in_cursor)) {
+                let new_source = Rc::new(Source {
+                    parent: source.clone(),
+                    source_id: source_id,
+                    source_type: SourceType::Row(Cow::Owned(row))
+                });
+
+                let pred_result = match predicate {
+                    Some(predicate) => try!(self.resolve_value(predicate, Some(&new_source))).tests_true(),
+                    None => true
+                };
+
+                if pred_result {
+                    *one_or_more_rows = true;
+                    return Ok(Some(new_source));
+                }
+            }
+
+            match rows_if_none {
+                Some(row) if !*one_or_more_rows => {
+                    // no rows were matched
+                    *one_or_more_rows = true;
+
+                    Ok(Some(Rc::new(Source {
+                        parent: source.clone(),
+                        source_id: source_id,
+                        source_type: SourceType::Row(Cow::Owned(row.to_vec()))
+                    })))
+                },
+                _ => Ok(None)
+            }
+        })
+    }
+
+    fn resolve_value<'b>(&self, expr: &SExpression<Storage::Info>,
+        source: Option<&Rc<Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>>)
+    -> Result<<Storage::Info as DatabaseInfo>::ColumnValue, Error>
+    where 's: 'b
     {
         match expr {
             &SExpression::Value(ref v) => Ok(v.clone()),
+            &SExpression::Parameter(number) => {
+                match self.params.get(number as usize - 1) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(Error::Invalid(format!("parameter has no value: ?{}", number)))
+                }
+            },
             &SExpression::ColumnField { source_id, column_offset } => {
                 let row = source.and_then(|s| s.find_row_from_source_id(source_id));
                 match row {
@@ -241,7 +790,7 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
                                     None => Ok(ColumnValueOpsExt::null())
                                 }
                             },
-                            None => Err(format!("ColumnField: source id is not a valid row or group: {}", source_id))
+                            None => Err(Error::Invalid(format!("ColumnField: source id is not a valid row or group: {}", source_id)))
                         }
                     }
                 }
@@ -250,22 +799,24 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
                 let l = try!(self.resolve_value(lhs, source));
                 let r = try!(self.resolve_value(rhs, source));
 
-                Ok(match op {
-                    BinaryOp::Equal => l.equals(&r),
-                    BinaryOp::NotEqual => l.not_equals(&r),
-                    BinaryOp::LessThan => l.less_than(&r),
-                    BinaryOp::LessThanOrEqual => l.less_than_or_equal(&r),
-                    BinaryOp::GreaterThan => l.greater_than(&r),
-                    BinaryOp::GreaterThanOrEqual => l.greater_than_or_equal(&r),
-                    BinaryOp::And => l.and(&r),
-                    BinaryOp::Or => l.or(&r),
-                    BinaryOp::Concatenate => l.concat(&r),
-                    BinaryOp::Add => l.add(&r),
-                    BinaryOp::Subtract => l.sub(&r),
-                    BinaryOp::Multiply => l.mul(&r),
-                    BinaryOp::Divide => l.div(&r),
-                    _ => unimplemented!()
-                })
+                match op {
+                    BinaryOp::Equal => Ok(l.equals(&r)),
+                    BinaryOp::NotEqual => Ok(l.not_equals(&r)),
+                    BinaryOp::LessThan => Ok(l.less_than(&r)),
+                    BinaryOp::LessThanOrEqual => Ok(l.less_than_or_equal(&r)),
+                    BinaryOp::GreaterThan => Ok(l.greater_than(&r)),
+                    BinaryOp::GreaterThanOrEqual => Ok(l.greater_than_or_equal(&r)),
+                    BinaryOp::And => Ok(l.and(&r)),
+                    BinaryOp::Or => Ok(l.or(&r)),
+                    BinaryOp::Concatenate => Ok(l.concat(&r)),
+                    BinaryOp::Coalesce => Ok(if l.is_null() { r } else { l }),
+                    BinaryOp::Add => Ok(l.add(&r)),
+                    BinaryOp::Subtract => Ok(l.sub(&r)),
+                    BinaryOp::Multiply => Ok(l.mul(&r)),
+                    BinaryOp::Divide => Ok(l.div(&r)),
+                    BinaryOp::BitAnd => Err(Error::Invalid(format!("the & operator isn't supported"))),
+                    BinaryOp::BitOr => Err(Error::Invalid(format!("the | operator isn't supported")))
+                }
             },
             &SExpression::UnaryOp { op, ref expr } => {
                 let e = try!(self.resolve_value(expr, source));
@@ -274,26 +825,30 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
                     UnaryOp::Negate => e.negate()
                 })
             },
-            &SExpression::AggregateOp { op, source_id, ref value } => {
+            &SExpression::AggregateOp { op, source_id, ref value, distinct } => {
                 let group = source.and_then(|s| s.find_group_from_source_id(source_id));
                 match group {
                     Some(group) => {
                         let mut op_functor = get_aggregate_function(op);
+                        let mut fed_values = HashSet::new();
 
                         for row in group.iter() {
-                            let new_source = Source {
-                                parent: source,
+                            let new_source = Rc::new(Source {
+                                parent: source.cloned(),
                                 source_id: source_id,
-                                source_type: SourceType::Row(&row)
-                            };
+                                source_type: SourceType::Row(row)
+                            });
 
                             let v = try!(self.resolve_value(value, Some(&new_source)));
-                            op_functor.feed(v);
+
+                            if !distinct || fed_values.insert(v.clone()) {
+                                try!(op_functor.feed(v));
+                            }
                         }
 
                         Ok(op_functor.finish())
                     },
-                    None => Err(format!("AggregateOp: source id is not a valid group: {}", source_id))
+                    None => Err(Error::Invalid(format!("AggregateOp: source id is not a valid group: {}", source_id)))
                 }
             },
             &SExpression::CountAll { source_id } => {
@@ -302,7 +857,7 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
                         let count = group.count();
                         Ok(ColumnValueOps::from_u64(count))
                     },
-                    None => Err(format!("CountAll: source id is not a valid group: {}", source_id))
+                    None => Err(Error::Invalid(format!("CountAll: source id is not a valid group: {}", source_id)))
                 }
             },
             &SExpression::Map { source_id, ref yield_in_fn, ref yield_out_fn } => {
@@ -310,38 +865,59 @@ where <Storage::Info as DatabaseInfo>::Table: 'a
 
                 // yield_in_fn is expected to yield exactly one row
                 // yield_out_fn is expected to return a single resolved value
-                let mut r = None;
-                let mut row_count = 0;
-
-                try!(self.execute(yield_in_fn, &mut |row| {
-                    if row_count == 0 {
-                        r = Some(row.to_vec());
-                    }
-                    row_count += 1;
-                    Ok(())
-                }, source));
-
-                if row_count == 1 {
-                    let row = r.unwrap();
-
-                    let new_source = Source {
-                        parent: source,
-                        source_id: source_id,
-                        source_type: SourceType::Row(&row)
-                    };
-
-                    self.resolve_value(yield_out_fn, Some(&new_source))
-                } else {
-                    Err(format!("subquery must yield exactly one row"))
+                let mut cursor = try!(self.open(yield_in_fn, source));
+                let row = try!(self.next(yield_in_fn, &mut cursor));
+                let next_row = try!(self.next(yield_in_fn, &mut cursor));
+
+                match (row, next_row) {
+                    (Some(row), None) => {
+                        let new_source = Rc::new(Source {
+                            parent: source.cloned(),
+                            source_id: source_id,
+                            source_type: SourceType::Row(Cow::Owned(row))
+                        });
+
+                        self.resolve_value(yield_out_fn, Some(&new_source))
+                    },
+                    _ => Err(Error::Invalid(format!("subquery must yield exactly one row")))
                 }
             },
             &SExpression::Scan { .. } |
+            &SExpression::Search { .. } |
             &SExpression::LeftJoin { .. } |
+            &SExpression::HashJoin { .. } |
+            &SExpression::OuterJoin { .. } |
             &SExpression::TempGroupBy { .. } |
+            &SExpression::Sort { .. } |
+            &SExpression::Distinct { .. } |
+            &SExpression::Limit { .. } |
             &SExpression::Yield { .. } |
             &SExpression::If { .. } => {
-                Err(format!("encounted expression that cannot resolve to a single value"))
+                Err(Error::Invalid(format!("encounted expression that cannot resolve to a single value")))
             }
         }
     }
+
+    fn resolve_values<'b>(&self, exprs: &[SExpression<Storage::Info>],
+        source: Option<&Rc<Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>>)
+    -> Result<Vec<<Storage::Info as DatabaseInfo>::ColumnValue>, Error>
+    where 's: 'b
+    {
+        exprs.iter().map(|e| self.resolve_value(e, source)).collect()
+    }
+
+    fn resolve_row_count<'b>(&self, expr: &SExpression<Storage::Info>,
+        source: Option<&Rc<Source<'b, <Storage::Info as DatabaseInfo>::ColumnValue>>>,
+        clause: &str)
+    -> Result<u64, Error>
+    where 's: 'b
+    {
+        let value = try!(self.resolve_value(expr, source));
+        let is_negative = value.less_than(&ColumnValueOps::from_u64(0)).tests_true();
+
+        match value.to_u64() {
+            Ok(n) if !is_negative => Ok(n),
+            _ => Err(Error::TypeMismatch(format!("{} must be a non-negative integer", clause)))
+        }
+    }
 }
@@ -0,0 +1,107 @@
+//! Chooses the keys to hash join a table on, from the predicates that every
+//! row of the table must pass.
+
+use databaseinfo::{ColumnInfo, DatabaseInfo, TableInfo};
+use types::DbType;
+use super::sexpression::{BinaryOp, SExpression};
+
+/// The keys of a hash join; see `SExpression::HashJoin`.
+pub struct HashJoinKeys<DB: DatabaseInfo> {
+    /// Columns of the joined table
+    pub build_keys: Vec<SExpression<DB>>,
+    /// Columns of the tables that it's joined to
+    pub probe_keys: Vec<SExpression<DB>>
+}
+
+/// The kinds of types whose values are equal exactly when their hash keys are.
+#[derive(PartialEq)]
+enum KeyType {
+    Integer,
+    F64,
+    String,
+    Bytes
+}
+
+fn key_type(dbtype: &DbType) -> Option<KeyType> {
+    match dbtype {
+        &DbType::Null => None,
+        &DbType::Integer { .. } => Some(KeyType::Integer),
+        &DbType::F64 => Some(KeyType::F64),
+        &DbType::String => Some(KeyType::String),
+        &DbType::ByteFixed(..) | &DbType::ByteDynamic => Some(KeyType::Bytes)
+    }
+}
+
+/// Returns the keys of the `column = column` predicates that join the table to
+/// the bound tables. Returns None if there are none.
+///
+/// The predicates refer to the table's rows with `source_id`.
+/// `bound_tables` are the tables whose rows are known when the table is joined,
+/// along with the source ids of their rows.
+pub fn hash_join_keys<'a, 'b, DB: DatabaseInfo>(table: &'a <DB as DatabaseInfo>::Table, source_id: u32,
+    predicates: &[&'b SExpression<DB>], bound_tables: &[(u32, &'a <DB as DatabaseInfo>::Table)])
+-> Option<HashJoinKeys<DB>>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    // The key type of a column of one of the tables
+    let column_key_type = |table: &'a <DB as DatabaseInfo>::Table, column_offset: u32| {
+        table.find_column_by_offset(column_offset).and_then(|column| key_type(column.get_dbtype()))
+    };
+
+    let mut keys = HashJoinKeys {
+        build_keys: Vec::new(),
+        probe_keys: Vec::new()
+    };
+
+    for predicate in predicates {
+        let (lhs, rhs) = match *predicate {
+            &SExpression::BinaryOp { op: BinaryOp::Equal, ref lhs, ref rhs } => (lhs, rhs),
+            _ => continue
+        };
+
+        let columns = match (column_of(lhs), column_of(rhs)) {
+            (Some(l), Some(r)) => if l.0 == source_id { (l, r) } else { (r, l) },
+            _ => continue
+        };
+
+        let ((build_source_id, build_offset), (probe_source_id, probe_offset)) = columns;
+
+        if build_source_id != source_id {
+            continue;
+        }
+
+        let probe_table = match bound_tables.iter().find(|&&(id, _)| id == probe_source_id) {
+            Some(&(_, probe_table)) => probe_table,
+            None => continue
+        };
+
+        let build_type = column_key_type(table, build_offset);
+
+        if build_type.is_none() || build_type != column_key_type(probe_table, probe_offset) {
+            continue;
+        }
+
+        keys.build_keys.push(SExpression::ColumnField {
+            source_id: build_source_id,
+            column_offset: build_offset
+        });
+        keys.probe_keys.push(SExpression::ColumnField {
+            source_id: probe_source_id,
+            column_offset: probe_offset
+        });
+    }
+
+    if keys.build_keys.is_empty() {
+        None
+    } else {
+        Some(keys)
+    }
+}
+
+/// Returns the source id and column offset of a column field.
+fn column_of<DB: DatabaseInfo>(expr: &SExpression<DB>) -> Option<(u32, u32)> {
+    match expr {
+        &SExpression::ColumnField { source_id, column_offset } => Some((source_id, column_offset)),
+        _ => None
+    }
+}
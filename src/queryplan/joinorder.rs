@@ -0,0 +1,286 @@
+//! Chooses the order in which the tables of a FROM clause are nested, and the
+//! level of the nesting at which each predicate is checked.
+//!
+//! The cost of a plan is the estimated number of rows it reads. A table that's
+//! nested in other tables is read once for every row of the tables around it
+//! that passes the predicates checked so far, unless it's hash joined: then
+//! it's read once, and each row around it only reads the rows with its key.
+
+use databaseinfo::DatabaseInfo;
+use super::FromWhereTableOrSubquery;
+use super::search::{EQUAL_SELECTIVITY, OTHER_SELECTIVITY};
+use super::sexpression::{BinaryOp, IfChain, SExpression};
+
+/// A subquery's rows aren't counted until it's executed, so it's assumed to
+/// yield this many.
+const SUBQUERY_ROW_COUNT: f64 = 1000.0;
+
+/// The tables of a larger FROM clause keep the order they were written in,
+/// as every subset of the tables is considered.
+const MAX_ORDERED_TABLES: usize = 10;
+
+/// Splits a predicate into the predicates that are ANDed together.
+pub fn into_conjuncts<DB: DatabaseInfo>(predicate: SExpression<DB>, out: &mut Vec<SExpression<DB>>) {
+    match predicate {
+        SExpression::BinaryOp { op: BinaryOp::And, lhs, rhs } => {
+            into_conjuncts(*lhs, out);
+            into_conjuncts(*rhs, out);
+        },
+        predicate => out.push(predicate)
+    }
+}
+
+/// ANDs the predicates together. Returns None if there are none.
+pub fn conjunction<DB: DatabaseInfo>(predicates: Vec<SExpression<DB>>) -> Option<SExpression<DB>> {
+    predicates.into_iter().fold(None, |lhs, rhs| {
+        Some(match lhs {
+            Some(lhs) => SExpression::BinaryOp {
+                op: BinaryOp::And,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs)
+            },
+            None => rhs
+        })
+    })
+}
+
+/// Runs `yield_fn` if all of the predicates are true.
+pub fn filter<DB: DatabaseInfo>(predicates: Vec<SExpression<DB>>, yield_fn: SExpression<DB>) -> SExpression<DB> {
+    match conjunction(predicates) {
+        Some(predicate) => SExpression::If {
+            chains: vec![IfChain {
+                predicate: predicate,
+                yield_fn: yield_fn
+            }],
+            else_: None
+        },
+        None => yield_fn
+    }
+}
+
+/// Adds the source ids that the expression, or any expression in it, refers to.
+pub fn source_ids<DB: DatabaseInfo>(expr: &SExpression<DB>, out: &mut Vec<u32>) {
+    match expr {
+        &SExpression::Scan { ref yield_fn, .. } => {
+            source_ids(yield_fn, out);
+        },
+        &SExpression::Search { ref min, ref max, ref yield_fn, .. } => {
+            for v in min.iter().chain(max.iter()).flat_map(|values| values.iter()) {
+                source_ids(v, out);
+            }
+            source_ids(yield_fn, out);
+        },
+        &SExpression::LeftJoin { ref yield_in_fn, ref predicate, ref yield_out_fn, .. } => {
+            source_ids(yield_in_fn, out);
+            source_ids(predicate, out);
+            source_ids(yield_out_fn, out);
+        },
+        &SExpression::HashJoin { ref yield_in_fn, ref build_keys, ref probe_keys, ref predicate, ref yield_out_fn, .. } => {
+            source_ids(yield_in_fn, out);
+            for v in build_keys.iter().chain(probe_keys.iter()) {
+                source_ids(v, out);
+            }
+            if let &Some(ref e) = predicate {
+                source_ids(e, out);
+            }
+            source_ids(yield_out_fn, out);
+        },
+        &SExpression::OuterJoin { ref left_fn, ref right_fn, ref predicate, .. } => {
+            source_ids(left_fn, out);
+            source_ids(right_fn, out);
+            source_ids(predicate, out);
+        },
+        &SExpression::Map { ref yield_in_fn, ref yield_out_fn, .. } => {
+            source_ids(yield_in_fn, out);
+            source_ids(yield_out_fn, out);
+        },
+        &SExpression::TempGroupBy { ref yield_in_fn, ref group_by_values, ref yield_out_fn, .. } => {
+            source_ids(yield_in_fn, out);
+            for v in group_by_values {
+                source_ids(v, out);
+            }
+            source_ids(yield_out_fn, out);
+        },
+        &SExpression::Sort { ref yield_in_fn, ref sort_by, ref yield_out_fn, .. } => {
+            source_ids(yield_in_fn, out);
+            for key in sort_by {
+                source_ids(&key.expr, out);
+            }
+            source_ids(yield_out_fn, out);
+        },
+        &SExpression::Distinct { ref yield_in_fn } => {
+            source_ids(yield_in_fn, out);
+        },
+        &SExpression::Limit { ref yield_in_fn, ref count, ref offset } => {
+            source_ids(yield_in_fn, out);
+            source_ids(count, out);
+            if let &Some(ref e) = offset {
+                source_ids(e, out);
+            }
+        },
+        &SExpression::Yield { ref fields } => {
+            for v in fields {
+                source_ids(v, out);
+            }
+        },
+        &SExpression::If { ref chains, ref else_ } => {
+            for chain in chains {
+                source_ids(&chain.predicate, out);
+                source_ids(&chain.yield_fn, out);
+            }
+            if let &Some(ref e) = else_ {
+                source_ids(e, out);
+            }
+        },
+        &SExpression::UnaryOp { ref expr, .. } => {
+            source_ids(expr, out);
+        },
+        &SExpression::BinaryOp { ref lhs, ref rhs, .. } => {
+            source_ids(lhs, out);
+            source_ids(rhs, out);
+        },
+        &SExpression::AggregateOp { source_id, ref value, .. } => {
+            out.push(source_id);
+            source_ids(value, out);
+        },
+        &SExpression::ColumnField { source_id, .. } |
+        &SExpression::CountAll { source_id } => {
+            out.push(source_id);
+        },
+        &SExpression::Value(..) |
+        &SExpression::Parameter(..) => ()
+    }
+}
+
+/// The fraction of rows that are assumed to pass the predicate
+fn selectivity<DB: DatabaseInfo>(predicate: &SExpression<DB>) -> f64 {
+    match predicate {
+        &SExpression::BinaryOp { op: BinaryOp::Equal, .. } => EQUAL_SELECTIVITY,
+        _ => OTHER_SELECTIVITY
+    }
+}
+
+fn row_count<'a, DB: DatabaseInfo>(table: &FromWhereTableOrSubquery<'a, DB>) -> f64
+where <DB as DatabaseInfo>::Table: 'a
+{
+    match table {
+        &FromWhereTableOrSubquery::Table { row_count, .. } => row_count as f64,
+        &FromWhereTableOrSubquery::Subquery { .. } => SUBQUERY_ROW_COUNT
+    }
+}
+
+/// The number of rows read from the table: once for the whole join, and each
+/// time it's searched, scanned or probed
+fn read_count<'a, 'b, DB: DatabaseInfo>(table: &FromWhereTableOrSubquery<'a, DB>,
+    predicates: &[&'b SExpression<DB>], unbound_source_ids: &[u32],
+    bound_tables: &[(u32, &'a <DB as DatabaseInfo>::Table)])
+-> (f64, f64)
+where <DB as DatabaseInfo>::Table: 'a
+{
+    let rows = row_count(table);
+    let source_id = table.source_id();
+    let search = table.index_search(source_id, predicates, unbound_source_ids);
+
+    if let Some(keys) = table.hash_join_keys(source_id, predicates, search.as_ref(), bound_tables) {
+        return (rows, rows * EQUAL_SELECTIVITY.powi(keys.build_keys.len() as i32));
+    }
+
+    match search {
+        Some(search) => (0.0, search.estimate_rows(rows)),
+        None => (0.0, rows)
+    }
+}
+
+/// Returns true if `order_tables` orders a cross join of this many tables.
+/// Fewer tables have only one order, and more have too many orders to compare.
+pub fn is_ordered(table_count: usize) -> bool {
+    table_count > 1 && table_count <= MAX_ORDERED_TABLES
+}
+
+/// Returns the order to nest the tables of a cross join in, as offsets into `tables`.
+/// `predicates` are the predicates that every row of the join must pass.
+///
+/// The tables keep their written order unless another order is estimated to read fewer rows.
+pub fn order_tables<'a, DB: DatabaseInfo>(tables: &[FromWhereTableOrSubquery<'a, DB>], predicates: &[SExpression<DB>])
+-> Vec<usize>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    let n = tables.len();
+    let written_order: Vec<usize> = (0..n).collect();
+
+    if !is_ordered(n) {
+        return written_order;
+    }
+
+    let table_source_ids: Vec<u32> = tables.iter().map(|t| t.source_id()).collect();
+    let predicate_refs: Vec<&SExpression<DB>> = predicates.iter().collect();
+
+    // The tables that each predicate refers to, as a set of bits
+    let predicate_tables: Vec<u32> = predicates.iter().map(|predicate| {
+        let mut ids = Vec::new();
+        source_ids(predicate, &mut ids);
+
+        table_source_ids.iter().enumerate().fold(0, |set, (i, id)| {
+            if ids.contains(id) { set | (1 << i) } else { set }
+        })
+    }).collect();
+
+    // The rows of the tables in the set that pass every predicate that can be checked
+    let joined_rows = |set: u32| -> f64 {
+        let rows = (0..n).filter(|&i| set & (1 << i) != 0).fold(1.0, |rows, i| rows * row_count(&tables[i]));
+
+        predicates.iter().zip(predicate_tables.iter()).fold(rows, |rows, (predicate, &refs)| {
+            if refs != 0 && refs & !set == 0 { rows * selectivity(predicate) } else { rows }
+        })
+    };
+
+    // The rows read by nesting the table in the tables in the set
+    let nested_read_count = |i: usize, set: u32| -> f64 {
+        let unbound: Vec<u32> = (0..n).filter(|&j| set & (1 << j) == 0).map(|j| table_source_ids[j]).collect();
+        let bound: Vec<_> = (0..n).filter(|&j| set & (1 << j) != 0).filter_map(|j| tables[j].bound_table()).collect();
+
+        let (once, each) = read_count(&tables[i], &predicate_refs, &unbound, &bound);
+        once + joined_rows(set) * each
+    };
+
+    // The cheapest order for each set of outer tables.
+    // A set's subsets are always lower numbers, so they're visited first.
+    let all = (1u32 << n) - 1;
+    let mut best: Vec<Option<(f64, Vec<usize>)>> = vec![None; all as usize + 1];
+    best[0] = Some((0.0, Vec::new()));
+
+    for set in 0..all {
+        let (cost, order) = match best[set as usize].take() {
+            Some(b) => b,
+            None => continue
+        };
+
+        for i in (0..n).filter(|&i| set & (1 << i) == 0) {
+            let next = (set | (1 << i)) as usize;
+            let next_cost = cost + nested_read_count(i, set);
+
+            let is_better = match best[next] {
+                Some((best_cost, _)) => next_cost < best_cost,
+                None => true
+            };
+
+            if is_better {
+                let mut next_order = order.clone();
+                next_order.push(i);
+                best[next] = Some((next_cost, next_order));
+            }
+        }
+    }
+
+    let (best_cost, best_order) = best[all as usize].take().unwrap();
+
+    let (written_cost, _) = written_order.iter().fold((0.0, 0), |(cost, set), &i| {
+        (cost + nested_read_count(i, set), set | (1 << i))
+    });
+
+    if written_cost <= best_cost {
+        written_order
+    } else {
+        best_order
+    }
+}
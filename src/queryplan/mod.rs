@@ -1,5 +1,6 @@
 use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
-use databaseinfo::{DatabaseInfo, TableInfo};
+use databaseinfo::{DatabaseInfo, IndexInfo, TableInfo};
+use databasestorage::DatabaseStorage;
 use identifier::Identifier;
 use sqlsyntax::ast;
 
@@ -7,10 +8,16 @@ use std::fmt;
 use std::collections::HashMap;
 
 mod execute;
+mod hashjoin;
+mod joinorder;
+mod search;
 mod sexpression;
 mod source;
 pub use self::execute::*;
 pub use self::sexpression::*;
+use self::hashjoin::*;
+use self::joinorder::*;
+use self::search::*;
 use self::source::*;
 
 pub enum QueryPlanCompileError {
@@ -23,7 +30,10 @@ pub enum QueryPlanCompileError {
     UnknownFunctionName(Identifier),
     AggregateFunctionRequiresOneArgument,
     AggregateFunctionHasNoQueryToAggregate,
-    AggregateAllMustBeCount(Identifier)
+    AggregateAllMustBeCount(Identifier),
+    OrderByPositionOutOfRange(u32),
+    ParameterHasNoValue(u32),
+    DatabaseNameNotSupported(String)
 }
 
 impl fmt::Display for QueryPlanCompileError {
@@ -61,14 +71,21 @@ impl fmt::Display for QueryPlanCompileError {
             &AggregateAllMustBeCount(ref name) => {
                 write!(f, "aggregate (*) function must be `count` (found {})", name)
             },
+            &OrderByPositionOutOfRange(position) => {
+                write!(f, "ORDER BY position is out of range: {}", position)
+            },
+            &ParameterHasNoValue(number) => {
+                write!(f, "parameter has no value: ?{}", number)
+            },
+            &DatabaseNameNotSupported(ref name) => {
+                write!(f, "tables can't be qualified with a database name: {}", name)
+            },
         }
     }
 }
 
-pub struct QueryPlan<'a, DB: DatabaseInfo>
-where <DB as DatabaseInfo>::Table: 'a
-{
-    pub expr: SExpression<'a, DB>,
+pub struct QueryPlan<DB: DatabaseInfo> {
+    pub expr: SExpression<DB>,
     pub out_column_names: Vec<Identifier>
 }
 
@@ -76,11 +93,22 @@ fn new_identifier(value: &str) -> Result<Identifier, QueryPlanCompileError> {
     Identifier::new(value).ok_or(QueryPlanCompileError::BadIdentifier(value.to_string()))
 }
 
-impl<'a, DB: DatabaseInfo> QueryPlan<'a, DB>
-where <DB as DatabaseInfo>::Table: 'a
-{
-    pub fn compile_select(db: &'a DB, stmt: ast::SelectStatement)
-    -> Result<QueryPlan<'a, DB>, QueryPlanCompileError>
+/// The predicate of a join without one
+fn true_value<DB: DatabaseInfo>() -> SExpression<DB> {
+    SExpression::Value(ColumnValueOps::from_3vl(1))
+}
+
+/// The values of a row that's padded with NULLs by an outer join
+fn null_row<V: ColumnValueOps>(column_count: u32) -> Vec<V> {
+    (0..column_count).map(|_| ColumnValueOpsExt::null()).collect()
+}
+
+impl<DB: DatabaseInfo> QueryPlan<DB> {
+    /// Compiles a SELECT statement whose placeholders are numbered from `?1`
+    /// to `?parameter_count`. Their values are given when the plan is executed.
+    pub fn compile_select(db: &DB, stmt: &ast::SelectStatement, parameter_count: u32)
+    -> Result<QueryPlan<DB>, QueryPlanCompileError>
+    where DB: DatabaseStorage<Info=DB>
     {
         let scope = SourceScope::new(None, Vec::new(), Vec::new());
 
@@ -90,11 +118,14 @@ where <DB as DatabaseInfo>::Table: 'a
         let mut next_query_id = 1;
 
         let mut groups_info = GroupsInfo::new();
+        let row_count = |table| db.scan_table(table).count();
 
         let plan = {
             let compiler = QueryCompiler {
                 query_id: 0,
                 db: db,
+                parameter_count: parameter_count,
+                row_count: &row_count,
                 source_id_to_query_id: &mut source_id_to_query_id,
                 query_to_aggregated_source_id: &mut query_to_aggregated_source_id,
                 next_source_id: &mut next_source_id,
@@ -111,31 +142,95 @@ where <DB as DatabaseInfo>::Table: 'a
     }
 }
 
-pub fn compile_ast_expression<'a, DB: DatabaseInfo>(db: &'a DB, expr: ast::Expression)
--> Result<SExpression<'a, DB>, QueryPlanCompileError>
-where <DB as DatabaseInfo>::Table: 'a
+/// The source id of the row in an expression compiled with a table.
+const ROW_SOURCE_ID: u32 = 0;
+
+/// Compiles an expression that isn't part of a query.
+///
+/// If a table is provided, the expression may refer to the table's columns.
+/// Such an expression is executed on one of the table's rows with
+/// `ExecuteQueryPlan::execute_expression_on_row`.
+/// Its placeholders are numbered as with `QueryPlan::compile_select`.
+pub fn compile_ast_expression<'a, DB>(db: &'a DB, table: Option<&'a <DB as DatabaseInfo>::Table>, expr: &ast::Expression,
+    parameter_count: u32)
+-> Result<SExpression<DB>, QueryPlanCompileError>
+where DB: DatabaseStorage<Info=DB> + DatabaseInfo, <DB as DatabaseInfo>::Table: 'a
 {
-    let scope = SourceScope::new(None, Vec::new(), Vec::new());
-
     let mut source_id_to_query_id = HashMap::new();
     let mut query_to_aggregated_source_id = HashMap::new();
     let mut next_source_id = 0;
     let mut next_query_id = 1;
 
     let mut groups_info = GroupsInfo::new();
+    let row_count = |table| db.scan_table(table).count();
 
     let mut compiler = QueryCompiler {
         query_id: 0,
         db: db,
+        parameter_count: parameter_count,
+        row_count: &row_count,
         source_id_to_query_id: &mut source_id_to_query_id,
         query_to_aggregated_source_id: &mut query_to_aggregated_source_id,
         next_source_id: &mut next_source_id,
         next_query_id: &mut next_query_id
     };
 
+    let scope = match table {
+        Some(table) => {
+            let source_id = compiler.new_source_id();
+            assert_eq!(source_id, ROW_SOURCE_ID);
+
+            let source_table = TableOrSubquery {
+                source_id: source_id,
+                column_offset: 0,
+                out_column_names: table.get_column_names()
+            };
+
+            SourceScope::new(None, vec![source_table], vec![table.get_name().clone()])
+        },
+        None => SourceScope::new(None, Vec::new(), Vec::new())
+    };
+
     compiler.ast_expression_to_sexpression(expr, &scope, &mut groups_info)
 }
 
+/// Compiles a plan that yields the rows of a table that pass the WHERE
+/// clause, as they're yielded by `DatabaseStorage::scan_rows`: every column,
+/// followed by the rowid. This finds the rows of an UPDATE or DELETE.
+///
+/// The WHERE clause is planned as it would be for a SELECT, so an index is
+/// searched if one finds the rows.
+pub fn compile_table_search<'a, DB>(db: &'a DB, table: &'a <DB as DatabaseInfo>::Table, where_expr: Option<&ast::Expression>,
+    parameter_count: u32)
+-> Result<SExpression<DB>, QueryPlanCompileError>
+where DB: DatabaseStorage<Info=DB> + DatabaseInfo, <DB as DatabaseInfo>::Table: 'a
+{
+    let where_expr = match where_expr {
+        Some(expr) => Some(try!(compile_ast_expression(db, Some(table), expr, parameter_count))),
+        None => None
+    };
+
+    let from_where = FromWhere::Cross {
+        tables: vec![FromWhereTableOrSubquery::Table {
+            source_id: ROW_SOURCE_ID,
+            table: table,
+            // A single table isn't ordered
+            row_count: 0
+        }],
+        where_expr: where_expr
+    };
+
+    // The rowid is the value after the last column
+    Ok(from_where.evaluate(SExpression::Yield {
+        fields: (0..table.get_column_count() + 1).map(|column_offset| {
+            SExpression::ColumnField {
+                source_id: ROW_SOURCE_ID,
+                column_offset: column_offset
+            }
+        }).collect()
+    }))
+}
+
 #[derive(Debug)]
 struct GroupsInfo {
     innermost_nonaggregated_query: Option<u32>
@@ -166,6 +261,10 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
 {
     query_id: u32,
     db: &'a DB,
+    /// The largest placeholder number
+    parameter_count: u32,
+    /// Counts the rows of a table, for choosing the order to join tables in
+    row_count: &'z Fn(&'a <DB as DatabaseInfo>::Table) -> u64,
     source_id_to_query_id: &'z mut HashMap<u32, u32>,
     query_to_aggregated_source_id: &'z mut HashMap<u32, u32>,
     next_source_id: &'z mut u32,
@@ -177,12 +276,12 @@ where <DB as DatabaseInfo>::Table: 'a
 {
     Cross {
         tables: Vec<FromWhereTableOrSubquery<'a, DB>>,
-        where_expr: Option<SExpression<'a, DB>>
+        where_expr: Option<SExpression<DB>>
     },
     Join {
         outer_table: FromWhereTableOrSubquery<'a, DB>,
         joins: Vec<FromWhereJoin<'a, DB>>,
-        where_expr: Option<SExpression<'a, DB>>
+        where_expr: Option<SExpression<DB>>
     }
 }
 
@@ -191,12 +290,13 @@ where <DB as DatabaseInfo>::Table: 'a
 {
     Inner {
         table: FromWhereTableOrSubquery<'a, DB>,
-        on: SExpression<'a, DB>
+        on: Option<SExpression<DB>>
     },
     Left {
         source_id: u32,
-        table: SExpression<'a, DB>,
-        on: SExpression<'a, DB>,
+        table: FromWhereTableOrSubquery<'a, DB>,
+        column_count: u32,
+        on: SExpression<DB>,
         right_rows_if_none: Vec<<DB as DatabaseInfo>::ColumnValue>
     }
 }
@@ -206,64 +306,219 @@ where <DB as DatabaseInfo>::Table: 'a
 {
     Table {
         source_id: u32,
-        table: &'a <DB as DatabaseInfo>::Table
+        table: &'a <DB as DatabaseInfo>::Table,
+        /// Only counted for the tables that `order_tables` orders, and 0 otherwise
+        row_count: u64
     },
     Subquery {
         source_id: u32,
-        expr: SExpression<'a, DB>
+        expr: SExpression<DB>
     }
 }
 
 impl<'a, DB: DatabaseInfo> FromWhere<'a, DB>
 where <DB as DatabaseInfo>::Table: 'a
 {
-    pub fn evaluate(self, inner_expr: SExpression<'a, DB>) -> SExpression<'a, DB> {
-        let core_expr = |where_expr| {
-            if let Some(where_expr) = where_expr {
-                SExpression::If {
-                    chains: vec![IfChain {
-                        predicate: where_expr,
-                        yield_fn: inner_expr
-                    }],
-                    else_: None
-                }
-            } else {
-                inner_expr
-            }
-        };
+    pub fn evaluate(self, inner_expr: SExpression<DB>) -> SExpression<DB> {
+        let mut predicates = Vec::new();
 
-        match self {
+        // Tables joined with commas are nested in the cheapest order.
+        // The ON clause of an inner join is just another predicate; a LEFT JOIN
+        // keeps its ON clause, and every table keeps its place around it.
+        let levels = match self {
             FromWhere::Cross { tables, where_expr } => {
-                tables.into_iter().rev().fold(core_expr(where_expr), |nested_expr, x| {
-                    x.into_sexpr(nested_expr)
-                })
+                if let Some(where_expr) = where_expr {
+                    into_conjuncts(where_expr, &mut predicates);
+                }
+
+                let order = order_tables(&tables, &predicates);
+                let mut tables: Vec<_> = tables.into_iter().map(Some).collect();
+
+                order.into_iter().map(|i| FromWhereLevel::Table(tables[i].take().unwrap())).collect()
             },
             FromWhere::Join { outer_table, joins, where_expr } => {
-                let s = joins.into_iter().rev().fold(core_expr(where_expr), |nested_expr, join| {
+                let mut levels = vec![FromWhereLevel::Table(outer_table)];
+
+                for join in joins {
                     match join {
                         FromWhereJoin::Inner { table, on } => {
-                            table.into_sexpr(SExpression::If {
-                                chains: vec![IfChain {
-                                    predicate: on,
-                                    yield_fn: nested_expr
-                                }],
-                                else_: None
-                            })
+                            if let Some(on) = on {
+                                into_conjuncts(on, &mut predicates);
+                            }
+                            levels.push(FromWhereLevel::Table(table));
                         },
-                        FromWhereJoin::Left { source_id, table, on, right_rows_if_none } => {
-                            SExpression::LeftJoin {
+                        FromWhereJoin::Left { source_id, table, column_count, on, right_rows_if_none } => {
+                            levels.push(FromWhereLevel::LeftJoin {
                                 source_id: source_id,
-                                yield_in_fn: Box::new(table),
-                                predicate: Box::new(on),
-                                yield_out_fn: Box::new(nested_expr),
+                                table: table,
+                                column_count: column_count,
+                                on: on,
                                 right_rows_if_none: right_rows_if_none
-                            }
+                            });
                         }
                     }
-                });
+                }
 
-                outer_table.into_sexpr(s)
+                if let Some(where_expr) = where_expr {
+                    into_conjuncts(where_expr, &mut predicates);
+                }
+
+                levels
             }
+        };
+
+        let level_source_ids: Vec<u32> = levels.iter().map(|level| level.source_id()).collect();
+
+        // Each table is searched with the predicates that its rows must pass,
+        // or hash joined on them if no index finds the rows with a given value.
+        // A table's search can only use the rows of the tables it's nested in.
+        let searches: Vec<_> = {
+            let predicate_refs: Vec<_> = predicates.iter().collect();
+
+            levels.iter().enumerate().map(|(i, level)| {
+                let unbound_source_ids = &level_source_ids[i..];
+                let bound_tables: Vec<_> = levels[..i].iter().filter_map(|level| level.bound_table()).collect();
+
+                let (source_id, table, predicates) = match level {
+                    &FromWhereLevel::Table(ref table) => (table.source_id(), table, predicate_refs.clone()),
+                    &FromWhereLevel::LeftJoin { source_id, ref table, ref on, .. } => {
+                        // The WHERE clause is checked after the right rows are padded with NULLs,
+                        // so only the ON clause can narrow the right rows.
+                        let mut on_predicates = Vec::new();
+                        conjuncts(on, &mut on_predicates);
+
+                        (source_id, table, on_predicates)
+                    }
+                };
+
+                let search = table.index_search(source_id, &predicates, unbound_source_ids);
+
+                match table.hash_join_keys(source_id, &predicates, search.as_ref(), &bound_tables) {
+                    Some(keys) => LevelSearch::HashJoin(keys),
+                    None => LevelSearch::Index(search)
+                }
+            }).collect()
+        };
+
+        // Each predicate is checked as soon as the rows it refers to are known:
+        // just inside the last of their levels, or before the first level if
+        // it refers to none of them.
+        // A predicate that only refers to a hash joined table is checked
+        // before its rows are added to the hash table.
+        let mut level_predicates: Vec<Vec<_>> = (0..levels.len() + 1).map(|_| Vec::new()).collect();
+        let mut build_predicates: Vec<Vec<_>> = levels.iter().map(|_| Vec::new()).collect();
+
+        for predicate in predicates {
+            let mut ids = Vec::new();
+            source_ids(&predicate, &mut ids);
+
+            match level_source_ids.iter().rposition(|id| ids.contains(id)) {
+                Some(i) => {
+                    let is_build_predicate = match (&levels[i], &searches[i]) {
+                        (&FromWhereLevel::Table(..), &LevelSearch::HashJoin(..)) => {
+                            ids.iter().all(|&id| id == level_source_ids[i])
+                        },
+                        _ => false
+                    };
+
+                    if is_build_predicate {
+                        build_predicates[i].push(predicate);
+                    } else {
+                        level_predicates[i + 1].push(predicate);
+                    }
+                },
+                None => level_predicates[0].push(predicate)
+            }
+        }
+
+        let mut level_predicates = level_predicates.into_iter().rev();
+        let innermost = filter(level_predicates.next().unwrap(), inner_expr);
+
+        let levels = levels.into_iter().zip(searches).zip(build_predicates);
+
+        levels.rev().zip(level_predicates).fold(innermost, |nested_expr, (((level, search), build_predicates), predicates)| {
+            let expr = match (level, search) {
+                (FromWhereLevel::Table(table), LevelSearch::Index(search)) => table.into_sexpr(nested_expr, search),
+                (FromWhereLevel::Table(table), LevelSearch::HashJoin(keys)) => {
+                    let source_id = table.source_id();
+                    let column_count = table.column_count();
+
+                    SExpression::HashJoin {
+                        source_id: source_id,
+                        yield_in_fn: Box::new(table.yield_all_columns(column_count, build_predicates, None)),
+                        build_keys: keys.build_keys,
+                        probe_keys: keys.probe_keys,
+                        predicate: None,
+                        yield_out_fn: Box::new(nested_expr),
+                        right_rows_if_none: None
+                    }
+                },
+                (FromWhereLevel::LeftJoin { source_id, table, column_count, on, right_rows_if_none }, LevelSearch::Index(search)) => {
+                    SExpression::LeftJoin {
+                        source_id: source_id,
+                        yield_in_fn: Box::new(table.yield_all_columns(column_count, Vec::new(), search)),
+                        predicate: Box::new(on),
+                        yield_out_fn: Box::new(nested_expr),
+                        right_rows_if_none: right_rows_if_none
+                    }
+                },
+                (FromWhereLevel::LeftJoin { source_id, table, column_count, on, right_rows_if_none }, LevelSearch::HashJoin(keys)) => {
+                    SExpression::HashJoin {
+                        source_id: source_id,
+                        yield_in_fn: Box::new(table.yield_all_columns(column_count, Vec::new(), None)),
+                        build_keys: keys.build_keys,
+                        probe_keys: keys.probe_keys,
+                        predicate: Some(Box::new(on)),
+                        yield_out_fn: Box::new(nested_expr),
+                        right_rows_if_none: Some(right_rows_if_none)
+                    }
+                }
+            };
+
+            filter(predicates, expr)
+        })
+    }
+}
+
+/// How the rows of a level are found
+enum LevelSearch<'a, DB: DatabaseInfo>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    /// Scanned, or searched with an index
+    Index(Option<IndexSearch<'a, DB>>),
+    HashJoin(HashJoinKeys<DB>)
+}
+
+/// A table of a FROM clause, in the order they're nested
+enum FromWhereLevel<'a, DB: DatabaseInfo>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    Table(FromWhereTableOrSubquery<'a, DB>),
+    LeftJoin {
+        source_id: u32,
+        table: FromWhereTableOrSubquery<'a, DB>,
+        column_count: u32,
+        on: SExpression<DB>,
+        right_rows_if_none: Vec<<DB as DatabaseInfo>::ColumnValue>
+    }
+}
+
+impl<'a, DB: DatabaseInfo> FromWhereLevel<'a, DB>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    /// The source id that predicates use for the level's rows
+    fn source_id(&self) -> u32 {
+        match self {
+            &FromWhereLevel::Table(ref table) => table.source_id(),
+            &FromWhereLevel::LeftJoin { source_id, .. } => source_id
+        }
+    }
+
+    /// The level's table and the source id of its rows, if it isn't a subquery
+    fn bound_table(&self) -> Option<(u32, &'a <DB as DatabaseInfo>::Table)> {
+        match self {
+            &FromWhereLevel::Table(ref table) => table.bound_table(),
+            &FromWhereLevel::LeftJoin { source_id, ref table, .. } => table.bound_table().map(|(_, table)| (source_id, table))
         }
     }
 }
@@ -271,7 +526,7 @@ where <DB as DatabaseInfo>::Table: 'a
 impl<'a, DB: DatabaseInfo> FromWhereTableOrSubquery<'a, DB>
 where <DB as DatabaseInfo>::Table: 'a
 {
-    fn into_sexpr(self, nested_expr: SExpression<'a, DB>) -> SExpression<'a, DB> {
+    fn into_sexpr(self, nested_expr: SExpression<DB>, search: Option<IndexSearch<'a, DB>>) -> SExpression<DB> {
         match self {
             FromWhereTableOrSubquery::Subquery { source_id, expr } => {
                 SExpression::Map {
@@ -280,16 +535,73 @@ where <DB as DatabaseInfo>::Table: 'a
                     yield_out_fn: Box::new(nested_expr)
                 }
             },
-            FromWhereTableOrSubquery::Table { source_id, table } => {
-                SExpression::Scan {
-                    source_id: source_id,
-                    table: table,
-                    yield_fn: Box::new(nested_expr)
+            FromWhereTableOrSubquery::Table { source_id, table, .. } => {
+                match search {
+                    Some(search) => SExpression::Search {
+                        table: table.get_name().clone(),
+                        source_id: source_id,
+                        index: search.index.get_name().clone(),
+                        min: search.min,
+                        max: search.max,
+                        yield_fn: Box::new(nested_expr)
+                    },
+                    None => SExpression::Scan {
+                        source_id: source_id,
+                        table: table.get_name().clone(),
+                        yield_fn: Box::new(nested_expr)
+                    }
                 }
             }
         }
     }
 
+    /// The index search that yields the fewest rows that can pass the predicates.
+    /// `source_id` is the source id that the predicates use for this table's rows.
+    fn index_search<'b>(&self, source_id: u32, predicates: &[&'b SExpression<DB>], unbound_source_ids: &[u32])
+    -> Option<IndexSearch<'a, DB>>
+    {
+        match self {
+            &FromWhereTableOrSubquery::Table { table, .. } => {
+                choose_index(table, source_id, predicates, unbound_source_ids)
+            },
+            &FromWhereTableOrSubquery::Subquery { .. } => None
+        }
+    }
+
+    /// The keys to hash join the table on, if it isn't searched for rows with a given value.
+    /// `source_id` is the source id that the predicates use for this table's rows.
+    fn hash_join_keys<'b>(&self, source_id: u32, predicates: &[&'b SExpression<DB>],
+        search: Option<&IndexSearch<'a, DB>>, bound_tables: &[(u32, &'a <DB as DatabaseInfo>::Table)])
+    -> Option<HashJoinKeys<DB>>
+    {
+        if search.map_or(false, |search| search.has_equal_columns()) {
+            return None;
+        }
+
+        match self {
+            &FromWhereTableOrSubquery::Table { table, .. } => {
+                hash_join_keys(table, source_id, predicates, bound_tables)
+            },
+            &FromWhereTableOrSubquery::Subquery { .. } => None
+        }
+    }
+
+    /// The table and the source id of its rows, if it isn't a subquery
+    fn bound_table(&self) -> Option<(u32, &'a <DB as DatabaseInfo>::Table)> {
+        match self {
+            &FromWhereTableOrSubquery::Table { source_id, table, .. } => Some((source_id, table)),
+            &FromWhereTableOrSubquery::Subquery { .. } => None
+        }
+    }
+
+    /// The number of columns of a table. Only tables are hash joined.
+    fn column_count(&self) -> u32 {
+        match self {
+            &FromWhereTableOrSubquery::Table { table, .. } => table.get_column_count(),
+            &FromWhereTableOrSubquery::Subquery { .. } => unreachable!()
+        }
+    }
+
     fn source_id(&self) -> u32 {
         match self {
             &FromWhereTableOrSubquery::Table { source_id, .. } => source_id,
@@ -297,18 +609,21 @@ where <DB as DatabaseInfo>::Table: 'a
         }
     }
 
-    fn yield_all_columns(self, column_count: u32) -> SExpression<'a, DB> {
+    /// Yields every column of the rows that pass the predicates.
+    fn yield_all_columns(self, column_count: u32, predicates: Vec<SExpression<DB>>, search: Option<IndexSearch<'a, DB>>)
+    -> SExpression<DB>
+    {
         // TODO: remove column_count parameter, put that information in the type
         let source_id = self.source_id();
 
-        self.into_sexpr(SExpression::Yield {
+        self.into_sexpr(filter(predicates, SExpression::Yield {
             fields: (0..column_count).map(|column_offset| {
                 SExpression::ColumnField {
                     source_id: source_id,
                     column_offset: column_offset
                 }
             }).collect()
-        })
+        }), search)
     }
 }
 
@@ -348,28 +663,24 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
         *self.source_id_to_query_id.get(&source_id).unwrap()
     }
 
-    fn compile<'b>(mut self, stmt: ast::SelectStatement, outer_scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
-    -> Result<QueryPlan<'a, DB>, QueryPlanCompileError>
+    fn compile<'b>(mut self, stmt: &ast::SelectStatement, outer_scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
+    -> Result<QueryPlan<DB>, QueryPlanCompileError>
     {
-        // Unimplemented syntaxes: ORDER BY
-        // TODO - implement them!
-        if !stmt.order_by.is_empty() { unimplemented!() }
-
         // FROM and WHERE are compiled together.
         // This makes sense for INNER and OUTER joins, which also
         // contain ON (conditional) expressions.
 
-        let (new_scope, from_where) = try!(self.from_where(stmt.from, stmt.where_expr, outer_scope, groups_info));
+        let (new_scope, from_where) = try!(self.from_where(&stmt.from, stmt.where_expr.as_ref(), outer_scope, groups_info));
 
         let (mut group_by_values, having_predicate) = if !stmt.group_by.is_empty() {
             let query_id = self.query_id;
             self.new_aggregated_source_id(query_id);
 
-            let group_by_values = try!(stmt.group_by.into_iter().map(|expr| {
+            let group_by_values = try!(stmt.group_by.iter().map(|expr| {
                 self.ast_expression_to_sexpression(expr, &new_scope, groups_info)
             }).collect());
 
-            let having_predicate = if let Some(having) = stmt.having {
+            let having_predicate = if let Some(ref having) = stmt.having {
                 Some(try!(self.ast_expression_to_sexpression(having, &new_scope, groups_info)))
             } else {
                 None
@@ -380,7 +691,26 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
             (vec![], None)
         };
 
-        let (column_names, select_exprs) = try!(self.select(stmt.result_columns, &new_scope, groups_info));
+        let (column_names, select_exprs) = try!(self.select(&stmt.result_columns, &new_scope, groups_info));
+
+        // An ORDER BY term either refers to a result column, or is an
+        // expression that is yielded after the result columns.
+        let result_column_count = select_exprs.len() as u32;
+        let mut fields = select_exprs;
+        let mut sort_by = Vec::new();
+
+        for term in &stmt.order_by {
+            let column_offset = match try!(order_by_result_column(&term.expr, &column_names)) {
+                Some(column_offset) => column_offset,
+                None => {
+                    let e = try!(self.ast_expression_to_sexpression(&term.expr, &new_scope, groups_info));
+                    fields.push(e);
+                    fields.len() as u32 - 1
+                }
+            };
+
+            sort_by.push((column_offset, ast_order_to_sort_order(&term.order)));
+        }
 
         let grouped_source_id = self.query_to_aggregated_source_id.get(&self.query_id).cloned();
 
@@ -388,11 +718,12 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
             let yield_every_column = SExpression::Yield {
                 fields: new_scope.tables().iter().flat_map(|table| {
                     let source_id = table.source_id;
+                    let column_offset = table.column_offset;
 
-                    (0..table.out_column_names.len() as u32).map(move |column_offset| {
+                    (0..table.out_column_names.len() as u32).map(move |i| {
                         SExpression::ColumnField {
                             source_id: source_id,
-                            column_offset: column_offset
+                            column_offset: column_offset + i
                         }
                     })
                 }).collect()
@@ -403,10 +734,12 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
             let mapping = {
                 let mut c = 0;
 
+                // The tables on both sides of an outer join share a source,
+                // and are yielded in the order of their columns in it
                 new_scope.tables().iter().map(|table| {
                     let m = Mapping {
                         source_id: source_id,
-                        column_offset: c
+                        column_offset: c - table.column_offset
                     };
 
                     c += table.out_column_names.len() as u32;
@@ -415,7 +748,7 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                 }).collect()
             };
 
-            let mut yield_out_fn = SExpression::Yield { fields: select_exprs };
+            let mut yield_out_fn = SExpression::Yield { fields: fields };
 
             for expr in &mut group_by_values {
                 remap_columns_in_sexpression(expr, &mapping);
@@ -440,7 +773,65 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                 yield_out_fn: Box::new(yield_out_fn)
             }
         } else {
-            from_where.evaluate(SExpression::Yield { fields: select_exprs })
+            from_where.evaluate(SExpression::Yield { fields: fields })
+        };
+
+        let expr = if sort_by.is_empty() {
+            expr
+        } else {
+            let source_id = self.new_source_id();
+
+            SExpression::Sort {
+                source_id: source_id,
+                yield_in_fn: Box::new(expr),
+                sort_by: sort_by.into_iter().map(|(column_offset, order)| {
+                    SortKey {
+                        expr: SExpression::ColumnField {
+                            source_id: source_id,
+                            column_offset: column_offset
+                        },
+                        order: order
+                    }
+                }).collect(),
+                // Leave out the ORDER BY expressions
+                yield_out_fn: Box::new(SExpression::Yield {
+                    fields: (0..result_column_count).map(|column_offset| {
+                        SExpression::ColumnField {
+                            source_id: source_id,
+                            column_offset: column_offset
+                        }
+                    }).collect()
+                })
+            }
+        };
+
+        // DISTINCT comes after the sort, so that the first row of each
+        // duplicate is kept in order.
+        let expr = if stmt.distinct {
+            SExpression::Distinct {
+                yield_in_fn: Box::new(expr)
+            }
+        } else {
+            expr
+        };
+
+        // LIMIT and OFFSET can't refer to this query's columns.
+        let expr = if let Some(ref limit) = stmt.limit {
+            let count = try!(self.ast_expression_to_sexpression(&limit.count, outer_scope, groups_info));
+
+            let offset = if let Some(ref offset) = limit.offset {
+                Some(Box::new(try!(self.ast_expression_to_sexpression(offset, outer_scope, groups_info))))
+            } else {
+                None
+            };
+
+            SExpression::Limit {
+                yield_in_fn: Box::new(expr),
+                count: Box::new(count),
+                offset: offset
+            }
+        } else {
+            expr
         };
 
         Ok(QueryPlan {
@@ -449,34 +840,34 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
         })
     }
 
-    fn from_where<'b>(&mut self, from: ast::From, where_expr: Option<ast::Expression>, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
+    fn from_where<'b>(&mut self, from: &ast::From, where_expr: Option<&ast::Expression>, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
     -> Result<(SourceScope<'b>, FromWhere<'a, DB>), QueryPlanCompileError>
     {
-        // TODO - avoid naive nested scans when indices are available
-
         // All FROM subqueries are nested, never correlated.
         match from {
-            ast::From::Cross(v) => self.from_where_cross(v, where_expr, scope, groups_info),
-            ast::From::Join { table, joins } => self.from_where_join(table, joins, where_expr, scope, groups_info)
+            &ast::From::Cross(ref v) => self.from_where_cross(v, where_expr, scope, groups_info),
+            &ast::From::Join { ref table, ref joins } => self.from_where_join(table, joins, where_expr, scope, groups_info)
         }
     }
 
-    fn ast_table_or_subquery_to<'b>(&mut self, ast_table_or_subquery: ast::TableOrSubquery, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
+    fn ast_table_or_subquery_to<'b>(&mut self, ast_table_or_subquery: &ast::TableOrSubquery, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
     -> Result<((TableOrSubquery, FromWhereTableOrSubquery<'a, DB>), Identifier), QueryPlanCompileError>
     {
         match ast_table_or_subquery {
-            ast::TableOrSubquery::Subquery { subquery, alias } => {
+            &ast::TableOrSubquery::Subquery { ref subquery, ref alias } => {
                 let plan = {
                     let compiler = QueryCompiler {
                         query_id: self.new_query_id(),
                         db: self.db,
+                        parameter_count: self.parameter_count,
+                        row_count: self.row_count,
                         source_id_to_query_id: self.source_id_to_query_id,
                         query_to_aggregated_source_id: self.query_to_aggregated_source_id,
                         next_source_id: self.next_source_id,
                         next_query_id: self.next_query_id
                     };
 
-                    try!(compiler.compile(*subquery, scope, groups_info))
+                    try!(compiler.compile(subquery, scope, groups_info))
                 };
                 let alias_identifier = try!(new_identifier(&alias));
 
@@ -484,6 +875,7 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
 
                 let s = TableOrSubquery {
                     source_id: source_id,
+                    column_offset: 0,
                     out_column_names: plan.out_column_names
                 };
 
@@ -494,14 +886,18 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
 
                 Ok(((s, t), alias_identifier))
             },
-            ast::TableOrSubquery::Table { table, alias } => {
+            &ast::TableOrSubquery::Table { ref table, ref alias } => {
+                if let Some(ref database_name) = table.database_name {
+                    return Err(QueryPlanCompileError::DatabaseNameNotSupported(format!("{}.{}", database_name, table.table_name)));
+                }
+
                 let table_name_identifier = try!(new_identifier(&table.table_name));
                 let table = match self.db.find_table_by_name(&table_name_identifier) {
                     Some(table) => table,
                     None => return Err(QueryPlanCompileError::TableDoesNotExist(table_name_identifier))
                 };
 
-                let alias_identifier = if let Some(alias) = alias {
+                let alias_identifier = if let &Some(ref alias) = alias {
                     try!(new_identifier(&alias))
                 } else {
                     table_name_identifier
@@ -511,12 +907,14 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
 
                 let s = TableOrSubquery {
                     source_id: source_id,
+                    column_offset: 0,
                     out_column_names: table.get_column_names()
                 };
 
                 let t = FromWhereTableOrSubquery::Table {
                     source_id: source_id,
-                    table: table
+                    table: table,
+                    row_count: 0
                 };
 
                 Ok(((s, t), alias_identifier))
@@ -524,16 +922,25 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
         }
     }
 
-    fn from_where_cross<'b>(&mut self, ast_cross_tables: Vec<ast::TableOrSubquery>, where_expr: Option<ast::Expression>, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
+    fn from_where_cross<'b>(&mut self, ast_cross_tables: &[ast::TableOrSubquery], where_expr: Option<&ast::Expression>, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
     -> Result<(SourceScope<'b>, FromWhere<'a, DB>), QueryPlanCompileError>
     {
-        let a: Vec<_> = try!(ast_cross_tables.into_iter().map(|ast_table_or_subquery| {
+        let a: Vec<_> = try!(ast_cross_tables.iter().map(|ast_table_or_subquery| {
             self.ast_table_or_subquery_to(ast_table_or_subquery, scope, groups_info)
         }).collect());
 
         let (tables, table_aliases): (Vec<_>, _) = a.into_iter().unzip();
 
-        let (source_tables, fromwhere_tables) = tables.into_iter().unzip();
+        let (source_tables, mut fromwhere_tables): (_, Vec<_>) = tables.into_iter().unzip();
+
+        // The row counts are only needed to order the tables
+        if is_ordered(fromwhere_tables.len()) {
+            for fromwhere_table in fromwhere_tables.iter_mut() {
+                if let &mut FromWhereTableOrSubquery::Table { table, ref mut row_count, .. } = fromwhere_table {
+                    *row_count = (self.row_count)(table);
+                }
+            }
+        }
 
         let new_scope = SourceScope::new(Some(scope), source_tables, table_aliases);
 
@@ -549,50 +956,116 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
         }))
     }
 
-    fn from_where_join<'b>(&mut self, table: ast::TableOrSubquery, joins: Vec<ast::Join>, where_expr: Option<ast::Expression>, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
+    fn from_where_join<'b>(&mut self, table: &ast::TableOrSubquery, joins: &[ast::Join], where_expr: Option<&ast::Expression>, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
     -> Result<(SourceScope<'b>, FromWhere<'a, DB>), QueryPlanCompileError>
     {
         let ((source_table, fromwhere_table), alias) = try!(self.ast_table_or_subquery_to(table, scope, groups_info));
 
         let mut new_scope = SourceScope::new(Some(scope), vec![source_table], vec![alias]);
 
-        let j = try!(joins.into_iter().map(|join| {
-            let ((source_table, fromwhere_table), alias) = try!(self.ast_table_or_subquery_to(join.table, scope, groups_info));
+        let mut outer_table = fromwhere_table;
+        let mut j = Vec::new();
 
-            match join.operator {
-                ast::JoinOperator::Inner => {
+        for join in joins {
+            let ((source_table, fromwhere_table), alias) = try!(self.ast_table_or_subquery_to(&join.table, scope, groups_info));
+            let column_count = source_table.out_column_names.len() as u32;
+
+            match &join.operator {
+                &ast::JoinOperator::Inner | &ast::JoinOperator::Cross => {
                     new_scope.tables.push(source_table);
                     new_scope.table_aliases.push(alias);
 
-                    let on = try!(self.ast_expression_to_sexpression(join.on, &new_scope, groups_info));
-                    Ok(FromWhereJoin::Inner {
+                    let on = try!(self.join_predicate(&join.constraint, false, false, &mut new_scope, groups_info));
+                    j.push(FromWhereJoin::Inner {
                         table: fromwhere_table,
                         on: on
-                    })
+                    });
                 },
-                ast::JoinOperator::Left => {
+                &ast::JoinOperator::Left => {
                     let source_id = self.new_source_id();
 
                     let left_join_source_table = TableOrSubquery {
                         source_id: source_id,
+                        column_offset: 0,
                         out_column_names: source_table.out_column_names
                     };
 
-                    let column_count = left_join_source_table.out_column_names.len() as u32;
-
                     new_scope.tables.push(left_join_source_table);
                     new_scope.table_aliases.push(alias);
 
-                    let on = try!(self.ast_expression_to_sexpression(join.on, &new_scope, groups_info));
-                    Ok(FromWhereJoin::Left {
+                    let on = try!(self.join_predicate(&join.constraint, false, true, &mut new_scope, groups_info));
+                    j.push(FromWhereJoin::Left {
                         source_id: source_id,
-                        table: fromwhere_table.yield_all_columns(column_count),
-                        on: on,
-                        right_rows_if_none: (0..column_count).map(|_| ColumnValueOpsExt::null()).collect()
-                    })
+                        table: fromwhere_table,
+                        column_count: column_count,
+                        on: on.unwrap_or_else(true_value),
+                        right_rows_if_none: null_row(column_count)
+                    });
+                },
+                &ast::JoinOperator::Right | &ast::JoinOperator::Full => {
+                    // The tables joined so far are the left side of the join.
+                    // The rows of both sides are joined into the rows of a new source.
+                    let left_fields: Vec<_> = new_scope.tables.iter().flat_map(|table| {
+                        let source_id = table.source_id;
+                        let column_offset = table.column_offset;
+
+                        (0..table.out_column_names.len() as u32).map(move |i| {
+                            SExpression::ColumnField {
+                                source_id: source_id,
+                                column_offset: column_offset + i
+                            }
+                        })
+                    }).collect();
+
+                    let left_column_count = left_fields.len() as u32;
+
+                    let left = FromWhere::Join {
+                        outer_table: outer_table,
+                        joins: j,
+                        where_expr: None
+                    };
+                    let left_fn = left.evaluate(SExpression::Yield { fields: left_fields });
+
+                    let source_id = self.new_source_id();
+                    let mut column_offset = 0;
+
+                    for table in new_scope.tables.iter_mut() {
+                        table.source_id = source_id;
+                        table.column_offset = column_offset;
+                        column_offset += table.out_column_names.len() as u32;
+                    }
+
+                    new_scope.tables.push(TableOrSubquery {
+                        source_id: source_id,
+                        column_offset: left_column_count,
+                        out_column_names: source_table.out_column_names
+                    });
+                    new_scope.table_aliases.push(alias);
+
+                    let is_full = join.operator == ast::JoinOperator::Full;
+                    let on = try!(self.join_predicate(&join.constraint, true, is_full, &mut new_scope, groups_info));
+
+                    let right_rows_if_none = if is_full {
+                        Some(null_row(column_count))
+                    } else {
+                        None
+                    };
+
+                    outer_table = FromWhereTableOrSubquery::Subquery {
+                        source_id: source_id,
+                        expr: SExpression::OuterJoin {
+                            source_id: source_id,
+                            left_fn: Box::new(left_fn),
+                            right_fn: Box::new(fromwhere_table.yield_all_columns(column_count, Vec::new(), None)),
+                            predicate: Box::new(on.unwrap_or_else(true_value)),
+                            left_rows_if_none: Some(null_row(left_column_count)),
+                            right_rows_if_none: right_rows_if_none
+                        }
+                    };
+                    j = Vec::new();
                 }
             }
-        }).collect());
+        }
 
         let where_expr = if let Some(where_expr) = where_expr {
             Some(try!(self.ast_expression_to_sexpression(where_expr, &new_scope, groups_info)))
@@ -601,14 +1074,112 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
         };
 
         Ok((new_scope, FromWhere::Join {
-            outer_table: fromwhere_table,
+            outer_table: outer_table,
             joins: j,
             where_expr: where_expr
         }))
     }
 
-    fn select<'b>(&mut self, result_columns: Vec<ast::SelectColumn>, scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
-    -> Result<(Vec<Identifier>, Vec<SExpression<'a, DB>>), QueryPlanCompileError>
+    /// Compiles the predicate of a join to the last table of the scope.
+    /// Returns None if the join has no predicate.
+    ///
+    /// The columns of USING and NATURAL are merged in the scope. A side of
+    /// the join is outer if its rows can be NULL; see `SourceScope::merge_columns`.
+    fn join_predicate<'b>(&mut self, constraint: &ast::JoinConstraint, left_is_outer: bool, right_is_outer: bool,
+        scope: &mut SourceScope<'b>, groups_info: &mut GroupsInfo)
+    -> Result<Option<SExpression<DB>>, QueryPlanCompileError>
+    {
+        let left_table_count = scope.tables.len() - 1;
+
+        let column_names = match constraint {
+            &ast::JoinConstraint::On(ref on) => {
+                return self.ast_expression_to_sexpression(on, scope, groups_info).map(Some);
+            },
+            &ast::JoinConstraint::None => return Ok(None),
+            &ast::JoinConstraint::Using(ref names) => {
+                try!(names.iter().map(|name| new_identifier(name)).collect::<Result<Vec<_>, _>>())
+            },
+            &ast::JoinConstraint::Natural => {
+                // The columns of the right table that a left table has too
+                scope.tables[left_table_count].out_column_names.iter().filter(|name| {
+                    match scope.find_local_column(left_table_count, name) {
+                        Err(GetColumnResult::None) => false,
+                        _ => true
+                    }
+                }).cloned().collect()
+            }
+        };
+
+        let mut predicates = Vec::new();
+
+        for name in column_names {
+            let left_columns = match scope.find_local_column(left_table_count, &name) {
+                Ok(columns) => columns,
+                Err(GetColumnResult::Ambiguous) => return Err(QueryPlanCompileError::AmbiguousColumnName(name)),
+                Err(_) => return Err(QueryPlanCompileError::ColumnDoesNotExist(name))
+            };
+
+            let lhs = {
+                let offsets = left_columns.iter().map(|&column| scope.column_offset(column)).collect();
+                self.column_value(offsets, groups_info)
+            };
+            let rhs = try!(self.join_column(&scope.tables[left_table_count..], &name, groups_info));
+
+            predicates.push(SExpression::BinaryOp {
+                op: BinaryOp::Equal,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs)
+            });
+
+            scope.merge_columns(&name, left_columns, left_is_outer, right_is_outer);
+        }
+
+        Ok(conjunction(predicates))
+    }
+
+    /// The column of USING or NATURAL in the right side of a join
+    fn join_column(&mut self, tables: &[TableOrSubquery], name: &Identifier, groups_info: &mut GroupsInfo)
+    -> Result<SExpression<DB>, QueryPlanCompileError>
+    {
+        let (source_id, column_offset) = match find_column_offset(tables, name) {
+            GetColumnOffsetResult::One(v) => v,
+            GetColumnOffsetResult::None => return Err(QueryPlanCompileError::ColumnDoesNotExist(name.clone())),
+            GetColumnOffsetResult::Ambiguous(..) => return Err(QueryPlanCompileError::AmbiguousColumnName(name.clone()))
+        };
+
+        groups_info.add_query_id(self.get_query_id_from_source_id(source_id));
+
+        Ok(SExpression::ColumnField {
+            source_id: source_id,
+            column_offset: column_offset
+        })
+    }
+
+    /// The value of a column found by its name: the first value of the
+    /// (source id, column offset) columns that isn't NULL
+    fn column_value(&mut self, columns: Vec<(u32, u32)>, groups_info: &mut GroupsInfo) -> SExpression<DB> {
+        let mut fields = columns.into_iter().map(|(source_id, column_offset)| {
+            groups_info.add_query_id(self.get_query_id_from_source_id(source_id));
+
+            SExpression::ColumnField {
+                source_id: source_id,
+                column_offset: column_offset
+            }
+        });
+
+        let first = fields.next().unwrap();
+
+        fields.fold(first, |lhs, rhs| {
+            SExpression::BinaryOp {
+                op: BinaryOp::Coalesce,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs)
+            }
+        })
+    }
+
+    fn select<'b>(&mut self, result_columns: &[ast::SelectColumn], scope: &'b SourceScope<'b>, groups_info: &mut GroupsInfo)
+    -> Result<(Vec<Identifier>, Vec<SExpression<DB>>), QueryPlanCompileError>
     {
         let mut arbitrary_column_count = 0;
 
@@ -623,34 +1194,41 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
 
         for c in result_columns {
             match c {
-                ast::SelectColumn::AllColumns => {
+                &ast::SelectColumn::AllColumns => {
                     groups_info.add_query_id(self.query_id);
 
-                    a.extend(scope.tables().iter().flat_map(|table| {
-                        let source_id = table.source_id;
+                    // The merged columns of USING and NATURAL come first,
+                    // in place of the columns they merge
+                    for column in &scope.merged_columns {
+                        let offsets = column.value_columns.iter().map(|&c| scope.column_offset(c)).collect();
+                        a.push((column.name.clone(), self.column_value(offsets, groups_info)));
+                    }
 
-                        table.out_column_names.iter().enumerate().map(move |(i, name)| {
-                            (name.clone(), SExpression::ColumnField {
-                                source_id: source_id,
-                                column_offset: i as u32
-                            })
-                        })
-                    }));
+                    for (i, table) in scope.tables().iter().enumerate() {
+                        for (j, name) in table.out_column_names.iter().enumerate() {
+                            if !scope.is_merged((i, j as u32)) {
+                                a.push((name.clone(), SExpression::ColumnField {
+                                    source_id: table.source_id,
+                                    column_offset: table.column_offset + j as u32
+                                }));
+                            }
+                        }
+                    }
                 },
-                ast::SelectColumn::Expr { expr, alias } => {
-                    let column_name = if let Some(alias) = alias {
+                &ast::SelectColumn::Expr { ref expr, ref alias } => {
+                    let column_name = if let &Some(ref alias) = alias {
                         try!(new_identifier(&alias))
                     } else {
                         // if the expression is a simple identifier, make that
                         // the column name. else, assign an arbitrary name.
-                        if let &ast::Expression::Ident(ref n) = &expr {
+                        if let &ast::Expression::Ident(ref n) = expr {
                             try!(new_identifier(n))
                         } else {
                             arbitrary_column_name()
                         }
                     };
 
-                    let e = try!(self.ast_expression_to_sexpression(expr, &scope, groups_info));
+                    let e = try!(self.ast_expression_to_sexpression(expr, scope, groups_info));
                     a.push((column_name, e));
                 }
             }
@@ -659,30 +1237,23 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
         Ok(a.into_iter().unzip())
     }
 
-    fn ast_expression_to_sexpression<'b>(&mut self, ast: ast::Expression, scope: &'b SourceScope<'b>,
+    fn ast_expression_to_sexpression<'b>(&mut self, ast: &ast::Expression, scope: &'b SourceScope<'b>,
         groups_info: &mut GroupsInfo)
-    -> Result<SExpression<'a, DB>, QueryPlanCompileError>
+    -> Result<SExpression<DB>, QueryPlanCompileError>
     {
         use std::borrow::IntoCow;
 
         match ast {
-            ast::Expression::Ident(s) => {
+            &ast::Expression::Ident(ref s) => {
                 let column_identifier = try!(new_identifier(&s));
 
-                let (source_id, column_offset) = match scope.get_column_offset(&column_identifier) {
-                    GetColumnOffsetResult::One(v) => v,
-                    GetColumnOffsetResult::None => return Err(QueryPlanCompileError::ColumnDoesNotExist(column_identifier)),
-                    GetColumnOffsetResult::Ambiguous(..) => return Err(QueryPlanCompileError::AmbiguousColumnName(column_identifier))
-                };
-
-                groups_info.add_query_id(self.get_query_id_from_source_id(source_id));
-
-                Ok(SExpression::ColumnField {
-                    source_id: source_id,
-                    column_offset: column_offset
-                })
+                match scope.get_column(&column_identifier) {
+                    GetColumnResult::One(columns) => Ok(self.column_value(columns, groups_info)),
+                    GetColumnResult::None => Err(QueryPlanCompileError::ColumnDoesNotExist(column_identifier)),
+                    GetColumnResult::Ambiguous => Err(QueryPlanCompileError::AmbiguousColumnName(column_identifier))
+                }
             },
-            ast::Expression::IdentMember(s1, s2) => {
+            &ast::Expression::IdentMember(ref s1, ref s2) => {
                 let table_identifier = try!(new_identifier(&s1));
                 let column_identifier = try!(new_identifier(&s2));
 
@@ -699,17 +1270,17 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                     column_offset: column_offset
                 })
             },
-            ast::Expression::UnaryOp { expr, op } => {
-                let e = try!(self.ast_expression_to_sexpression(*expr, scope, groups_info));
+            &ast::Expression::UnaryOp { ref expr, ref op } => {
+                let e = try!(self.ast_expression_to_sexpression(expr, scope, groups_info));
 
                 Ok(SExpression::UnaryOp {
                     op: ast_unaryop_to_sexpression_unaryop(op),
                     expr: Box::new(e)
                 })
             },
-            ast::Expression::BinaryOp { lhs, rhs, op } => {
-                let l = try!(self.ast_expression_to_sexpression(*lhs, scope, groups_info));
-                let r = try!(self.ast_expression_to_sexpression(*rhs, scope, groups_info));
+            &ast::Expression::BinaryOp { ref lhs, ref rhs, ref op } => {
+                let l = try!(self.ast_expression_to_sexpression(lhs, scope, groups_info));
+                let r = try!(self.ast_expression_to_sexpression(rhs, scope, groups_info));
 
                 Ok(SExpression::BinaryOp {
                     op: ast_binaryop_to_sexpression_binaryop(op),
@@ -717,34 +1288,43 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                     rhs: Box::new(r)
                 })
             },
-            ast::Expression::StringLiteral(s) => {
-                match DB::ColumnValue::from_string_literal(s.into_cow()) {
+            &ast::Expression::StringLiteral(ref s) => {
+                match DB::ColumnValue::from_string_literal((&s[..]).into_cow()) {
                     Ok(value) => Ok(SExpression::Value(value)),
                     Err(s) => Err(QueryPlanCompileError::BadStringLiteral(s.into_owned()))
                 }
             },
-            ast::Expression::Number(s) => {
-                match DB::ColumnValue::from_number_literal(s.into_cow()) {
+            &ast::Expression::Number(ref s) => {
+                match DB::ColumnValue::from_number_literal((&s[..]).into_cow()) {
                     Ok(value) => Ok(SExpression::Value(value)),
                     Err(s) => Err(QueryPlanCompileError::BadNumberLiteral(s.into_owned()))
                 }
             },
-            ast::Expression::Null => {
+            &ast::Expression::Null => {
                 Ok(SExpression::Value(ColumnValueOpsExt::null()))
             },
-            ast::Expression::Subquery(subquery) => {
+            &ast::Expression::Placeholder(number) => {
+                if number <= self.parameter_count {
+                    Ok(SExpression::Parameter(number))
+                } else {
+                    Err(QueryPlanCompileError::ParameterHasNoValue(number))
+                }
+            },
+            &ast::Expression::Subquery(ref subquery) => {
                 let source_id = self.new_source_id();
 
                 let compiler = QueryCompiler {
                     query_id: self.new_query_id(),
                     db: self.db,
+                    parameter_count: self.parameter_count,
+                    row_count: self.row_count,
                     source_id_to_query_id: self.source_id_to_query_id,
                     query_to_aggregated_source_id: self.query_to_aggregated_source_id,
                     next_source_id: self.next_source_id,
                     next_query_id: self.next_query_id
                 };
 
-                let plan = try!(compiler.compile(*subquery, scope, groups_info));
+                let plan = try!(compiler.compile(subquery, scope, groups_info));
 
                 Ok(SExpression::Map {
                     source_id: source_id,
@@ -755,7 +1335,7 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                     })
                 })
             },
-            ast::Expression::FunctionCall { name, arguments } => {
+            &ast::Expression::FunctionCall { ref name, distinct, ref arguments } => {
                 let ident = try!(new_identifier(&name));
 
                 macro_rules! aggregate {
@@ -763,7 +1343,7 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                         if arguments.len() != 1 {
                             Err(QueryPlanCompileError::AggregateFunctionRequiresOneArgument)
                         } else {
-                            let arg = arguments.into_iter().nth(0).unwrap();
+                            let arg = &arguments[0];
 
                             let mut g = GroupsInfo::new();
 
@@ -778,7 +1358,8 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                                     Ok(SExpression::AggregateOp {
                                         op: $op,
                                         source_id: source_id,
-                                        value: Box::new(value)
+                                        value: Box::new(value),
+                                        distinct: distinct
                                     })
                                 } else {
                                     // cannot aggregate over query defined inside the expression
@@ -801,7 +1382,7 @@ where DB: 'a, <DB as DatabaseInfo>::Table: 'a
                     _ => Err(QueryPlanCompileError::UnknownFunctionName(ident))
                 }
             },
-            ast::Expression::FunctionCallAggregateAll { name } => {
+            &ast::Expression::FunctionCallAggregateAll { ref name } => {
                 let ident = try!(new_identifier(&name));
 
                 match &ident as &str {
@@ -826,8 +1407,8 @@ struct Mapping {
     column_offset: u32
 }
 
-fn remap_columns_in_sexpression<'a, DB>(expr: &mut SExpression<'a, DB>, mapping: &HashMap<u32, Mapping>)
-where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a
+fn remap_columns_in_sexpression<DB>(expr: &mut SExpression<DB>, mapping: &HashMap<u32, Mapping>)
+where DB: DatabaseInfo
 {
     match expr {
         &mut SExpression::ColumnField { ref mut source_id, ref mut column_offset } => {
@@ -842,13 +1423,19 @@ where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a
     }
 }
 
-fn iter_mut_expressions_in_expression<'a, DB, F>(expr: &mut SExpression<'a, DB>, mut cb: F)
-where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a, F: FnMut(&mut SExpression<'a, DB>)
+fn iter_mut_expressions_in_expression<DB, F>(expr: &mut SExpression<DB>, mut cb: F)
+where DB: DatabaseInfo, F: FnMut(&mut SExpression<DB>)
 {
     match expr {
         &mut SExpression::Scan { ref mut yield_fn, .. } => {
             cb(yield_fn);
         },
+        &mut SExpression::Search { ref mut min, ref mut max, ref mut yield_fn, .. } => {
+            for v in min.iter_mut().chain(max.iter_mut()).flat_map(|values| values.iter_mut()) {
+                cb(v);
+            }
+            cb(yield_fn);
+        },
         &mut SExpression::Map { ref mut yield_in_fn, ref mut yield_out_fn, .. } => {
             cb(yield_in_fn);
             cb(yield_out_fn);
@@ -860,6 +1447,23 @@ where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a, F: FnMut(&mut SExp
             }
             cb(yield_out_fn);
         },
+        &mut SExpression::Sort { ref mut yield_in_fn, ref mut sort_by, ref mut yield_out_fn, .. } => {
+            cb(yield_in_fn);
+            for key in sort_by {
+                cb(&mut key.expr);
+            }
+            cb(yield_out_fn);
+        },
+        &mut SExpression::Distinct { ref mut yield_in_fn } => {
+            cb(yield_in_fn);
+        },
+        &mut SExpression::Limit { ref mut yield_in_fn, ref mut count, ref mut offset } => {
+            cb(yield_in_fn);
+            cb(count);
+            if let Some(e) = offset.as_mut() {
+                cb(e);
+            }
+        },
         &mut SExpression::Yield { ref mut fields } => {
             for v in fields {
                 cb(v);
@@ -893,35 +1497,67 @@ where DB: DatabaseInfo + 'a, <DB as DatabaseInfo>::Table: 'a, F: FnMut(&mut SExp
     }
 }
 
-fn ast_binaryop_to_sexpression_binaryop(ast: ast::BinaryOp) -> BinaryOp {
+/// Returns the offset of the result column that an ORDER BY term refers to:
+/// either by the column's name, or by its position (starting from 1).
+fn order_by_result_column(expr: &ast::Expression, column_names: &[Identifier])
+-> Result<Option<u32>, QueryPlanCompileError>
+{
+    match expr {
+        &ast::Expression::Number(ref s) => {
+            match s.parse::<u32>() {
+                Ok(position) => {
+                    if position >= 1 && position as usize <= column_names.len() {
+                        Ok(Some(position - 1))
+                    } else {
+                        Err(QueryPlanCompileError::OrderByPositionOutOfRange(position))
+                    }
+                },
+                // Not a position, just a constant
+                Err(_) => Ok(None)
+            }
+        },
+        &ast::Expression::Ident(ref s) => {
+            let name = try!(new_identifier(s));
+            Ok(column_names.iter().position(|n| n == &name).map(|i| i as u32))
+        },
+        _ => Ok(None)
+    }
+}
+
+fn ast_order_to_sort_order(ast: &ast::Order) -> SortOrder {
     match ast {
-        ast::BinaryOp::Equal => BinaryOp::Equal,
-        ast::BinaryOp::NotEqual => BinaryOp::NotEqual,
-        ast::BinaryOp::LessThan => BinaryOp::LessThan,
-        ast::BinaryOp::LessThanOrEqual => BinaryOp::LessThanOrEqual,
-        ast::BinaryOp::GreaterThan => BinaryOp::GreaterThan,
-        ast::BinaryOp::GreaterThanOrEqual => BinaryOp::GreaterThanOrEqual,
-        ast::BinaryOp::And => BinaryOp::And,
-        ast::BinaryOp::Or => BinaryOp::Or,
-        ast::BinaryOp::Add => BinaryOp::Add,
-        ast::BinaryOp::Subtract => BinaryOp::Subtract,
-        ast::BinaryOp::Multiply => BinaryOp::Multiply,
-        ast::BinaryOp::Divide => BinaryOp::Divide,
-        ast::BinaryOp::BitAnd => BinaryOp::BitAnd,
-        ast::BinaryOp::BitOr => BinaryOp::BitOr,
-        ast::BinaryOp::Concatenate => BinaryOp::Concatenate,
+        &ast::Order::Ascending => SortOrder::Ascending,
+        &ast::Order::Descending => SortOrder::Descending
     }
 }
 
-fn ast_unaryop_to_sexpression_unaryop(ast: ast::UnaryOp) -> UnaryOp {
+fn ast_binaryop_to_sexpression_binaryop(ast: &ast::BinaryOp) -> BinaryOp {
     match ast {
-        ast::UnaryOp::Negate => UnaryOp::Negate,
+        &ast::BinaryOp::Equal => BinaryOp::Equal,
+        &ast::BinaryOp::NotEqual => BinaryOp::NotEqual,
+        &ast::BinaryOp::LessThan => BinaryOp::LessThan,
+        &ast::BinaryOp::LessThanOrEqual => BinaryOp::LessThanOrEqual,
+        &ast::BinaryOp::GreaterThan => BinaryOp::GreaterThan,
+        &ast::BinaryOp::GreaterThanOrEqual => BinaryOp::GreaterThanOrEqual,
+        &ast::BinaryOp::And => BinaryOp::And,
+        &ast::BinaryOp::Or => BinaryOp::Or,
+        &ast::BinaryOp::Add => BinaryOp::Add,
+        &ast::BinaryOp::Subtract => BinaryOp::Subtract,
+        &ast::BinaryOp::Multiply => BinaryOp::Multiply,
+        &ast::BinaryOp::Divide => BinaryOp::Divide,
+        &ast::BinaryOp::BitAnd => BinaryOp::BitAnd,
+        &ast::BinaryOp::BitOr => BinaryOp::BitOr,
+        &ast::BinaryOp::Concatenate => BinaryOp::Concatenate,
     }
 }
 
-impl<'a, DB: DatabaseInfo> fmt::Display for QueryPlan<'a, DB>
-where <DB as DatabaseInfo>::Table: 'a
-{
+fn ast_unaryop_to_sexpression_unaryop(ast: &ast::UnaryOp) -> UnaryOp {
+    match ast {
+        &ast::UnaryOp::Negate => UnaryOp::Negate,
+    }
+}
+
+impl<DB: DatabaseInfo> fmt::Display for QueryPlan<DB> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         let cn: Vec<_> = self.out_column_names.iter().map(|n| format!("`{}`", n)).collect();
 
@@ -0,0 +1,244 @@
+//! Chooses the index to search a table with, from the predicates that every
+//! row of the table must pass.
+
+use databaseinfo::{DatabaseInfo, IndexInfo, TableInfo};
+use super::sexpression::{BinaryOp, SExpression};
+
+/// The fraction of rows that are assumed to have a given value
+pub const EQUAL_SELECTIVITY: f64 = 0.1;
+/// The fraction of rows that are assumed to be in a range, or to pass any other predicate
+pub const OTHER_SELECTIVITY: f64 = 1.0 / 3.0;
+
+/// A range of an index; see `SExpression::Search`.
+pub struct IndexSearch<'a, DB: DatabaseInfo>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    pub index: &'a <<DB as DatabaseInfo>::Table as TableInfo>::Index,
+    pub min: Option<Vec<SExpression<DB>>>,
+    pub max: Option<Vec<SExpression<DB>>>,
+    /// The number of columns with an equal minimum and maximum
+    equal_count: usize,
+    /// True if the column after the equal columns has a minimum or maximum
+    has_range: bool
+}
+
+impl<'a, DB: DatabaseInfo> IndexSearch<'a, DB>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    /// Estimates how many of the table's rows the search yields.
+    pub fn estimate_rows(&self, row_count: f64) -> f64 {
+        if self.index.is_unique() && self.equal_count == self.index.get_column_offsets().len() {
+            return row_count.min(1.0);
+        }
+
+        let mut rows = row_count * EQUAL_SELECTIVITY.powi(self.equal_count as i32);
+        if self.has_range {
+            rows *= OTHER_SELECTIVITY;
+        }
+        rows
+    }
+
+    /// Returns true if the search is for rows with a given value in one or more columns.
+    pub fn has_equal_columns(&self) -> bool {
+        self.equal_count > 0
+    }
+}
+
+/// Splits a predicate into the predicates that are ANDed together.
+pub fn conjuncts<'b, DB: DatabaseInfo>(predicate: &'b SExpression<DB>, out: &mut Vec<&'b SExpression<DB>>) {
+    match predicate {
+        &SExpression::BinaryOp { op: BinaryOp::And, ref lhs, ref rhs } => {
+            conjuncts(lhs, out);
+            conjuncts(rhs, out);
+        },
+        predicate => out.push(predicate)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Bound {
+    Equal,
+    /// The column is greater than (or equal to) the value
+    Lower,
+    /// The column is less than (or equal to) the value
+    Upper
+}
+
+/// A predicate that compares a column of the table with a value that's known
+/// before the table is searched.
+struct Comparison<'b, DB: DatabaseInfo + 'b> {
+    column_offset: u32,
+    bound: Bound,
+    value: &'b SExpression<DB>
+}
+
+/// Returns the search of the index that yields the fewest rows that can pass the predicates.
+/// Returns None if no index helps.
+///
+/// The predicates refer to the table's rows with `source_id`.
+/// The values of the search may not refer to any of the `unbound_source_ids`,
+/// as their rows aren't known when the table is searched.
+pub fn choose_index<'a, 'b, DB: DatabaseInfo>(table: &'a <DB as DatabaseInfo>::Table, source_id: u32,
+    predicates: &[&'b SExpression<DB>], unbound_source_ids: &[u32])
+-> Option<IndexSearch<'a, DB>>
+where <DB as DatabaseInfo>::Table: 'a
+{
+    let comparisons: Vec<Comparison<DB>> = predicates.iter().filter_map(|predicate| {
+        comparison(predicate, source_id, unbound_source_ids)
+    }).collect();
+
+    if comparisons.is_empty() {
+        return None;
+    }
+
+    // Prefer the most equal columns, and then a range on the next column
+    let mut best: Option<((usize, bool), IndexSearch<DB>)> = None;
+
+    for i in 0..table.get_index_count() {
+        let index = table.find_index_by_offset(i).unwrap();
+
+        let mut min = Vec::new();
+        let mut max = Vec::new();
+        let mut equal_count = 0;
+        let mut has_range = false;
+
+        for &column_offset in index.get_column_offsets() {
+            let find = |bound| {
+                comparisons.iter().find(|c| c.column_offset == column_offset && c.bound == bound).map(|c| copy_known(c.value))
+            };
+
+            if let Some(value) = find(Bound::Equal) {
+                min.push(copy_known(&value));
+                max.push(value);
+                equal_count += 1;
+                continue;
+            }
+
+            let lower = find(Bound::Lower);
+            let upper = find(Bound::Upper);
+
+            has_range = lower.is_some() || upper.is_some();
+            min.extend(lower);
+            max.extend(upper);
+            break;
+        }
+
+        let score = (equal_count, has_range);
+
+        if score == (0, false) {
+            continue;
+        }
+
+        let is_better = match best {
+            Some((best_score, _)) => score > best_score,
+            None => true
+        };
+
+        if is_better {
+            best = Some((score, IndexSearch {
+                index: index,
+                min: if min.is_empty() { None } else { Some(min) },
+                max: if max.is_empty() { None } else { Some(max) },
+                equal_count: equal_count,
+                has_range: has_range
+            }));
+        }
+    }
+
+    best.map(|(_, search)| search)
+}
+
+fn comparison<'b, DB: DatabaseInfo>(predicate: &'b SExpression<DB>, source_id: u32, unbound_source_ids: &[u32])
+-> Option<Comparison<'b, DB>>
+{
+    let (op, lhs, rhs) = match predicate {
+        &SExpression::BinaryOp { op, ref lhs, ref rhs } => (op, lhs, rhs),
+        _ => return None
+    };
+
+    let bound = match op {
+        BinaryOp::Equal => Bound::Equal,
+        BinaryOp::GreaterThan | BinaryOp::GreaterThanOrEqual => Bound::Lower,
+        BinaryOp::LessThan | BinaryOp::LessThanOrEqual => Bound::Upper,
+        _ => return None
+    };
+
+    if let Some(column_offset) = column_of(lhs, source_id) {
+        if is_known(rhs, unbound_source_ids) {
+            return Some(Comparison {
+                column_offset: column_offset,
+                bound: bound,
+                value: rhs
+            });
+        }
+    }
+
+    if let Some(column_offset) = column_of(rhs, source_id) {
+        if is_known(lhs, unbound_source_ids) {
+            // `value < column` bounds the column from below
+            let bound = match bound {
+                Bound::Equal => Bound::Equal,
+                Bound::Lower => Bound::Upper,
+                Bound::Upper => Bound::Lower
+            };
+
+            return Some(Comparison {
+                column_offset: column_offset,
+                bound: bound,
+                value: lhs
+            });
+        }
+    }
+
+    None
+}
+
+fn column_of<DB: DatabaseInfo>(expr: &SExpression<DB>, source_id: u32) -> Option<u32> {
+    match expr {
+        &SExpression::ColumnField { source_id: s, column_offset } if s == source_id => Some(column_offset),
+        _ => None
+    }
+}
+
+/// Returns true if the expression's value can be found without the rows of the unbound sources.
+fn is_known<DB: DatabaseInfo>(expr: &SExpression<DB>, unbound_source_ids: &[u32]) -> bool {
+    match expr {
+        &SExpression::Value(..) |
+        &SExpression::Parameter(..) => true,
+        &SExpression::ColumnField { source_id, .. } => !unbound_source_ids.contains(&source_id),
+        &SExpression::UnaryOp { ref expr, .. } => is_known(expr, unbound_source_ids),
+        &SExpression::BinaryOp { ref lhs, ref rhs, .. } => {
+            is_known(lhs, unbound_source_ids) && is_known(rhs, unbound_source_ids)
+        },
+        _ => false
+    }
+}
+
+/// Copies an expression that `is_known` accepts.
+/// (The derived `Clone` of `SExpression` requires the database to be `Clone`.)
+fn copy_known<DB: DatabaseInfo>(expr: &SExpression<DB>) -> SExpression<DB> {
+    match expr {
+        &SExpression::Value(ref v) => SExpression::Value(v.clone()),
+        &SExpression::Parameter(number) => SExpression::Parameter(number),
+        &SExpression::ColumnField { source_id, column_offset } => {
+            SExpression::ColumnField {
+                source_id: source_id,
+                column_offset: column_offset
+            }
+        },
+        &SExpression::UnaryOp { op, ref expr } => {
+            SExpression::UnaryOp {
+                op: op,
+                expr: Box::new(copy_known(expr))
+            }
+        },
+        &SExpression::BinaryOp { op, ref lhs, ref rhs } => {
+            SExpression::BinaryOp {
+                op: op,
+                lhs: Box::new(copy_known(lhs)),
+                rhs: Box::new(copy_known(rhs))
+            }
+        },
+        _ => unreachable!()
+    }
+}
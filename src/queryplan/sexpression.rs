@@ -1,85 +1,160 @@
-use databaseinfo::{DatabaseInfo, TableInfo};
+use databaseinfo::DatabaseInfo;
+use identifier::Identifier;
 
 use std::fmt;
 
 #[derive(Clone)]
-pub struct IfChain<'a, DB: DatabaseInfo>
-where <DB as DatabaseInfo>::Table: 'a
-{
-    pub predicate: SExpression<'a, DB>,
-    pub yield_fn: SExpression<'a, DB>,
+pub struct IfChain<DB: DatabaseInfo> {
+    pub predicate: SExpression<DB>,
+    pub yield_fn: SExpression<DB>,
 }
 
 #[derive(Clone)]
-pub enum SExpression<'a, DB: DatabaseInfo>
-where <DB as DatabaseInfo>::Table: 'a
-{
+pub struct SortKey<DB: DatabaseInfo> {
+    pub expr: SExpression<DB>,
+    pub order: SortOrder
+}
+
+#[derive(Clone)]
+pub enum SExpression<DB: DatabaseInfo> {
+    /// Iterates through the rows of the table.
+    /// Tables and indexes are found by name when the plan is executed, so a
+    /// plan doesn't borrow the database that it's compiled against.
     Scan {
-        table: &'a <DB as DatabaseInfo>::Table,
+        table: Identifier,
+        source_id: u32,
+        yield_fn: Box<SExpression<DB>>
+    },
+    /// Iterates through the rows of the table in a range of one of its indexes.
+    /// `min` and `max` are inclusive values for the first indexed columns.
+    /// Rows outside of the range may also be yielded, so yield_fn must still
+    /// check the predicates that the range came from.
+    Search {
+        table: Identifier,
         source_id: u32,
-        yield_fn: Box<SExpression<'a, DB>>
+        index: Identifier,
+        min: Option<Vec<SExpression<DB>>>,
+        max: Option<Vec<SExpression<DB>>>,
+        yield_fn: Box<SExpression<DB>>
     },
     LeftJoin {
         source_id: u32,
-        yield_in_fn: Box<SExpression<'a, DB>>,
-        predicate: Box<SExpression<'a, DB>>,
-        yield_out_fn: Box<SExpression<'a, DB>>,
+        yield_in_fn: Box<SExpression<DB>>,
+        predicate: Box<SExpression<DB>>,
+        yield_out_fn: Box<SExpression<DB>>,
         right_rows_if_none: Vec<<DB as DatabaseInfo>::ColumnValue>
     },
+    /// Joins the rows of yield_in_fn to the known rows on equal keys.
+    ///
+    /// yield_in_fn is executed once per query, and its rows are kept in a hash
+    /// table by their `build_keys`. Each time the node is executed, yield_out_fn
+    /// runs for the rows whose build keys equal the `probe_keys`.
+    /// Keys with a NULL value are never equal, and the values of both keys
+    /// must have the same kind of type (integer, F64, string or bytes).
+    ///
+    /// yield_in_fn may only refer to its own rows, as they're kept.
+    /// With `right_rows_if_none`, the node is a left join: a row is only joined
+    /// if the predicate is also true, and yield_out_fn runs with
+    /// `right_rows_if_none` if no row is joined.
+    HashJoin {
+        source_id: u32,
+        yield_in_fn: Box<SExpression<DB>>,
+        build_keys: Vec<SExpression<DB>>,
+        probe_keys: Vec<SExpression<DB>>,
+        predicate: Option<Box<SExpression<DB>>>,
+        yield_out_fn: Box<SExpression<DB>>,
+        right_rows_if_none: Option<Vec<<DB as DatabaseInfo>::ColumnValue>>
+    },
+    /// Yields the rows of left_fn joined to the rows of right_fn that pass the
+    /// predicate. Each yielded row is a left row followed by a right row, and
+    /// the predicate refers to it with `source_id`.
+    ///
+    /// A left row that isn't joined to any right row is joined to
+    /// `right_rows_if_none`, if it's given. Likewise, a right row that isn't
+    /// joined to any left row is joined to `left_rows_if_none`, after all of
+    /// the left rows.
+    OuterJoin {
+        source_id: u32,
+        left_fn: Box<SExpression<DB>>,
+        right_fn: Box<SExpression<DB>>,
+        predicate: Box<SExpression<DB>>,
+        left_rows_if_none: Option<Vec<<DB as DatabaseInfo>::ColumnValue>>,
+        right_rows_if_none: Option<Vec<<DB as DatabaseInfo>::ColumnValue>>
+    },
     Map {
         source_id: u32,
-        yield_in_fn: Box<SExpression<'a, DB>>,
-        yield_out_fn: Box<SExpression<'a, DB>>
+        yield_in_fn: Box<SExpression<DB>>,
+        yield_out_fn: Box<SExpression<DB>>
     },
     TempGroupBy {
         source_id: u32,
-        yield_in_fn: Box<SExpression<'a, DB>>,
-        group_by_values: Vec<SExpression<'a, DB>>,
-        yield_out_fn: Box<SExpression<'a, DB>>
+        yield_in_fn: Box<SExpression<DB>>,
+        group_by_values: Vec<SExpression<DB>>,
+        yield_out_fn: Box<SExpression<DB>>
+    },
+    /// Collects every row from yield_in_fn, and runs yield_out_fn for each
+    /// row in order of the sort keys.
+    Sort {
+        source_id: u32,
+        yield_in_fn: Box<SExpression<DB>>,
+        sort_by: Vec<SortKey<DB>>,
+        yield_out_fn: Box<SExpression<DB>>
+    },
+    /// Yields each distinct row from yield_in_fn once.
+    Distinct {
+        yield_in_fn: Box<SExpression<DB>>
+    },
+    /// Skips the first `offset` rows from yield_in_fn, then yields up to
+    /// `count` rows. yield_in_fn is stopped once `count` rows are yielded.
+    Limit {
+        yield_in_fn: Box<SExpression<DB>>,
+        count: Box<SExpression<DB>>,
+        offset: Option<Box<SExpression<DB>>>
     },
     Yield {
-        fields: Vec<SExpression<'a, DB>>
+        fields: Vec<SExpression<DB>>
     },
     ColumnField {
         source_id: u32,
         column_offset: u32
     },
     If {
-        chains: Vec<IfChain<'a, DB>>,
+        chains: Vec<IfChain<DB>>,
         /// Run if all predicates were false.
-        else_: Option<Box<SExpression<'a, DB>>>
+        else_: Option<Box<SExpression<DB>>>
     },
     UnaryOp {
         op: UnaryOp,
-        expr: Box<SExpression<'a, DB>>
+        expr: Box<SExpression<DB>>
     },
     BinaryOp {
         op: BinaryOp,
-        lhs: Box<SExpression<'a, DB>>,
-        rhs: Box<SExpression<'a, DB>>
+        lhs: Box<SExpression<DB>>,
+        rhs: Box<SExpression<DB>>
     },
     AggregateOp {
         op: AggregateOp,
         source_id: u32,
-        value: Box<SExpression<'a, DB>>
+        value: Box<SExpression<DB>>,
+        /// Only aggregate the distinct values
+        distinct: bool
     },
     CountAll {
         source_id: u32
     },
-    Value(<DB as DatabaseInfo>::ColumnValue)
+    Value(<DB as DatabaseInfo>::ColumnValue),
+    /// The value of the placeholder with the number, starting from 1.
+    /// The values are given when the plan is executed.
+    Parameter(u32)
 }
 
-impl<'a, DB: DatabaseInfo> fmt::Display for SExpression<'a, DB>
-where <DB as DatabaseInfo>::Table: 'a
-{
+impl<DB: DatabaseInfo> fmt::Display for SExpression<DB> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         self.format(f, 0)
     }
 }
 
-impl<'a, DB: DatabaseInfo> SExpression<'a, DB>
-where <DB as DatabaseInfo>::Table: 'a
-{
+impl<DB: DatabaseInfo> SExpression<DB> {
     fn format(&self, f: &mut fmt::Formatter, indent: usize) -> Result<(), fmt::Error> {
         macro_rules! write_indent {
             ($i:expr) => (
@@ -92,8 +167,24 @@ where <DB as DatabaseInfo>::Table: 'a
         write_indent!(indent);
 
         match self {
-            &SExpression::Scan { table, source_id, ref yield_fn } => {
-                try!(writeln!(f, "(scan `{}` :source-id {}", table.get_name(), source_id));
+            &SExpression::Scan { ref table, source_id, ref yield_fn } => {
+                try!(writeln!(f, "(scan `{}` :source-id {}", table, source_id));
+                try!(yield_fn.format(f, indent + 1));
+                write!(f, ")")
+            },
+            &SExpression::Search { ref table, source_id, ref index, ref min, ref max, ref yield_fn } => {
+                try!(writeln!(f, "(search `{}` :source-id {} :index `{}`", table, source_id, index));
+                for &(name, bound) in &[("min", min), ("max", max)] {
+                    if let Some(values) = bound.as_ref() {
+                        write_indent!(indent + 1);
+                        try!(write!(f, "({}", name));
+                        for value in values {
+                            try!(writeln!(f, ""));
+                            try!(value.format(f, indent + 2));
+                        }
+                        try!(writeln!(f, ")"));
+                    }
+                }
                 try!(yield_fn.format(f, indent + 1));
                 write!(f, ")")
             },
@@ -112,6 +203,55 @@ where <DB as DatabaseInfo>::Table: 'a
                 }
                 write!(f, "))")
             },
+            &SExpression::HashJoin { source_id, ref yield_in_fn, ref build_keys, ref probe_keys, ref predicate, ref yield_out_fn, ref right_rows_if_none } => {
+                try!(writeln!(f, "(hash-join :source-id {}", source_id));
+                try!(yield_in_fn.format(f, indent + 1));
+                try!(writeln!(f, ""));
+                for &(name, keys) in &[("build-keys", build_keys), ("probe-keys", probe_keys)] {
+                    write_indent!(indent + 1);
+                    try!(write!(f, "({}", name));
+                    for key in keys {
+                        try!(writeln!(f, ""));
+                        try!(key.format(f, indent + 2));
+                    }
+                    try!(writeln!(f, ")"));
+                }
+                if let Some(predicate) = predicate.as_ref() {
+                    try!(predicate.format(f, indent + 1));
+                    try!(writeln!(f, ""));
+                }
+                try!(yield_out_fn.format(f, indent + 1));
+                if let Some(right_rows_if_none) = right_rows_if_none.as_ref() {
+                    try!(writeln!(f, ""));
+                    write_indent!(indent + 1);
+                    try!(write!(f, "(right-rows-if-none "));
+                    for value in right_rows_if_none {
+                        try!(write!(f, "{} ", value));
+                    }
+                    try!(write!(f, ")"));
+                }
+                write!(f, ")")
+            },
+            &SExpression::OuterJoin { source_id, ref left_fn, ref right_fn, ref predicate, ref left_rows_if_none, ref right_rows_if_none } => {
+                try!(writeln!(f, "(outer-join :source-id {}", source_id));
+                try!(left_fn.format(f, indent + 1));
+                try!(writeln!(f, ""));
+                try!(right_fn.format(f, indent + 1));
+                try!(writeln!(f, ""));
+                try!(predicate.format(f, indent + 1));
+                for &(name, rows) in &[("left-rows-if-none", left_rows_if_none), ("right-rows-if-none", right_rows_if_none)] {
+                    if let Some(rows) = rows.as_ref() {
+                        try!(writeln!(f, ""));
+                        write_indent!(indent + 1);
+                        try!(write!(f, "({} ", name));
+                        for value in rows {
+                            try!(write!(f, "{} ", value));
+                        }
+                        try!(write!(f, ")"));
+                    }
+                }
+                write!(f, ")")
+            },
             &SExpression::Map { source_id, ref yield_in_fn, ref yield_out_fn } => {
                 try!(writeln!(f, "(map :source-id {}", source_id));
                 try!(yield_in_fn.format(f, indent + 1));
@@ -133,6 +273,45 @@ where <DB as DatabaseInfo>::Table: 'a
                 try!(yield_out_fn.format(f, indent + 1));
                 write!(f, ")")
             },
+            &SExpression::Sort { source_id, ref yield_in_fn, ref sort_by, ref yield_out_fn } => {
+                try!(writeln!(f, "(sort :source-id {}", source_id));
+                try!(yield_in_fn.format(f, indent + 1));
+                try!(writeln!(f, ""));
+                write_indent!(indent+1);
+                try!(write!(f, "(sort-by"));
+                for key in sort_by {
+                    try!(writeln!(f, ""));
+                    write_indent!(indent+2);
+                    try!(writeln!(f, "({}", key.order.name()));
+                    try!(key.expr.format(f, indent + 3));
+                    try!(write!(f, ")"));
+                }
+                try!(writeln!(f, ")"));
+                try!(yield_out_fn.format(f, indent + 1));
+                write!(f, ")")
+            },
+            &SExpression::Distinct { ref yield_in_fn } => {
+                try!(writeln!(f, "(distinct"));
+                try!(yield_in_fn.format(f, indent + 1));
+                write!(f, ")")
+            },
+            &SExpression::Limit { ref yield_in_fn, ref count, ref offset } => {
+                try!(writeln!(f, "(limit"));
+                try!(yield_in_fn.format(f, indent + 1));
+                try!(writeln!(f, ""));
+                write_indent!(indent+1);
+                try!(writeln!(f, "(count"));
+                try!(count.format(f, indent + 2));
+                try!(write!(f, ")"));
+                if let Some(offset) = offset.as_ref() {
+                    try!(writeln!(f, ""));
+                    write_indent!(indent+1);
+                    try!(writeln!(f, "(offset"));
+                    try!(offset.format(f, indent + 2));
+                    try!(write!(f, ")"));
+                }
+                write!(f, ")")
+            },
             &SExpression::Yield { ref fields } => {
                 try!(write!(f, "(yield"));
                 for field in fields {
@@ -170,8 +349,9 @@ where <DB as DatabaseInfo>::Table: 'a
                 try!(expr.format(f, indent + 1));
                 write!(f, ")")
             },
-            &SExpression::AggregateOp { ref op, source_id, ref value } => {
-                try!(writeln!(f, "({} :source-id {} ", op.name(), source_id));
+            &SExpression::AggregateOp { ref op, source_id, ref value, distinct } => {
+                let distinct = if distinct { "-distinct" } else { "" };
+                try!(writeln!(f, "({}{} :source-id {} ", op.name(), distinct, source_id));
                 try!(value.format(f, indent + 1));
                 write!(f, ")")
             },
@@ -180,6 +360,9 @@ where <DB as DatabaseInfo>::Table: 'a
             },
             &SExpression::Value(ref v) => {
                 write!(f, "{}", v)
+            },
+            &SExpression::Parameter(number) => {
+                write!(f, "?{}", number)
             }
         }
     }
@@ -202,6 +385,8 @@ pub enum BinaryOp {
     BitAnd,
     BitOr,
     Concatenate,
+    /// The left value, or the right value if the left value is NULL
+    Coalesce
 }
 
 impl BinaryOp {
@@ -223,7 +408,8 @@ impl BinaryOp {
             &Divide => "/",
             &BitAnd => "&",
             &BitOr => "|",
-            &Concatenate => "concat"
+            &Concatenate => "concat",
+            &Coalesce => "coalesce"
         }
     }
 }
@@ -265,3 +451,20 @@ impl AggregateOp {
         }
     }
 }
+
+#[derive(Copy, Clone)]
+pub enum SortOrder {
+    Ascending,
+    Descending
+}
+
+impl SortOrder {
+    fn name(&self) -> &'static str {
+        use self::SortOrder::*;
+
+        match self {
+            &Ascending => "asc",
+            &Descending => "desc"
+        }
+    }
+}
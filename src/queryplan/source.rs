@@ -3,14 +3,30 @@ use identifier::Identifier;
 pub struct TableOrSubquery
 {
     pub source_id: u32,
+    /// The offset of the first column in the source's rows.
+    /// The tables on both sides of an outer join share the source of its rows.
+    pub column_offset: u32,
     pub out_column_names: Vec<Identifier>
 }
 
+/// The column of a USING or NATURAL join, which takes the place of the
+/// joined columns of the same name.
+/// Columns are given as (table index, column index) in the scope.
+pub struct MergedColumn
+{
+    pub name: Identifier,
+    /// The joined columns, which can only be found by their table's name
+    pub columns: Vec<(usize, u32)>,
+    /// The columns whose first value that isn't NULL is the merged column's value
+    pub value_columns: Vec<(usize, u32)>
+}
+
 pub struct SourceScope<'a>
 {
     parent: Option<&'a SourceScope<'a>>,
     pub tables: Vec<TableOrSubquery>,
-    pub table_aliases: Vec<Identifier>
+    pub table_aliases: Vec<Identifier>,
+    pub merged_columns: Vec<MergedColumn>
 }
 
 pub enum GetColumnOffsetResult {
@@ -19,19 +35,50 @@ pub enum GetColumnOffsetResult {
     Ambiguous(Vec<(u32, u32)>)
 }
 
+/// A column found by its name alone. Its value is the first value of the
+/// (source id, column offset) columns that isn't NULL; there's more than one
+/// column for a merged column.
+pub enum GetColumnResult {
+    One(Vec<(u32, u32)>),
+    None,
+    Ambiguous
+}
+
 fn get_candidates<'a, I>(tables: I, name: &Identifier) -> Vec<(u32, u32)>
 where I: Iterator<Item=&'a TableOrSubquery>
 {
     tables.flat_map(|table| {
         let source_id = table.source_id;
+        let column_offset = table.column_offset;
 
         table.out_column_names.iter()
             .enumerate()
             .filter_map(|(i, ident)| if ident == name { Some(i as u32) } else { None })
-            .map(move |offset| (source_id, offset))
+            .map(move |offset| (source_id, column_offset + offset))
     }).collect()
 }
 
+fn to_result(candidates: Vec<(u32, u32)>) -> GetColumnOffsetResult {
+    match candidates.len() {
+        0 => GetColumnOffsetResult::None,
+        1 => GetColumnOffsetResult::One(candidates[0]),
+        _ => GetColumnOffsetResult::Ambiguous(candidates)
+    }
+}
+
+fn to_column_result(mut candidates: Vec<Vec<(u32, u32)>>) -> GetColumnResult {
+    match candidates.len() {
+        0 => GetColumnResult::None,
+        1 => GetColumnResult::One(candidates.pop().unwrap()),
+        _ => GetColumnResult::Ambiguous
+    }
+}
+
+/// Finds a column of the tables, without looking in any parent scope.
+pub fn find_column_offset(tables: &[TableOrSubquery], column_name: &Identifier) -> GetColumnOffsetResult {
+    to_result(get_candidates(tables.iter(), column_name))
+}
+
 impl<'a> SourceScope<'a> {
     pub fn new(parent: Option<&'a SourceScope<'a>>, tables: Vec<TableOrSubquery>, table_aliases: Vec<Identifier>)
     -> SourceScope<'a>
@@ -39,43 +86,113 @@ impl<'a> SourceScope<'a> {
         SourceScope {
             parent: parent,
             tables: tables,
-            table_aliases: table_aliases
+            table_aliases: table_aliases,
+            merged_columns: Vec::new()
         }
     }
 
     pub fn tables(&self) -> &[TableOrSubquery] { &self.tables }
 
+    pub fn get_column(&self, column_name: &Identifier) -> GetColumnResult {
+        to_column_result(self.get_columns(column_name))
+    }
 
-    pub fn get_column_offset(&self, column_name: &Identifier) -> GetColumnOffsetResult {
-        let candidates = self.get_column_offsets(column_name);
+    /// Finds a column of the first `table_count` tables of the scope,
+    /// without looking in any parent scope.
+    /// Returns the columns as (table index, column index).
+    pub fn find_local_column(&self, table_count: usize, column_name: &Identifier)
+    -> Result<Vec<(usize, u32)>, GetColumnResult>
+    {
+        let mut candidates = self.local_candidates(table_count, column_name);
 
         match candidates.len() {
-            0 => GetColumnOffsetResult::None,
-            1 => GetColumnOffsetResult::One(candidates[0]),
-            _ => GetColumnOffsetResult::Ambiguous(candidates)
+            1 => Ok(candidates.pop().unwrap()),
+            0 => Err(GetColumnResult::None),
+            _ => Err(GetColumnResult::Ambiguous)
         }
     }
 
-    pub fn get_table_column_offset(&self, table_name: &Identifier, column_name: &Identifier) -> GetColumnOffsetResult {
-        let candidates = self.get_table_column_offsets(table_name, column_name);
+    /// The (source id, column offset) of a column given as (table index, column index)
+    pub fn column_offset(&self, column: (usize, u32)) -> (u32, u32) {
+        let table = &self.tables[column.0];
+        (table.source_id, table.column_offset + column.1)
+    }
 
-        match candidates.len() {
-            0 => GetColumnOffsetResult::None,
-            1 => GetColumnOffsetResult::One(candidates[0]),
-            _ => GetColumnOffsetResult::Ambiguous(candidates)
+    /// Merges the columns that a USING or NATURAL join joins to the last table.
+    /// `left_columns` is the column in the tables before it, from `find_local_column`.
+    /// A side is outer if its rows can be all NULL. The merged column's value
+    /// is the left column's if the left side isn't outer, the right column's
+    /// if only the left side is outer, or else the first that isn't NULL.
+    pub fn merge_columns(&mut self, name: &Identifier, left_columns: Vec<(usize, u32)>,
+        left_is_outer: bool, right_is_outer: bool)
+    {
+        let right_table = self.tables.len() - 1;
+        let i = self.tables[right_table].out_column_names.iter().position(|n| n == name).unwrap();
+        let right_column = (right_table, i as u32);
+
+        let mut columns = match self.merged_columns.iter().position(|c| &c.name == name) {
+            Some(i) => self.merged_columns.remove(i).columns,
+            None => left_columns.clone()
+        };
+        columns.push(right_column);
+
+        let value_columns = match (left_is_outer, right_is_outer) {
+            (false, _) => left_columns,
+            (true, false) => vec![right_column],
+            (true, true) => {
+                let mut v = left_columns;
+                v.push(right_column);
+                v
+            }
+        };
+
+        self.merged_columns.push(MergedColumn {
+            name: name.clone(),
+            columns: columns,
+            value_columns: value_columns
+        });
+    }
+
+    /// Returns true if a join merged the column into another column
+    pub fn is_merged(&self, column: (usize, u32)) -> bool {
+        self.merged_columns.iter().any(|c| c.columns.contains(&column))
+    }
+
+    fn local_candidates(&self, table_count: usize, column_name: &Identifier) -> Vec<Vec<(usize, u32)>> {
+        let mut candidates: Vec<_> = self.merged_columns.iter()
+            .filter(|c| &c.name == column_name)
+            .map(|c| c.value_columns.clone())
+            .collect();
+
+        for (i, table) in self.tables[..table_count].iter().enumerate() {
+            for (j, ident) in table.out_column_names.iter().enumerate() {
+                let column = (i, j as u32);
+
+                if ident == column_name && !self.is_merged(column) {
+                    candidates.push(vec![column]);
+                }
+            }
         }
+
+        candidates
     }
 
-    fn get_column_offsets(&self, column_name: &Identifier) -> Vec<(u32, u32)> {
-        let mut candidates = get_candidates(self.tables.iter(), column_name);
+    fn get_columns(&self, column_name: &Identifier) -> Vec<Vec<(u32, u32)>> {
+        let mut candidates: Vec<_> = self.local_candidates(self.tables.len(), column_name).into_iter().map(|columns| {
+            columns.into_iter().map(|column| self.column_offset(column)).collect()
+        }).collect();
 
         if let Some(parent) = self.parent {
-            candidates.extend(parent.get_column_offsets(column_name));
+            candidates.extend(parent.get_columns(column_name));
         }
 
         candidates
     }
 
+    pub fn get_table_column_offset(&self, table_name: &Identifier, column_name: &Identifier) -> GetColumnOffsetResult {
+        to_result(self.get_table_column_offsets(table_name, column_name))
+    }
+
     fn get_table_column_offsets(&self, table_name: &Identifier, column_name: &Identifier) -> Vec<(u32, u32)> {
         let tables = self.table_aliases.iter().enumerate().filter_map(|(i, name)| {
             if name == table_name { Some(&self.tables[i]) }
@@ -0,0 +1,253 @@
+//! Table rows are stored as the keys of a rowid index.
+//!
+//! | Size and type | Name                                                 |
+//! |---------------|------------------------------------------------------|
+//! | 8, u64-be     | Row id                                               |
+//! | For each column:                                                     |
+//! | 1, optional   | Null flag; only present if the column is nullable    |
+//! | Variable      | Column data; omitted if the column is null           |
+//! | For each variable-length column:                                     |
+//! | 8, u64-be     | Data length; 0 if the column is null                 |
+//!
+//! The row id comes first so that rows sort by their row id.
+
+use byteutils;
+use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
+use databaseinfo::ColumnInfo;
+use error::Error;
+use identifier::Identifier;
+use types::Variant;
+
+use std::borrow::Cow;
+use std::fmt;
+
+pub enum UpdateError {
+    ValidationError {
+        column_name: Identifier,
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &UpdateError::ValidationError { ref column_name } => {
+                write!(f, "Problem validating column: {}", column_name)
+            }
+        }
+    }
+}
+
+/// Every key of the row with the rowid begins with these 8 bytes.
+pub fn rowid_prefix(rowid: u64) -> Vec<u8> {
+    let mut buf = vec![0; 8];
+    byteutils::write_udbinteger(rowid, &mut buf);
+    buf
+}
+
+/// Encodes a row as a key.
+///
+/// `column_data` contains the data of each column, in column order, and
+/// whether the column is null. The null flag must be specified if and only
+/// if the column is nullable.
+pub fn encode_row<C, I>(rowid: u64, columns: &[C], column_data: I) -> Result<Vec<u8>, UpdateError>
+where C: ColumnInfo, I: ExactSizeIterator, I: Iterator<Item = (Box<[u8]>, Option<bool>)>
+{
+    assert_eq!(columns.len(), column_data.len());
+
+    let mut key = rowid_prefix(rowid);
+
+    let mut lengths = Vec::new();
+
+    for (column, (data_box, is_null)) in columns.iter().zip(column_data) {
+        let data: &[u8] = &data_box;
+        let dbtype = column.get_dbtype();
+
+        trace!("column data for {}: {:?}", column.get_name(), data);
+
+        assert_eq!(column.is_nullable(), is_null.is_some());
+
+        let len = data.len() as u64;
+
+        match is_null {
+            Some(true) => {
+                assert_eq!(len, 0);
+                key.push(1);
+            },
+            Some(false) => key.push(0),
+            None => ()
+        };
+
+        if is_null != Some(true) && !dbtype.is_valid_length(len) {
+            return Err(UpdateError::ValidationError {
+                column_name: column.get_name().clone()
+            });
+        }
+
+        if dbtype.is_variable_length() {
+            let mut buf = [0; 8];
+            byteutils::write_udbinteger(len, &mut buf);
+            lengths.extend_from_slice(&buf);
+        }
+
+        key.extend_from_slice(data);
+    }
+
+    key.extend(lengths);
+
+    Ok(key)
+}
+
+pub fn decode_rowid(raw_key: &[u8]) -> u64 {
+    byteutils::read_udbinteger(&raw_key[0..8])
+}
+
+/// Decodes the column values of a key made by `encode_row`, followed by the rowid.
+/// This is how the rows of a table are yielded by `DatabaseStorage`.
+pub fn decode_row_with_rowid<C: ColumnInfo>(columns: &[C], raw_key: &[u8]) -> Result<Vec<Variant>, Error> {
+    let mut row = try!(decode_row(columns, raw_key));
+    row.push(Variant::UnsignedInteger(decode_rowid(raw_key)));
+    Ok(row)
+}
+
+/// Decodes the column values of a key made by `encode_row`.
+/// Fails if the key is truncated, or a column's data isn't valid for its type,
+/// as happens if a database file is corrupt.
+pub fn decode_row<C: ColumnInfo>(columns: &[C], raw_key: &[u8]) -> Result<Vec<Variant>, Error> {
+    let corrupt = || Error::Io(format!("a row key of {} bytes is truncated or corrupt", raw_key.len()));
+
+    let variable_column_count = columns.iter().filter(|column| {
+        column.get_dbtype().is_variable_length()
+    }).count();
+
+    // The offset of the variable lengths, which is where the column data ends
+    let data_end = match raw_key.len().checked_sub(variable_column_count*8) {
+        Some(o) if o >= 8 => o,
+        _ => return Err(corrupt())
+    };
+
+    let variable_lengths: Vec<_> = (0..variable_column_count).map(|i| {
+        let o = data_end + i*8;
+        byteutils::read_udbinteger(&raw_key[o..o+8])
+    }).collect();
+
+    trace!("variable lengths: {:?}", variable_lengths);
+
+    let mut variable_length_offset = 0;
+    let mut key_offset = 8;
+    let mut row = Vec::with_capacity(columns.len());
+
+    for column in columns {
+        let dbtype = *column.get_dbtype();
+
+        let is_null = if column.is_nullable() {
+            if key_offset >= data_end {
+                return Err(corrupt());
+            }
+
+            let flag = raw_key[key_offset];
+            key_offset += 1;
+            flag != 0
+        } else {
+            false
+        };
+
+        let size = match dbtype.get_fixed_length() {
+            Some(l) => l,
+            None => {
+                let l = variable_lengths[variable_length_offset];
+                variable_length_offset += 1;
+                l
+            }
+        };
+
+        if is_null {
+            row.push(ColumnValueOpsExt::null());
+        } else {
+            if size > (data_end - key_offset) as u64 {
+                return Err(corrupt());
+            }
+
+            let size = size as usize;
+            let bytes = &raw_key[key_offset..key_offset + size];
+
+            trace!("from bytes: {:?}, {:?}", dbtype, bytes);
+            let value = try!(ColumnValueOps::from_bytes(dbtype, Cow::Borrowed(bytes)).map_err(|_| corrupt()));
+            key_offset += size;
+            row.push(value);
+        }
+    }
+
+    Ok(row)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_row, decode_rowid, encode_row};
+    use databaseinfo::{ColumnInfo, ForeignKey};
+    use identifier::Identifier;
+    use types::{DbType, F64NoNaN, Variant};
+
+    struct Column {
+        name: Identifier,
+        dbtype: DbType,
+        nullable: bool
+    }
+
+    impl ColumnInfo for Column {
+        fn get_offset(&self) -> u32 { 0 }
+        fn get_name(&self) -> &Identifier { &self.name }
+        fn get_dbtype(&self) -> &DbType { &self.dbtype }
+        fn is_nullable(&self) -> bool { self.nullable }
+        fn is_primary_key(&self) -> bool { false }
+        fn is_unique(&self) -> bool { false }
+        fn get_foreign_key(&self) -> Option<&ForeignKey> { None }
+    }
+
+    fn column(dbtype: DbType, nullable: bool) -> Column {
+        Column {
+            name: Identifier::new("c").unwrap(),
+            dbtype: dbtype,
+            nullable: nullable
+        }
+    }
+
+    #[test]
+    fn test_corrupt_keys_fail_to_decode() {
+        let columns = vec![column(DbType::Integer { signed: false, bytes: 4 }, true), column(DbType::String, false), column(DbType::F64, false)];
+        let column_data = vec![
+            (vec![0, 0, 0, 7].into_boxed_slice(), Some(false)),
+            (b"abc\0".to_vec().into_boxed_slice(), None),
+            (vec![0xC0, 0, 0, 0, 0, 0, 0, 0].into_boxed_slice(), None)
+        ];
+
+        let key = encode_row(5, &columns, column_data.into_iter()).ok().unwrap();
+        assert_eq!(decode_rowid(&key), 5);
+        assert_eq!(decode_row(&columns, &key).unwrap(), vec![
+            Variant::UnsignedInteger(7),
+            Variant::StringLiteral("abc".to_string()),
+            Variant::Float(F64NoNaN::new(2.0).unwrap())
+        ]);
+
+        for length in 0..key.len() {
+            assert!(decode_row(&columns, &key[0..length]).is_err(), "{:?} decoded", &key[0..length]);
+        }
+
+        // The string isn't terminated
+        let mut bad_string = key.clone();
+        bad_string[16] = b'd';
+        assert!(decode_row(&columns, &bad_string).is_err());
+
+        // The float is NaN
+        let mut bad_float = key.clone();
+        for v in bad_float[17..25].iter_mut() {
+            *v = 0xFF;
+        }
+        assert!(decode_row(&columns, &bad_float).is_err());
+
+        // The string is longer than the key
+        let mut bad_length = key.clone();
+        let last = bad_length.len() - 1;
+        bad_length[last] = 0xFF;
+        assert!(decode_row(&columns, &bad_length).is_err());
+    }
+}
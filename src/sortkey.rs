@@ -0,0 +1,362 @@
+//! Encodes values as keys whose bytes sort in the same order as the values.
+//! Keys can then be compared with a plain byte comparison, without knowing
+//! the types of the values in them; see `docs/Indexing and Sorting.md`.
+//!
+//! | Type            | Key                                                      |
+//! |-----------------|----------------------------------------------------------|
+//! | Unsigned integer| Big-endian                                               |
+//! | Signed integer  | Big-endian, plus half of the unsigned maximum            |
+//! | F64             | Big-endian; sign bit flipped if positive, all bits if negative |
+//! | String          | UTF-8, escaped and terminated                            |
+//! | Byte array      | Escaped and terminated; fixed-size arrays are stored as-is |
+//!
+//! Escaped data has each `00` byte replaced with `00 FF`, and is terminated by
+//! `00 00`. A value that's a prefix of a longer value therefore sorts first.
+//!
+//! The key of a nullable column begins with a null flag: `00` if the value is
+//! NULL, or `01` otherwise. NULL sorts before every other value.
+//!
+//! Every key of a column has a different length or differs before its end,
+//! so composite keys are just the concatenation of their columns' keys.
+//! A descending column has every byte of its key inverted.
+
+use byteutils;
+use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
+use types::DbType;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Order {
+    Ascending,
+    Descending
+}
+
+/// A column of a composite key
+#[derive(Copy, Clone, Debug)]
+pub struct KeyColumn {
+    pub dbtype: DbType,
+    pub nullable: bool,
+    pub order: Order
+}
+
+// Type tags of `push_any_value`; values of different types sort by their tag.
+const TAG_NULL: u8 = 0;
+const TAG_NUMBER: u8 = 1;
+const TAG_STRING: u8 = 2;
+const TAG_BYTES: u8 = 3;
+
+// Tags of the exact value that follows a number's F64 key
+const EXACT_BELOW_INTEGERS: u8 = 0;
+const EXACT_NEGATIVE: u8 = 1;
+const EXACT_NON_NEGATIVE: u8 = 2;
+const EXACT_ABOVE_INTEGERS: u8 = 3;
+
+/// Encodes a composite key of the values, one for each column.
+pub fn encode_key<'a, V, I>(columns: &[KeyColumn], values: I) -> Result<Vec<u8>, ()>
+where V: ColumnValueOps + Clone + 'a, I: IntoIterator<Item=&'a V>
+{
+    let mut key = Vec::new();
+    let mut count = 0;
+
+    for (column, value) in columns.iter().zip(values) {
+        try!(push_value(&mut key, column, value));
+        count += 1;
+    }
+
+    assert_eq!(count, columns.len());
+    Ok(key)
+}
+
+/// Appends the key of a value in the column.
+/// Fails if the value is NULL and the column isn't nullable, or if the value
+/// can't be converted to the column's type.
+pub fn push_value<V>(key: &mut Vec<u8>, column: &KeyColumn, value: &V) -> Result<(), ()>
+where V: ColumnValueOps + Clone
+{
+    let start = key.len();
+
+    if value.is_null() {
+        if !column.nullable {
+            return Err(());
+        }
+        key.push(0);
+    } else {
+        if column.nullable {
+            key.push(1);
+        }
+
+        let data = try!(value.clone().to_bytes(column.dbtype));
+        push_data(key, column.dbtype, &data);
+    }
+
+    if column.order == Order::Descending {
+        invert(&mut key[start..]);
+    }
+
+    Ok(())
+}
+
+/// Appends the key of a value whose type isn't known ahead of time,
+/// such as the result of an expression.
+///
+/// The key begins with a tag for the value's type: NULL sorts first, followed
+/// by numbers, strings, and byte arrays.
+/// Integers and F64 are ordered together by their value.
+pub fn push_any_value<V>(key: &mut Vec<u8>, order: Order, value: &V) -> Result<(), ()>
+where V: ColumnValueOps + Clone
+{
+    let start = key.len();
+
+    let dbtype = match value.get_dbtype() {
+        // A byte array value isn't tied to a size
+        DbType::ByteFixed(..) => DbType::ByteDynamic,
+        dbtype => dbtype
+    };
+
+    if value.is_null() || dbtype == DbType::Null {
+        key.push(TAG_NULL);
+    } else {
+        let data = try!(value.clone().to_bytes(dbtype));
+
+        match dbtype {
+            DbType::Integer { signed, .. } => {
+                // Negative integers have a tag of their own, so that the
+                // exact value can be their two's complement
+                let (n, exact_tag, exact) = if signed {
+                    let n = byteutils::read_sdbinteger(&data);
+                    (n as f64, if n < 0 { EXACT_NEGATIVE } else { EXACT_NON_NEGATIVE }, n as u64)
+                } else {
+                    let n = byteutils::read_udbinteger(&data);
+                    (n as f64, EXACT_NON_NEGATIVE, n)
+                };
+
+                push_number(key, n, exact_tag, exact);
+            },
+            DbType::F64 => {
+                let n = byteutils::read_dbfloat(&data);
+
+                // Only a whole number can be equal to an integer, and its
+                // integer part is then its exact value
+                let (exact_tag, exact) = if n < -9223372036854775808.0 {
+                    (EXACT_BELOW_INTEGERS, 0)
+                } else if n < 0.0 {
+                    (EXACT_NEGATIVE, n as i64 as u64)
+                } else if n < 18446744073709551616.0 {
+                    (EXACT_NON_NEGATIVE, n as u64)
+                } else {
+                    (EXACT_ABOVE_INTEGERS, 0)
+                };
+
+                push_number(key, n, exact_tag, exact);
+            },
+            DbType::String => {
+                key.push(TAG_STRING);
+                push_data(key, dbtype, &data);
+            },
+            _ => {
+                key.push(TAG_BYTES);
+                push_data(key, dbtype, &data);
+            }
+        }
+    }
+
+    if order == Order::Descending {
+        invert(&mut key[start..]);
+    }
+
+    Ok(())
+}
+
+/// Appends the key of a number: its nearest F64, followed by its exact value
+/// for the integers that round to the same F64.
+fn push_number(key: &mut Vec<u8>, n: f64, exact_tag: u8, exact: u64) {
+    let mut buf = [0; 8];
+
+    key.push(TAG_NUMBER);
+    byteutils::write_dbfloat(n, &mut buf);
+    key.extend_from_slice(&buf);
+
+    key.push(exact_tag);
+    byteutils::write_udbinteger(exact, &mut buf);
+    key.extend_from_slice(&buf);
+}
+
+/// Appends the key of a non-null value, from its data as stored in a row
+fn push_data(key: &mut Vec<u8>, dbtype: DbType, data: &[u8]) {
+    match dbtype {
+        DbType::Null => (),
+        // The row data of numbers is already in the right order; -0 is written as 0
+        DbType::Integer { .. } | DbType::F64 | DbType::ByteFixed(..) => key.extend_from_slice(data),
+        DbType::String => {
+            // Strings are stored with a null terminator, which is replaced by the key's
+            push_escaped(key, &data[..data.len() - 1]);
+        },
+        DbType::ByteDynamic => push_escaped(key, data)
+    }
+}
+
+fn push_escaped(key: &mut Vec<u8>, data: &[u8]) {
+    for &b in data {
+        key.push(b);
+        if b == 0 {
+            key.push(0xFF);
+        }
+    }
+    key.extend_from_slice(&[0, 0]);
+}
+
+fn invert(bytes: &mut [u8]) {
+    for b in bytes.iter_mut() {
+        *b = !*b;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_key, push_any_value, KeyColumn, Order};
+    use types::{DbType, Variant};
+    use types::F64NoNaN;
+
+    fn float(value: f64) -> Variant {
+        Variant::Float(F64NoNaN::new(value).unwrap())
+    }
+
+    fn string(value: &str) -> Variant {
+        Variant::StringLiteral(value.to_string())
+    }
+
+    fn column(dbtype: DbType, nullable: bool, order: Order) -> KeyColumn {
+        KeyColumn {
+            dbtype: dbtype,
+            nullable: nullable,
+            order: order
+        }
+    }
+
+    /// Asserts that the keys of the values sort in the order they're given,
+    /// and that they sort in reverse when descending.
+    fn assert_sorted(dbtype: DbType, nullable: bool, values: Vec<Variant>) {
+        for &order in [Order::Ascending, Order::Descending].iter() {
+            let columns = [column(dbtype, nullable, order)];
+            let keys: Vec<Vec<u8>> = values.iter().map(|value| {
+                encode_key(&columns, &[value.clone()]).unwrap()
+            }).collect();
+
+            for (i, pair) in keys.windows(2).enumerate() {
+                let ok = match order {
+                    Order::Ascending => pair[0] < pair[1],
+                    Order::Descending => pair[0] > pair[1]
+                };
+                assert!(ok, "{:?} {:?}: {} and {}", dbtype, order, values[i], values[i + 1]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_integers() {
+        assert_sorted(DbType::Integer { signed: false, bytes: 2 }, true,
+            vec![Variant::Null, Variant::UnsignedInteger(0), Variant::UnsignedInteger(1),
+                 Variant::UnsignedInteger(255), Variant::UnsignedInteger(256), Variant::UnsignedInteger(65535)]);
+
+        assert_sorted(DbType::Integer { signed: true, bytes: 4 }, false,
+            vec![Variant::SignedInteger(-2147483648), Variant::SignedInteger(-256), Variant::SignedInteger(-1),
+                 Variant::SignedInteger(0), Variant::SignedInteger(1), Variant::SignedInteger(2147483647)]);
+    }
+
+    #[test]
+    fn test_floats() {
+        use std::f64;
+
+        assert_sorted(DbType::F64, true,
+            vec![Variant::Null, float(f64::NEG_INFINITY), float(-1e300), float(-1.0), float(-1e-300),
+                 float(0.0), float(1e-300), float(0.5), float(1.0), float(1e300), float(f64::INFINITY)]);
+
+        let columns = [column(DbType::F64, false, Order::Ascending)];
+        assert_eq!(encode_key(&columns, &[float(-0.0)]), encode_key(&columns, &[float(0.0)]));
+    }
+
+    #[test]
+    fn test_strings_and_bytes() {
+        assert_sorted(DbType::String, true,
+            vec![Variant::Null, string(""), string("\0"), string("\0\0"), string("\u{1}"),
+                 string("Apple"), string("Apples"), string("Banana"), string("apple"), string("\u{C4}pfel")]);
+
+        assert_sorted(DbType::ByteDynamic, false,
+            vec![Variant::Bytes(vec![]), Variant::Bytes(vec![0]), Variant::Bytes(vec![0, 0]),
+                 Variant::Bytes(vec![0, 0xFF]), Variant::Bytes(vec![0, 0xFF, 0]), Variant::Bytes(vec![1]),
+                 Variant::Bytes(vec![0xFF]), Variant::Bytes(vec![0xFF, 0xFF])]);
+    }
+
+    #[test]
+    fn test_composite_keys() {
+        let columns = [
+            column(DbType::String, true, Order::Ascending),
+            column(DbType::Integer { signed: true, bytes: 8 }, false, Order::Descending)
+        ];
+
+        let rows = vec![
+            vec![Variant::Null, Variant::SignedInteger(5)],
+            vec![Variant::Null, Variant::SignedInteger(-5)],
+            vec![string("a"), Variant::SignedInteger(1)],
+            vec![string("a"), Variant::SignedInteger(0)],
+            vec![string("a\0"), Variant::SignedInteger(9)],
+            vec![string("ab"), Variant::SignedInteger(9)],
+            vec![string("b"), Variant::SignedInteger(-9)]
+        ];
+
+        let keys: Vec<Vec<u8>> = rows.iter().map(|row| encode_key(&columns, row).unwrap()).collect();
+
+        for pair in keys.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+
+        // NULL isn't allowed in a column that isn't nullable
+        assert!(encode_key(&columns, &[string("a"), Variant::Null]).is_err());
+    }
+
+    #[test]
+    fn test_any_value() {
+        let values = vec![
+            Variant::Null,
+            float(-1e300),
+            Variant::SignedInteger(-5),
+            float(-1.5),
+            Variant::SignedInteger(-1),
+            float(-0.5),
+            Variant::UnsignedInteger(0),
+            float(0.5),
+            Variant::SignedInteger(1),
+            float(1.5),
+            Variant::UnsignedInteger(2),
+            float(2.5),
+            float(9007199254740992.0),
+            Variant::UnsignedInteger(9007199254740993),
+            Variant::UnsignedInteger(!0),
+            float(18446744073709551616.0),
+            string(""),
+            string("a"),
+            Variant::Bytes(vec![]),
+            Variant::Bytes(vec![0])
+        ];
+
+        let keys: Vec<Vec<u8>> = values.iter().map(|value| {
+            let mut key = Vec::new();
+            push_any_value(&mut key, Order::Ascending, value).unwrap();
+            key
+        }).collect();
+
+        for (i, pair) in keys.windows(2).enumerate() {
+            assert!(pair[0] < pair[1], "{} and {}", values[i], values[i + 1]);
+        }
+
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        push_any_value(&mut a, Order::Descending, &Variant::SignedInteger(3)).unwrap();
+        push_any_value(&mut b, Order::Descending, &Variant::UnsignedInteger(3)).unwrap();
+        assert_eq!(a, b);
+
+        // Integers and floats that are equal have the same key
+        let mut c = Vec::new();
+        push_any_value(&mut c, Order::Descending, &float(3.0)).unwrap();
+        assert_eq!(a, c);
+    }
+}
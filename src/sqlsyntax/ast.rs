@@ -1,9 +1,9 @@
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum UnaryOp {
     Negate
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum BinaryOp {
     Equal,
     NotEqual,
@@ -22,15 +22,18 @@ pub enum BinaryOp {
     Concatenate,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Expression {
     Ident(String),
     IdentMember(String, String),
     StringLiteral(String),
     Number(String),
     Null,
+    /// ?, ?NNN or :name; the number of the parameter whose value it has, starting from 1
+    Placeholder(u32),
     /// name(argument1, argument2, argument3...)
-    FunctionCall { name: String, arguments: Vec<Expression> },
+    /// or name(DISTINCT argument1, ...)
+    FunctionCall { name: String, distinct: bool, arguments: Vec<Expression> },
     /// name(*)
     FunctionCallAggregateAll { name: String },
     UnaryOp {
@@ -46,13 +49,13 @@ pub enum Expression {
     Subquery(Box<SelectStatement>)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Table {
     pub database_name: Option<String>,
     pub table_name: String
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum TableOrSubquery {
     Subquery {
         subquery: Box<SelectStatement>,
@@ -64,7 +67,7 @@ pub enum TableOrSubquery {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum SelectColumn {
     AllColumns,
     Expr {
@@ -73,17 +76,19 @@ pub enum SelectColumn {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct SelectStatement {
+    pub distinct: bool,
     pub result_columns: Vec<SelectColumn>,
     pub from: From,
     pub where_expr: Option<Expression>,
     pub group_by: Vec<Expression>,
     pub having: Option<Expression>,
-    pub order_by: Vec<OrderingTerm>
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Limit>
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum From {
     Cross(Vec<TableOrSubquery>),
     Join {
@@ -92,62 +97,111 @@ pub enum From {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum JoinOperator {
     Left,
-    Inner
+    Right,
+    Full,
+    Inner,
+    Cross
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum JoinConstraint {
+    On(Expression),
+    /// Joins on the named columns being equal in both tables
+    Using(Vec<String>),
+    /// Joins on every column with the same name in both tables being equal
+    Natural,
+    None
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Join {
     pub operator: JoinOperator,
     pub table: TableOrSubquery,
-    pub on: Expression
+    pub constraint: JoinConstraint
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Order {
     Ascending,
     Descending
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct OrderingTerm {
     pub expr: Expression,
     pub order: Order
 }
 
-#[derive(Debug)]
+/// LIMIT count OFFSET offset
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limit {
+    pub count: Expression,
+    pub offset: Option<Expression>
+}
+
+#[derive(Clone, Debug)]
 pub struct InsertStatement {
     pub table: Table,
     pub into_columns: Option<Vec<String>>,
     pub source: InsertSource
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum InsertSource {
     Values(Vec<Vec<Expression>>),
     Select(Box<SelectStatement>)
 }
 
-#[derive(Debug)]
+/// column_name = expr
+#[derive(Clone, Debug)]
+pub struct UpdateAssignment {
+    pub column_name: String,
+    pub expr: Expression
+}
+
+#[derive(Clone, Debug)]
+pub struct UpdateStatement {
+    pub table: Table,
+    pub assignments: Vec<UpdateAssignment>,
+    pub where_expr: Option<Expression>
+}
+
+#[derive(Clone, Debug)]
+pub struct DeleteStatement {
+    pub table: Table,
+    pub where_expr: Option<Expression>
+}
+
+#[derive(Clone, Debug)]
 pub struct CreateTableColumnConstraint {
     pub name: Option<String>,
     pub constraint: CreateTableColumnConstraintType
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum CreateTableColumnConstraintType {
     PrimaryKey,
     Unique,
     Nullable,
     ForeignKey {
         table: Table,
-        columns: Option<Vec<String>>
+        columns: Option<Vec<String>>,
+        on_delete: Option<ForeignKeyAction>
     }
 }
 
-#[derive(Debug)]
+/// ON DELETE CASCADE, RESTRICT, or SET NULL
+#[derive(Clone, Debug, PartialEq)]
+pub enum ForeignKeyAction {
+    Cascade,
+    Restrict,
+    SetNull
+}
+
+#[derive(Clone, Debug)]
 pub struct CreateTableColumn {
     pub column_name: String,
     pub type_name: String,
@@ -159,26 +213,76 @@ pub struct CreateTableColumn {
     pub constraints: Vec<CreateTableColumnConstraint>
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CreateTableStatement {
     pub table: Table,
     pub columns: Vec<CreateTableColumn>
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+pub struct CreateIndexStatement {
+    pub name: String,
+    pub table: Table,
+    pub columns: Vec<String>,
+    pub unique: bool
+}
+
+#[derive(Clone, Debug)]
 pub enum CreateStatement {
-    Table(CreateTableStatement)
+    Table(CreateTableStatement),
+    Index(CreateIndexStatement)
+}
+
+#[derive(Clone, Debug)]
+pub struct DropTableStatement {
+    pub table: Table,
+    pub if_exists: bool
+}
+
+#[derive(Clone, Debug)]
+pub enum DropStatement {
+    Table(DropTableStatement)
+}
+
+#[derive(Clone, Debug)]
+pub enum AlterTableAction {
+    RenameTo(String),
+    AddColumn(CreateTableColumn),
+    DropColumn(String)
+}
+
+#[derive(Clone, Debug)]
+pub struct AlterTableStatement {
+    pub table: Table,
+    pub action: AlterTableAction
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+pub enum AlterStatement {
+    Table(AlterTableStatement)
+}
+
+#[derive(Clone, Debug)]
 pub enum ExplainStatement {
     Select(SelectStatement)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+pub enum TransactionStatement {
+    Begin,
+    Commit,
+    Rollback
+}
+
+#[derive(Clone, Debug)]
 pub enum Statement {
     Select(SelectStatement),
     Insert(InsertStatement),
+    Update(UpdateStatement),
+    Delete(DeleteStatement),
     Create(CreateStatement),
-    Explain(ExplainStatement)
+    Drop(DropStatement),
+    Alter(AlterStatement),
+    Explain(ExplainStatement),
+    Transaction(TransactionStatement)
 }
@@ -1,19 +1,23 @@
 /// Disclaimer: The lexer is basically spaghetti. What did you expect?
 
+use std::fmt;
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     // Words
-    Select, From, Where, Group, Having, By, Limit,
+    Select, From, Where, Group, Having, By, Limit, Offset,
     Distinct,
     Order, Asc, Desc,
-    As, Join, Inner, Outer, Left, Right, On,
-    Insert, Into, Values, Update, Delete,
+    As, Join, Inner, Outer, Left, Right, Full, Cross, Natural, On, Using,
+    Insert, Into, Values, Update, Set, Delete,
     Create, Table, Index, Constraint,
-    Primary, Key, Unique, References,
+    Drop, Alter, Rename, To, Add, Column, If, Exists,
+    Primary, Key, Unique, References, Cascade, Restrict,
     And, Or,
     Between, In,
     Is, Not, Null,
     Explain,
+    Begin, Commit, Rollback, Transaction,
 
     // Non-letter tokens
     Equal,
@@ -35,8 +39,9 @@ pub enum Token {
     /// *, the wildcard for SELECT
     Asterisk,
 
-    /// ?, the prepared statement placeholder
-    PreparedStatementPlaceholder,
+    /// ?, ?NNN or :name, a prepared statement placeholder, with the number of its parameter.
+    /// The number is 0 if it's out of range.
+    PreparedStatementPlaceholder(u32),
 
     // Tokens with values
     Number(String),
@@ -64,7 +69,6 @@ fn character_to_token(c: char) -> Option<Token> {
         '|' => Pipe,
         '*' => Asterisk,
         '/' => ForwardSlash,
-        '?' => PreparedStatementPlaceholder,
         _ => return None
     })
 }
@@ -83,6 +87,7 @@ fn word_to_token(word: String) -> Token {
         "having" => Having,
         "by" => By,
         "limit" => Limit,
+        "offset" => Offset,
         "distinct" => Distinct,
         "order" => Order,
         "asc" => Asc,
@@ -93,20 +98,35 @@ fn word_to_token(word: String) -> Token {
         "outer" => Outer,
         "left" => Left,
         "right" => Right,
+        "full" => Full,
+        "cross" => Cross,
+        "natural" => Natural,
         "on" => On,
+        "using" => Using,
         "insert" => Insert,
         "into" => Into,
         "values" => Values,
         "update" => Update,
+        "set" => Set,
         "delete" => Delete,
         "create" => Create,
         "table" => Table,
         "index" => Index,
         "constraint" => Constraint,
+        "drop" => Drop,
+        "alter" => Alter,
+        "rename" => Rename,
+        "to" => To,
+        "add" => Add,
+        "column" => Column,
+        "if" => If,
+        "exists" => Exists,
         "primary" => Primary,
         "key" => Key,
         "unique" => Unique,
         "references" => References,
+        "cascade" => Cascade,
+        "restrict" => Restrict,
         "and" => And,
         "or" => Or,
         "between" => Between,
@@ -115,6 +135,10 @@ fn word_to_token(word: String) -> Token {
         "not" => Not,
         "null" => Null,
         "explain" => Explain,
+        "begin" => Begin,
+        "commit" => Commit,
+        "rollback" => Rollback,
+        "transaction" => Transaction,
         _ => Ident(word)
     }
 }
@@ -125,25 +149,109 @@ enum LexerState {
     Backtick,
     Apostrophe { escaping: bool },
     Number { decimal: bool },
+    /// A placeholder's number (after ?) or name (after :)
+    Placeholder { named: bool },
     /// Disambiguate an operator sequence.
     OperatorDisambiguate { first: char },
     LineComment,
     BlockComment { was_prev_char_asterisk: bool }
 }
 
+/// The largest number a placeholder can have
+pub const MAX_PLACEHOLDER_NUMBER: u32 = 32766;
+
+/// A position in the SQL text. Lines and columns start from 1, and columns
+/// are counted in characters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub line: u32,
+    pub column: u32
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// The locations of the first and last characters of a token
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexerError {
+    UnknownCharacter(char, Location),
+    /// A backtick that's never closed, at the location of the opening backtick
+    UnterminatedIdentifier(Location),
+    /// An apostrophe that's never closed, at the location of the opening apostrophe
+    UnterminatedString(Location),
+    /// A colon that isn't followed by a name
+    MissingPlaceholderName(Location)
+}
+
+impl LexerError {
+    pub fn location(&self) -> Location {
+        match self {
+            &LexerError::UnknownCharacter(_, location) |
+            &LexerError::UnterminatedIdentifier(location) |
+            &LexerError::UnterminatedString(location) |
+            &LexerError::MissingPlaceholderName(location) => location
+        }
+    }
+}
+
+impl fmt::Display for LexerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &LexerError::UnknownCharacter(c, _) => write!(f, "unknown character {:?}", c),
+            &LexerError::UnterminatedIdentifier(_) => write!(f, "quoted identifier has no closing backtick"),
+            &LexerError::UnterminatedString(_) => write!(f, "string literal has no closing apostrophe"),
+            &LexerError::MissingPlaceholderName(_) => write!(f, "expected a placeholder name after :")
+        }
+    }
+}
+
 pub struct Lexer {
-    pub tokens: Vec<Token>,
+    pub tokens: Vec<SpannedToken>,
+    /// The names of the parameters of the placeholders, by their number minus one.
+    /// Parameters of placeholders without names are None.
+    pub placeholder_names: Vec<Option<String>>,
+    /// The errors in the characters that were fed, in order.
+    /// The characters of an error are skipped, and lexing carries on.
+    pub errors: Vec<LexerError>,
 
     state: LexerState,
-    string_buffer: String
+    string_buffer: String,
+    /// The location of the next character
+    location: Location,
+    /// The location of the character before the next one
+    previous_location: Location,
+    /// The location of the first character of the current state
+    state_location: Location
 }
 
 impl Lexer {
     pub fn new() -> Lexer {
+        let start = Location { line: 1, column: 1 };
+
         Lexer {
             tokens: Vec::new(),
+            placeholder_names: Vec::new(),
+            errors: Vec::new(),
             state: LexerState::NoState,
-            string_buffer: String::new()
+            string_buffer: String::new(),
+            location: start,
+            previous_location: start,
+            state_location: start
         }
     }
 
@@ -154,57 +262,116 @@ impl Lexer {
         }
     }
 
-    fn no_state(&mut self, c: char) -> Result<LexerState, char> {
+    fn no_state(&mut self, c: char) -> LexerState {
+        self.state_location = self.location;
+
         match c {
             'a'...'z' | 'A'...'Z' | '_' => {
                 self.string_buffer.push(c);
-                Ok(LexerState::Word)
+                LexerState::Word
             },
             '`' => {
-                Ok(LexerState::Backtick)
+                LexerState::Backtick
             }
             '\'' => {
                 // string literal
-                Ok(LexerState::Apostrophe { escaping: false })
+                LexerState::Apostrophe { escaping: false }
             },
             '0'...'9' => {
                 self.string_buffer.push(c);
-                Ok(LexerState::Number { decimal: false })
+                LexerState::Number { decimal: false }
+            },
+            '?' => {
+                LexerState::Placeholder { named: false }
             },
-            ' ' | '\t' | '\n' => {
+            ':' => {
+                LexerState::Placeholder { named: true }
+            },
+            ' ' | '\t' | '\n' | '\r' => {
                 // whitespace
-                Ok(LexerState::NoState)
+                LexerState::NoState
             },
             c => {
                 use self::Token::*;
 
                 match character_to_token(c) {
                     Some(LessThan) | Some(GreaterThan) | Some(Minus) | Some(Pipe) | Some(ForwardSlash) => {
-                        Ok(LexerState::OperatorDisambiguate { first: c })
+                        LexerState::OperatorDisambiguate { first: c }
                     },
                     Some(token) => {
-                        self.tokens.push(token);
-                        Ok(LexerState::NoState)
+                        self.push_token(token);
+                        LexerState::NoState
                     },
                     None => {
-                        // unknown character
-                        Err(c)
+                        let location = self.location;
+                        self.fail(LexerError::UnknownCharacter(c, location));
+                        LexerState::NoState
                     }
                 }
             }
         }
     }
 
+    /// Pushes a token that began with the current state, and ends with the current character.
+    fn push_token(&mut self, token: Token) {
+        let span = Span { start: self.state_location, end: self.location };
+        self.tokens.push(SpannedToken { token: token, span: span });
+    }
+
+    /// Pushes a token that began with the current state, and ended with the previous character.
+    fn push_previous_token(&mut self, token: Token) {
+        let span = Span { start: self.state_location, end: self.previous_location };
+        self.tokens.push(SpannedToken { token: token, span: span });
+    }
+
+    fn fail(&mut self, error: LexerError) {
+        self.errors.push(error);
+    }
+
     fn move_string_buffer(&mut self) -> String {
         use std::mem;
         mem::replace(&mut self.string_buffer, String::new())
     }
 
+    /// Numbers the placeholder in the string buffer, as SQLite does:
+    /// `?` is numbered one more than the largest number so far, `?NNN` is numbered NNN,
+    /// and `:name` is numbered like `?` the first time the name is used.
+    fn push_placeholder(&mut self, named: bool) {
+        let buffer = self.move_string_buffer();
+
+        let number = if named {
+            let existing = self.placeholder_names.iter().position(|name| name.as_ref() == Some(&buffer));
+
+            match existing {
+                Some(i) => i as u32 + 1,
+                None => {
+                    self.placeholder_names.push(Some(buffer));
+                    self.placeholder_names.len() as u32
+                }
+            }
+        } else if buffer.is_empty() {
+            self.placeholder_names.push(None);
+            self.placeholder_names.len() as u32
+        } else {
+            match buffer.parse() {
+                Ok(number) if number >= 1 && number <= MAX_PLACEHOLDER_NUMBER => {
+                    while self.placeholder_names.len() < number as usize {
+                        self.placeholder_names.push(None);
+                    }
+                    number
+                },
+                _ => 0
+            }
+        };
+
+        self.push_previous_token(Token::PreparedStatementPlaceholder(number));
+    }
+
     pub fn feed_character(&mut self, c: Option<char>) {
         self.state = match self.state {
             LexerState::NoState => {
                 match c {
-                    Some(c) => self.no_state(c).unwrap(),
+                    Some(c) => self.no_state(c),
                     None => LexerState::NoState
                 }
             },
@@ -217,13 +384,13 @@ impl Lexer {
                         }
                         c => {
                             let buffer = self.move_string_buffer();
-                            self.tokens.push(word_to_token(buffer));
-                            self.no_state(c).unwrap()
+                            self.push_previous_token(word_to_token(buffer));
+                            self.no_state(c)
                         }
                     },
                     None => {
                         let buffer = self.move_string_buffer();
-                        self.tokens.push(word_to_token(buffer));
+                        self.push_previous_token(word_to_token(buffer));
                         LexerState::NoState
                     }
                 }
@@ -232,7 +399,7 @@ impl Lexer {
                 match c {
                     Some('`') => {
                         let buffer = self.move_string_buffer();
-                        self.tokens.push(Token::Ident(buffer));
+                        self.push_token(Token::Ident(buffer));
                         LexerState::NoState
                     },
                     Some(c) => {
@@ -240,8 +407,10 @@ impl Lexer {
                         LexerState::Backtick
                     },
                     None => {
-                        // error: backtick did not finish
-                        unimplemented!()
+                        let location = self.state_location;
+                        self.fail(LexerError::UnterminatedIdentifier(location));
+                        self.string_buffer.clear();
+                        LexerState::NoState
                     }
                 }
             },
@@ -251,7 +420,7 @@ impl Lexer {
                         (false, '\'') => {
                             // unescaped apostrophe
                             let buffer = self.move_string_buffer();
-                            self.tokens.push(Token::StringLiteral(buffer));
+                            self.push_token(Token::StringLiteral(buffer));
                             LexerState::NoState
                         },
                         (false, '\\') => {
@@ -264,8 +433,10 @@ impl Lexer {
                         }
                     }
                 } else {
-                    // error: apostrophe did not finish
-                    unimplemented!()
+                    let location = self.state_location;
+                    self.fail(LexerError::UnterminatedString(location));
+                    self.string_buffer.clear();
+                    LexerState::NoState
                 }
             },
             LexerState::Number { decimal } => {
@@ -282,35 +453,60 @@ impl Lexer {
                         },
                         c => {
                             let buffer = self.move_string_buffer();
-                            self.tokens.push(Token::Number(buffer));
-                            self.no_state(c).unwrap()
+                            self.push_previous_token(Token::Number(buffer));
+                            self.no_state(c)
                         }
                     }
                 } else {
                     let buffer = self.move_string_buffer();
-                    self.tokens.push(Token::Number(buffer));
+                    self.push_previous_token(Token::Number(buffer));
                     LexerState::NoState
                 }
             },
+            LexerState::Placeholder { named } => {
+                match c {
+                    Some(c @ '0'...'9') => {
+                        self.string_buffer.push(c);
+                        LexerState::Placeholder { named: named }
+                    },
+                    Some(c @ 'a'...'z') | Some(c @ 'A'...'Z') | Some(c @ '_') if named => {
+                        self.string_buffer.push(c);
+                        LexerState::Placeholder { named: named }
+                    },
+                    c => {
+                        if named && self.string_buffer.is_empty() {
+                            let location = self.state_location;
+                            self.fail(LexerError::MissingPlaceholderName(location));
+                        } else {
+                            self.push_placeholder(named);
+                        }
+
+                        match c {
+                            Some(c) => self.no_state(c),
+                            None => LexerState::NoState
+                        }
+                    }
+                }
+            },
             LexerState::OperatorDisambiguate { first } => {
                 use self::Token::*;
 
                 if let Some(c) = c {
                     match (first, c) {
                         ('<', '>') => {
-                            self.tokens.push(NotEqual);
+                            self.push_token(NotEqual);
                             LexerState::NoState
                         },
                         ('<', '=') => {
-                            self.tokens.push(LessThanOrEqual);
+                            self.push_token(LessThanOrEqual);
                             LexerState::NoState
                         },
                         ('>', '=') => {
-                            self.tokens.push(GreaterThanOrEqual);
+                            self.push_token(GreaterThanOrEqual);
                             LexerState::NoState
                         },
                         ('|', '|') => {
-                            self.tokens.push(DoublePipe);
+                            self.push_token(DoublePipe);
                             LexerState::NoState
                         },
                         ('-', '-') => {
@@ -320,12 +516,12 @@ impl Lexer {
                             LexerState::BlockComment { was_prev_char_asterisk: false }
                         },
                         _ => {
-                            self.tokens.push(character_to_token(first).unwrap());
-                            self.no_state(c).unwrap()
+                            self.push_previous_token(character_to_token(first).unwrap());
+                            self.no_state(c)
                         }
                     }
                 } else {
-                    self.tokens.push(character_to_token(first).unwrap());
+                    self.push_previous_token(character_to_token(first).unwrap());
                     LexerState::NoState
                 }
             },
@@ -343,6 +539,19 @@ impl Lexer {
                 }
             }
         };
+
+        if c.is_some() {
+            self.previous_location = self.location;
+        }
+
+        match c {
+            Some('\n') => {
+                self.location.line += 1;
+                self.location.column = 1;
+            },
+            Some(_) => self.location.column += 1,
+            None => ()
+        }
     }
 
     pub fn feed_characters<I>(&mut self, iter: I)
@@ -354,18 +563,30 @@ impl Lexer {
     }
 }
 
-pub fn parse(sql: &str) -> Vec<Token> {
+/// Returns the first error if there are any.
+pub fn parse(sql: &str) -> Result<Vec<SpannedToken>, LexerError> {
     let mut lexer = Lexer::new();
 
     lexer.feed_characters(sql.chars());
     lexer.feed_character(None);
 
-    lexer.tokens
+    match lexer.errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(lexer.tokens)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::parse;
+    use super::{LexerError, Location, Span, SpannedToken};
+
+    fn tokens(tokens: &[SpannedToken]) -> Vec<super::Token> {
+        tokens.iter().map(|t| t.token.clone()).collect()
+    }
+
+    fn parse(sql: &str) -> Vec<super::Token> {
+        tokens(&super::parse(sql).unwrap())
+    }
 
     fn id(value: &str) -> super::Token {
         super::Token::Ident(value.to_string())
@@ -453,6 +674,30 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_sql_lexer_placeholders() {
+        use super::Lexer;
+        use super::Token::*;
+
+        let mut lexer = Lexer::new();
+        lexer.feed_characters("? = :id, ?5 <> ?, :name||:id,?".chars());
+        lexer.feed_character(None);
+
+        assert_eq!(tokens(&lexer.tokens), vec![
+            PreparedStatementPlaceholder(1), Equal, PreparedStatementPlaceholder(2), Comma,
+            PreparedStatementPlaceholder(5), NotEqual, PreparedStatementPlaceholder(6), Comma,
+            PreparedStatementPlaceholder(7), DoublePipe, PreparedStatementPlaceholder(2), Comma,
+            PreparedStatementPlaceholder(8)
+        ]);
+        assert_eq!(lexer.placeholder_names, vec![
+            None, Some("id".to_string()), None, None, None, None, Some("name".to_string()), None
+        ]);
+
+        assert_eq!(parse("?0 ?32767 ?32766"), vec![
+            PreparedStatementPlaceholder(0), PreparedStatementPlaceholder(0), PreparedStatementPlaceholder(32766)
+        ]);
+    }
+
     #[test]
     fn test_sql_lexer_blockcomment() {
         use super::Token::*;
@@ -467,4 +712,41 @@ mod test {
 
         assert_eq!(parse("a/* test\ntest** /\nb*/c"), vec![id("a"), id("c")]);
     }
+
+    #[test]
+    fn test_sql_lexer_errors() {
+        let at = |line, column| Location { line: line, column: column };
+
+        assert_eq!(super::parse("SELECT a\nFROM t WHERE b = \"c\";"), Err(LexerError::UnknownCharacter('"', at(2, 18))));
+        assert_eq!(super::parse("SELECT 'abc"), Err(LexerError::UnterminatedString(at(1, 8))));
+        assert_eq!(super::parse("SELECT `abc"), Err(LexerError::UnterminatedIdentifier(at(1, 8))));
+        assert_eq!(super::parse("SELECT a = : ;"), Err(LexerError::MissingPlaceholderName(at(1, 12))));
+
+        // Every error is kept, and lexing carries on after each
+        let mut lexer = super::Lexer::new();
+        lexer.feed_characters("a % b $ 'c".chars());
+        lexer.feed_character(None);
+
+        assert_eq!(lexer.errors, vec![
+            LexerError::UnknownCharacter('%', at(1, 3)), LexerError::UnknownCharacter('$', at(1, 7)), LexerError::UnterminatedString(at(1, 9))
+        ]);
+        assert_eq!(tokens(&lexer.tokens), vec![id("a"), id("b")]);
+        assert_eq!(super::parse("a % b $ 'c").map_err(|e| e.location()), Err(at(1, 3)));
+        assert_eq!(parse("a\r\nb"), vec![id("a"), id("b")]);
+    }
+
+    #[test]
+    fn test_sql_lexer_spans() {
+        let span = |line, start, end| Span { start: Location { line: line, column: start }, end: Location { line: line, column: end } };
+        let spans: Vec<Span> = super::parse("SELECT `a b`,\n  'c'||d >=?12;").unwrap().iter().map(|t| t.span).collect();
+
+        assert_eq!(spans, vec![
+            span(1, 1, 6), span(1, 8, 12), span(1, 13, 13),
+            span(2, 3, 5), span(2, 6, 7), span(2, 8, 8), span(2, 10, 11), span(2, 12, 14), span(2, 15, 15)
+        ]);
+
+        // The last token ends at the last character
+        assert_eq!(super::parse("a <").unwrap()[1].span, span(1, 3, 3));
+        assert_eq!(super::parse("abc").unwrap()[0].span, span(1, 1, 3));
+    }
 }
@@ -5,34 +5,159 @@ pub mod ast;
 pub mod lexer;
 pub mod parser;
 
-pub fn parse_statement(query: &str) -> ast::Statement {
-    let tokens = lexer::parse(query);
-    parser::parse_statement(&tokens).unwrap()
+use error::Error;
+
+/// Parses a single statement, which may be followed by a semicolon.
+pub fn parse_statement(query: &str) -> Result<ast::Statement, Error> {
+    let tokens = try!(lexer::parse(query));
+    Ok(try!(parser::parse_statement(&tokens)))
 }
 
-pub fn parse_statements(query: &str) -> Vec<ast::Statement> {
-    let tokens = lexer::parse(query);
-    parser::parse_statements(&tokens).unwrap()
+/// Parses a series of statements, each followed by a semicolon.
+///
+/// If any of the statements have errors, the first error of each of those
+/// statements is returned, in order.
+pub fn parse_statements(query: &str) -> Result<Vec<ast::Statement>, Vec<Error>> {
+    let mut lexer = lexer::Lexer::new();
+
+    lexer.feed_characters(query.chars());
+    lexer.feed_character(None);
+
+    let parser_errors = match parser::parse_statements(&lexer.tokens) {
+        Ok(statements) => {
+            if lexer.errors.is_empty() {
+                return Ok(statements);
+            }
+            Vec::new()
+        },
+        Err(errors) => errors
+    };
+
+    let statement_ends: Vec<lexer::Location> = lexer.tokens.iter()
+        .filter(|t| t.token == lexer::Token::Semicolon)
+        .map(|t| t.span.end)
+        .collect();
+    let statement_number = |location: lexer::Location| statement_ends.iter().take_while(|&&end| end < location).count();
+
+    // The characters of a lexer error are skipped, which can cause a parser
+    // error later in the same statement. The lexer error is the one that's kept.
+    let mut errors: Vec<(usize, lexer::Location, Error)> = lexer.errors.into_iter().map(|e| {
+        (statement_number(e.location()), e.location(), Error::from(e))
+    }).collect();
+
+    for e in parser_errors {
+        let number = statement_number(e.location());
+
+        if !errors.iter().any(|&(n, _, _)| n == number) {
+            errors.push((number, e.location(), Error::from(e)));
+        }
+    }
+
+    errors.sort_by(|a, b| a.1.cmp(&b.1));
+    errors.dedup_by_key(|e| e.0);
+
+    Err(errors.into_iter().map(|(_, _, e)| e).collect())
 }
 
 #[cfg(test)]
 mod test {
-    use super::parse_statement as parse;
+    use super::ast;
+    use error::Error;
+
+    fn parse(sql: &str) -> ast::Statement {
+        super::parse_statement(sql).unwrap()
+    }
 
     #[test]
     fn test_sql_parser() {
         parse("SELECT *, (name + 4), count(*) AS amount FROM (SELECT * FROM foo) subq, table1 GROUP BY name HAVING count(*) > 5;");
         parse("SELECT * FROM foo INNER JOIN bar ON foo.id = bar.fooId ORDER BY a DESC, b;");
+        parse("SELECT * FROM foo RIGHT OUTER JOIN bar ON foo.id = bar.fooId FULL JOIN baz USING (id, name);");
+        parse("SELECT * FROM foo CROSS JOIN bar NATURAL LEFT JOIN baz NATURAL JOIN qux;");
         parse("SELECT avg(milliseconds) / 1000 seconds FROM track;");
+        parse("SELECT * FROM foo ORDER BY a LIMIT 10;");
+        parse("SELECT * FROM foo LIMIT 10 OFFSET 5 * 2;");
+        parse("SELECT DISTINCT a, count(DISTINCT b) FROM foo GROUP BY a;");
+        parse("SELECT * FROM main.foo, bar WHERE foo.id = bar.id;");
 
         parse("INSERT INTO table1 VALUES (1, 2), (3, 4), (5, 6);");
         parse("INSERT INTO table1 (a, b) VALUES ('foo' || 'bar', 2);");
         parse("INSERT INTO table1 SELECT * FROM foo;");
+        parse("INSERT INTO table1 (a, b) VALUES (?, ?2 || :name);");
+
+        parse("UPDATE table1 SET a = a + 1;");
+        parse("UPDATE table1 SET a = 2, b = 'foo' || b WHERE a = 1 AND b <> 'bar';");
+        parse("DELETE FROM table1;");
+        parse("DELETE FROM table1 WHERE a = (SELECT max(a) FROM table1);");
 
         parse("CREATE TABLE test (
             foo     INT CONSTRAINT pk PRIMARY KEY,
             bar     VARCHAR(256),
             data    BYTE[32] NULL UNIQUE
         );");
+        parse("CREATE TABLE test2 (
+            id      U32 PRIMARY KEY,
+            foo     INT REFERENCES test,
+            baz     U32 NULL REFERENCES test (baz) ON DELETE SET NULL,
+            parent  U32 REFERENCES test2 (id) ON DELETE CASCADE
+        );");
+        parse("CREATE INDEX test_bar ON test (bar);");
+        parse("CREATE UNIQUE INDEX test_foo_bar ON test (foo, bar);");
+
+        parse("DROP TABLE test;");
+        parse("DROP TABLE IF EXISTS test;");
+        parse("ALTER TABLE test RENAME TO test2;");
+        parse("ALTER TABLE test ADD COLUMN baz U32 NULL;");
+        parse("ALTER TABLE test ADD baz BYTE[8];");
+        parse("ALTER TABLE test DROP COLUMN bar;");
+        parse("ALTER TABLE test DROP bar;");
+
+        parse("BEGIN;");
+        parse("BEGIN TRANSACTION;");
+        parse("COMMIT;");
+        parse("ROLLBACK TRANSACTION;");
+    }
+
+    #[test]
+    fn test_sql_parser_error_locations() {
+        use super::{lexer, parser};
+
+        let location = |sql| {
+            let tokens = lexer::parse(sql).unwrap();
+            let location = parser::parse_statement(&tokens).err().unwrap().location();
+            (location.line, location.column)
+        };
+
+        assert_eq!(location("SELECT a\nFROM t WHERE;"), (2, 13));
+        assert_eq!(location("INSERT INTO t VALUES (1) (2);"), (1, 26));
+        // After the last token if the statement ends too soon
+        assert_eq!(location("SELECT a FROM"), (1, 14));
+        assert_eq!(location("SELECT a FROM t WHERE a = ?0;"), (1, 27));
+    }
+
+    #[test]
+    fn test_sql_parse_statements() {
+        use super::parse_statements;
+
+        let locations = |sql| -> Vec<(u32, u32)> {
+            parse_statements(sql).err().unwrap().into_iter().map(|e| {
+                match e {
+                    Error::Syntax { location: Some(location), .. } => (location.line, location.column),
+                    e => panic!("expected a syntax error, got {:?}", e)
+                }
+            }).collect()
+        };
+
+        assert_eq!(parse_statements("SELECT a FROM t; DROP TABLE t;").unwrap().len(), 2);
+        assert_eq!(parse_statements("").unwrap().len(), 0);
+
+        // Every statement with an error is reported, once
+        assert_eq!(locations("SELECT FROM t;\nSELECT a FROM t;\nINSERT INTO t VALUES (1) (2);\nSELECT \"a\" FROM # t;"), vec![(1, 8), (3, 26), (4, 8)]);
+        assert_eq!(locations("SELECT a FROM t;;"), vec![(1, 17)]);
+        assert_eq!(locations("SELECT a FROM t; SELECT 'a"), vec![(1, 25)]);
+        assert_eq!(locations("SELECT a FROM t"), vec![(1, 16)]);
+
+        assert!(super::parse_statement("SELECT a FROM t; SELECT a FROM t;").is_err());
+        assert!(super::parse_statement("SELECT 'a FROM t;").is_err());
     }
 }
@@ -3,15 +3,26 @@
 use std::marker::{PhantomData, Sized};
 use std::fmt;
 
-use super::lexer::Token;
+use super::lexer::{Location, SpannedToken, Token};
 use super::ast::*;
 
 mod tokens;
 use self::tokens::Tokens;
 
+/// The location is where the unexpected token begins, or the end of the
+/// tokens if there are no more.
 pub enum RuleError {
-    ExpectingFirst(&'static str, Option<Token>),
-    Expecting(&'static str, Option<Token>)
+    ExpectingFirst(&'static str, Option<Token>, Location),
+    Expecting(&'static str, Option<Token>, Location)
+}
+
+impl RuleError {
+    pub fn location(&self) -> Location {
+        match self {
+            &RuleError::ExpectingFirst(_, _, location) |
+            &RuleError::Expecting(_, _, location) => location
+        }
+    }
 }
 
 impl fmt::Display for RuleError {
@@ -19,10 +30,10 @@ impl fmt::Display for RuleError {
         use self::RuleError::*;
 
         match self {
-            &ExpectingFirst(s, Some(ref token)) => write!(f, "Expected {}; got {:?}", s, token),
-            &Expecting(s, Some(ref token)) => write!(f, "Expected {}; got {:?}", s, token),
-            &ExpectingFirst(s, None) => write!(f, "Expected {}; got no more tokens", s),
-            &Expecting(s, None) => write!(f, "Expected {}; got no more tokens", s)
+            &ExpectingFirst(s, Some(ref token), _) => write!(f, "Expected {}; got {:?}", s, token),
+            &Expecting(s, Some(ref token), _) => write!(f, "Expected {}; got {:?}", s, token),
+            &ExpectingFirst(s, None, _) => write!(f, "Expected {}; got no more tokens", s),
+            &Expecting(s, None, _) => write!(f, "Expected {}; got no more tokens", s)
         }
     }
 }
@@ -39,7 +50,7 @@ fn rule_result_not_first<T>(rule_result: RuleResult<T>) -> RuleResult<T> {
     use self::RuleError::*;
 
     match rule_result {
-        Err(ExpectingFirst(s, t)) => Err(Expecting(s, t)),
+        Err(ExpectingFirst(s, t, l)) => Err(Expecting(s, t, l)),
         value => value
     }
 }
@@ -246,6 +257,8 @@ impl Expression {
     }
 
     fn parse_beginning(tokens: &mut Tokens) -> RuleResult<Expression> {
+        let location = tokens.location();
+
         if tokens.pop_if_token(&Token::Plus) {
             // Unary, positive
 
@@ -280,11 +293,12 @@ impl Expression {
 
                     Ok(Expression::FunctionCallAggregateAll { name: ident })
                 } else {
+                    let distinct = tokens.pop_if_token(&Token::Distinct);
                     let arguments = try_notfirst!(Expression::parse_comma_delimited(tokens));
 
                     try_notfirst!(tokens.pop_token_expecting(&Token::RightParen, ") after function arguments"));
 
-                    Ok(Expression::FunctionCall { name: ident, arguments: arguments })
+                    Ok(Expression::FunctionCall { name: ident, distinct: distinct, arguments: arguments })
                 }
             } else if tokens.pop_if_token(&Token::Dot) {
                 let ident2 = try_notfirst!(tokens.pop_ident_expecting("ident after ."));
@@ -297,6 +311,13 @@ impl Expression {
             Ok(Expression::StringLiteral(string))
         } else if let Some(number) = tokens.pop_if_number() {
             Ok(Expression::Number(number))
+        } else if let Some(number) = tokens.pop_if_placeholder() {
+            if number == 0 {
+                // The lexer numbers the placeholders that are out of range 0
+                Err(RuleError::ExpectingFirst("placeholder number from 1 to 32766", Some(Token::PreparedStatementPlaceholder(0)), location))
+            } else {
+                Ok(Expression::Placeholder(number))
+            }
         } else {
             Err(tokens.expecting("identifier or number"))
         }
@@ -321,12 +342,21 @@ impl Rule for AsAlias {
 impl Rule for Table {
     type Output = Table;
     fn parse(tokens: &mut Tokens) -> RuleResult<Table> {
-        let table_name = try!(tokens.pop_ident_expecting("table name"));
+        let name = try!(tokens.pop_ident_expecting("table name"));
 
-        Ok(Table {
-            database_name: None,
-            table_name: table_name
-        })
+        if tokens.pop_if_token(&Token::Dot) {
+            let table_name = try_notfirst!(tokens.pop_ident_expecting("table name after ."));
+
+            Ok(Table {
+                database_name: Some(name),
+                table_name: table_name
+            })
+        } else {
+            Ok(Table {
+                database_name: None,
+                table_name: name
+            })
+        }
     }
 }
 
@@ -378,6 +408,8 @@ impl Rule for SelectStatement {
     fn parse(tokens: &mut Tokens) -> RuleResult<SelectStatement> {
         try!(tokens.pop_token_expecting(&Token::Select, "SELECT"));
 
+        let distinct = tokens.pop_if_token(&Token::Distinct);
+
         let result_columns: Vec<SelectColumn> = try_notfirst!(SelectColumn::parse_comma_delimited(tokens));
 
         let from = try_notfirst!(From::parse(tokens));
@@ -411,13 +443,32 @@ impl Rule for SelectStatement {
             Vec::new()
         };
 
+        let limit = if tokens.pop_if_token(&Token::Limit) {
+            let count = try_notfirst!(Expression::parse(tokens));
+
+            let offset = if tokens.pop_if_token(&Token::Offset) {
+                Some(try_notfirst!(Expression::parse(tokens)))
+            } else {
+                None
+            };
+
+            Some(Limit {
+                count: count,
+                offset: offset
+            })
+        } else {
+            None
+        };
+
         Ok(SelectStatement {
+            distinct: distinct,
             result_columns: result_columns,
             from: from,
             where_expr: where_expr,
             group_by: group_by,
             having: having,
-            order_by: order_by
+            order_by: order_by,
+            limit: limit
         })
     }
 }
@@ -458,14 +509,27 @@ impl Rule for JoinOperator {
 
             try_notfirst!(tokens.pop_token_expecting(&Token::Join, "JOIN after LEFT (OUTER)"));
             Ok(JoinOperator::Left)
+        } else if tokens.pop_if_token(&Token::Right) {
+            tokens.pop_if_token(&Token::Outer);
+
+            try_notfirst!(tokens.pop_token_expecting(&Token::Join, "JOIN after RIGHT (OUTER)"));
+            Ok(JoinOperator::Right)
+        } else if tokens.pop_if_token(&Token::Full) {
+            tokens.pop_if_token(&Token::Outer);
+
+            try_notfirst!(tokens.pop_token_expecting(&Token::Join, "JOIN after FULL (OUTER)"));
+            Ok(JoinOperator::Full)
         } else if tokens.pop_if_token(&Token::Inner) {
             try_notfirst!(tokens.pop_token_expecting(&Token::Join, "JOIN after INNER"));
             Ok(JoinOperator::Inner)
+        } else if tokens.pop_if_token(&Token::Cross) {
+            try_notfirst!(tokens.pop_token_expecting(&Token::Join, "JOIN after CROSS"));
+            Ok(JoinOperator::Cross)
         } else if tokens.pop_if_token(&Token::Join) {
             // implied inner join
             Ok(JoinOperator::Inner)
         } else {
-            Err(tokens.expecting("Join operator (LEFT, INNER, or JOIN)"))
+            Err(tokens.expecting("Join operator (LEFT, RIGHT, FULL, INNER, CROSS, or JOIN)"))
         }
     }
 }
@@ -473,15 +537,31 @@ impl Rule for JoinOperator {
 impl Rule for Join {
     type Output = Join;
     fn parse(tokens: &mut Tokens) -> RuleResult<Join> {
-        let operator = try!(JoinOperator::parse(tokens));
+        let natural = tokens.pop_if_token(&Token::Natural);
+
+        let operator = if natural {
+            try_notfirst!(JoinOperator::parse(tokens))
+        } else {
+            try!(JoinOperator::parse(tokens))
+        };
+
         let table = try_notfirst!(TableOrSubquery::parse(tokens));
-        try_notfirst!(tokens.pop_token_expecting(&Token::On, "ON"));
-        let on = try_notfirst!(Expression::parse(tokens));
+
+        let constraint = if natural {
+            JoinConstraint::Natural
+        } else if operator == JoinOperator::Cross {
+            JoinConstraint::None
+        } else if tokens.pop_if_token(&Token::Using) {
+            JoinConstraint::Using(try_notfirst!(ParensCommaDelimitedRule::<Ident>::parse(tokens)))
+        } else {
+            try_notfirst!(tokens.pop_token_expecting(&Token::On, "ON or USING"));
+            JoinConstraint::On(try_notfirst!(Expression::parse(tokens)))
+        };
 
         Ok(Join {
             operator: operator,
             table: table,
-            on: on
+            constraint: constraint
         })
     }
 }
@@ -541,6 +621,65 @@ impl Rule for InsertSource {
     }
 }
 
+impl Rule for UpdateAssignment {
+    type Output = UpdateAssignment;
+    fn parse(tokens: &mut Tokens) -> RuleResult<UpdateAssignment> {
+        let column_name = try!(tokens.pop_ident_expecting("column name"));
+        try_notfirst!(tokens.pop_token_expecting(&Token::Equal, "= after column name"));
+        let expr = try_notfirst!(Expression::parse(tokens));
+
+        Ok(UpdateAssignment {
+            column_name: column_name,
+            expr: expr
+        })
+    }
+}
+
+impl Rule for UpdateStatement {
+    type Output = UpdateStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<UpdateStatement> {
+        try!(tokens.pop_token_expecting(&Token::Update, "UPDATE"));
+
+        let table = try_notfirst!(Table::parse(tokens));
+
+        try_notfirst!(tokens.pop_token_expecting(&Token::Set, "SET"));
+        let assignments = try_notfirst!(UpdateAssignment::parse_comma_delimited(tokens));
+
+        let where_expr = if tokens.pop_if_token(&Token::Where) {
+            Some(try_notfirst!(Expression::parse(tokens)))
+        } else {
+            None
+        };
+
+        Ok(UpdateStatement {
+            table: table,
+            assignments: assignments,
+            where_expr: where_expr
+        })
+    }
+}
+
+impl Rule for DeleteStatement {
+    type Output = DeleteStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<DeleteStatement> {
+        try!(tokens.pop_token_expecting(&Token::Delete, "DELETE"));
+        try_notfirst!(tokens.pop_token_expecting(&Token::From, "FROM after DELETE"));
+
+        let table = try_notfirst!(Table::parse(tokens));
+
+        let where_expr = if tokens.pop_if_token(&Token::Where) {
+            Some(try_notfirst!(Expression::parse(tokens)))
+        } else {
+            None
+        };
+
+        Ok(DeleteStatement {
+            table: table,
+            where_expr: where_expr
+        })
+    }
+}
+
 impl Rule for CreateTableColumnConstraint {
     type Output = CreateTableColumnConstraint;
     fn parse(tokens: &mut Tokens) -> RuleResult<CreateTableColumnConstraint> {
@@ -578,9 +717,18 @@ impl Rule for CreateTableColumnConstraintType {
         } else if tokens.pop_if_token(&Token::References) {
             let table = try_notfirst!(Table::parse(tokens));
             let columns = try_notfirst!(ParensCommaDelimitedRule::<Ident>::parse_lookahead(tokens));
+
+            let on_delete = if tokens.pop_if_token(&Token::On) {
+                try_notfirst!(tokens.pop_token_expecting(&Token::Delete, "DELETE after ON"));
+                Some(try_notfirst!(ForeignKeyAction::parse(tokens)))
+            } else {
+                None
+            };
+
             Ok(ForeignKey {
                 table: table,
-                columns: columns
+                columns: columns,
+                on_delete: on_delete
             })
         } else {
             Err(tokens.expecting("column constraint"))
@@ -588,6 +736,22 @@ impl Rule for CreateTableColumnConstraintType {
     }
 }
 
+impl Rule for ForeignKeyAction {
+    type Output = ForeignKeyAction;
+    fn parse(tokens: &mut Tokens) -> RuleResult<ForeignKeyAction> {
+        if tokens.pop_if_token(&Token::Cascade) {
+            Ok(ForeignKeyAction::Cascade)
+        } else if tokens.pop_if_token(&Token::Restrict) {
+            Ok(ForeignKeyAction::Restrict)
+        } else if tokens.pop_if_token(&Token::Set) {
+            try_notfirst!(tokens.pop_token_expecting(&Token::Null, "NULL after SET"));
+            Ok(ForeignKeyAction::SetNull)
+        } else {
+            Err(tokens.expecting("CASCADE, RESTRICT, or SET NULL"))
+        }
+    }
+}
+
 impl Rule for CreateTableColumn {
     type Output = CreateTableColumn;
     fn parse(tokens: &mut Tokens) -> RuleResult<CreateTableColumn> {
@@ -645,6 +809,31 @@ impl Rule for CreateTableStatement {
     }
 }
 
+impl Rule for CreateIndexStatement {
+    type Output = CreateIndexStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<CreateIndexStatement> {
+        let unique = tokens.pop_if_token(&Token::Unique);
+
+        if unique {
+            try_notfirst!(tokens.pop_token_expecting(&Token::Index, "INDEX after UNIQUE"));
+        } else {
+            try!(tokens.pop_token_expecting(&Token::Index, "INDEX"));
+        }
+
+        let name = try_notfirst!(tokens.pop_ident_expecting("index name"));
+        try_notfirst!(tokens.pop_token_expecting(&Token::On, "ON after index name"));
+        let table = try_notfirst!(Table::parse(tokens));
+        let columns = try_notfirst!(ParensCommaDelimitedRule::<Ident>::parse(tokens));
+
+        Ok(CreateIndexStatement {
+            name: name,
+            table: table,
+            columns: columns,
+            unique: unique
+        })
+    }
+}
+
 impl Rule for CreateStatement {
     type Output = CreateStatement;
     fn parse(tokens: &mut Tokens) -> RuleResult<CreateStatement> {
@@ -652,6 +841,95 @@ impl Rule for CreateStatement {
 
         if let Some(stmt) = try_notfirst!(CreateTableStatement::parse_lookahead(tokens)) {
             Ok(CreateStatement::Table(stmt))
+        } else if let Some(stmt) = try_notfirst!(CreateIndexStatement::parse_lookahead(tokens)) {
+            Ok(CreateStatement::Index(stmt))
+        } else {
+            Err(tokens.expecting("TABLE or INDEX"))
+        }
+    }
+}
+
+impl Rule for DropTableStatement {
+    type Output = DropTableStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<DropTableStatement> {
+        try!(tokens.pop_token_expecting(&Token::Table, "TABLE"));
+
+        let if_exists = if tokens.pop_if_token(&Token::If) {
+            try_notfirst!(tokens.pop_token_expecting(&Token::Exists, "EXISTS after IF"));
+            true
+        } else {
+            false
+        };
+
+        let table = try_notfirst!(Table::parse(tokens));
+
+        Ok(DropTableStatement {
+            table: table,
+            if_exists: if_exists
+        })
+    }
+}
+
+impl Rule for DropStatement {
+    type Output = DropStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<DropStatement> {
+        try!(tokens.pop_token_expecting(&Token::Drop, "DROP"));
+
+        if let Some(stmt) = try_notfirst!(DropTableStatement::parse_lookahead(tokens)) {
+            Ok(DropStatement::Table(stmt))
+        } else {
+            Err(tokens.expecting("TABLE"))
+        }
+    }
+}
+
+impl Rule for AlterTableAction {
+    type Output = AlterTableAction;
+    fn parse(tokens: &mut Tokens) -> RuleResult<AlterTableAction> {
+        if tokens.pop_if_token(&Token::Rename) {
+            try_notfirst!(tokens.pop_token_expecting(&Token::To, "TO after RENAME"));
+            let table_name = try_notfirst!(tokens.pop_ident_expecting("new table name"));
+            Ok(AlterTableAction::RenameTo(table_name))
+        } else if tokens.pop_if_token(&Token::Add) {
+            // "Column" is optional. Pop if it exists.
+            tokens.pop_if_token(&Token::Column);
+
+            let column = try_notfirst!(CreateTableColumn::parse(tokens));
+            Ok(AlterTableAction::AddColumn(column))
+        } else if tokens.pop_if_token(&Token::Drop) {
+            // "Column" is optional. Pop if it exists.
+            tokens.pop_if_token(&Token::Column);
+
+            let column_name = try_notfirst!(tokens.pop_ident_expecting("column name"));
+            Ok(AlterTableAction::DropColumn(column_name))
+        } else {
+            Err(tokens.expecting("RENAME, ADD, or DROP"))
+        }
+    }
+}
+
+impl Rule for AlterTableStatement {
+    type Output = AlterTableStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<AlterTableStatement> {
+        try!(tokens.pop_token_expecting(&Token::Table, "TABLE"));
+
+        let table = try_notfirst!(Table::parse(tokens));
+        let action = try_notfirst!(AlterTableAction::parse(tokens));
+
+        Ok(AlterTableStatement {
+            table: table,
+            action: action
+        })
+    }
+}
+
+impl Rule for AlterStatement {
+    type Output = AlterStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<AlterStatement> {
+        try!(tokens.pop_token_expecting(&Token::Alter, "ALTER"));
+
+        if let Some(stmt) = try_notfirst!(AlterTableStatement::parse_lookahead(tokens)) {
+            Ok(AlterStatement::Table(stmt))
         } else {
             Err(tokens.expecting("TABLE"))
         }
@@ -671,6 +949,26 @@ impl Rule for ExplainStatement {
     }
 }
 
+impl Rule for TransactionStatement {
+    type Output = TransactionStatement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<TransactionStatement> {
+        let stmt = if tokens.pop_if_token(&Token::Begin) {
+            TransactionStatement::Begin
+        } else if tokens.pop_if_token(&Token::Commit) {
+            TransactionStatement::Commit
+        } else if tokens.pop_if_token(&Token::Rollback) {
+            TransactionStatement::Rollback
+        } else {
+            return Err(tokens.expecting("BEGIN, COMMIT, or ROLLBACK"));
+        };
+
+        // "Transaction" is optional. Pop if it exists.
+        tokens.pop_if_token(&Token::Transaction);
+
+        Ok(stmt)
+    }
+}
+
 impl Rule for Statement {
     type Output = Statement;
     fn parse(tokens: &mut Tokens) -> RuleResult<Statement> {
@@ -678,34 +976,40 @@ impl Rule for Statement {
             Ok(Statement::Select(select))
         } else if let Some(insert) = try!(InsertStatement::parse_lookahead(tokens)) {
             Ok(Statement::Insert(insert))
+        } else if let Some(update) = try!(UpdateStatement::parse_lookahead(tokens)) {
+            Ok(Statement::Update(update))
+        } else if let Some(delete) = try!(DeleteStatement::parse_lookahead(tokens)) {
+            Ok(Statement::Delete(delete))
         } else if let Some(create) = try!(CreateStatement::parse_lookahead(tokens)) {
             Ok(Statement::Create(create))
+        } else if let Some(drop) = try!(DropStatement::parse_lookahead(tokens)) {
+            Ok(Statement::Drop(drop))
+        } else if let Some(alter) = try!(AlterStatement::parse_lookahead(tokens)) {
+            Ok(Statement::Alter(alter))
         } else if let Some(explain) = try!(ExplainStatement::parse_lookahead(tokens)) {
             Ok(Statement::Explain(explain))
+        } else if let Some(transaction) = try!(TransactionStatement::parse_lookahead(tokens)) {
+            Ok(Statement::Transaction(transaction))
         } else {
-            Err(tokens.expecting("SELECT, INSERT, CREATE, or EXPLAIN statement"))
+            Err(tokens.expecting("SELECT, INSERT, UPDATE, DELETE, CREATE, DROP, ALTER, EXPLAIN, BEGIN, COMMIT, or ROLLBACK statement"))
         }
     }
 }
 
 #[allow(dead_code)]
-struct Statements;
+/// A statement followed by a semicolon
+struct TerminatedStatement;
 
-impl Rule for Statements {
-    type Output = Vec<Statement>;
-    fn parse(tokens: &mut Tokens) -> RuleResult<Vec<Statement>> {
-        let mut statements = Vec::new();
-
-        while let Some(stmt) = try!(Statement::parse_lookahead(tokens)) {
-            statements.push(stmt);
-            try!(tokens.pop_token_expecting(&Token::Semicolon, "semicolon"));
-        }
-
-        Ok(statements)
+impl Rule for TerminatedStatement {
+    type Output = Statement;
+    fn parse(tokens: &mut Tokens) -> RuleResult<Statement> {
+        let statement = try!(Statement::parse(tokens));
+        try_notfirst!(tokens.pop_token_expecting(&Token::Semicolon, "semicolon"));
+        Ok(statement)
     }
 }
 
-pub fn parse_statement(tokens_slice: &[Token]) -> Result<Statement, RuleError> {
+pub fn parse_statement(tokens_slice: &[SpannedToken]) -> Result<Statement, RuleError> {
     let mut tokens = Tokens::new(tokens_slice);
     let statement = try!(Statement::parse(&mut tokens));
 
@@ -716,10 +1020,28 @@ pub fn parse_statement(tokens_slice: &[Token]) -> Result<Statement, RuleError> {
     Ok(statement)
 }
 
-/// Parses a series of statements separated by semicolons
-pub fn parse_statements(tokens_slice: &[Token]) -> Result<Vec<Statement>, RuleError> {
+/// Parses a series of statements, each followed by a semicolon.
+///
+/// A statement with an error is skipped up to the next semicolon, and parsing
+/// carries on from there, so that the error of every statement is found.
+pub fn parse_statements(tokens_slice: &[SpannedToken]) -> Result<Vec<Statement>, Vec<RuleError>> {
     let mut tokens = Tokens::new(tokens_slice);
-    let statements = try!(Statements::parse(&mut tokens));
-    try!(tokens.expect_no_more_tokens());
-    Ok(statements)
+    let mut statements = Vec::new();
+    let mut errors = Vec::new();
+
+    while !tokens.is_empty() {
+        match TerminatedStatement::parse(&mut tokens) {
+            Ok(statement) => statements.push(statement),
+            Err(e) => {
+                errors.push(e);
+                tokens.skip_past(&Token::Semicolon);
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(statements)
+    } else {
+        Err(errors)
+    }
 }
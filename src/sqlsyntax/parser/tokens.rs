@@ -1,28 +1,59 @@
-use super::super::lexer::Token;
+use super::super::lexer::{Location, SpannedToken, Token};
 use super::{RuleError, RuleResult};
 
 #[derive(Copy, Clone)]
 pub struct Tokens<'a> {
-    tokens: &'a [Token]
+    tokens: &'a [SpannedToken],
+    /// The location just after the last token
+    end: Location
 }
 
 impl<'a> Tokens<'a> {
     fn peek_clone(&self) -> Option<Token> {
         if self.tokens.len() > 0 {
-            Some(self.tokens[0].clone())
+            Some(self.tokens[0].token.clone())
         } else {
             None
         }
     }
 
-    pub fn new(tokens: &'a [Token]) -> Tokens<'a> {
+    pub fn new(tokens: &'a [SpannedToken]) -> Tokens<'a> {
+        let end = match tokens.last() {
+            Some(token) => Location { line: token.span.end.line, column: token.span.end.column + 1 },
+            None => Location { line: 1, column: 1 }
+        };
+
         Tokens {
-            tokens: tokens
+            tokens: tokens,
+            end: end
+        }
+    }
+
+    /// The location of the next token, or the end if there are no more tokens
+    pub fn location(&self) -> Location {
+        match self.tokens.first() {
+            Some(token) => token.span.start,
+            None => self.end
         }
     }
 
     pub fn expecting(&self, expecting_message: &'static str) -> RuleError {
-        RuleError::ExpectingFirst(expecting_message, self.peek_clone())
+        RuleError::ExpectingFirst(expecting_message, self.peek_clone(), self.location())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Skips every token up to and including the next one that's equal to `token`.
+    /// If there isn't one, every token is skipped.
+    pub fn skip_past(&mut self, token: &Token) {
+        let count = match self.tokens.iter().position(|t| &t.token == token) {
+            Some(i) => i + 1,
+            None => self.tokens.len()
+        };
+
+        self.tokens = &self.tokens[count..];
     }
 
     pub fn expect_no_more_tokens(&self) -> RuleResult<()> {
@@ -40,7 +71,7 @@ impl<'a> Tokens<'a> {
 
     pub fn pop_if_token(&mut self, token: &Token) -> bool {
         if self.tokens.len() > 0 {
-            if &self.tokens[0] == token {
+            if &self.tokens[0].token == token {
                 self.tokens = &self.tokens[1..];
                 true
             } else {
@@ -54,7 +85,7 @@ impl<'a> Tokens<'a> {
     #[must_use]
     pub fn pop_if_number(&mut self) -> Option<String> {
         if self.tokens.len() > 0 {
-            let token = &self.tokens[0];
+            let token = &self.tokens[0].token;
 
             if let &Token::Number(ref s) = token {
                 let ident = s.clone();
@@ -71,7 +102,7 @@ impl<'a> Tokens<'a> {
     #[must_use]
     pub fn pop_if_string_literal(&mut self) -> Option<String> {
         if self.tokens.len() > 0 {
-            let token = &self.tokens[0];
+            let token = &self.tokens[0].token;
 
             if let &Token::StringLiteral(ref s) = token {
                 let ident = s.clone();
@@ -85,9 +116,25 @@ impl<'a> Tokens<'a> {
         }
     }
 
+    #[must_use]
+    pub fn pop_if_placeholder(&mut self) -> Option<u32> {
+        if self.tokens.len() > 0 {
+            let token = &self.tokens[0].token;
+
+            if let &Token::PreparedStatementPlaceholder(number) = token {
+                self.tokens = &self.tokens[1..];
+                Some(number)
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }
+
     pub fn pop_if_ident(&mut self) -> Option<String> {
         if self.tokens.len() > 0 {
-            let token = &self.tokens[0];
+            let token = &self.tokens[0].token;
 
             if let &Token::Ident(ref s) = token {
                 let ident = s.clone();
@@ -103,7 +150,7 @@ impl<'a> Tokens<'a> {
 
     pub fn pop_ident_expecting(&mut self, expecting_message: &'static str) -> RuleResult<String> {
         if self.tokens.len() > 0 {
-            let token = &self.tokens[0];
+            let token = &self.tokens[0].token;
 
             if let &Token::Ident(ref s) = token {
                 let ident = s.clone();
@@ -119,7 +166,7 @@ impl<'a> Tokens<'a> {
 
     pub fn pop_number_expecting(&mut self, expecting_message: &'static str) -> RuleResult<String> {
         if self.tokens.len() > 0 {
-            let token = &self.tokens[0];
+            let token = &self.tokens[0].token;
 
             if let &Token::Number(ref s) = token {
                 let ident = s.clone();
@@ -135,7 +182,7 @@ impl<'a> Tokens<'a> {
 
     pub fn pop_expecting(&mut self, expecting_message: &'static str) -> RuleResult<&'a Token> {
         if self.tokens.len() > 0 {
-            let token = &self.tokens[0];
+            let token = &self.tokens[0].token;
             self.tokens = &self.tokens[1..];
             Ok(token)
         } else {
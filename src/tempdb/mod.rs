@@ -1,38 +1,54 @@
-//! A temporary in-memory database meant to hold us over until the pager and
-//! B+Tree modules are finalized.
+//! An in-memory database engine, whose tables and indexes are kept in
+//! `BTreeSet`s.
 //!
-//! This module will be removed once the pager and B+Tree are functional.
+//! Nothing is written to disk, and everything is lost once the database is
+//! dropped. It backs `Connection::open_in_memory`.
 
 use std::borrow::Cow;
 use std::collections::BTreeSet;
+use std::collections::Bound::{Included, Unbounded};
 
-use columnvalueops::{ColumnValueOps, ColumnValueOpsExt};
-use databaseinfo::{DatabaseInfo, TableInfo, ColumnInfo};
+use databaseengine::{self, ColumnDefinition, CompiledStatement, DatabaseEngine, DatabaseStorageMut, IndexDefinition};
+use databaseinfo::DatabaseInfo;
 use databasestorage::{Group, DatabaseStorage};
+use error::Error;
 use identifier::Identifier;
-use types::{DbType, Variant};
+use indexkey;
+use rowkey;
+use types::Variant;
 use sqlsyntax::ast;
-use queryplan::{self, ExecuteQueryPlan, QueryPlan};
+
+pub use databaseengine::{ExecuteStatementResponse, ExecuteStatementResult};
 
 mod table;
-use self::table::Table;
+use self::table::{Index, Table};
 
 pub struct TempDb {
-    tables: Vec<Table>
+    tables: Vec<Table>,
+
+    /// Every change since the transaction began, oldest first.
+    /// Outside of a transaction, every change since the statement began.
+    undo_log: Vec<Undo>,
+    /// The length of `undo_log` when the current statement began
+    statement_start: usize,
+    in_transaction: bool,
+    schema_version: u64
 }
 
-pub enum ExecuteStatementResponse<'a> {
-    Created,
-    Inserted(u64),
-    Select {
-        column_names: Box<[String]>,
-        rows: Box<Iterator<Item=Box<[Variant]>> + 'a>
-    },
-    Explain(String)
+enum Undo {
+    /// Undone by removing the last table
+    CreateTable,
+    /// Undone by removing the table's last index
+    CreateIndex { table_name: Identifier },
+    DropTable { index: usize, table: Table },
+    RenameTable { name: Identifier, new_name: Identifier },
+    /// The columns, rows and indexes from before the table was altered
+    ReplaceColumns { table_name: Identifier, columns: Vec<table::Column>, rowid_index: BTreeSet<Vec<u8>>, indexes: Vec<Index> },
+    InsertRow { table_name: Identifier, key: Vec<u8> },
+    UpdateRow { table_name: Identifier, old_key: Vec<u8>, new_key: Vec<u8> },
+    DeleteRow { table_name: Identifier, key: Vec<u8> }
 }
 
-pub type ExecuteStatementResult<'a> = Result<ExecuteStatementResponse<'a>, String>;
-
 impl DatabaseInfo for TempDb {
     type Table = Table;
     type ColumnValue = Variant;
@@ -40,6 +56,10 @@ impl DatabaseInfo for TempDb {
     fn find_table_by_name(&self, name: &Identifier) -> Option<&Table> {
         self.tables.iter().find(|t| &t.name == name)
     }
+
+    fn get_table_names(&self) -> Vec<Identifier> {
+        self.tables.iter().map(|t| t.name.clone()).collect()
+    }
 }
 
 struct ScanGroup<'a> {
@@ -58,65 +78,21 @@ impl<'a> Group for ScanGroup<'a> {
     }
 
     fn iter<'b>(&'b self) -> Box<Iterator<Item=Cow<'b, [Variant]>> + 'b> {
-        let table = self.table;
-        let columns: &'b [self::table::Column] = &table.columns;
-
-        Box::new(table.rowid_index.iter().map(move |key_v| {
-            use byteutils;
-            use std::borrow::IntoCow;
-
-            let raw_key: &[u8] = &key_v;
-            trace!("KEY: {:?}", raw_key);
-
-            let variable_column_count = columns.iter().filter(|column| {
-                column.dbtype.is_variable_length()
-            }).count();
-
-            let variable_lengths: Vec<_> = (0..variable_column_count).map(|i| {
-                let o = raw_key.len() - variable_column_count*8 + i*8;
-                byteutils::read_udbinteger(&raw_key[o..o+8])
-            }).collect();
-
-            trace!("variable lengths: {:?}", variable_lengths);
-
-            let _rowid: u64 = byteutils::read_udbinteger(&raw_key[0..8]);
-
-            let mut variable_length_offset = 0;
-            let mut key_offset = 8;
-
-            let v: Vec<Variant> = columns.iter().map(|column| {
-                let is_null = if column.nullable {
-                    let flag = raw_key[key_offset];
-                    key_offset += 1;
-                    flag != 0
-                } else {
-                    false
-                };
+        table_rows(self.table)
+    }
+}
 
-                if is_null {
-                    ColumnValueOpsExt::null()
-                } else {
-                    let size = match column.dbtype.get_fixed_length() {
-                        Some(l) => l as usize,
-                        None => {
-                            let l = variable_lengths[variable_length_offset];
-                            variable_length_offset += 1;
-                            l as usize
-                        }
-                    };
+fn table_rows<'a>(table: &'a Table) -> Box<Iterator<Item=Cow<'a, [Variant]>> + 'a> {
+    let columns: &'a [self::table::Column] = &table.columns;
 
-                    let bytes = &raw_key[key_offset..key_offset + size];
+    Box::new(table.rowid_index.iter().map(move |key_v| {
+        use std::borrow::IntoCow;
 
-                    trace!("from bytes: {:?}, {:?}", column.dbtype, bytes);
-                    let value = ColumnValueOps::from_bytes(column.dbtype, bytes.into_cow()).unwrap();
-                    key_offset += size;
-                    value
-                }
-            }).collect();
+        let raw_key: &[u8] = &key_v;
+        trace!("KEY: {:?}", raw_key);
 
-            v.into_cow()
-        }))
-    }
+        table::decode_row_with_rowid(columns, raw_key).into_cow()
+    }))
 }
 
 impl DatabaseStorage for TempDb {
@@ -129,226 +105,394 @@ impl DatabaseStorage for TempDb {
             table: table
         })
     }
+
+    fn scan_rows<'a>(&'a self, table: &'a Table)
+    -> Box<Iterator<Item=Result<Cow<'a, [Variant]>, Error>> + 'a>
+    {
+        Box::new(table_rows(table).map(Ok))
+    }
+
+    fn search_index<'a>(&'a self, table: &'a Table, index: &'a Index, min: Option<&[Variant]>, max: Option<&[Variant]>)
+    -> Box<Iterator<Item=Result<Cow<'a, [Variant]>, Error>> + 'a>
+    {
+        let min = min.and_then(|values| indexkey::encode_values(&table.columns, &index.columns, values));
+        let max = max.and_then(|values| indexkey::encode_values(&table.columns, &index.columns, values));
+
+        let keys = match min {
+            Some(ref min) => index.keys.range::<[u8], _>((Included(&min[..]), Unbounded)),
+            None => index.keys.range::<[u8], _>((Unbounded, Unbounded))
+        };
+
+        Box::new(keys.take_while(move |key| {
+            match max {
+                Some(ref max) => indexkey::is_at_or_before(key, max),
+                None => true
+            }
+        }).map(move |key| {
+            use std::borrow::IntoCow;
+
+            let row_key = table.find_key(indexkey::decode_rowid(key)).unwrap();
+            Ok(table::decode_row_with_rowid(&table.columns, &row_key).into_cow())
+        }))
+    }
 }
 
 impl TempDb {
     pub fn new() -> TempDb {
         TempDb {
-            tables: Vec::new()
+            tables: Vec::new(),
+            undo_log: Vec::new(),
+            statement_start: 0,
+            in_transaction: false,
+            schema_version: databaseengine::new_schema_version()
         }
     }
 
-    pub fn execute_statement(&mut self, stmt: ast::Statement) -> ExecuteStatementResult {
-        match stmt {
-            ast::Statement::Create(create_stmt) => {
-                match create_stmt {
-                    ast::CreateStatement::Table(s) => self.create_table(s)
-                }
-            },
-            ast::Statement::Insert(insert_stmt) => self.insert_into(insert_stmt),
-            ast::Statement::Select(select_stmt) => self.select(select_stmt),
-            ast::Statement::Explain(explain_stmt) => self.explain(explain_stmt)
+    fn add_table(&mut self, table: Table) -> Result<(), Error> {
+        if self.tables.iter().any(|t| t.name == table.name) {
+            Err(Error::Invalid(format!("Table {} already exists", table.name)))
+        } else {
+            debug!("adding table: {:?}", table);
+            self.tables.push(table);
+
+            Ok(())
         }
     }
 
-    fn create_table(&mut self, stmt: ast::CreateTableStatement) -> ExecuteStatementResult {
-        if stmt.table.database_name.is_some() {
-            unimplemented!()
+    fn get_table_mut(&mut self, table_name: &Identifier) -> Result<&mut Table, Error> {
+        match self.tables.iter_mut().find(|t| &t.name == table_name) {
+            Some(s) => Ok(s),
+            None => Err(Error::UnknownTable(table_name.to_string()))
         }
+    }
 
-        let table_name = Identifier::new(&stmt.table.table_name).unwrap();
-
-        let columns_result: Result<_, String>;
-        columns_result = stmt.columns.into_iter().enumerate().map(|(i, column)| {
-            let name = Identifier::new(&column.column_name).unwrap();
-            let type_name = Identifier::new(&column.type_name).unwrap();
-            let type_array_size = match column.type_array_size {
-                Some(Some(s)) => {
-                    let v = try!(self.parse_number_as_u64(s));
-                    Some(Some(v))
+    /// Undoes every change after the first `len` entries of the undo log.
+    fn rollback_undo_log(&mut self, len: usize) {
+        while self.undo_log.len() > len {
+            match self.undo_log.pop().unwrap() {
+                Undo::CreateTable => {
+                    let table = self.tables.pop().unwrap();
+                    debug!("removing table: {:?}", table.name);
                 },
-                Some(None) => Some(None),
-                None => None
-            };
+                Undo::CreateIndex { table_name } => {
+                    let table = self.get_table_mut(&table_name).unwrap();
+                    let index = table.indexes.pop().unwrap();
+                    debug!("removing index: {:?}", index.name);
+                },
+                Undo::DropTable { index, table } => {
+                    self.tables.insert(index, table);
+                },
+                Undo::RenameTable { name, new_name } => {
+                    rename_table(&mut self.tables, &new_name, &name);
+                },
+                Undo::ReplaceColumns { table_name, columns, rowid_index, indexes } => {
+                    let table = self.get_table_mut(&table_name).unwrap();
+                    table.columns = columns;
+                    table.rowid_index = rowid_index;
+                    table.indexes = indexes;
+                },
+                Undo::InsertRow { table_name, key } => {
+                    let table = self.get_table_mut(&table_name).unwrap();
+                    table.remove_key(&key);
+                    table.next_rowid -= 1;
+                },
+                Undo::UpdateRow { table_name, old_key, new_key } => {
+                    let table = self.get_table_mut(&table_name).unwrap();
+                    table.remove_key(&new_key);
+                    table.insert_key(old_key);
+                },
+                Undo::DeleteRow { table_name, key } => {
+                    let table = self.get_table_mut(&table_name).unwrap();
+                    table.insert_key(key);
+                }
+            }
+        }
+    }
+}
 
-            let dbtype = try!(DbType::from_identifier(&type_name, type_array_size).ok_or(format!("{} is not a valid column type", type_name)));
+/// Renames the table, and the foreign keys of every table that reference it
+fn rename_table(tables: &mut [Table], name: &Identifier, new_name: &Identifier) {
+    for table in tables.iter_mut() {
+        if &table.name == name {
+            table.name = new_name.clone();
+        }
 
-            let nullable = column.constraints.iter().any(|c| {
-                c.constraint == ast::CreateTableColumnConstraintType::Nullable
-            });
+        for column in table.columns.iter_mut() {
+            if let Some(ref mut foreign_key) = column.foreign_key {
+                if &foreign_key.table == name {
+                    foreign_key.table = new_name.clone();
+                }
+            }
+        }
+    }
+}
 
-            Ok(table::Column {
+impl DatabaseStorageMut for TempDb {
+    fn create_table(&mut self, name: Identifier, columns: Vec<ColumnDefinition>) -> Result<(), Error> {
+        let columns = columns.into_iter().enumerate().map(|(i, column)| {
+            table::Column {
                 offset: i as u32,
-                name: name,
-                dbtype: dbtype,
-                nullable: nullable
-            })
+                name: column.name,
+                dbtype: column.dbtype,
+                nullable: column.nullable,
+                primary_key: column.primary_key,
+                unique: column.unique,
+                foreign_key: column.foreign_key
+            }
         }).collect();
 
-        let columns = try!(columns_result);
-
         try!(self.add_table(Table {
-            name: table_name,
+            name: name,
             columns: columns,
             next_rowid: 1,
-            rowid_index: BTreeSet::new()
+            rowid_index: BTreeSet::new(),
+            indexes: Vec::new()
         }));
 
-        Ok(ExecuteStatementResponse::Created)
+        self.undo_log.push(Undo::CreateTable);
+        Ok(())
     }
 
-    fn insert_into(&mut self, stmt: ast::InsertStatement) -> ExecuteStatementResult {
-        trace!("inserting row: {:?}", stmt);
-
-        let table_name = stmt.table.table_name;
-        let column_types: Vec<(DbType, bool)>;
-        let ast_index_to_column_index: Vec<u32>;
+    fn create_index(&mut self, table_name: &Identifier, index: IndexDefinition) -> Result<(), Error> {
+        if self.tables.iter().any(|t| t.indexes.iter().any(|i| i.name == index.name)) {
+            return Err(Error::Invalid(format!("Index {} already exists", index.name)));
+        }
 
         {
-            let table = try!(self.get_table_mut(&table_name));
-
-            column_types = table.get_columns().iter().map(|c| {
-                (c.dbtype, c.nullable)
-            }).collect();
-
-            ast_index_to_column_index = match stmt.into_columns {
-                // Column names listed; map specified columns
-                Some(v) => try!(v.into_iter().map(|column_name| {
-                    let ident = Identifier::new(&column_name).unwrap();
-                    match table.find_column_by_name(&ident) {
-                        Some(column) => Ok(column.get_offset()),
-                        None => Err(format!("column {} not in table", column_name))
-                    }
-                }).collect()),
-                // No column names are listed; map all columns
-                None => (0..table.get_column_count()).collect()
+            let table = try!(self.get_table_mut(table_name));
+
+            let mut index = Index {
+                name: index.name,
+                columns: index.columns,
+                unique: index.unique,
+                keys: BTreeSet::new()
             };
 
-            trace!("ast_index_to_column_index: {:?}", ast_index_to_column_index);
+            try!(table.fill_index(&mut index));
+
+            debug!("adding index: {:?}", index.name);
+            table.indexes.push(index);
         }
 
-        match stmt.source {
-            ast::InsertSource::Values(rows) => {
-                let mut count = 0;
+        self.undo_log.push(Undo::CreateIndex {
+            table_name: table_name.clone()
+        });
+        Ok(())
+    }
 
-                for row in rows {
-                    if ast_index_to_column_index.len() != row.len() {
-                        return Err(format!("INSERT value contains wrong amount of columns"));
-                    }
+    fn drop_table(&mut self, name: &Identifier) -> Result<(), Error> {
+        let index = try!(self.tables.iter().position(|t| &t.name == name).ok_or(Error::UnknownTable(name.to_string())));
+        let table = self.tables.remove(index);
 
-                    let mut exprs: Vec<Option<ast::Expression>>;
-                    exprs = (0..column_types.len()).map(|_| None).collect();
+        debug!("dropping table: {:?}", table.name);
 
-                    for (i, expr) in row.into_iter().enumerate() {
-                        exprs[ast_index_to_column_index[i] as usize] = Some(expr);
-                    }
+        self.undo_log.push(Undo::DropTable {
+            index: index,
+            table: table
+        });
+        Ok(())
+    }
 
-                    // TODO: don't allow expressions that SELECT the same table that's being inserted into
-                    let v: Vec<_> = try!({column_types.iter().zip(exprs.into_iter()).map(|(&(dbtype, nullable), expr)| {
-                        match expr {
-                            Some(expr) => {
-                                // TODO - allocate buffer outside of loop
-                                let mut buf = Vec::new();
-
-                                let execute = ExecuteQueryPlan::new(self);
-
-                                let sexpr = match queryplan::compile_ast_expression(self, expr).map_err(|e| format!("{}", e)) {
-                                    Ok(v) => v,
-                                    Err(e) => return Err(e)
-                                };
-                                let value = try!(execute.execute_expression(&sexpr));
-
-                                let is_null = try!(variant_to_data(value, dbtype, nullable, &mut buf));
-                                Ok((buf.into_boxed_slice(), is_null))
-                            },
-                            None => {
-                                // use default value for column type
-                                let is_null = if nullable { Some(true) } else { None };
-                                Ok((dbtype.get_default().into_owned().into_boxed_slice(), is_null))
-                            }
-                        }
-                    }).collect()});
-
-                    let mut table = try!(self.get_table_mut(&table_name));
-                    try!(table.insert_row(v.into_iter()).map_err(|e| format!("{}", e)));
-                    count += 1;
-                }
+    fn rename_table(&mut self, name: &Identifier, new_name: Identifier) -> Result<(), Error> {
+        if self.tables.iter().any(|t| t.name == new_name) {
+            return Err(Error::Invalid(format!("Table {} already exists", new_name)));
+        }
 
-                Ok(ExecuteStatementResponse::Inserted(count))
-            },
-            ast::InsertSource::Select(_s) => unimplemented!()
+        if !self.tables.iter().any(|t| &t.name == name) {
+            return Err(Error::UnknownTable(name.to_string()));
         }
+
+        rename_table(&mut self.tables, name, &new_name);
+
+        self.undo_log.push(Undo::RenameTable {
+            name: name.clone(),
+            new_name: new_name
+        });
+        Ok(())
     }
 
-    fn select(&self, stmt: ast::SelectStatement) -> ExecuteStatementResult {
-        let plan = try!(QueryPlan::compile_select(self, stmt).map_err(|e| format!("{}", e)));
-        debug!("{}", plan);
+    fn replace_columns(&mut self, table_name: &Identifier, columns: Vec<ColumnDefinition>, rows: Vec<(u64, Vec<(Box<[u8]>, Option<bool>)>)>) -> Result<(), Error> {
+        let columns: Vec<table::Column> = columns.into_iter().enumerate().map(|(i, column)| {
+            table::Column {
+                offset: i as u32,
+                name: column.name,
+                dbtype: column.dbtype,
+                nullable: column.nullable,
+                primary_key: column.primary_key,
+                unique: column.unique,
+                foreign_key: column.foreign_key
+            }
+        }).collect();
 
-        let mut rows = Vec::new();
+        let mut rowid_index = BTreeSet::new();
+        for (rowid, column_data) in rows {
+            let key = try!(rowkey::encode_row(rowid, &columns, column_data.into_iter()));
+            rowid_index.insert(key);
+        }
 
-        let execute = ExecuteQueryPlan::new(self);
-        try!(execute.execute_query_plan(&plan.expr, &mut |r| {
-            rows.push(r.to_vec().into_boxed_slice());
-            Ok(())
-        }));
+        let (old_columns, old_rowid_index, old_indexes, result) = {
+            let table = try!(self.get_table_mut(table_name));
+
+            let old_columns = ::std::mem::replace(&mut table.columns, columns);
+            let old_rowid_index = ::std::mem::replace(&mut table.rowid_index, rowid_index);
+            let old_indexes = ::std::mem::replace(&mut table.indexes, Vec::new());
+
+            // Indexes follow their columns by name.
+            // The undo entry must be pushed even if an index fails.
+            let mut result = Ok(());
+
+            for old_index in &old_indexes {
+                let columns: Option<Vec<u32>> = old_index.columns.iter().map(|&offset| {
+                    let name = &old_columns[offset as usize].name;
+                    table.columns.iter().position(|c| &c.name == name).map(|offset| offset as u32)
+                }).collect();
+
+                if let Some(columns) = columns {
+                    let mut index = Index {
+                        name: old_index.name.clone(),
+                        columns: columns,
+                        unique: old_index.unique,
+                        keys: BTreeSet::new()
+                    };
 
-        let column_names: Vec<String> = plan.out_column_names.iter().map(|ident| ident.to_string()).collect();
+                    if let Err(e) = table.fill_index(&mut index) {
+                        result = Err(e);
+                        break;
+                    }
+                    table.indexes.push(index);
+                } else {
+                    debug!("dropping index: {:?}", old_index.name);
+                }
+            }
 
-        Ok(ExecuteStatementResponse::Select {
-            column_names: column_names.into_boxed_slice(),
-            rows: Box::new(rows.into_iter())
-        })
+            (old_columns, old_rowid_index, old_indexes, result)
+        };
+
+        self.undo_log.push(Undo::ReplaceColumns {
+            table_name: table_name.clone(),
+            columns: old_columns,
+            rowid_index: old_rowid_index,
+            indexes: old_indexes
+        });
+        result
     }
 
-    fn explain(&self, stmt: ast::ExplainStatement) -> ExecuteStatementResult {
-        use queryplan::QueryPlan;
+    fn insert_row(&mut self, table_name: &Identifier, column_data: Vec<(Box<[u8]>, Option<bool>)>) -> Result<(), Error> {
+        let key = {
+            let table = try!(self.get_table_mut(table_name));
+            try!(table.insert_row(column_data.into_iter()))
+        };
+
+        self.undo_log.push(Undo::InsertRow {
+            table_name: table_name.clone(),
+            key: key
+        });
+        Ok(())
+    }
 
-        match stmt {
-            ast::ExplainStatement::Select(select) => {
-                let plan = try!(QueryPlan::compile_select(self, select).map_err(|e| format!("{}", e)));
+    fn rows_with_rowids(&self, table_name: &Identifier) -> Result<Vec<(u64, Vec<Variant>)>, Error> {
+        let table = try!(self.find_table_by_name(table_name).ok_or(Error::UnknownTable(table_name.to_string())));
 
-                Ok(ExecuteStatementResponse::Explain(plan.to_string()))
-            }
-        }
+        Ok(table.rowid_index.iter().map(|key| {
+            (rowkey::decode_rowid(key), table::decode_row(&table.columns, key))
+        }).collect())
     }
 
-    fn add_table(&mut self, table: Table) -> Result<(), String> {
-        if self.tables.iter().any(|t| t.name == table.name) {
-            Err(format!("Table {} already exists", table.name))
-        } else {
-            debug!("adding table: {:?}", table);
-            self.tables.push(table);
+    fn update_row(&mut self, table_name: &Identifier, rowid: u64, column_data: Vec<(Box<[u8]>, Option<bool>)>) -> Result<(), Error> {
+        let (old_key, new_key) = {
+            let table = try!(self.get_table_mut(table_name));
+            let old_key = try!(table.find_key(rowid).ok_or(Error::Invalid(format!("Could not find row {} in {}", rowid, table_name))));
+            let new_key = try!(table.replace_row(&old_key, column_data.into_iter()));
+            (old_key, new_key)
+        };
+
+        self.undo_log.push(Undo::UpdateRow {
+            table_name: table_name.clone(),
+            old_key: old_key,
+            new_key: new_key
+        });
+        Ok(())
+    }
 
-            Ok(())
-        }
+    fn delete_row(&mut self, table_name: &Identifier, rowid: u64) -> Result<(), Error> {
+        let key = {
+            let table = try!(self.get_table_mut(table_name));
+            let key = try!(table.find_key(rowid).ok_or(Error::Invalid(format!("Could not find row {} in {}", rowid, table_name))));
+            trace!("deleting row {} from {}", rowid, table.name);
+            table.remove_key(&key);
+            key
+        };
+
+        self.undo_log.push(Undo::DeleteRow {
+            table_name: table_name.clone(),
+            key: key
+        });
+        Ok(())
     }
 
-    fn get_table_mut(&mut self, table_name: &str) -> Result<&mut Table, String> {
-        let table_name = try!(Identifier::new(table_name).ok_or(format!("Bad table name: {}", table_name)));
+    fn begin_transaction(&mut self) -> Result<(), Error> {
+        self.in_transaction = true;
+        Ok(())
+    }
 
-        match self.tables.iter_mut().find(|t| t.name == table_name) {
-            Some(s) => Ok(s),
-            None => Err(format!("Could not find table named {}", table_name))
+    fn commit_transaction(&mut self) -> Result<(), Error> {
+        // Nothing to write; everything is in memory.
+        self.in_transaction = false;
+        self.undo_log.clear();
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Error> {
+        self.in_transaction = false;
+        self.rollback_undo_log(0);
+        Ok(())
+    }
+
+    fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    fn begin_statement(&mut self) -> Result<(), Error> {
+        self.statement_start = self.undo_log.len();
+        Ok(())
+    }
+
+    fn end_statement(&mut self, success: bool) -> Result<(), Error> {
+        if !success {
+            let statement_start = self.statement_start;
+            self.rollback_undo_log(statement_start);
+        }
+
+        if !self.in_transaction {
+            self.undo_log.clear();
         }
+
+        Ok(())
+    }
+
+    fn schema_version(&self) -> u64 {
+        self.schema_version
     }
 
-    fn parse_number_as_u64(&self, number: String) -> Result<u64, String> {
-        number.parse().map_err(|_| format!("{} is not a valid number", number))
+    fn set_schema_version(&mut self, version: u64) {
+        self.schema_version = version;
     }
 }
 
-fn variant_to_data(value: Variant, column_type: DbType, nullable: bool, buf: &mut Vec<u8>)
--> Result<Option<bool>, String> {
-    match (value.is_null(), nullable) {
-        (true, true) => Ok(Some(true)),
-        (true, false) => {
-            Err(format!("cannot insert NULL into column that doesn't allow NULL"))
-        },
-        (false, nullable) => {
-            let bytes = value.to_bytes(column_type).unwrap();
-            buf.extend_from_slice(&bytes);
-
-            Ok(if nullable { Some(false) } else { None })
-        }
+impl DatabaseEngine for TempDb {
+    fn execute_statement_with_params(&mut self, stmt: &ast::Statement, params: &[Variant]) -> ExecuteStatementResult {
+        databaseengine::execute_statement(self, stmt, params)
+    }
+
+    fn compile_statement(&self, stmt: &ast::Statement, parameter_count: u32) -> Result<CompiledStatement, Error> {
+        databaseengine::compile_statement(self, stmt, parameter_count)
+    }
+
+    fn is_up_to_date(&self, stmt: &CompiledStatement) -> bool {
+        databaseengine::is_up_to_date(self, stmt)
+    }
+
+    fn execute_compiled(&mut self, stmt: &CompiledStatement, params: &[Variant]) -> ExecuteStatementResult {
+        databaseengine::execute_compiled(self, stmt, params)
     }
 }
@@ -1,25 +1,12 @@
-use byteutils;
-use types::DbType;
-use databaseinfo::{ColumnInfo, TableInfo};
+use types::{DbType, Variant};
+use databaseengine;
+use error::Error;
+use databaseinfo::{ColumnInfo, ForeignKey, IndexInfo, TableInfo};
 use identifier::Identifier;
+use indexkey;
+use rowkey;
 use std::collections::BTreeSet;
-use std::fmt;
-
-pub enum UpdateError {
-    ValidationError {
-        column_name: Identifier,
-    }
-}
-
-impl fmt::Display for UpdateError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        match self {
-            &UpdateError::ValidationError { ref column_name } => {
-                write!(f, "Problem validating column: {}", column_name)
-            }
-        }
-    }
-}
+use std::collections::Bound::{Included, Unbounded};
 
 #[derive(Debug)]
 pub struct Table {
@@ -27,7 +14,40 @@ pub struct Table {
     pub columns: Vec<Column>,
 
     pub next_rowid: u64,
-    pub rowid_index: BTreeSet<Vec<u8>>
+    pub rowid_index: BTreeSet<Vec<u8>>,
+    pub indexes: Vec<Index>
+}
+
+#[derive(Debug)]
+pub struct Index {
+    pub name: Identifier,
+    /// The offsets of the indexed columns
+    pub columns: Vec<u32>,
+    pub unique: bool,
+
+    /// See the `indexkey` module for the key layout.
+    pub keys: BTreeSet<Vec<u8>>
+}
+
+impl Index {
+    /// Fails if another row has the same values in a unique index.
+    fn check_unique(&self, table: &Table, rowid: u64, row: &[Variant]) -> Result<(), Error> {
+        if !self.unique || indexkey::has_null(&self.columns, row) {
+            return Ok(());
+        }
+
+        let prefix = indexkey::encode_prefix(&table.columns, &self.columns, row);
+
+        let duplicate = self.keys.range::<[u8], _>((Included(&prefix[..]), Unbounded))
+            .take_while(|key| key.starts_with(&prefix))
+            .any(|key| indexkey::decode_rowid(key) != rowid);
+
+        if duplicate {
+            Err(databaseengine::unique_violation(table, &self.columns))
+        } else {
+            Ok(())
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -35,11 +55,15 @@ pub struct Column {
     pub offset: u32,
     pub name: Identifier,
     pub dbtype: DbType,
-    pub nullable: bool
+    pub nullable: bool,
+    pub primary_key: bool,
+    pub unique: bool,
+    pub foreign_key: Option<ForeignKey>
 }
 
 impl TableInfo for Table {
     type Column = Column;
+    type Index = Index;
 
     fn get_name(&self) -> &Identifier { &self.name }
 
@@ -58,90 +82,131 @@ impl TableInfo for Table {
     fn find_column_by_name(&self, name: &Identifier) -> Option<&Column> {
         self.columns.iter().find(|c| &c.name == name)
     }
+
+    fn get_index_count(&self) -> u32 { self.indexes.len() as u32 }
+
+    fn find_index_by_offset(&self, offset: u32) -> Option<&Index> {
+        self.indexes.get(offset as usize)
+    }
+}
+
+impl IndexInfo for Index {
+    fn get_name(&self) -> &Identifier { &self.name }
+    fn get_column_offsets(&self) -> &[u32] { &self.columns }
+    fn is_unique(&self) -> bool { self.unique }
 }
 
 impl Table {
-    /// rowid is automatically added, and is not included as a specified column
-    pub fn insert_row<I>(&mut self, column_data: I) -> Result<(), UpdateError>
+    /// rowid is automatically added, and is not included as a specified column.
+    /// Returns the key of the new row.
+    pub fn insert_row<I>(&mut self, column_data: I) -> Result<Vec<u8>, Error>
     where I: ExactSizeIterator, I: Iterator<Item = (Box<[u8]>, Option<bool>)>
     {
-        assert_eq!(self.columns.len(), column_data.len());
+        trace!("columns: {:?}", self.columns);
 
-        let mut key: Vec<u8> = Vec::new();
-        {
-            let mut buf = [0; 8];
-            byteutils::write_udbinteger(self.next_rowid, &mut buf);
-            key.extend_from_slice(&buf);
-        }
+        let key = try!(rowkey::encode_row(self.next_rowid, &self.columns, column_data));
+        try!(self.check_unique(&key));
 
-        let mut lengths = Vec::new();
+        trace!("inserting row {} into {}", self.next_rowid, self.name);
+        trace!("inserting key into {}: {:?}", self.name, key);
 
-        trace!("columns: {:?}", self.columns);
+        self.insert_key(key.clone());
+        self.next_rowid += 1;
+        Ok(key)
+    }
 
-        for (column, (data_box, is_null)) in self.columns.iter().zip(column_data) {
-            let data: &[u8] = &data_box;
+    fn check_unique(&self, key: &[u8]) -> Result<(), Error> {
+        let rowid = rowkey::decode_rowid(key);
+        let row = decode_row(&self.columns, key);
 
-            trace!("column data for {}: {:?}", column.name, data);
+        for index in &self.indexes {
+            try!(index.check_unique(self, rowid, &row));
+        }
 
-            let len = data.len() as u64;
+        Ok(())
+    }
 
-            let append_data = match is_null {
-                Some(true) => {
-                    assert_eq!(len, 0);
-                    key.push(1);
+    /// Adds the row's key to the table and to every index.
+    pub fn insert_key(&mut self, key: Vec<u8>) {
+        let rowid = rowkey::decode_rowid(&key);
+        let row = decode_row(&self.columns, &key);
 
-                    false
-                },
-                Some(false) => {
-                    key.push(0);
+        for index in &mut self.indexes {
+            index.keys.insert(indexkey::encode_key(&self.columns, &index.columns, &row, rowid));
+        }
 
-                    true
-                },
-                None => true
-            };
+        self.rowid_index.insert(key);
+    }
 
-            if append_data {
-                if column.dbtype.is_valid_length(len) {
-                    if column.dbtype.is_variable_length() {
-                        let mut buf = [0; 8];
-                        byteutils::write_udbinteger(len, &mut buf);
-                        lengths.extend_from_slice(&buf);
-                    }
+    /// Removes the row's key from the table and from every index.
+    pub fn remove_key(&mut self, key: &[u8]) {
+        let rowid = rowkey::decode_rowid(key);
+        let row = decode_row(&self.columns, key);
 
-                    assert_eq!(column.nullable, is_null.is_some());
+        for index in &mut self.indexes {
+            index.keys.remove(&indexkey::encode_key(&self.columns, &index.columns, &row, rowid));
+        }
 
-                    if let Some(is_null) = is_null {
-                        if is_null {
-                        }
+        self.rowid_index.remove(key);
+    }
 
-                    }
+    /// Fills an empty index with every row of the table.
+    /// Fails if the index is unique and two rows have the same values.
+    pub fn fill_index(&self, index: &mut Index) -> Result<(), Error> {
+        for key in &self.rowid_index {
+            let rowid = rowkey::decode_rowid(key);
+            let row = decode_row(&self.columns, key);
 
-                    key.extend_from_slice(data);
-                } else {
-                    return Err(UpdateError::ValidationError {
-                        column_name: column.name.clone()
-                    });
-                }
-            }
+            try!(index.check_unique(self, rowid, &row));
+            index.keys.insert(indexkey::encode_key(&self.columns, &index.columns, &row, rowid));
         }
 
-        key.extend(lengths);
+        Ok(())
+    }
 
-        trace!("inserting row {} into {}", self.next_rowid, self.name);
-        trace!("inserting key into {}: {:?}", self.name, key);
+    pub fn find_key(&self, rowid: u64) -> Option<Vec<u8>> {
+        let prefix = rowkey::rowid_prefix(rowid);
 
-        self.rowid_index.insert(key);
-        self.next_rowid += 1;
-        Ok(())
+        match self.rowid_index.range::<[u8], _>((Included(&prefix[..]), Unbounded)).next() {
+            Some(key) if key.starts_with(&prefix) => Some(key.clone()),
+            _ => None
+        }
     }
 
-    pub fn get_columns(&self) -> &Vec<Column> {
-        &self.columns
+    /// Replaces the row that has the old key, keeping its rowid.
+    /// Returns the new key.
+    pub fn replace_row<I>(&mut self, old_key: &[u8], column_data: I) -> Result<Vec<u8>, Error>
+    where I: ExactSizeIterator, I: Iterator<Item = (Box<[u8]>, Option<bool>)>
+    {
+        let rowid = rowkey::decode_rowid(old_key);
+        let key = try!(rowkey::encode_row(rowid, &self.columns, column_data));
+        try!(self.check_unique(&key));
+
+        trace!("replacing row {} in {}", rowid, self.name);
+
+        self.remove_key(old_key);
+        self.insert_key(key.clone());
+        Ok(key)
     }
 }
 
+/// Decodes the column values of a row's key. The keys are only ever made by
+/// `rowkey::encode_row` and never leave memory, so they always decode.
+pub fn decode_row(columns: &[Column], key: &[u8]) -> Vec<Variant> {
+    rowkey::decode_row(columns, key).unwrap()
+}
+
+/// As `decode_row`, but followed by the rowid.
+pub fn decode_row_with_rowid(columns: &[Column], key: &[u8]) -> Vec<Variant> {
+    rowkey::decode_row_with_rowid(columns, key).unwrap()
+}
+
 impl ColumnInfo for Column {
     fn get_offset(&self) -> u32 { self.offset }
     fn get_name(&self) -> &Identifier { &self.name }
     fn get_dbtype(&self) -> &DbType { &self.dbtype }
+    fn is_nullable(&self) -> bool { self.nullable }
+    fn is_primary_key(&self) -> bool { self.primary_key }
+    fn is_unique(&self) -> bool { self.unique }
+    fn get_foreign_key(&self) -> Option<&ForeignKey> { self.foreign_key.as_ref() }
 }
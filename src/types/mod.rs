@@ -7,7 +7,7 @@ pub use self::variant::Variant;
 
 use std::borrow::Cow;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum DbType {
     /// A type that only contains the NULL value.
     Null,
@@ -34,6 +34,14 @@ impl fmt::Debug for Variant {
     }
 }
 
+/// NaN isn't a value, so it's NULL.
+fn float(value: f64) -> Variant {
+    match F64NoNaN::new(value) {
+        Some(value) => Variant::Float(value),
+        None => Variant::Null
+    }
+}
+
 fn from_bool(value: bool) -> Variant {
     Variant::UnsignedInteger(if value { 1 } else { 0 })
 }
@@ -56,7 +64,7 @@ impl ColumnValueOps for Variant {
     }
 
     fn from_f64(value: f64) -> Variant {
-        Variant::Float(F64NoNaN::new(value).unwrap())
+        float(value)
     }
 
     fn to_f64(self) -> Result<f64, ()> {
@@ -104,8 +112,12 @@ impl ColumnValueOps for Variant {
                 }
             },
             DbType::F64 => {
-                let f = byteutils::read_dbfloat(&bytes);
-                Ok(Variant::Float(F64NoNaN::new(f).unwrap()))
+                if bytes.len() != 8 {
+                    Err(())
+                } else {
+                    let f = byteutils::read_dbfloat(&bytes);
+                    F64NoNaN::new(f).map(Variant::Float).ok_or(())
+                }
             },
             DbType::String => {
                 let len = bytes.len();
@@ -291,19 +303,20 @@ impl ColumnValueOps for Variant {
         }
     }
 
+    // Integer arithmetic that overflows is done with floats instead, as SQLite does.
+
     fn add(&self, rhs: &Self) -> Self {
-        // TODO: treat overflow!
         let dbtype = self.get_dbtype();
         if let Some(r) = rhs.clone().cast(dbtype) {
             match (self, r) {
                 (&Variant::UnsignedInteger(l), Variant::UnsignedInteger(r)) => {
-                    Variant::UnsignedInteger(l + r)
+                    l.checked_add(r).map(Variant::UnsignedInteger).unwrap_or_else(|| float(l as f64 + r as f64))
                 },
                 (&Variant::SignedInteger(l), Variant::SignedInteger(r)) => {
-                    Variant::SignedInteger(l + r)
+                    l.checked_add(r).map(Variant::SignedInteger).unwrap_or_else(|| float(l as f64 + r as f64))
                 },
                 (&Variant::Float(l), Variant::Float(r)) => {
-                    Variant::Float(F64NoNaN::new(*l + *r).unwrap())
+                    float(*l + *r)
                 },
                 _ => self.clone()
             }
@@ -313,18 +326,21 @@ impl ColumnValueOps for Variant {
     }
 
     fn sub(&self, rhs: &Self) -> Self {
-        // TODO: treat overflow!
         let dbtype = self.get_dbtype();
         if let Some(r) = rhs.clone().cast(dbtype) {
             match (self, r) {
                 (&Variant::UnsignedInteger(l), Variant::UnsignedInteger(r)) => {
-                    Variant::UnsignedInteger(l - r)
+                    if l >= r {
+                        Variant::UnsignedInteger(l - r)
+                    } else {
+                        Variant::UnsignedInteger(r - l).negate()
+                    }
                 },
                 (&Variant::SignedInteger(l), Variant::SignedInteger(r)) => {
-                    Variant::SignedInteger(l - r)
+                    l.checked_sub(r).map(Variant::SignedInteger).unwrap_or_else(|| float(l as f64 - r as f64))
                 },
                 (&Variant::Float(l), Variant::Float(r)) => {
-                    Variant::Float(F64NoNaN::new(*l - *r).unwrap())
+                    float(*l - *r)
                 },
                 _ => self.clone()
             }
@@ -334,18 +350,17 @@ impl ColumnValueOps for Variant {
     }
 
     fn mul(&self, rhs: &Self) -> Self {
-        // TODO: treat overflow!
         let dbtype = self.get_dbtype();
         if let Some(r) = rhs.clone().cast(dbtype) {
             match (self, r) {
                 (&Variant::UnsignedInteger(l), Variant::UnsignedInteger(r)) => {
-                    Variant::UnsignedInteger(l * r)
+                    l.checked_mul(r).map(Variant::UnsignedInteger).unwrap_or_else(|| float(l as f64 * r as f64))
                 },
                 (&Variant::SignedInteger(l), Variant::SignedInteger(r)) => {
-                    Variant::SignedInteger(l * r)
+                    l.checked_mul(r).map(Variant::SignedInteger).unwrap_or_else(|| float(l as f64 * r as f64))
                 },
                 (&Variant::Float(l), Variant::Float(r)) => {
-                    Variant::Float(F64NoNaN::new(*l * *r).unwrap())
+                    float(*l * *r)
                 },
                 _ => self.clone()
             }
@@ -355,7 +370,6 @@ impl ColumnValueOps for Variant {
     }
 
     fn div(&self, rhs: &Self) -> Self {
-        // TODO: treat overflow!
         let dbtype = self.get_dbtype();
         if let Some(r) = rhs.clone().cast(dbtype) {
             match (self, r) {
@@ -366,13 +380,13 @@ impl ColumnValueOps for Variant {
                     Variant::UnsignedInteger(l / r)
                 },
                 (&Variant::SignedInteger(l), Variant::SignedInteger(r)) => {
-                    Variant::SignedInteger(l / r)
+                    l.checked_div(r).map(Variant::SignedInteger).unwrap_or_else(|| float(l as f64 / r as f64))
                 },
                 (&Variant::Float(l), Variant::Float(r)) => {
                     if r == F64NoNaN::new(0.0).unwrap() {
                         Variant::Null
                     } else {
-                        Variant::Float(F64NoNaN::new(*l / *r).unwrap())
+                        float(*l / *r)
                     }
                 },
                 _ => self.clone()
@@ -383,10 +397,17 @@ impl ColumnValueOps for Variant {
     }
 
     fn negate(&self) -> Self {
-        // TODO: treat overflow!
         match self {
-            &Variant::SignedInteger(n) => Variant::SignedInteger(-n),
-            &Variant::UnsignedInteger(n) => Variant::SignedInteger(-(n as i64)),
+            &Variant::SignedInteger(n) => n.checked_neg().map(Variant::SignedInteger).unwrap_or_else(|| float(-(n as f64))),
+            &Variant::UnsignedInteger(n) => {
+                if n <= i64::max_value() as u64 {
+                    Variant::SignedInteger(-(n as i64))
+                } else if n == i64::max_value() as u64 + 1 {
+                    Variant::SignedInteger(i64::min_value())
+                } else {
+                    float(-(n as f64))
+                }
+            },
             &Variant::Float(n) => Variant::Float(F64NoNaN::new(-*n).unwrap()),
             &Variant::Null |
             &Variant::Bytes(..) |